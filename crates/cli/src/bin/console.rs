@@ -1,15 +1,225 @@
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
 
-fn main() -> io::Result<()> {
-    let mut stream = TcpStream::connect("127.0.0.1:6379")?;
-    let mut reader = BufReader::new(stream.try_clone()?);
+use parser::{registry, tokenize};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use zyncdb_core::Reply;
+use zyncdb_core::config::Config;
+use zyncdb_core::resp;
+
+/// A byte stream to the server: TCP or a Unix socket, with or without TLS.
+trait Stream: Read + Write {}
+
+impl<S: Read + Write> Stream for S {}
+
+/// A connection to the server, read as whole replies. The console asks for
+/// RESP3, where every reply says where it ends, so a multi-line reply cannot
+/// be mistaken for the start of the next one.
+struct Connection {
+    stream: Box<dyn Stream>,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes)?;
+        self.stream.flush()
+    }
+
+    fn next(&mut self) -> io::Result<Reply> {
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            match resp::decode_reply(&self.buf) {
+                Ok(Some((reply, used))) => {
+                    self.buf.drain(..used);
+                    return Ok(reply);
+                }
+                Ok(None) => {}
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            }
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "server closed the connection",
+                ));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Blank lines and comments get no reply, so they are not sent.
+fn is_blank(line: &str) -> bool {
+    tokenize(line).is_ok_and(|tokens| tokens.is_empty())
+}
+
+fn is_exit(line: &str) -> bool {
+    let first = line.split_whitespace().next().unwrap_or_default();
+    registry::lookup(first).is_some_and(|spec| matches!(spec.name, "exit" | "shutdown"))
+}
+
+/// Take `--<name> <value>` out of the arguments, for the console's own
+/// options that the server's settings do not know about.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let at = args
+        .iter()
+        .position(|arg| *arg == flag || arg.strip_prefix(&flag).is_some_and(|rest| rest.starts_with('=')))?;
+    let arg = args.remove(at);
+    match arg.split_once('=') {
+        Some((_, value)) => Some(value.to_string()),
+        None if at < args.len() => Some(args.remove(at)),
+        None => {
+            eprintln!("console: missing value for '{}'", flag);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Take the flag `--<name>` out of the arguments. Returns whether it was there.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let flag = format!("--{}", name);
+    let before = args.len();
+    args.retain(|arg| *arg != flag);
+    args.len() < before
+}
+
+fn tls_error(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", path.display(), e))
+}
+
+/// TLS settings that trust the authorities in `ca`, presenting the client
+/// certificate and key in `identity` if given, for mutual TLS.
+fn tls_config(ca: &Path, identity: Option<(&Path, &Path)>) -> io::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).map_err(|e| tls_error(ca, e))? {
+        roots.add(cert.map_err(|e| tls_error(ca, e))?).map_err(|e| tls_error(ca, e))?;
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots);
+    match identity {
+        None => Ok(builder.with_no_client_auth()),
+        Some((cert, key)) => {
+            let certs = CertificateDer::pem_file_iter(cert)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| tls_error(cert, e))?;
+            let key = PrivateKeyDer::from_pem_file(key).map_err(|e| tls_error(key, e))?;
+            builder.with_client_auth_cert(certs, key).map_err(|e| tls_error(cert, e))
+        }
+    }
+}
 
-    let mut welcome = String::new();
-    reader.read_line(&mut welcome)?; // read welcome message
-    println!("{}", welcome.trim());
+/// `--tls` and the options that go with it.
+struct TlsOptions {
+    enabled: bool,
+    /// Authorities to check the server against; the server's own
+    /// `tls-ca-cert-file` if not given.
+    cacert: Option<String>,
+    /// Client certificate and key, for mutual TLS.
+    cert: Option<String>,
+    key: Option<String>,
+    /// The name to expect on the server's certificate; its `bind` address if
+    /// not given.
+    sni: Option<String>,
+}
+
+impl TlsOptions {
+    fn take(args: &mut Vec<String>) -> TlsOptions {
+        TlsOptions {
+            enabled: take_flag(args, "tls"),
+            cacert: take_option(args, "cacert"),
+            cert: take_option(args, "cert"),
+            key: take_option(args, "key"),
+            sni: take_option(args, "sni"),
+        }
+    }
+}
+
+/// Connect to the Unix socket `socket` if given, else where the server's
+/// settings say it listens: TCP, or its `unixsocket` when `port` is 0. Over
+/// TLS if asked.
+fn connect(config: &Config, socket: Option<&Path>, tls: &TlsOptions) -> io::Result<Box<dyn Stream>> {
+    let socket = socket.or(config.unixsocket.as_deref().filter(|_| config.port == 0));
+    let transport: Box<dyn Stream> = match socket {
+        #[cfg(unix)]
+        Some(path) => Box::new(
+            std::os::unix::net::UnixStream::connect(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?,
+        ),
+        #[cfg(not(unix))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "--socket needs a Unix system")),
+        None => Box::new(TcpStream::connect(config.addr())?),
+    };
+    if !tls.enabled {
+        return Ok(transport);
+    }
+    let Some(ca) = tls.cacert.clone().map(Into::into).or(config.tlscacertfile.clone()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--tls needs --cacert <file>"));
+    };
+    let identity = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+        (None, None) => None,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "--cert and --key go together")),
+    };
+    let name = ServerName::try_from(tls.sni.clone().unwrap_or(config.bind.clone()))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let client = ClientConnection::new(Arc::new(tls_config(&ca, identity)?), name).map_err(io::Error::other)?;
+    Ok(Box::new(StreamOwned::new(client, transport)))
+}
+
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // A password or API token to log in with, as `--user` or the default user.
+    let auth = take_option(&mut args, "auth");
+    let user = take_option(&mut args, "user");
+    let socket = take_option(&mut args, "socket");
+    let tls = TlsOptions::take(&mut args);
+    // The server's own settings say where it listens, and its password.
+    let config = match Config::load(args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("console: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stream = match connect(&config, socket.as_deref().map(Path::new), &tls) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("console: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut server = Connection { stream, buf: Vec::new() };
+    server.send(b"HELLO 3\r\n")?;
+    server.next()?;
+    // A hashed requirepass is no use for logging in.
+    let secret = auth.or(Some(config.requirepass).filter(|pass| !pass.is_empty() && !pass.starts_with("$argon2")));
+    if let Some(secret) = secret {
+        let mut request = Vec::new();
+        let args = ["AUTH".to_string()].into_iter().chain(user).chain([secret]);
+        resp::encode(&Reply::Array(args.map(Reply::Bulk).collect()), resp::Protocol::Resp2, &mut request);
+        server.send(&request)?;
+        if let reply @ Reply::Error(_) = server.next()? {
+            eprintln!("console: {}", reply);
+            std::process::exit(1);
+        }
+    }
 
     let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return pipeline(stdin.lock(), &mut server);
+    }
+
+    println!("Welcome to zyncdb 🦀");
+    println!("Type 'help' for commands.");
     let mut input = String::new();
 
     loop {
@@ -17,22 +227,45 @@ fn main() -> io::Result<()> {
         io::stdout().flush()?;
 
         input.clear();
-        stdin.read_line(&mut input)?;
-        if input.trim().is_empty() {
+        if stdin.read_line(&mut input)? == 0 {
+            break;
+        }
+        if is_blank(&input) {
             continue;
         }
 
-        stream.write_all(input.as_bytes())?;
-        stream.flush()?;
+        server.send(format!("{}\r\n", input.trim_end()).as_bytes())?;
+        println!("{}", server.next()?);
 
-        let mut response = String::new();
-        reader.read_line(&mut response)?;
-        println!("{}", response.trim());
-
-        if input.trim() == "exit" {
+        if is_exit(&input) {
             break;
         }
     }
 
     Ok(())
 }
+
+/// Send every command of a script in one write, then print the replies in
+/// order. The server closes the connection after `exit`, so nothing after it
+/// is sent.
+fn pipeline(input: impl BufRead, server: &mut Connection) -> io::Result<()> {
+    let mut requests = String::new();
+    let mut count = 0;
+    for line in input.lines() {
+        let line = line?;
+        if is_blank(&line) {
+            continue;
+        }
+        requests.push_str(line.trim_end());
+        requests.push_str("\r\n");
+        count += 1;
+        if is_exit(&line) {
+            break;
+        }
+    }
+    server.send(requests.as_bytes())?;
+    for _ in 0..count {
+        println!("{}", server.next()?);
+    }
+    Ok(())
+}
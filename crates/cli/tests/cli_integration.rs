@@ -1,22 +1,55 @@
-#[test]
-fn test_put_and_get() {
-    use std::process::{Command, Stdio};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn run_cli(lines: &[&str]) -> String {
+    // Tests run in parallel, and each CLI locks its data directory.
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("zyncdb_cli_{}_{}", std::process::id(), run));
+    std::fs::create_dir_all(&dir).unwrap();
 
-    let mut child = Command::new("cargo")
-        .args(&["run", "-p", "cli"])
+    let mut child = Command::new(env!("CARGO_BIN_EXE_zyncdb"))
+        .current_dir(&dir)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .expect("Failed to start CLI");
 
     let stdin = child.stdin.as_mut().unwrap();
-    use std::io::Write;
-    writeln!(stdin, "put foo bar").unwrap();
-    writeln!(stdin, "get foo").unwrap();
-    writeln!(stdin, "exit").unwrap();
+    for line in lines {
+        writeln!(stdin, "{}", line).unwrap();
+    }
 
     let output = child.wait_with_output().unwrap();
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    let _ = std::fs::remove_dir_all(&dir);
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn test_put_and_get() {
+    let stdout = run_cli(&["put foo bar", "get foo", "exit"]);
     assert!(stdout.contains("ok"));
     assert!(stdout.contains("bar"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_invalid_keys_are_rejected() {
+    let long_key = format!("put {} v", "k".repeat(256));
+    let stdout = run_cli(&["put a|b x", &long_key, "get a|b", "exit"]);
+    assert!(stdout.contains("Error: key contains disallowed character '|'"));
+    assert!(stdout.contains("Error: key is longer than 255 bytes"));
+}
+
+#[test]
+fn test_config_set_changes_the_limits() {
+    let stdout = run_cli(&[
+        "put abcdefghi 1",
+        "config set max-key-len 8",
+        "put abcdefghi 2",
+        "get abcdefghi",
+        "exit",
+    ]);
+    assert!(stdout.contains("Error: key is longer than 8 bytes"));
+    assert!(stdout.contains("> 1\n"));
+}
@@ -0,0 +1,434 @@
+//! Users and what they may run and touch.
+//!
+//! Every authenticated connection runs as a user. A user is granted command
+//! categories, taken from the registry flags (`read`, `write`, `admin` and
+//! `dangerous`), globs for the keys it may touch, and optionally the
+//! namespaces it is confined to. The server checks a command against its user
+//! before the store sees it.
+//!
+//! Users are changed with Redis-style `ACL SETUSER` rules and kept in the ACL
+//! file as one `user <name> <rule> ...` line each, passwords as argon2 hashes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use argon2::password_hash::PasswordHash;
+use parser::registry::{self, CommandSpec};
+use parser::Command;
+
+use crate::auth::{check_password, hash_secret, verify_secret, write_private};
+use crate::planner::namespace_prefix;
+use crate::query::glob;
+
+/// Where users are kept, inside the data directory.
+pub const ACL_PATH: &str = ".zyncdb.acl";
+/// The user connections start as, and the one `requirepass` logs in.
+pub const DEFAULT_USER: &str = "default";
+
+/// A group of commands a user may be allowed to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Read,
+    Write,
+    Admin,
+    /// Admin commands that stop the server or rewrite all of its data.
+    Dangerous,
+}
+
+const CATEGORIES: [Category; 4] = [Category::Read, Category::Write, Category::Admin, Category::Dangerous];
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Read => "read",
+            Category::Write => "write",
+            Category::Admin => "admin",
+            Category::Dangerous => "dangerous",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Category> {
+        CATEGORIES.into_iter().find(|category| category.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The categories of the command named `name`, from its registry flags.
+    /// Commands in none, such as PING, may be run by every user.
+    pub fn of(name: &str) -> Vec<Category> {
+        registry::lookup(name).map_or_else(Vec::new, Category::of_spec)
+    }
+
+    /// The categories of `command`, from the flags of its subcommand where
+    /// that has its own; see `Command::spec`.
+    pub fn of_command(command: &Command) -> Vec<Category> {
+        command.spec().map_or_else(Vec::new, Category::of_spec)
+    }
+
+    fn of_spec(spec: &CommandSpec) -> Vec<Category> {
+        [
+            ("readonly", Category::Read),
+            ("write", Category::Write),
+            ("admin", Category::Admin),
+            ("dangerous", Category::Dangerous),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| spec.has_flag(flag))
+        .map(|(_, category)| category)
+        .collect()
+    }
+}
+
+/// One user's credentials and permissions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// Whether any password logs in as this user.
+    pub nopass: bool,
+    /// Argon2 hashes of the user's passwords.
+    passwords: Vec<String>,
+    categories: BTreeSet<Category>,
+    /// Globs for the keys the user may touch.
+    keys: Vec<String>,
+    /// Namespaces the user is confined to; `None` for no confinement.
+    namespaces: Option<Vec<String>>,
+}
+
+impl User {
+    /// A user that can do nothing until rules grant it something.
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            categories: BTreeSet::new(),
+            keys: Vec::new(),
+            namespaces: None,
+        }
+    }
+
+    /// The `default` user before any rules: any password, everything allowed.
+    fn unrestricted(name: &str) -> User {
+        User {
+            enabled: true,
+            nopass: true,
+            categories: CATEGORIES.into_iter().collect(),
+            keys: vec!["*".to_string()],
+            ..User::new(name)
+        }
+    }
+
+    /// Apply one `ACL SETUSER` rule:
+    /// - `on`, `off`: enable or disable the user.
+    /// - `>password`, `<password`: add or remove a password; `#<hash>` adds
+    ///   an argon2 hash; `nopass` takes any password; `resetpass` none.
+    /// - `+@<category>`, `-@<category>`, with `all` for every category;
+    ///   `allcommands` and `nocommands` are `+@all` and `-@all`.
+    /// - `~<glob>` allows matching keys; `allkeys` is `~*`; `resetkeys`
+    ///   allows none.
+    /// - `ns:<namespace>` confines the user to the keys of the namespaces
+    ///   named so; `allnamespaces` lifts the confinement.
+    /// - `reset`: back to a user that can do nothing.
+    pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_ascii_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allcommands" => self.categories = CATEGORIES.into_iter().collect(),
+            "nocommands" => self.categories.clear(),
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allnamespaces" => self.namespaces = None,
+            "reset" => *self = User::new(&self.name),
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    self.passwords.push(hash_secret(password));
+                    self.nopass = false;
+                } else if let Some(password) = rule.strip_prefix('<') {
+                    let before = self.passwords.len();
+                    self.passwords.retain(|hash| !verify_secret(password, hash));
+                    if self.passwords.len() == before {
+                        return Err("no such password".to_string());
+                    }
+                } else if let Some(hash) = rule.strip_prefix('#') {
+                    if !hash.starts_with("$argon2") || PasswordHash::new(hash).is_err() {
+                        return Err("not an argon2 hash".to_string());
+                    }
+                    self.passwords.push(hash.to_string());
+                    self.nopass = false;
+                } else if let Some(pattern) = rule.strip_prefix('~') {
+                    if !self.keys.iter().any(|key| key == pattern) {
+                        self.keys.push(pattern.to_string());
+                    }
+                } else if let Some(namespace) = rule.strip_prefix("ns:").filter(|ns| !ns.is_empty()) {
+                    let namespaces = self.namespaces.get_or_insert_with(Vec::new);
+                    if !namespaces.iter().any(|ns| ns == namespace) {
+                        namespaces.push(namespace.to_string());
+                    }
+                } else if let Some((sign, category)) = lower
+                    .strip_prefix("+@")
+                    .map(|category| (true, category))
+                    .or_else(|| lower.strip_prefix("-@").map(|category| (false, category)))
+                {
+                    let categories = match category {
+                        "all" => CATEGORIES.to_vec(),
+                        _ => vec![Category::parse(category).ok_or("unknown command category")?],
+                    };
+                    for category in categories {
+                        if sign {
+                            self.categories.insert(category);
+                        } else {
+                            self.categories.remove(&category);
+                        }
+                    }
+                } else {
+                    return Err("syntax error".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The rules that make this user from a `reset` one.
+    pub fn rules(&self) -> Vec<String> {
+        let mut rules = vec![if self.enabled { "on" } else { "off" }.to_string()];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        if self.categories.len() == CATEGORIES.len() {
+            rules.push("+@all".to_string());
+        } else if self.categories.is_empty() {
+            rules.push("-@all".to_string());
+        } else {
+            rules.extend(self.categories.iter().map(|category| format!("+@{}", category.name())));
+        }
+        if self.keys.is_empty() {
+            rules.push("resetkeys".to_string());
+        }
+        rules.extend(self.keys.iter().map(|pattern| format!("~{}", pattern)));
+        if let Some(namespaces) = &self.namespaces {
+            rules.extend(namespaces.iter().map(|ns| format!("ns:{}", ns)));
+        }
+        rules
+    }
+
+    pub fn categories(&self) -> impl Iterator<Item = Category> + '_ {
+        self.categories.iter().copied()
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub fn namespaces(&self) -> Option<&[String]> {
+        self.namespaces.as_deref()
+    }
+
+    /// Whether `password` logs in as this user. For the `default` user a
+    /// configured `requirepass` is one more password and overrides `nopass`.
+    /// Slow, as hash checks are meant to be.
+    pub fn accepts(&self, password: &str, requirepass: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let required = self.name == DEFAULT_USER && !requirepass.is_empty();
+        if required && check_password(requirepass, password) {
+            return true;
+        }
+        (self.nopass && !required) || self.passwords.iter().any(|hash| verify_secret(password, hash))
+    }
+
+    /// Why this user may not run `command`, if it may not: a missing
+    /// category, or a key or namespace outside its patterns.
+    pub fn check(&self, command: &Command) -> Result<(), String> {
+        if let Command::Batch { commands, .. } = command {
+            return commands.iter().try_for_each(|command| self.check(command));
+        }
+        if matches!(command, Command::AclWhoami) {
+            return Ok(());
+        }
+        let name = command.name();
+        if !Category::of_command(command).iter().all(|category| self.categories.contains(category)) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name, name
+            ));
+        }
+        if !command.keys().into_iter().all(|key| self.may_touch_key(key)) {
+            return Err("NOPERM No permissions to access a key".to_string());
+        }
+        if let Some(namespace) = command.namespaces().into_iter().find(|ns| !self.may_touch_namespace(ns)) {
+            return Err(format!("NOPERM No permissions to access the '{}' namespace", namespace));
+        }
+        if command.touches_every_key() && !self.may_touch_everything() {
+            return Err(format!("NOPERM No permissions to run '{}' over every key", name));
+        }
+        Ok(())
+    }
+
+    fn may_touch_key(&self, key: &str) -> bool {
+        self.keys.iter().any(|pattern| glob(key, pattern))
+            && self.namespaces.as_ref().is_none_or(|namespaces| {
+                namespaces.iter().any(|ns| key.starts_with(&namespace_prefix(ns)))
+            })
+    }
+
+    /// Whether every key of `namespace` is allowed: some glob is a plain
+    /// prefix of the namespace's keys followed by `*`.
+    fn may_touch_namespace(&self, namespace: &str) -> bool {
+        let prefix = namespace_prefix(namespace);
+        self.namespaces.as_ref().is_none_or(|namespaces| namespaces.iter().any(|ns| ns == namespace))
+            && self.keys.iter().any(|pattern| {
+                pattern.strip_suffix('*').is_some_and(|head| {
+                    !head.contains(['*', '?']) && prefix.starts_with(head)
+                })
+            })
+    }
+
+    fn may_touch_everything(&self) -> bool {
+        self.namespaces.is_none() && self.keys.iter().any(|pattern| pattern == "*")
+    }
+}
+
+/// Why users could not be read, changed or saved.
+#[derive(Debug)]
+pub enum AclError {
+    /// A rule given to `ACL SETUSER`, and what is wrong with it.
+    Rule(String, String),
+    DefaultUser,
+    /// A line of the ACL file, and what is wrong with it.
+    Line(usize, String),
+    Io(io::Error),
+}
+
+impl fmt::Display for AclError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AclError::Rule(rule, reason) => {
+                write!(f, "Error in ACL SETUSER modifier '{}': {}", rule, reason)
+            }
+            AclError::DefaultUser => write!(f, "The 'default' user cannot be removed"),
+            AclError::Line(line, reason) => write!(f, "ACL file line {}: {}", line, reason),
+            AclError::Io(e) => write!(f, "ACL file error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for AclError {
+    fn from(e: io::Error) -> Self {
+        AclError::Io(e)
+    }
+}
+
+/// The users of a server, always including `default`.
+#[derive(Debug)]
+pub struct Acl {
+    /// The ACL file, rewritten whole on every change; `None` keeps users in
+    /// memory only.
+    path: Option<PathBuf>,
+    users: BTreeMap<String, User>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let default = User::unrestricted(DEFAULT_USER);
+        Acl { path: None, users: BTreeMap::from([(DEFAULT_USER.to_string(), default)]) }
+    }
+}
+
+impl Acl {
+    /// Load the users in `path`, if the file exists.
+    pub fn load(path: &Path) -> Result<Acl, AclError> {
+        let mut acl = Acl { path: Some(path.to_path_buf()), ..Acl::default() };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(acl),
+            Err(e) => return Err(e.into()),
+        };
+        for (n, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => continue,
+                Some(comment) if comment.starts_with('#') => continue,
+                Some("user") => {}
+                Some(_) => return Err(AclError::Line(n + 1, "expected 'user <name> <rule> ...'".to_string())),
+            }
+            let name = words
+                .next()
+                .ok_or_else(|| AclError::Line(n + 1, "missing user name".to_string()))?;
+            let mut user = User::new(name);
+            for rule in words {
+                user.apply(rule)
+                    .map_err(|reason| AclError::Line(n + 1, format!("rule '{}': {}", rule, reason)))?;
+            }
+            acl.users.insert(name.to_string(), user);
+        }
+        Ok(acl)
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    /// `ACL SETUSER`: create the user if needed and apply `rules` in order.
+    /// A bad rule leaves the user as it was.
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), AclError> {
+        let mut user = self.users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule).map_err(|reason| AclError::Rule(rule.clone(), reason))?;
+        }
+        let previous = self.users.insert(name.to_string(), user);
+        if let Err(e) = self.save() {
+            match previous {
+                Some(previous) => self.users.insert(name.to_string(), previous),
+                None => self.users.remove(name),
+            };
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// `ACL DELUSER`: remove the named users. Returns how many there were.
+    pub fn del_users(&mut self, names: &[String]) -> Result<usize, AclError> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err(AclError::DefaultUser);
+        }
+        let removed = names.iter().filter(|name| self.users.remove(name.as_str()).is_some()).count();
+        if removed > 0 {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// `ACL LIST`: each user as its ACL file line.
+    pub fn list(&self) -> Vec<String> {
+        self.users
+            .values()
+            .map(|user| format!("user {} {}", user.name, user.rules().join(" ")))
+            .collect()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut text = String::new();
+        for line in self.list() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        write_private(path, &text)
+    }
+}
@@ -0,0 +1,276 @@
+//! Passwords, API tokens and throttling of failed logins.
+//!
+//! With `requirepass` set, a connection must authenticate before it may run
+//! anything but AUTH, HELLO and QUIT, giving either a password or an API
+//! token made by `TOKEN CREATE`. A token is shown once, when it is created;
+//! the token file keeps only an argon2 hash of its secret, under an id that
+//! starts the token so that a login checks a single hash. See `acl.rs` for
+//! users.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use storage::codec::encode_record;
+
+use crate::catalog;
+
+/// Where tokens are kept, inside the data directory.
+pub const TOKENS_PATH: &str = ".zyncdb.tokens";
+const TOKEN_PREFIX: &str = "zdb_";
+/// Failed logins an address may make before it has to wait.
+const FREE_ATTEMPTS: u32 = 3;
+/// The wait after the first failure past `FREE_ATTEMPTS`; it doubles with
+/// each further one.
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// An argon2 hash of `secret`, as a PHC string.
+pub fn hash_secret(secret: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .expect("argon2 takes any secret with a generated salt")
+        .to_string()
+}
+
+/// Whether `secret` is the one `hash` was made from.
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(secret.as_bytes(), &hash).is_ok())
+}
+
+/// Whether `given` is the configured password, which may be set in plain text
+/// or as an argon2 hash.
+pub fn check_password(requirepass: &str, given: &str) -> bool {
+    if requirepass.starts_with("$argon2") {
+        return verify_secret(given, requirepass);
+    }
+    // Compare every byte, so the time taken does not tell how much matched.
+    requirepass.len() == given.len()
+        && requirepass
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Replace the file at `path` with `text`, readable by its owner only, as
+/// files holding secret hashes must be. Written through a temporary file so a
+/// crash leaves the old or the new copy.
+pub(crate) fn write_private(path: &Path, text: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    // A stale temporary file would keep its permissions.
+    let _ = fs::remove_file(&tmp);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// An API token, without its secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub name: String,
+    /// The user the token logs in as: the one who created it.
+    pub user: String,
+    id: String,
+    hash: String,
+    /// Seconds since the Unix epoch from which the token is refused.
+    pub expires: Option<u64>,
+}
+
+impl Token {
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|at| at <= now())
+    }
+
+    /// Whether `token` is this one. Slow, as hash checks are meant to be.
+    pub fn verify(&self, token: &str) -> bool {
+        token_parts(token).is_some_and(|(id, secret)| id == self.id && verify_secret(secret, &self.hash))
+    }
+
+    fn record(&self) -> Vec<String> {
+        let expires = self.expires.map(|at| at.to_string()).unwrap_or_default();
+        vec![self.name.clone(), self.user.clone(), self.id.clone(), expires, self.hash.clone()]
+    }
+
+    fn from_record(record: &[String]) -> Option<Token> {
+        let [name, user, id, expires, hash] = record else {
+            return None;
+        };
+        Some(Token {
+            name: name.clone(),
+            user: user.clone(),
+            id: id.clone(),
+            hash: hash.clone(),
+            expires: if expires.is_empty() { None } else { Some(expires.parse().ok()?) },
+        })
+    }
+}
+
+/// A token's id and secret: `zdb_<id>_<secret>`.
+fn token_parts(token: &str) -> Option<(&str, &str)> {
+    token.strip_prefix(TOKEN_PREFIX)?.split_once('_')
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0; bytes];
+    OsRng.fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Why a token could not be made or removed.
+#[derive(Debug)]
+pub enum TokenError {
+    Exists(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Exists(name) => write!(f, "token '{}' already exists", name),
+            TokenError::Io(e) => write!(f, "token file error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for TokenError {
+    fn from(e: io::Error) -> Self {
+        TokenError::Io(e)
+    }
+}
+
+/// The API tokens of a data directory.
+#[derive(Debug, Default)]
+pub struct Tokens {
+    /// The token file, rewritten whole on every change; `None` keeps tokens
+    /// in memory only.
+    path: Option<PathBuf>,
+    tokens: Vec<Token>,
+}
+
+impl Tokens {
+    /// Load the tokens in `path`, if the file exists. Expired tokens are dropped.
+    pub fn load(path: &Path) -> io::Result<Tokens> {
+        let tokens = catalog::load(path)?
+            .iter()
+            .filter_map(|record| Token::from_record(record))
+            .filter(|token| !token.is_expired())
+            .collect();
+        Ok(Tokens { path: Some(path.to_path_buf()), tokens })
+    }
+
+    /// Make a token named `name` that logs in as `user`, valid for `ttl`
+    /// seconds or until revoked, and return it. This is the only time it can
+    /// be seen.
+    pub fn create(&mut self, name: &str, user: &str, ttl: Option<u64>) -> Result<String, TokenError> {
+        self.tokens.retain(|token| !token.is_expired());
+        if self.tokens.iter().any(|token| token.name == name) {
+            return Err(TokenError::Exists(name.to_string()));
+        }
+        let id = random_hex(8);
+        let secret = random_hex(32);
+        self.tokens.push(Token {
+            name: name.to_string(),
+            user: user.to_string(),
+            id: id.clone(),
+            hash: hash_secret(&secret),
+            expires: ttl.map(|ttl| now() + ttl),
+        });
+        if let Err(e) = self.save() {
+            self.tokens.pop();
+            return Err(e.into());
+        }
+        Ok(format!("{}{}_{}", TOKEN_PREFIX, id, secret))
+    }
+
+    /// Remove the token named `name`. Returns whether there was one.
+    pub fn revoke(&mut self, name: &str) -> io::Result<bool> {
+        let before = self.tokens.len();
+        self.tokens.retain(|token| token.name != name);
+        if self.tokens.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Tokens that have not expired, oldest first.
+    pub fn list(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().filter(|token| !token.is_expired())
+    }
+
+    /// The unexpired token `token` claims to be, still to be `verify`-ed.
+    pub fn find(&self, token: &str) -> Option<&Token> {
+        let (id, _) = token_parts(token)?;
+        self.list().find(|candidate| candidate.id == id)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut text = String::new();
+        for token in &self.tokens {
+            text.push_str(&encode_record(&token.record()));
+            text.push('\n');
+        }
+        write_private(path, &text)
+    }
+}
+
+/// Failed logins by client address. After `FREE_ATTEMPTS` failures an
+/// address must wait before trying again, twice as long after each further
+/// failure, up to `MAX_BACKOFF`. A success clears its record.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    failures: HashMap<String, (u32, Instant)>,
+}
+
+impl Throttle {
+    /// How long `client` must still wait before its next attempt, if at all.
+    pub fn wait(&self, client: &str) -> Option<Duration> {
+        let (count, last) = self.failures.get(client)?;
+        let ready = *last + backoff(*count);
+        ready.checked_duration_since(Instant::now())
+    }
+
+    pub fn failed(&mut self, client: &str) {
+        // Forget addresses that have waited out the longest backoff.
+        self.failures.retain(|_, (_, last)| last.elapsed() < MAX_BACKOFF);
+        let entry = self.failures.entry(client.to_string()).or_insert((0, Instant::now()));
+        *entry = (entry.0 + 1, Instant::now());
+    }
+
+    pub fn succeeded(&mut self, client: &str) {
+        self.failures.remove(client);
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    match failures.checked_sub(FREE_ATTEMPTS) {
+        None => Duration::ZERO,
+        Some(extra) => BASE_BACKOFF
+            .saturating_mul(1 << extra.min(16))
+            .min(MAX_BACKOFF),
+    }
+}
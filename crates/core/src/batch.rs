@@ -0,0 +1,125 @@
+use std::time::Instant;
+
+use parser::Command;
+use storage::{Storage, Value};
+
+use crate::error::KvError;
+use crate::exec::execute;
+use crate::kv::KvStore;
+use crate::planner;
+use crate::reply::Reply;
+
+/// The state of the keys an atomic batch touches, taken before it runs.
+pub(crate) struct Savepoint {
+    entries: Vec<(String, Option<Value>, Option<Instant>)>,
+}
+
+impl KvStore {
+    pub(crate) fn savepoint(&mut self, keys: &[&str]) -> Savepoint {
+        let entries = keys
+            .iter()
+            .map(|&key| {
+                let value = self.lookup(key).cloned();
+                (key.to_string(), value, self.expirations.get(key).copied())
+            })
+            .collect();
+        Savepoint { entries }
+    }
+
+    pub(crate) fn restore(&mut self, savepoint: Savepoint) {
+        for (key, value, expiry) in savepoint.entries {
+            match value {
+                Some(value) => {
+                    self.write_value(key.clone(), value);
+                }
+                None => {
+                    self.remove_value(&key);
+                }
+            }
+            match expiry {
+                Some(expiry) => self.expirations.insert(key, expiry),
+                None => self.expirations.remove(&key),
+            };
+        }
+        self.storage.flush();
+    }
+
+    /// Run `f`, undoing its changes to `keys` and dropping its WAL records if
+    /// it fails. Inside an atomic batch the batch's own buffer is used.
+    pub(crate) fn all_or_nothing<T>(
+        &mut self,
+        keys: &[&str],
+        f: impl FnOnce(&mut KvStore) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let outer = self.wal_buffer.is_some();
+        let savepoint = self.savepoint(keys);
+        let mark = self.wal_buffer.as_ref().map_or(0, Vec::len);
+        if !outer {
+            self.wal_buffer = Some(Vec::new());
+        }
+        let result = f(self);
+        if result.is_err() {
+            self.restore(savepoint);
+            if let Some(buffer) = &mut self.wal_buffer {
+                buffer.truncate(mark);
+            }
+        }
+        if !outer {
+            let records = self.wal_buffer.take().unwrap_or_default();
+            if !records.is_empty() {
+                self.log_group(&records);
+            }
+        }
+        result
+    }
+}
+
+/// Run `commands` in order and reply with one result per command.
+///
+/// An atomic batch stops at the first error and undoes everything before it;
+/// its WAL records are written as one group only if every command succeeds.
+/// The failing command keeps its error and every other entry reports the abort.
+pub(crate) fn run(store: &mut KvStore, commands: Vec<Command>, atomic: bool) -> Reply {
+    if let Err(e) = store.limits.check_batch(commands.len()) {
+        return Reply::error(e);
+    }
+    if !atomic {
+        return Reply::Array(commands.into_iter().map(|c| execute(store, c)).collect());
+    }
+
+    let mut keys: Vec<String> = commands
+        .iter()
+        .flat_map(Command::keys)
+        .map(str::to_string)
+        .collect();
+    // Queries do not name their keys; cover every key in their namespaces.
+    for namespace in commands.iter().flat_map(Command::namespaces) {
+        keys.extend(store.keys_with_prefix(&planner::namespace_prefix(namespace)));
+    }
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let savepoint = store.savepoint(&keys);
+    store.wal_buffer = Some(Vec::new());
+
+    let total = commands.len();
+    let mut replies = Vec::with_capacity(total);
+    for command in commands {
+        let reply = execute(store, command);
+        if reply.is_error() {
+            store.wal_buffer = None;
+            store.restore(savepoint);
+            let failed = replies.len();
+            let aborted = || Reply::Error("EXECABORT batch rolled back".to_string());
+            let mut out: Vec<Reply> = (0..failed).map(|_| aborted()).collect();
+            out.push(reply);
+            out.extend((failed + 1..total).map(|_| aborted()));
+            return Reply::Array(out);
+        }
+        replies.push(reply);
+    }
+
+    let records = store.wal_buffer.take().unwrap_or_default();
+    if !records.is_empty() {
+        store.log_group(&records);
+    }
+    Reply::Array(replies)
+}
@@ -0,0 +1,76 @@
+//! Definitions that must survive WAL compaction: indexes and schemas.
+//!
+//! The catalog sits next to the WAL with a `.catalog` extension and holds one
+//! `|`-encoded record per line. It is small and rewritten whole on every
+//! change, through a synced temporary file so a crash leaves the old or the new
+//! copy. A change whose write fails is undone and reported to the caller.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use storage::codec::{decode_record, encode_record};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+
+pub(crate) fn path_for(wal_path: &Path) -> PathBuf {
+    wal_path.with_extension("catalog")
+}
+
+pub(crate) fn load(path: &Path) -> io::Result<Vec<Vec<String>>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(decode_record)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn save(path: &Path, records: &[Vec<String>]) -> io::Result<()> {
+    let mut text = String::new();
+    for record in records {
+        text.push_str(&encode_record(record));
+        text.push('\n');
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    // Make the rename itself durable.
+    #[cfg(unix)]
+    {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        fs::File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// The error for a definition change whose catalog write failed.
+pub(crate) fn catalog_error(e: io::Error) -> KvError {
+    KvError::Catalog(e.to_string())
+}
+
+impl KvStore {
+    fn catalog_records(&self) -> Vec<Vec<String>> {
+        [
+            self.schemas.records(),
+            self.indexes.records(),
+            self.text_indexes.records(),
+        ]
+        .concat()
+    }
+
+    /// Write the current definitions to the catalog, if this store has one.
+    pub(crate) fn save_catalog(&self) -> io::Result<()> {
+        match &self.catalog {
+            Some(path) => save(path, &self.catalog_records()),
+            None => Ok(()),
+        }
+    }
+}
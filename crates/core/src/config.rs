@@ -0,0 +1,737 @@
+//! Settings for the server and the CLI.
+//!
+//! A setting takes, in rising precedence, its default, its value in the config
+//! file, a `ZYNCDB_<NAME>` environment variable and a `--<name>` flag. The
+//! file holds `name value` lines, as in redis.conf, or `name = value` lines,
+//! as in TOML; `#` starts a comment and `[section]` headers are ignored.
+//! `CONFIG SET` changes the settings marked as runtime ones, and
+//! `CONFIG REWRITE` writes the current values back into the file, keeping its
+//! comments and layout.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use parser::Command;
+
+use crate::acl::ACL_PATH;
+use crate::auth::{TOKENS_PATH, write_private};
+use crate::kv::{Backend, KvStore};
+use crate::limits::{Charset, Limits};
+use crate::query::glob;
+use crate::reply::Reply;
+use crate::wal::{FsyncPolicy, SNAPSHOT_PATH, WAL_PATH};
+
+/// Where the file backend keeps its data, inside `dir`.
+const DATA_PATH: &str = ".zyncdb.data";
+
+/// Every setting, by its canonical name, in the order `CONFIG GET` lists them.
+const NAMES: &[&str] = &[
+    "bind",
+    "port",
+    "dir",
+    "backend",
+    "appendfsync",
+    "save",
+    "maxclients",
+    "maxmemory",
+    "loglevel",
+    "requirepass",
+    "tlscertfile",
+    "tlskeyfile",
+    "tlscacertfile",
+    "tlsauthclients",
+    "unixsocket",
+    "unixsocketperm",
+    "maxkeylen",
+    "maxvaluelen",
+    "allowemptyvalues",
+    "keycharset",
+    "maxbatch",
+    "maxnamespacekeys",
+];
+
+/// Settings `CONFIG SET` may change while running; the others are read once
+/// at startup.
+const RUNTIME: &[&str] = &[
+    "appendfsync",
+    "save",
+    "maxclients",
+    "maxmemory",
+    "loglevel",
+    "requirepass",
+    "maxkeylen",
+    "maxvaluelen",
+    "allowemptyvalues",
+    "keycharset",
+    "maxbatch",
+    "maxnamespacekeys",
+];
+
+/// Take a snapshot once `seconds` have passed and at least `changes` writes
+/// were made since the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Whether TLS clients must present a certificate signed by `tlscacertfile`.
+/// A verified certificate logs the client in as the ACL user named by its
+/// common name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    No,
+    Optional,
+    Yes,
+}
+
+/// Where a store keeps its data between snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Memory,
+    File,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bind: String,
+    /// 0 for no TCP listener, with `unixsocket` set instead.
+    pub port: u16,
+    /// Directory holding the WAL, snapshot, catalog and file backend.
+    pub dir: PathBuf,
+    pub backend: BackendKind,
+    pub appendfsync: FsyncPolicy,
+    /// Automatic snapshots; empty for none.
+    pub save: Vec<SaveRule>,
+    pub maxclients: usize,
+    /// Data size in bytes above which writes are refused; 0 for no limit.
+    pub maxmemory: u64,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    pub loglevel: String,
+    /// Password clients must AUTH with, in plain text or as an argon2 hash;
+    /// empty for none. See `auth.rs`.
+    pub requirepass: String,
+    /// PEM certificate chain and private key of the server; with both set,
+    /// the listener speaks only TLS.
+    pub tlscertfile: Option<PathBuf>,
+    pub tlskeyfile: Option<PathBuf>,
+    /// PEM certificates of the authorities that sign client certificates.
+    pub tlscacertfile: Option<PathBuf>,
+    pub tlsauthclients: TlsAuthClients,
+    /// A Unix socket to listen on besides, or instead of, TCP.
+    pub unixsocket: Option<PathBuf>,
+    /// Permission bits of `unixsocket`, given in octal.
+    pub unixsocketperm: u32,
+    /// What the store accepts; see `limits.rs`.
+    pub limits: Limits,
+    /// The file the settings were read from, which `CONFIG REWRITE` updates.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1".to_string(),
+            port: 6379,
+            dir: PathBuf::from("."),
+            backend: BackendKind::Memory,
+            appendfsync: FsyncPolicy::EverySec,
+            save: vec![
+                SaveRule { seconds: 3600, changes: 1 },
+                SaveRule { seconds: 300, changes: 100 },
+                SaveRule { seconds: 60, changes: 10_000 },
+            ],
+            maxclients: 10_000,
+            maxmemory: 0,
+            loglevel: "info".to_string(),
+            requirepass: String::new(),
+            tlscertfile: None,
+            tlskeyfile: None,
+            tlscacertfile: None,
+            tlsauthclients: TlsAuthClients::No,
+            unixsocket: None,
+            unixsocketperm: 0o700,
+            limits: Limits::default(),
+            file: None,
+        }
+    }
+}
+
+/// Why settings could not be read, changed or saved.
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownSetting(String),
+    /// The value cannot be used for the setting; holds the setting, the value
+    /// and what was expected.
+    InvalidValue(String, String, &'static str),
+    /// `CONFIG SET` on a setting that is only read at startup.
+    StartupOnly(String),
+    MissingValue(String),
+    /// A config file line, counted from 1, that could not be used.
+    Line(usize, Box<ConfigError>),
+    Io(PathBuf, io::Error),
+    /// `CONFIG REWRITE` without a config file to write to.
+    NoFile,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownSetting(name) => write!(f, "unknown setting '{}'", name),
+            ConfigError::InvalidValue(name, value, expected) => {
+                write!(f, "invalid value '{}' for '{}': expected {}", value, name, expected)
+            }
+            ConfigError::StartupOnly(name) => write!(f, "'{}' can only be set at startup", name),
+            ConfigError::MissingValue(name) => write!(f, "missing value for '{}'", name),
+            ConfigError::Line(line, e) => write!(f, "line {}: {}", line, e),
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::NoFile => write!(f, "the server is running without a config file"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// How a config file line sets its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    /// `name value`
+    Redis,
+    /// `name = value`
+    Toml,
+}
+
+impl Config {
+    /// Settings from the command line and the process environment. The
+    /// config file is `--config <path>`, a leading path argument or
+    /// `$ZYNCDB_CONFIG`.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Config, ConfigError> {
+        Config::load_from(args, |name| std::env::var(name).ok())
+    }
+
+    /// As `load`, reading environment variables through `env`.
+    pub fn load_from(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let mut file = env("ZYNCDB_CONFIG").map(PathBuf::from);
+        let mut flags = Vec::new();
+        let mut args = args.into_iter().peekable();
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            file = Some(PathBuf::from(path));
+        }
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(ConfigError::UnknownSetting(arg));
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args.next().ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    (flag.to_string(), value)
+                }
+            };
+            if name == "config" {
+                file = Some(PathBuf::from(value));
+            } else {
+                flags.push((name, value));
+            }
+        }
+
+        let mut config = match &file {
+            Some(path) => {
+                let text =
+                    fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+                let mut config = Config::parse(&text)?;
+                config.file = Some(path.clone());
+                config
+            }
+            None => Config::default(),
+        };
+        for name in NAMES {
+            if let Some(value) = env(&format!("ZYNCDB_{}", name.to_uppercase())) {
+                config.set(name, &value)?;
+            }
+        }
+        for (name, value) in flags {
+            config.set(&name, &value)?;
+        }
+        Ok(config)
+    }
+
+    /// The defaults overridden by the settings in a config file's `text`.
+    /// Repeated `save` lines add up, as in redis.conf.
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut saves: Option<Vec<String>> = None;
+        for (i, line) in text.lines().enumerate() {
+            let at = |e| ConfigError::Line(i + 1, Box::new(e));
+            let Some((name, raw, style)) = split_line(line) else {
+                continue;
+            };
+            let value = unquote(raw, style).map_err(|_| {
+                at(ConfigError::InvalidValue(name.to_string(), raw.to_string(), "a closed quote"))
+            })?;
+            if canonical(name) == Some("save") {
+                let saves = saves.get_or_insert_with(Vec::new);
+                if value.trim().is_empty() {
+                    saves.clear();
+                } else {
+                    saves.push(value);
+                }
+                continue;
+            }
+            config.set(name, &value).map_err(at)?;
+        }
+        if let Some(saves) = saves {
+            config.set("save", &saves.join(" "))?;
+        }
+        Ok(config)
+    }
+
+    /// The current value of setting `name`, as `CONFIG GET` shows it.
+    pub fn get(&self, name: &str) -> Result<String, ConfigError> {
+        let name = canonical(name).ok_or_else(|| ConfigError::UnknownSetting(name.to_string()))?;
+        Ok(match name {
+            "bind" => self.bind.clone(),
+            "port" => self.port.to_string(),
+            "dir" => self.dir.display().to_string(),
+            "backend" => match self.backend {
+                BackendKind::Memory => "memory",
+                BackendKind::File => "file",
+            }
+            .to_string(),
+            "appendfsync" => match self.appendfsync {
+                FsyncPolicy::Always => "always",
+                FsyncPolicy::EverySec => "everysec",
+                FsyncPolicy::No => "no",
+            }
+            .to_string(),
+            "save" => self
+                .save
+                .iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "maxclients" => self.maxclients.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "loglevel" => self.loglevel.clone(),
+            "requirepass" => self.requirepass.clone(),
+            "tlscertfile" => path_value(&self.tlscertfile),
+            "tlskeyfile" => path_value(&self.tlskeyfile),
+            "tlscacertfile" => path_value(&self.tlscacertfile),
+            "tlsauthclients" => match self.tlsauthclients {
+                TlsAuthClients::No => "no",
+                TlsAuthClients::Optional => "optional",
+                TlsAuthClients::Yes => "yes",
+            }
+            .to_string(),
+            "unixsocket" => path_value(&self.unixsocket),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm),
+            "maxkeylen" => self.limits.max_key_len.to_string(),
+            "maxvaluelen" => self.limits.max_value_len.to_string(),
+            "allowemptyvalues" => if self.limits.allow_empty_values { "yes" } else { "no" }.to_string(),
+            "keycharset" => match self.limits.charset {
+                Charset::Printable => "printable",
+                Charset::Identifier => "identifier",
+                Charset::Any => "any",
+            }
+            .to_string(),
+            "maxbatch" => self.limits.max_batch.to_string(),
+            "maxnamespacekeys" => self.limits.max_namespace_keys.unwrap_or(0).to_string(),
+            _ => unreachable!("every name in NAMES has a value"),
+        })
+    }
+
+    /// Change setting `name`, whether or not it can change at runtime.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ConfigError> {
+        let canonical =
+            canonical(name).ok_or_else(|| ConfigError::UnknownSetting(name.to_string()))?;
+        let invalid = |expected| ConfigError::InvalidValue(canonical.to_string(), value.to_string(), expected);
+        let value = value.trim();
+        match canonical {
+            "bind" if !value.is_empty() => self.bind = value.to_string(),
+            "bind" => return Err(invalid("an address")),
+            "port" => {
+                self.port = value.parse().map_err(|_| invalid("a port number"))?
+            }
+            "dir" if !value.is_empty() => self.dir = PathBuf::from(value),
+            "dir" => return Err(invalid("a directory")),
+            "backend" => {
+                self.backend = match value.to_ascii_lowercase().as_str() {
+                    "memory" => BackendKind::Memory,
+                    "file" => BackendKind::File,
+                    _ => return Err(invalid("'memory' or 'file'")),
+                }
+            }
+            "appendfsync" => {
+                self.appendfsync = match value.to_ascii_lowercase().as_str() {
+                    "always" => FsyncPolicy::Always,
+                    "everysec" => FsyncPolicy::EverySec,
+                    "no" => FsyncPolicy::No,
+                    _ => return Err(invalid("'always', 'everysec' or 'no'")),
+                }
+            }
+            "save" => {
+                let numbers: Option<Vec<u64>> =
+                    value.split_whitespace().map(|n| n.parse().ok()).collect();
+                self.save = match numbers {
+                    Some(numbers) if numbers.len().is_multiple_of(2) => numbers
+                        .chunks(2)
+                        .map(|pair| SaveRule { seconds: pair[0], changes: pair[1] })
+                        .collect(),
+                    _ => return Err(invalid("'<seconds> <changes>' pairs")),
+                }
+            }
+            "maxclients" => {
+                self.maxclients = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| invalid("a positive number"))?
+            }
+            "maxmemory" => self.maxmemory = parse_size(value).ok_or_else(|| invalid("a size"))?,
+            "loglevel" => {
+                self.loglevel = match value.to_ascii_lowercase().as_str() {
+                    "off" | "nothing" => "off",
+                    "error" => "error",
+                    "warn" | "warning" => "warn",
+                    "info" | "notice" => "info",
+                    "debug" | "verbose" => "debug",
+                    "trace" => "trace",
+                    _ => return Err(invalid("'error', 'warn', 'info', 'debug' or 'trace'")),
+                }
+                .to_string()
+            }
+            "requirepass" => self.requirepass = value.to_string(),
+            "tlscertfile" => self.tlscertfile = path_setting(value),
+            "tlskeyfile" => self.tlskeyfile = path_setting(value),
+            "tlscacertfile" => self.tlscacertfile = path_setting(value),
+            "tlsauthclients" => {
+                self.tlsauthclients = match value.to_ascii_lowercase().as_str() {
+                    "no" => TlsAuthClients::No,
+                    "optional" => TlsAuthClients::Optional,
+                    "yes" => TlsAuthClients::Yes,
+                    _ => return Err(invalid("'no', 'optional' or 'yes'")),
+                }
+            }
+            "unixsocket" => self.unixsocket = path_setting(value),
+            "unixsocketperm" => {
+                self.unixsocketperm = u32::from_str_radix(value, 8)
+                    .ok()
+                    .filter(|&mode| mode <= 0o777)
+                    .ok_or_else(|| invalid("octal permission bits"))?
+            }
+            "maxkeylen" => {
+                self.limits.max_key_len = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| invalid("a positive number"))?
+            }
+            "maxvaluelen" => {
+                self.limits.max_value_len = parse_size(value)
+                    .and_then(|n| usize::try_from(n).ok())
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| invalid("a positive size"))?
+            }
+            "allowemptyvalues" => {
+                self.limits.allow_empty_values = match value.to_ascii_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(invalid("'yes' or 'no'")),
+                }
+            }
+            "keycharset" => {
+                self.limits.charset = match value.to_ascii_lowercase().as_str() {
+                    "printable" => Charset::Printable,
+                    "identifier" => Charset::Identifier,
+                    "any" => Charset::Any,
+                    _ => return Err(invalid("'printable', 'identifier' or 'any'")),
+                }
+            }
+            "maxbatch" => {
+                self.limits.max_batch = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| invalid("a positive number"))?
+            }
+            "maxnamespacekeys" => {
+                let max: usize = value.parse().map_err(|_| invalid("a number"))?;
+                self.limits.max_namespace_keys = Some(max).filter(|&n| n > 0);
+            }
+            _ => unreachable!("every name in NAMES can be set"),
+        }
+        Ok(())
+    }
+
+    /// `CONFIG SET`: change a setting while running.
+    pub fn set_at_runtime(&mut self, name: &str, value: &str) -> Result<(), ConfigError> {
+        match canonical(name) {
+            Some(canonical) if !RUNTIME.contains(&canonical) => {
+                Err(ConfigError::StartupOnly(canonical.to_string()))
+            }
+            _ => self.set(name, value),
+        }
+    }
+
+    /// `CONFIG GET`: every setting whose name matches the glob `pattern`.
+    pub fn matching(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_ascii_lowercase();
+        NAMES
+            .iter()
+            .filter(|name| glob(name, &pattern))
+            .map(|name| (*name, self.get(name).expect("known setting")))
+            .collect()
+    }
+
+    /// Run `CONFIG GET`, `CONFIG SET` or `CONFIG REWRITE`. A `SET` of several
+    /// settings changes all of them or none.
+    pub fn execute(&mut self, command: Command) -> Reply {
+        match command {
+            Command::ConfigGet { pattern } => Reply::Map(
+                self.matching(&pattern)
+                    .into_iter()
+                    .map(|(name, value)| (Reply::Bulk(name.to_string()), Reply::Bulk(value)))
+                    .collect(),
+            ),
+            Command::ConfigSet { pairs } => {
+                let mut updated = self.clone();
+                for (name, value) in &pairs {
+                    if let Err(e) = updated.set_at_runtime(name, value) {
+                        return Reply::error(e);
+                    }
+                }
+                *self = updated;
+                Reply::Ok
+            }
+            Command::ConfigRewrite => match self.rewrite() {
+                Ok(()) => Reply::Ok,
+                Err(e) => Reply::error(e),
+            },
+            _ => Reply::Error("Unknown command".to_string()),
+        }
+    }
+
+    /// `CONFIG REWRITE`: save the current settings to the config file,
+    /// readable by its owner only.
+    pub fn rewrite(&self) -> Result<(), ConfigError> {
+        let path = self.file.as_ref().ok_or(ConfigError::NoFile)?;
+        let io_error = |e| ConfigError::Io(path.clone(), e);
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(io_error(e)),
+        };
+        // The file may hold `requirepass` in plain text.
+        write_private(path, &self.rewritten(&text)).map_err(io_error)
+    }
+
+    /// `text`, a config file, with each setting's first line updated to the
+    /// current value and its later lines dropped. Settings the file does not
+    /// mention are appended if they differ from the defaults.
+    pub fn rewritten(&self, text: &str) -> String {
+        let mut out = String::new();
+        let mut written = HashSet::new();
+        let mut file_style = Style::Redis;
+        for line in text.lines() {
+            let setting = split_line(line).and_then(|(name, _, style)| Some((canonical(name)?, style)));
+            match setting {
+                Some((name, style)) => {
+                    file_style = style;
+                    if written.insert(name) {
+                        out.push_str(&self.line(name, style));
+                        out.push('\n');
+                    }
+                }
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+        let defaults = Config::default();
+        for name in NAMES {
+            if !written.contains(name) && self.get(name).ok() != defaults.get(name).ok() {
+                out.push_str(&self.line(name, file_style));
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Setting `name` as a config file line.
+    fn line(&self, name: &str, style: Style) -> String {
+        let value = self.get(name).expect("known setting");
+        let numeric = !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+        match style {
+            Style::Toml if numeric => format!("{} = {}", name, value),
+            Style::Toml => format!("{} = {}", name, quote(&value)),
+            Style::Redis if value.is_empty() || value.contains(['"', '\'', '#']) => {
+                format!("{} {}", name, quote(&value))
+            }
+            // `save` pairs are written unquoted, as redis.conf does.
+            Style::Redis if name == "save" || !value.contains(char::is_whitespace) => {
+                format!("{} {}", name, value)
+            }
+            Style::Redis => format!("{} {}", name, quote(&value)),
+        }
+    }
+
+    /// `bind:port`, for listening.
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    pub fn wal_path(&self) -> PathBuf {
+        self.dir.join(WAL_PATH)
+    }
+
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(SNAPSHOT_PATH)
+    }
+
+    pub fn tokens_path(&self) -> PathBuf {
+        self.dir.join(TOKENS_PATH)
+    }
+
+    pub fn acl_path(&self) -> PathBuf {
+        self.dir.join(ACL_PATH)
+    }
+
+    /// Open the store in `dir`, creating the directory if needed: the last
+    /// snapshot, then the WAL, kept in the configured backend and held to
+    /// the configured limits.
+    pub fn open_store(&self) -> io::Result<KvStore> {
+        fs::create_dir_all(&self.dir)?;
+        let backend = match self.backend {
+            BackendKind::Memory => Backend::Memory,
+            BackendKind::File => Backend::File(path_string(&self.dir.join(DATA_PATH))),
+        };
+        let mut store = KvStore::open_with_snapshot_and_backend(
+            &self.snapshot_path(),
+            &self.wal_path(),
+            backend,
+            self.limits.clone(),
+        )?;
+        self.apply_to(&mut store);
+        Ok(store)
+    }
+
+    /// Put the settings the store holds itself, the fsync policy and the
+    /// limits, into effect on `store`; after `CONFIG SET` as well as at open.
+    pub fn apply_to(&self, store: &mut KvStore) {
+        store.set_fsync(self.appendfsync);
+        store.set_limits(self.limits.clone());
+    }
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// An optional path setting, empty for none.
+fn path_setting(value: &str) -> Option<PathBuf> {
+    Some(PathBuf::from(value)).filter(|_| !value.is_empty())
+}
+
+fn path_value(path: &Option<PathBuf>) -> String {
+    path.as_ref().map(|path| path.display().to_string()).unwrap_or_default()
+}
+
+/// The canonical name of a setting, ignoring case, `_` and `-`.
+fn canonical(name: &str) -> Option<&'static str> {
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let name = match name.as_str() {
+        "host" | "address" => "bind",
+        "datadir" => "dir",
+        "fsync" => "appendfsync",
+        "log" => "loglevel",
+        "password" => "requirepass",
+        name => name,
+    };
+    NAMES.iter().find(|n| **n == name).copied()
+}
+
+/// The name, raw value and style of a config file line, or `None` for blank
+/// lines, comments and section headers.
+fn split_line(line: &str) -> Option<(&str, &str, Style)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    match rest.strip_prefix('=') {
+        Some(value) => Some((name, value.trim(), Style::Toml)),
+        None => Some((name, rest, Style::Redis)),
+    }
+}
+
+/// A raw value without its quotes and trailing comment. Double quotes take
+/// `\` escapes; single quotes take the text as it is.
+fn unquote(raw: &str, style: Style) -> Result<String, ()> {
+    let mut chars = raw.chars();
+    let (quote, rest) = match chars.next() {
+        Some(q @ ('"' | '\'')) => (q, chars.as_str()),
+        _ => {
+            let value = match raw.find(" #") {
+                Some(comment) => &raw[..comment],
+                None if style == Style::Toml => raw.split('#').next().unwrap_or_default(),
+                None => raw,
+            };
+            return Ok(value.trim().to_string());
+        }
+    };
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == quote => {
+                let rest = chars.as_str().trim();
+                return if rest.is_empty() || rest.starts_with('#') {
+                    Ok(value)
+                } else {
+                    Err(())
+                };
+            }
+            '\\' if quote == '"' => value.push(chars.next().ok_or(())?),
+            c => value.push(c),
+        }
+    }
+    Err(())
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A byte count with an optional unit, as in redis.conf: `k`, `m` and `g` are
+/// powers of 1000, `kb`, `mb` and `gb` powers of 1024.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.to_ascii_lowercase();
+    let digits = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let multiplier = match unit.trim() {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+use crate::limits::LimitError;
+
+/// Errors returned by `KvStore` operations that inspect stored values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KvError {
+    /// The stored value (or the increment) is not a valid 64-bit integer.
+    NotInteger,
+    /// The stored value (or the increment) is not a valid float.
+    NotFloat,
+    /// The integer operation would overflow an `i64`.
+    Overflow,
+    /// The float operation would produce NaN or infinity.
+    NotFinite,
+    /// A write would grow a string beyond `MAX_STRING_LEN`.
+    StringTooLong,
+    /// A byte-level edit would leave the value as invalid UTF-8.
+    NotUtf8,
+    /// The key holds a value of a different type than the command expects.
+    WrongType,
+    /// The key does not exist.
+    NoSuchKey,
+    /// A stream ID argument could not be parsed.
+    InvalidStreamId,
+    /// An explicit `XADD` ID is not greater than the stream's last ID.
+    StreamIdTooSmall,
+    /// The stream or consumer group does not exist.
+    NoSuchGroup,
+    /// `XGROUP CREATE` named a group that already exists.
+    GroupExists,
+    /// A JSON argument could not be parsed.
+    InvalidJson(String),
+    /// A JSONPath expression could not be parsed.
+    InvalidJsonPath(String),
+    /// A single-value path matched nothing.
+    JsonPathNotFound(String),
+    /// The value at the path is not a number.
+    JsonNotNumber,
+    /// `JSON.SET` on a missing key must target the root.
+    JsonRootRequired,
+    /// A query produced a value that cannot be stored.
+    InvalidQueryValue(String),
+    /// `CREATE INDEX` named an index that already exists.
+    IndexExists(String),
+    /// `DROP INDEX` named an index that does not exist.
+    NoSuchIndex(String),
+    /// The write would give two keys the same value in a unique index.
+    UniqueViolation(String),
+    /// A write or migration breaks the namespace's schema.
+    SchemaViolation(String),
+    /// A `SCHEMA SET` definition cannot be used.
+    InvalidSchema(String),
+    /// `SCHEMA SET ... VERSION n` skipped or repeated a version; holds the
+    /// current one.
+    SchemaVersion(u32),
+    /// The namespace has no schema.
+    NoSuchSchema(String),
+    /// The write breaks the store's `Limits`.
+    Limit(LimitError),
+    /// The catalog could not be written; the definition change was undone.
+    Catalog(String),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::NotInteger => write!(f, "value is not an integer or out of range"),
+            KvError::NotFloat => write!(f, "value is not a valid float"),
+            KvError::Overflow => write!(f, "increment or decrement would overflow"),
+            KvError::NotFinite => write!(f, "increment would produce NaN or Infinity"),
+            KvError::StringTooLong => write!(f, "string exceeds maximum allowed size"),
+            KvError::NotUtf8 => write!(f, "resulting value is not valid UTF-8"),
+            KvError::WrongType => write!(
+                f,
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+            KvError::NoSuchKey => write!(f, "no such key"),
+            KvError::InvalidStreamId => {
+                write!(f, "Invalid stream ID specified as stream command argument")
+            }
+            KvError::StreamIdTooSmall => write!(
+                f,
+                "The ID specified in XADD is equal or smaller than the target stream top item"
+            ),
+            KvError::NoSuchGroup => write!(f, "NOGROUP No such key or consumer group"),
+            KvError::GroupExists => write!(f, "BUSYGROUP Consumer Group name already exists"),
+            KvError::InvalidJson(reason) => write!(f, "invalid JSON: {}", reason),
+            KvError::InvalidJsonPath(path) => write!(f, "invalid JSON path '{}'", path),
+            KvError::JsonPathNotFound(path) => write!(f, "path '{}' does not exist", path),
+            KvError::JsonNotNumber => write!(f, "value at path is not a number"),
+            KvError::JsonRootRequired => {
+                write!(f, "new objects must be created at the root")
+            }
+            KvError::InvalidQueryValue(reason) => write!(f, "invalid query value: {}", reason),
+            KvError::IndexExists(name) => write!(f, "index '{}' already exists", name),
+            KvError::NoSuchIndex(name) => write!(f, "no such index '{}'", name),
+            KvError::UniqueViolation(name) => {
+                write!(f, "value already exists in unique index '{}'", name)
+            }
+            KvError::SchemaViolation(reason) => write!(f, "schema violation: {}", reason),
+            KvError::InvalidSchema(reason) => write!(f, "invalid schema: {}", reason),
+            KvError::SchemaVersion(current) => {
+                write!(f, "schema is at version {}; expected the next version", current)
+            }
+            KvError::NoSuchSchema(ns) => write!(f, "namespace '{}' has no schema", ns),
+            KvError::Limit(e) => write!(f, "{}", e),
+            KvError::Catalog(reason) => write!(f, "catalog write failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<LimitError> for KvError {
+    fn from(e: LimitError) -> Self {
+        KvError::Limit(e)
+    }
+}
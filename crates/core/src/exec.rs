@@ -0,0 +1,446 @@
+use parser::registry::{self, CommandSpec};
+use parser::{Command, CommandQuery, ListEnd, SetCondition};
+
+use crate::kv::KvStore;
+use crate::reply::Reply;
+use crate::stream::StreamEntry;
+
+/// Execute a data command against `store`.
+///
+/// Front-end specific commands (`help`, `exit`, `snapshot`) and prepared
+/// statements, which belong to a connection, are handled by the CLI and server
+/// themselves; anything else that reaches here unhandled is reported as an
+/// unknown command. Blocking commands run once without waiting; the server
+/// retries them until data arrives or the timeout expires.
+pub fn execute(store: &mut KvStore, command: Command) -> Reply {
+    match command {
+        Command::Put { key, value } | Command::Insert { key, value } => {
+            ok(store.insert(key, value))
+        }
+        Command::Get { key } | Command::Select { key } => bulk(store.get_string(&key)),
+        Command::Delete { key } | Command::Remove { key } => {
+            if store.delete(&key) {
+                Reply::Status("deleted".to_string())
+            } else {
+                Reply::Nil
+            }
+        }
+        Command::List => Reply::Array(
+            store
+                .iter()
+                .map(|(k, v)| Reply::Bulk(format!("{} = {}", k, v)))
+                .collect(),
+        ),
+        Command::Ttl { key, seconds } => {
+            store.set_ttl(&key, seconds);
+            Reply::Status(format!("TTL set for '{}' to {} seconds", key, seconds))
+        }
+        Command::Batch { commands, atomic } => crate::batch::run(store, commands, atomic),
+        Command::Incr { key } => integer(store.incr(&key)),
+        Command::Decr { key } => integer(store.decr(&key)),
+        Command::IncrBy { key, delta } => integer(store.incr_by(&key, delta)),
+        Command::DecrBy { key, delta } => integer(store.decr_by(&key, delta)),
+        Command::IncrByFloat { key, delta } => match store.incr_by_float(&key, delta) {
+            Ok(v) => Reply::Bulk(v.to_string()),
+            Err(e) => Reply::error(e),
+        },
+        Command::Set {
+            key,
+            value,
+            condition,
+        } => {
+            let written = match condition {
+                SetCondition::Always => store.insert(key, value).map(|_| true),
+                SetCondition::IfNotExists => store.set_nx(&key, value),
+                SetCondition::IfExists => store.set_xx(&key, value),
+            };
+            match written {
+                Ok(true) => Reply::Ok,
+                Ok(false) => Reply::Nil,
+                Err(e) => Reply::error(e),
+            }
+        }
+        Command::SetNx { key, value } => integer(store.set_nx(&key, value).map(i64::from)),
+        Command::GetSet { key, value } => bulk(store.get_set(&key, value)),
+        Command::GetDel { key } => bulk(store.get_del(&key)),
+        Command::Append { key, value } => length(store.append(&key, &value)),
+        Command::StrLen { key } => length(store.strlen(&key)),
+        Command::GetRange { key, start, end } => bulk(store.get_range(&key, start, end).map(Some)),
+        Command::SetRange { key, offset, value } => length(store.set_range(&key, offset, &value)),
+        Command::MSet { pairs } => ok(store.mset(pairs)),
+        Command::MSetNx { pairs } => integer(store.mset_nx(pairs).map(i64::from)),
+        Command::MGet { keys } => {
+            Reply::Array(store.mget(&keys).into_iter().map(Reply::from).collect())
+        }
+        Command::Type { key } => {
+            Reply::Status(store.key_type(&key).unwrap_or("none").to_string())
+        }
+        Command::LPush { key, values } => length(store.lpush(&key, values)),
+        Command::RPush { key, values } => length(store.rpush(&key, values)),
+        Command::LPop { key, count } => pop(store.lpop(&key, count.unwrap_or(1)), count),
+        Command::RPop { key, count } => pop(store.rpop(&key, count.unwrap_or(1)), count),
+        Command::LRange { key, start, stop } => array(store.lrange(&key, start, stop)),
+        Command::LLen { key } => length(store.llen(&key)),
+        Command::LIndex { key, index } => bulk(store.lindex(&key, index)),
+        Command::LTrim { key, start, stop } => match store.ltrim(&key, start, stop) {
+            Ok(()) => Reply::Ok,
+            Err(e) => Reply::error(e),
+        },
+        Command::LMove {
+            source,
+            destination,
+            from,
+            to,
+        }
+        | Command::BLMove {
+            source,
+            destination,
+            from,
+            to,
+            ..
+        } => bulk(store.lmove(&source, &destination, from, to)),
+        Command::BLPop { keys, .. } => popped(store.pop_first(&keys, ListEnd::Left)),
+        Command::BRPop { keys, .. } => popped(store.pop_first(&keys, ListEnd::Right)),
+        Command::HSet { key, pairs } => length(store.hset(&key, pairs)),
+        Command::HGet { key, field } => bulk(store.hget(&key, &field)),
+        Command::HMGet { key, fields } => match store.hmget(&key, &fields) {
+            Ok(values) => Reply::Array(values.into_iter().map(Reply::from).collect()),
+            Err(e) => Reply::error(e),
+        },
+        Command::HDel { key, fields } => length(store.hdel(&key, &fields)),
+        Command::HGetAll { key } => match store.hgetall(&key) {
+            Ok(pairs) => Reply::Map(
+                pairs
+                    .into_iter()
+                    .map(|(f, v)| (Reply::Bulk(f), Reply::Bulk(v)))
+                    .collect(),
+            ),
+            Err(e) => Reply::error(e),
+        },
+        Command::HKeys { key } => array(store.hkeys(&key)),
+        Command::HLen { key } => length(store.hlen(&key)),
+        Command::HIncrBy { key, field, delta } => integer(store.hincr_by(&key, &field, delta)),
+        Command::HExists { key, field } => integer(store.hexists(&key, &field).map(i64::from)),
+        Command::SAdd { key, members } => length(store.sadd(&key, members)),
+        Command::SRem { key, members } => length(store.srem(&key, &members)),
+        Command::SMembers { key } => array(store.smembers(&key)),
+        Command::SIsMember { key, member } => {
+            integer(store.sismember(&key, &member).map(i64::from))
+        }
+        Command::SInter { keys } => array(store.sinter(&keys)),
+        Command::SUnion { keys } => array(store.sunion(&keys)),
+        Command::SDiff { keys } => array(store.sdiff(&keys)),
+        Command::SCard { key } => length(store.scard(&key)),
+        Command::ZAdd { key, members } => length(store.zadd(&key, members)),
+        Command::ZRem { key, members } => length(store.zrem(&key, &members)),
+        Command::ZScore { key, member } => {
+            bulk(store.zscore(&key, &member).map(|s| s.map(|s| s.to_string())))
+        }
+        Command::ZRank { key, member } => match store.zrank(&key, &member) {
+            Ok(Some(rank)) => Reply::Integer(rank as i64),
+            Ok(None) => Reply::Nil,
+            Err(e) => Reply::error(e),
+        },
+        Command::ZRange {
+            key,
+            range,
+            with_scores,
+        } => scored(store.zrange(&key, &range), with_scores),
+        Command::ZIncrBy { key, delta, member } => {
+            bulk(store.zincr_by(&key, delta, &member).map(|s| Some(s.to_string())))
+        }
+        Command::ZPopMin { key, count } => scored(store.zpop_min(&key, count.unwrap_or(1)), true),
+        Command::XAdd {
+            key,
+            trim,
+            id,
+            fields,
+        } => match store.xadd(&key, &id, fields, trim.as_ref()) {
+            Ok(id) => Reply::Bulk(id.to_string()),
+            Err(e) => Reply::error(e),
+        },
+        Command::XLen { key } => length(store.xlen(&key)),
+        Command::XRange {
+            key,
+            start,
+            end,
+            count,
+        } => match store.xrange(&key, &start, &end, count) {
+            Ok(entries) => stream_entries(entries),
+            Err(e) => Reply::error(e),
+        },
+        Command::XRead { count, streams, .. } => streams_reply(store.xread(&streams, count)),
+        Command::XTrim { key, trim } => length(store.xtrim(&key, &trim)),
+        Command::XGroupCreate {
+            key,
+            group,
+            id,
+            mkstream,
+        } => match store.xgroup_create(&key, &group, &id, mkstream) {
+            Ok(()) => Reply::Ok,
+            Err(e) => Reply::error(e),
+        },
+        Command::XReadGroup {
+            group,
+            consumer,
+            count,
+            no_ack,
+            streams,
+            ..
+        } => streams_reply(store.xreadgroup(&group, &consumer, &streams, count, no_ack)),
+        Command::XAck { key, group, ids } => length(store.xack(&key, &group, &ids)),
+        Command::XClaim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids,
+        } => match store.xclaim(&key, &group, &consumer, min_idle, &ids) {
+            Ok(entries) => stream_entries(entries),
+            Err(e) => Reply::error(e),
+        },
+        Command::XPending { key, group } => match store.xpending(&key, &group) {
+            Ok(rows) => Reply::Array(
+                rows.into_iter()
+                    .map(|(id, consumer, idle, count)| {
+                        Reply::Array(vec![
+                            Reply::Bulk(id.to_string()),
+                            Reply::Bulk(consumer),
+                            Reply::Integer(idle as i64),
+                            Reply::Integer(count as i64),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Err(e) => Reply::error(e),
+        },
+        Command::JsonSet {
+            key,
+            path,
+            value,
+            condition,
+        } => match store.json_set(&key, &path, &value, condition) {
+            Ok(true) => Reply::Ok,
+            Ok(false) => Reply::Nil,
+            Err(e) => Reply::error(e),
+        },
+        Command::JsonGet { key, path } => bulk(store.json_get(&key, path.as_deref())),
+        Command::JsonDel { key, path } => length(store.json_del(&key, path.as_deref())),
+        Command::JsonArrAppend { key, path, values } => {
+            match store.json_arrappend(&key, &path, &values) {
+                Ok(lengths) => Reply::Array(
+                    lengths
+                        .into_iter()
+                        .map(|n| n.map_or(Reply::Nil, |n| Reply::Integer(n as i64)))
+                        .collect(),
+                ),
+                Err(e) => Reply::error(e),
+            }
+        }
+        Command::JsonNumIncrBy { key, path, delta } => {
+            bulk(store.json_num_incr_by(&key, &path, &delta).map(Some))
+        }
+        Command::Introspect(query) => introspect(query),
+        Command::Ping { message: None } => Reply::Status("PONG".to_string()),
+        Command::Ping { message: Some(message) } => Reply::Bulk(message),
+        Command::Query(statement) => store.query(&statement).unwrap_or_else(Reply::error),
+        Command::CreateIndex(def) => ok(store.create_index(def)),
+        Command::DropIndex { name } => ok(store.drop_index(&name)),
+        Command::SchemaSet(def) => integer(store.set_schema(def).map(i64::from)),
+        Command::SchemaGet { namespace } => match store.schema(&namespace) {
+            Some(fields) => Reply::Map(
+                fields
+                    .into_iter()
+                    .map(|(f, v)| (Reply::Bulk(f), Reply::Bulk(v)))
+                    .collect(),
+            ),
+            None => Reply::Nil,
+        },
+        Command::SchemaDrop { namespace } => ok(store.drop_schema(&namespace)),
+        Command::FtCreate(def) => ok(store.ft_create(def)),
+        Command::FtDropIndex { index } => ok(store.ft_drop(&index)),
+        Command::FtSearch {
+            index,
+            query,
+            offset,
+            count,
+            with_scores,
+        } => match store.ft_search(&index, &query, offset, count) {
+            Ok((total, hits)) => {
+                let mut reply = vec![Reply::Integer(total as i64)];
+                for (key, score) in hits {
+                    reply.push(Reply::Bulk(key));
+                    if with_scores {
+                        reply.push(Reply::Bulk(score.to_string()));
+                    }
+                }
+                Reply::Array(reply)
+            }
+            Err(e) => Reply::error(e),
+        },
+        Command::Explain(statement) => Reply::Array(
+            store
+                .explain(&statement)
+                .into_iter()
+                .map(Reply::Status)
+                .collect(),
+        ),
+        Command::Snapshot
+        | Command::Exit
+        | Command::Help { .. }
+        | Command::Prepare { .. }
+        | Command::Execute { .. }
+        | Command::Deallocate { .. }
+        | Command::Hello { .. }
+        | Command::Auth { .. }
+        | Command::TokenCreate { .. }
+        | Command::TokenList
+        | Command::TokenRevoke { .. }
+        | Command::AclSetUser { .. }
+        | Command::AclGetUser { .. }
+        | Command::AclDelUser { .. }
+        | Command::AclList
+        | Command::AclWhoami
+        | Command::ConfigGet { .. }
+        | Command::ConfigSet { .. }
+        | Command::ConfigRewrite
+        | Command::Shutdown { .. } => {
+            Reply::Error("Unknown command".to_string())
+        }
+    }
+}
+
+/// Prepare a blocking command before the server starts waiting on it: `$`
+/// in `XREAD` must mean "the last ID when the wait began", not on every retry.
+pub fn prepare_blocking(store: &mut KvStore, mut command: Command) -> Result<Command, Reply> {
+    if let Command::XRead { streams, .. } = &mut command {
+        store.resolve_last_ids(streams).map_err(Reply::error)?;
+    }
+    Ok(command)
+}
+
+/// `COMMAND`, `COMMAND COUNT`, `COMMAND INFO name...` and `COMMAND DOCS name...`.
+fn introspect(query: CommandQuery) -> Reply {
+    let info = |spec: &CommandSpec| {
+        Reply::Array(vec![
+            Reply::Bulk(spec.name.to_string()),
+            Reply::Integer(spec.arity as i64),
+            Reply::Array(spec.flags.iter().map(|f| Reply::Status(f.to_string())).collect()),
+            Reply::Bulk(spec.group.to_string()),
+            Reply::Bulk(spec.since.to_string()),
+        ])
+    };
+    let docs = |spec: &CommandSpec| {
+        Reply::Array(vec![
+            Reply::Bulk(spec.name.to_string()),
+            Reply::Bulk(spec.summary.to_string()),
+            Reply::Bulk(format!("{} {}", spec.name, spec.usage).trim_end().to_string()),
+            Reply::Bulk(spec.since.to_string()),
+        ])
+    };
+    let named = |names: Vec<String>, describe: &dyn Fn(&CommandSpec) -> Reply| {
+        Reply::Array(
+            names
+                .iter()
+                .map(|name| registry::lookup(name).map_or(Reply::Nil, describe))
+                .collect(),
+        )
+    };
+    match query {
+        CommandQuery::List => Reply::Array(registry::COMMANDS.iter().map(info).collect()),
+        CommandQuery::Count => Reply::Integer(registry::COMMANDS.len() as i64),
+        CommandQuery::Info(names) => named(names, &info),
+        CommandQuery::Docs(names) => named(names, &docs),
+    }
+}
+
+fn ok<T, E: std::fmt::Display>(result: Result<T, E>) -> Reply {
+    match result {
+        Ok(_) => Reply::Ok,
+        Err(e) => Reply::error(e),
+    }
+}
+
+fn integer<E: std::fmt::Display>(result: Result<i64, E>) -> Reply {
+    match result {
+        Ok(n) => Reply::Integer(n),
+        Err(e) => Reply::error(e),
+    }
+}
+
+fn bulk<E: std::fmt::Display>(result: Result<Option<String>, E>) -> Reply {
+    match result {
+        Ok(value) => value.into(),
+        Err(e) => Reply::error(e),
+    }
+}
+
+fn array<E: std::fmt::Display>(result: Result<Vec<String>, E>) -> Reply {
+    match result {
+        Ok(items) => Reply::Array(items.into_iter().map(Reply::Bulk).collect()),
+        Err(e) => Reply::error(e),
+    }
+}
+
+/// `LPOP key` replies with a single element, `LPOP key count` with an array.
+fn pop<E: std::fmt::Display>(result: Result<Vec<String>, E>, count: Option<usize>) -> Reply {
+    match count {
+        Some(_) => array(result),
+        None => bulk(result.map(|mut items| items.pop())),
+    }
+}
+
+/// `BLPOP`/`BRPOP` reply with the key that was popped from and the element.
+fn popped<E: std::fmt::Display>(result: Result<Option<(String, String)>, E>) -> Reply {
+    match result {
+        Ok(Some((key, value))) => Reply::Array(vec![Reply::Bulk(key), Reply::Bulk(value)]),
+        Ok(None) => Reply::Nil,
+        Err(e) => Reply::error(e),
+    }
+}
+
+/// Sorted-set members, optionally interleaved with their scores.
+fn scored<E: std::fmt::Display>(result: Result<Vec<(String, f64)>, E>, with_scores: bool) -> Reply {
+    array(result.map(|entries| {
+        entries
+            .into_iter()
+            .flat_map(|(member, score)| {
+                let score = with_scores.then(|| score.to_string());
+                std::iter::once(member).chain(score)
+            })
+            .collect()
+    }))
+}
+
+fn stream_entries(entries: Vec<StreamEntry>) -> Reply {
+    Reply::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| {
+                let fields = fields
+                    .into_iter()
+                    .flat_map(|(f, v)| [Reply::Bulk(f), Reply::Bulk(v)])
+                    .collect();
+                Reply::Array(vec![Reply::Bulk(id.to_string()), Reply::Array(fields)])
+            })
+            .collect(),
+    )
+}
+
+/// `XREAD`/`XREADGROUP` reply with `[key, entries]` per stream, or nil when nothing was read.
+fn streams_reply<E: std::fmt::Display>(result: Result<Vec<(String, Vec<StreamEntry>)>, E>) -> Reply {
+    match result {
+        Ok(streams) if streams.is_empty() => Reply::Nil,
+        Ok(streams) => Reply::Array(
+            streams
+                .into_iter()
+                .map(|(key, entries)| Reply::Array(vec![Reply::Bulk(key), stream_entries(entries)]))
+                .collect(),
+        ),
+        Err(e) => Reply::error(e),
+    }
+}
+
+fn length<E: std::fmt::Display>(result: Result<usize, E>) -> Reply {
+    integer(result.map(|n| n as i64))
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use storage::{Storage, Value};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+
+impl KvStore {
+    fn hash(&mut self, key: &str) -> Result<Option<&HashMap<String, String>>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::Hash(fields)) => Ok(Some(fields)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Set each field/value pair in the hash at `key`, creating it if missing.
+    /// Returns the number of fields that were newly added.
+    pub fn hset(&mut self, key: &str, pairs: Vec<(String, String)>) -> Result<usize, KvError> {
+        self.hash(key)?;
+        self.check_create(key, Value::Hash(HashMap::new()))?;
+        self.check_elements(pairs.iter().flat_map(|(f, v)| [f, v]))?;
+        let mut record = vec!["HSET", key];
+        for (f, v) in &pairs {
+            record.push(f);
+            record.push(v);
+        }
+        self.log(&record);
+
+        if self.lookup(key).is_none() {
+            self.write_value(key.to_string(), Value::Hash(HashMap::new()));
+        }
+        let Some(Value::Hash(fields)) = self.lookup_mut(key) else {
+            unreachable!("hash was just created");
+        };
+        let added = pairs
+            .into_iter()
+            .filter(|(f, v)| fields.insert(f.clone(), v.clone()).is_none())
+            .count();
+        self.storage.flush();
+        Ok(added)
+    }
+
+    pub fn hget(&mut self, key: &str, field: &str) -> Result<Option<String>, KvError> {
+        Ok(self.hash(key)?.and_then(|h| h.get(field).cloned()))
+    }
+
+    pub fn hmget(&mut self, key: &str, fields: &[String]) -> Result<Vec<Option<String>>, KvError> {
+        let hash = self.hash(key)?;
+        Ok(fields
+            .iter()
+            .map(|f| hash.and_then(|h| h.get(f).cloned()))
+            .collect())
+    }
+
+    /// Remove `fields` from the hash; the key is removed once the hash is empty.
+    /// Returns the number of fields that existed.
+    pub fn hdel(&mut self, key: &str, fields: &[String]) -> Result<usize, KvError> {
+        if self.hash(key)?.is_none() {
+            return Ok(0);
+        }
+        let Some(Value::Hash(hash)) = self.lookup_mut(key) else {
+            unreachable!("hash checked above");
+        };
+        let removed = fields.iter().filter(|f| hash.remove(*f).is_some()).count();
+        let now_empty = hash.is_empty();
+        if removed > 0 {
+            let mut record = vec!["HDEL", key];
+            record.extend(fields.iter().map(String::as_str));
+            self.log(&record);
+        }
+        if now_empty {
+            self.remove_value(key);
+        } else {
+            self.storage.flush();
+        }
+        Ok(removed)
+    }
+
+    pub fn hgetall(&mut self, key: &str) -> Result<Vec<(String, String)>, KvError> {
+        Ok(self
+            .hash(key)?
+            .map(|h| h.iter().map(|(f, v)| (f.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+
+    pub fn hkeys(&mut self, key: &str) -> Result<Vec<String>, KvError> {
+        Ok(self
+            .hash(key)?
+            .map(|h| h.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    pub fn hlen(&mut self, key: &str) -> Result<usize, KvError> {
+        Ok(self.hash(key)?.map(|h| h.len()).unwrap_or(0))
+    }
+
+    pub fn hexists(&mut self, key: &str, field: &str) -> Result<bool, KvError> {
+        Ok(self.hash(key)?.is_some_and(|h| h.contains_key(field)))
+    }
+
+    /// Add `delta` to the integer in `field`. The result is logged as a field-level HSET.
+    pub fn hincr_by(&mut self, key: &str, field: &str, delta: i64) -> Result<i64, KvError> {
+        let current = match self.hget(key, field)? {
+            Some(v) => v.trim().parse::<i64>().map_err(|_| KvError::NotInteger)?,
+            None => 0,
+        };
+        let next = current.checked_add(delta).ok_or(KvError::Overflow)?;
+        self.hset(key, vec![(field.to_string(), next.to_string())])?;
+        Ok(next)
+    }
+}
+
+/// Re-apply a logged hash operation during WAL replay.
+pub(crate) fn replay(store: &mut HashMap<String, Value>, op: &str, key: &str, args: &[String]) -> bool {
+    match op {
+        "HSET" if args.len().is_multiple_of(2) => {
+            let entry = store
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Hash(HashMap::new()));
+            if !matches!(entry, Value::Hash(_)) {
+                *entry = Value::Hash(HashMap::new());
+            }
+            if let Value::Hash(hash) = entry {
+                for pair in args.chunks(2) {
+                    hash.insert(pair[0].clone(), pair[1].clone());
+                }
+            }
+            true
+        }
+        "HDEL" => {
+            if let Some(Value::Hash(hash)) = store.get_mut(key) {
+                for field in args {
+                    hash.remove(field);
+                }
+                if hash.is_empty() {
+                    store.remove(key);
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
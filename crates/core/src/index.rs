@@ -0,0 +1,313 @@
+//! Secondary indexes over a namespace.
+//!
+//! An index maps the value of `value`, a prefix of it, or
+//! `json_extract(value, path)` to the keys holding it. Only string and JSON
+//! values are indexed, and `NULL` (a missing path) is left out, so a unique
+//! index allows any number of keys without the field. Entries change in the
+//! same call that writes the key, and a write that would break a unique index
+//! fails before anything is logged. Definitions live in the catalog; entries
+//! are rebuilt from the data when the store opens.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Bound;
+
+use parser::CreateIndex;
+use parser::sql::{Column, Expr};
+use storage::Value;
+
+use crate::catalog::catalog_error;
+use crate::error::KvError;
+use crate::json::JsonPath;
+use crate::kv::KvStore;
+use crate::planner::namespace_prefix;
+use crate::query::{Datum, Row, display, eval};
+
+/// A finite number ordered with `total_cmp`.
+#[derive(Debug, Clone, Copy)]
+pub struct Number(f64);
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A value's position in an index. Numbers, including text that reads as a
+/// number, sort before all other text.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndexKey {
+    Number(Number),
+    Text(String),
+}
+
+impl IndexKey {
+    pub(crate) fn number(f: f64) -> IndexKey {
+        // `total_cmp` orders -0.0 before 0.0; they are the same value here.
+        IndexKey::Number(Number(f + 0.0))
+    }
+}
+
+impl fmt::Display for IndexKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexKey::Number(n) => write!(f, "{}", n.0),
+            IndexKey::Text(s) => write!(f, "'{}'", s.replace('\'', "''")),
+        }
+    }
+}
+
+pub(crate) struct Index {
+    pub(crate) def: CreateIndex,
+    prefix: String,
+    entries: BTreeMap<IndexKey, BTreeSet<String>>,
+    by_key: HashMap<String, IndexKey>,
+}
+
+impl Index {
+    fn new(def: CreateIndex) -> Index {
+        Index {
+            prefix: namespace_prefix(&def.namespace),
+            def,
+            entries: BTreeMap::new(),
+            by_key: HashMap::new(),
+        }
+    }
+
+    /// Where `value` at `key` belongs in this index, if anywhere.
+    fn key_for(&self, key: &str, value: &Value) -> Option<IndexKey> {
+        if !key.starts_with(&self.prefix) || !matches!(value, Value::String(_) | Value::Json(_)) {
+            return None;
+        }
+        let row = Row {
+            key: key.to_string(),
+            value: value.clone(),
+        };
+        self.key_of(&eval(&self.def.target, &row).ok()?)
+    }
+
+    /// Index key for a computed value, after applying the prefix length.
+    pub(crate) fn key_of(&self, datum: &Datum) -> Option<IndexKey> {
+        match self.def.prefix {
+            Some(len) if *datum != Datum::Null => {
+                Datum::Text(display(datum).chars().take(len).collect()).index_key()
+            }
+            _ => datum.index_key(),
+        }
+    }
+
+    /// Another key already holding `index_key` in a unique index.
+    fn conflict(&self, key: &str, index_key: &IndexKey) -> Option<&String> {
+        if !self.def.unique {
+            return None;
+        }
+        self.entries.get(index_key)?.iter().find(|k| *k != key)
+    }
+
+    fn set(&mut self, key: &str, index_key: Option<IndexKey>) {
+        if let Some(old) = self.by_key.remove(key)
+            && let Some(keys) = self.entries.get_mut(&old)
+        {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.entries.remove(&old);
+            }
+        }
+        if let Some(index_key) = index_key {
+            self.entries
+                .entry(index_key.clone())
+                .or_default()
+                .insert(key.to_string());
+            self.by_key.insert(key.to_string(), index_key);
+        }
+    }
+
+    /// Keys whose index key lies between `lower` and `upper`, in key order.
+    pub(crate) fn range(&self, lower: Bound<&IndexKey>, upper: Bound<&IndexKey>) -> Vec<String> {
+        // BTreeMap::range panics on inverted bounds; they select nothing anyway.
+        if let (Bound::Included(l) | Bound::Excluded(l), Bound::Included(u) | Bound::Excluded(u)) =
+            (lower, upper)
+        {
+            let both_included = matches!((lower, upper), (Bound::Included(_), Bound::Included(_)));
+            if l > u || (l == u && !both_included) {
+                return Vec::new();
+            }
+        }
+        let keys: BTreeSet<&String> = self
+            .entries
+            .range((lower, upper))
+            .flat_map(|(_, keys)| keys)
+            .collect();
+        keys.into_iter().cloned().collect()
+    }
+
+    /// The catalog record for this definition.
+    fn record(&self) -> Vec<String> {
+        let (kind, arg) = match &self.def.target {
+            Expr::JsonExtract(_, path) => ("json", path.clone()),
+            _ => (
+                "value",
+                self.def.prefix.map(|n| n.to_string()).unwrap_or_default(),
+            ),
+        };
+        vec![
+            "INDEX".to_string(),
+            self.def.name.clone(),
+            self.def.namespace.clone(),
+            kind.to_string(),
+            arg,
+            if self.def.unique { "1" } else { "0" }.to_string(),
+        ]
+    }
+
+    fn from_record(fields: &[String]) -> Option<Index> {
+        let [tag, name, namespace, kind, arg, unique] = fields else {
+            return None;
+        };
+        if tag != "INDEX" {
+            return None;
+        }
+        let value = Expr::Column(Column::Value);
+        let (target, prefix) = match kind.as_str() {
+            "json" => (Expr::JsonExtract(Box::new(value), arg.clone()), None),
+            "value" if arg.is_empty() => (value, None),
+            "value" => (value, Some(arg.parse().ok()?)),
+            _ => return None,
+        };
+        Some(Index::new(CreateIndex {
+            name: name.clone(),
+            namespace: namespace.clone(),
+            target,
+            prefix,
+            unique: unique == "1",
+        }))
+    }
+}
+
+/// Every index of a store, by name.
+#[derive(Default)]
+pub(crate) struct Indexes {
+    by_name: BTreeMap<String, Index>,
+}
+
+impl Indexes {
+    pub(crate) fn get(&self, name: &str) -> Option<&Index> {
+        self.by_name.get(name)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Index> {
+        self.by_name.values()
+    }
+
+    /// Fail if writing `value` at `key` would break a unique index.
+    pub(crate) fn check(&self, key: &str, value: &Value) -> Result<(), KvError> {
+        for index in self.by_name.values() {
+            if let Some(index_key) = index.key_for(key, value)
+                && index.conflict(key, &index_key).is_some()
+            {
+                return Err(KvError::UniqueViolation(index.def.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that `key` now holds `value`, or nothing.
+    pub(crate) fn update(&mut self, key: &str, value: Option<&Value>) {
+        for index in self.by_name.values_mut() {
+            let index_key = value.and_then(|value| index.key_for(key, value));
+            index.set(key, index_key);
+        }
+    }
+
+    pub(crate) fn clear_entries(&mut self) {
+        for index in self.by_name.values_mut() {
+            index.entries.clear();
+            index.by_key.clear();
+        }
+    }
+
+    pub(crate) fn records(&self) -> Vec<Vec<String>> {
+        self.by_name.values().map(Index::record).collect()
+    }
+}
+
+impl KvStore {
+    /// Create an index and fill it from the keys already in its namespace.
+    pub fn create_index(&mut self, def: CreateIndex) -> Result<(), KvError> {
+        if self.indexes.by_name.contains_key(&def.name) {
+            return Err(KvError::IndexExists(def.name));
+        }
+        if let Expr::JsonExtract(_, path) = &def.target {
+            JsonPath::parse(path)?;
+        }
+        let index = self.build_index(Index::new(def))?;
+        let name = index.def.name.clone();
+        self.indexes.by_name.insert(name.clone(), index);
+        self.save_catalog().map_err(|e| {
+            self.indexes.by_name.remove(&name);
+            catalog_error(e)
+        })
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> Result<(), KvError> {
+        let Some(index) = self.indexes.by_name.remove(name) else {
+            return Err(KvError::NoSuchIndex(name.to_string()));
+        };
+        self.save_catalog().map_err(|e| {
+            self.indexes.by_name.insert(name.to_string(), index);
+            catalog_error(e)
+        })
+    }
+
+    /// Names of the indexes on `namespace`.
+    pub fn index_names(&self, namespace: &str) -> Vec<String> {
+        self.indexes
+            .iter()
+            .filter(|index| index.def.namespace == namespace)
+            .map(|index| index.def.name.clone())
+            .collect()
+    }
+
+    fn build_index(&mut self, mut index: Index) -> Result<Index, KvError> {
+        for key in self.keys_with_prefix(&index.prefix) {
+            let Some(value) = self.lookup(&key) else {
+                continue;
+            };
+            let index_key = index.key_for(&key, value);
+            if let Some(index_key) = &index_key
+                && index.conflict(&key, index_key).is_some()
+            {
+                return Err(KvError::UniqueViolation(index.def.name.clone()));
+            }
+            index.set(&key, index_key);
+        }
+        Ok(index)
+    }
+
+    /// Load index definitions from the catalog and rebuild their entries.
+    pub(crate) fn load_indexes(&mut self, records: &[Vec<String>]) {
+        for index in records.iter().filter_map(|r| Index::from_record(r)) {
+            match self.build_index(index) {
+                Ok(index) => {
+                    self.indexes.by_name.insert(index.def.name.clone(), index);
+                }
+                Err(e) => eprintln!("Index rebuild error: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+
+use serde_json::{Number, Value as JsonValue};
+use storage::{JsonDoc, Value};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+use parser::SetCondition;
+
+/// One step of a JSONPath expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Wildcard,
+    /// `..`: the rest of the path applies to this node and every descendant.
+    Descend,
+}
+
+/// A concrete location inside a document, produced by resolving a path.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Loc {
+    Key(String),
+    Index(usize),
+}
+
+/// A parsed JSONPath. Paths starting with `$` may match many values; legacy
+/// paths (`.`, `.a.b`, `a[0]`) address a single value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+    legacy: bool,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<JsonPath, KvError> {
+        let invalid = || KvError::InvalidJsonPath(path.to_string());
+        let (legacy, rest) = if let Some(rest) = path.strip_prefix('$') {
+            (false, rest.to_string())
+        } else if path == "." {
+            (true, String::new())
+        } else if path.starts_with('.') || path.starts_with('[') {
+            (true, path.to_string())
+        } else {
+            // A legacy path may start with a bare member name.
+            (true, format!(".{}", path))
+        };
+
+        let chars: Vec<char> = rest.chars().collect();
+        let mut segments = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '.' => {
+                    i += 1;
+                    if chars.get(i) == Some(&'.') {
+                        segments.push(Segment::Descend);
+                        i += 1;
+                        if chars.get(i) == Some(&'[') {
+                            continue;
+                        }
+                    }
+                    if chars.get(i) == Some(&'*') {
+                        segments.push(Segment::Wildcard);
+                        i += 1;
+                        continue;
+                    }
+                    let start = i;
+                    while i < chars.len() && chars[i] != '.' && chars[i] != '[' {
+                        i += 1;
+                    }
+                    if start == i {
+                        return Err(invalid());
+                    }
+                    segments.push(Segment::Key(chars[start..i].iter().collect()));
+                }
+                '[' => {
+                    let close = chars[i..]
+                        .iter()
+                        .position(|&c| c == ']')
+                        .ok_or_else(invalid)?
+                        + i;
+                    let inner: String = chars[i + 1..close].iter().collect();
+                    let inner = inner.trim();
+                    let quoted = (inner.starts_with('\'') && inner.ends_with('\''))
+                        || (inner.starts_with('"') && inner.ends_with('"'));
+                    if inner == "*" {
+                        segments.push(Segment::Wildcard);
+                    } else if quoted && inner.len() >= 2 {
+                        segments.push(Segment::Key(inner[1..inner.len() - 1].to_string()));
+                    } else {
+                        segments.push(Segment::Index(inner.parse().map_err(|_| invalid())?));
+                    }
+                    i = close + 1;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if segments.last() == Some(&Segment::Descend) {
+            return Err(invalid());
+        }
+        Ok(JsonPath { segments, legacy })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    /// The first value in `doc` this path matches.
+    pub(crate) fn first<'a>(&self, doc: &'a JsonValue) -> Option<&'a JsonValue> {
+        self.resolve(doc).first().and_then(|loc| at(doc, loc))
+    }
+
+    /// Every value in `doc` this path matches, in document order.
+    pub(crate) fn values<'a>(&self, doc: &'a JsonValue) -> Vec<&'a JsonValue> {
+        self.resolve(doc).iter().filter_map(|loc| at(doc, loc)).collect()
+    }
+
+    /// Every location in `doc` this path matches, in document order.
+    fn resolve(&self, doc: &JsonValue) -> Vec<Vec<Loc>> {
+        let mut out = Vec::new();
+        walk(doc, &self.segments, &mut Vec::new(), &mut out);
+        out.dedup();
+        out
+    }
+}
+
+fn walk(node: &JsonValue, segments: &[Segment], prefix: &mut Vec<Loc>, out: &mut Vec<Vec<Loc>>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        out.push(prefix.clone());
+        return;
+    };
+    if *segment == Segment::Descend {
+        walk(node, rest, prefix, out);
+    }
+    let mut visit = |loc: Loc, child: &JsonValue, segments: &[Segment]| {
+        prefix.push(loc);
+        walk(child, segments, prefix, out);
+        prefix.pop();
+    };
+    match (segment, node) {
+        (Segment::Key(k), JsonValue::Object(members)) => {
+            if let Some(child) = members.get(k) {
+                visit(Loc::Key(k.clone()), child, rest);
+            }
+        }
+        (Segment::Index(i), JsonValue::Array(items)) => {
+            let idx = if *i < 0 { items.len() as i64 + i } else { *i };
+            if idx >= 0 && (idx as usize) < items.len() {
+                visit(Loc::Index(idx as usize), &items[idx as usize], rest);
+            }
+        }
+        (Segment::Wildcard, JsonValue::Object(members)) => {
+            for (k, child) in members {
+                visit(Loc::Key(k.clone()), child, rest);
+            }
+        }
+        (Segment::Wildcard, JsonValue::Array(items)) => {
+            for (i, child) in items.iter().enumerate() {
+                visit(Loc::Index(i), child, rest);
+            }
+        }
+        (Segment::Descend, JsonValue::Object(members)) => {
+            for (k, child) in members {
+                visit(Loc::Key(k.clone()), child, segments);
+            }
+        }
+        (Segment::Descend, JsonValue::Array(items)) => {
+            for (i, child) in items.iter().enumerate() {
+                visit(Loc::Index(i), child, segments);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn at_mut<'a>(doc: &'a mut JsonValue, loc: &[Loc]) -> Option<&'a mut JsonValue> {
+    loc.iter().try_fold(doc, |node, step| match (step, node) {
+        (Loc::Key(k), JsonValue::Object(members)) => members.get_mut(k),
+        (Loc::Index(i), JsonValue::Array(items)) => items.get_mut(*i),
+        _ => None,
+    })
+}
+
+fn at<'a>(doc: &'a JsonValue, loc: &[Loc]) -> Option<&'a JsonValue> {
+    loc.iter().try_fold(doc, |node, step| match (step, node) {
+        (Loc::Key(k), JsonValue::Object(members)) => members.get(k),
+        (Loc::Index(i), JsonValue::Array(items)) => items.get(*i),
+        _ => None,
+    })
+}
+
+pub(crate) fn parse_json(text: &str) -> Result<JsonValue, KvError> {
+    serde_json::from_str(text).map_err(|e| KvError::InvalidJson(e.to_string()))
+}
+
+/// Set `value` at `path`. Existing matches are replaced; otherwise a missing
+/// final member is added to each matching parent object. Returns whether the
+/// document changed.
+fn set(
+    doc: &mut Option<JsonValue>,
+    path: &JsonPath,
+    value: JsonValue,
+    condition: SetCondition,
+) -> Result<bool, KvError> {
+    let Some(root) = doc else {
+        if !path.is_root() {
+            return Err(KvError::JsonRootRequired);
+        }
+        if condition == SetCondition::IfExists {
+            return Ok(false);
+        }
+        *doc = Some(value);
+        return Ok(true);
+    };
+    let matches = path.resolve(root);
+    if !matches.is_empty() {
+        if condition == SetCondition::IfNotExists {
+            return Ok(false);
+        }
+        for loc in &matches {
+            if let Some(slot) = at_mut(root, loc) {
+                *slot = value.clone();
+            }
+        }
+        return Ok(true);
+    }
+    if condition == SetCondition::IfExists {
+        return Ok(false);
+    }
+    let Some((Segment::Key(name), parent_segments)) = path.segments.split_last() else {
+        return Ok(false);
+    };
+    let parent = JsonPath {
+        segments: parent_segments.to_vec(),
+        legacy: path.legacy,
+    };
+    let mut changed = false;
+    for loc in parent.resolve(root) {
+        if let Some(JsonValue::Object(members)) = at_mut(root, &loc) {
+            members.insert(name.clone(), value.clone());
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+/// Remove every value matched by a non-root `path`. Returns how many were removed.
+fn del(doc: &mut JsonValue, path: &JsonPath) -> usize {
+    let mut matches = path.resolve(doc);
+    // Deleting later array indices first keeps earlier locations valid.
+    matches.sort();
+    matches.reverse();
+    let mut removed = 0;
+    for loc in matches {
+        let Some((last, parent)) = loc.split_last() else {
+            continue;
+        };
+        let gone = match (at_mut(doc, parent), last) {
+            (Some(JsonValue::Object(members)), Loc::Key(k)) => members.remove(k).is_some(),
+            (Some(JsonValue::Array(items)), Loc::Index(i)) if *i < items.len() => {
+                items.remove(*i);
+                true
+            }
+            _ => false,
+        };
+        removed += usize::from(gone);
+    }
+    removed
+}
+
+/// Append `values` to each array matched by `path`, returning the new lengths
+/// (`None` where the match is not an array).
+fn arrappend(doc: &mut JsonValue, path: &JsonPath, values: &[JsonValue]) -> Vec<Option<usize>> {
+    path.resolve(doc)
+        .iter()
+        .map(|loc| match at_mut(doc, loc) {
+            Some(JsonValue::Array(items)) => {
+                items.extend(values.iter().cloned());
+                Some(items.len())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Add `delta` to each number matched by `path`. Integers stay integers while
+/// the sum fits in an `i64`; anything else becomes a float.
+fn numincrby(
+    doc: &mut JsonValue,
+    path: &JsonPath,
+    delta: &Number,
+) -> Result<Vec<Option<JsonValue>>, KvError> {
+    let mut results = Vec::new();
+    for loc in path.resolve(doc) {
+        let Some(JsonValue::Number(current)) = at_mut(doc, &loc) else {
+            results.push(None);
+            continue;
+        };
+        let next = match (current.as_i64(), delta.as_i64()) {
+            (Some(a), Some(b)) if a.checked_add(b).is_some() => Number::from(a + b),
+            _ => {
+                let sum = current.as_f64().unwrap_or(0.0) + delta.as_f64().unwrap_or(0.0);
+                Number::from_f64(sum).ok_or(KvError::NotFinite)?
+            }
+        };
+        *current = next.clone();
+        results.push(Some(JsonValue::Number(next)));
+    }
+    Ok(results)
+}
+
+fn parse_delta(delta: &str) -> Result<Number, KvError> {
+    match serde_json::from_str(delta) {
+        Ok(JsonValue::Number(n)) => Ok(n),
+        _ => Err(KvError::NotFloat),
+    }
+}
+
+impl KvStore {
+    fn json(&mut self, key: &str) -> Result<Option<JsonValue>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::Json(doc)) => Ok(Some(doc.decode())),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Store `doc` at `key` and log `record`, unless the write is refused.
+    fn store_json(&mut self, key: &str, doc: &JsonValue, record: &[&str]) -> Result<(), KvError> {
+        let value = Value::Json(JsonDoc::encode(doc));
+        self.check_write(key, &value)?;
+        self.log(record);
+        self.write_value(key.to_string(), value);
+        Ok(())
+    }
+
+    /// Set the JSON text `value` at `path`, creating the document when `path`
+    /// is the root. Returns `false` if an NX/XX condition prevented the write.
+    pub fn json_set(
+        &mut self,
+        key: &str,
+        path: &str,
+        value: &str,
+        condition: SetCondition,
+    ) -> Result<bool, KvError> {
+        let parsed = JsonPath::parse(path)?;
+        let new_value = parse_json(value)?;
+        let mut doc = self.json(key)?;
+        if !set(&mut doc, &parsed, new_value, condition)? {
+            return Ok(false);
+        }
+        // The condition already held, so replay can apply the patch unconditionally.
+        let doc = doc.as_ref().expect("document was just set");
+        self.store_json(key, doc, &["JSON.SET", key, path, value])?;
+        Ok(true)
+    }
+
+    /// Serialize the values at `path`. Legacy paths return the first match;
+    /// `$` paths return a JSON array of every match.
+    pub fn json_get(&mut self, key: &str, path: Option<&str>) -> Result<Option<String>, KvError> {
+        let parsed = JsonPath::parse(path.unwrap_or("."))?;
+        let Some(doc) = self.json(key)? else {
+            return Ok(None);
+        };
+        let matches: Vec<&JsonValue> = parsed
+            .resolve(&doc)
+            .iter()
+            .filter_map(|loc| at(&doc, loc))
+            .collect();
+        if parsed.is_legacy() {
+            Ok(matches.first().map(|v| v.to_string()))
+        } else {
+            Ok(Some(
+                JsonValue::Array(matches.into_iter().cloned().collect()).to_string(),
+            ))
+        }
+    }
+
+    /// Delete the values at `path` (the whole key for the root). Returns the
+    /// number of values removed.
+    pub fn json_del(&mut self, key: &str, path: Option<&str>) -> Result<usize, KvError> {
+        let path = path.unwrap_or("$");
+        let parsed = JsonPath::parse(path)?;
+        let Some(mut doc) = self.json(key)? else {
+            return Ok(0);
+        };
+        if parsed.is_root() {
+            self.delete(key);
+            return Ok(1);
+        }
+        let removed = del(&mut doc, &parsed);
+        if removed > 0 {
+            self.store_json(key, &doc, &["JSON.DEL", key, path])?;
+        }
+        Ok(removed)
+    }
+
+    /// Append JSON `values` to the arrays at `path`.
+    pub fn json_arrappend(
+        &mut self,
+        key: &str,
+        path: &str,
+        values: &[String],
+    ) -> Result<Vec<Option<usize>>, KvError> {
+        let parsed = JsonPath::parse(path)?;
+        let values = values
+            .iter()
+            .map(|v| parse_json(v))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut doc = self.json(key)?.ok_or(KvError::NoSuchKey)?;
+        let lengths = arrappend(&mut doc, &parsed, &values);
+        if lengths.iter().any(Option::is_some) {
+            let texts: Vec<String> = values.iter().map(JsonValue::to_string).collect();
+            let mut record = vec!["JSON.ARRAPPEND", key, path];
+            record.extend(texts.iter().map(String::as_str));
+            self.store_json(key, &doc, &record)?;
+        }
+        Ok(lengths)
+    }
+
+    /// Increment the numbers at `path` by `delta`. Returns the new value as JSON
+    /// text: a single number for legacy paths, an array (with `null` for
+    /// non-numbers) for `$` paths.
+    pub fn json_num_incr_by(
+        &mut self,
+        key: &str,
+        path: &str,
+        delta: &str,
+    ) -> Result<String, KvError> {
+        let parsed = JsonPath::parse(path)?;
+        let number = parse_delta(delta)?;
+        let mut doc = self.json(key)?.ok_or(KvError::NoSuchKey)?;
+        let results = numincrby(&mut doc, &parsed, &number)?;
+        if parsed.is_legacy() {
+            match results.first() {
+                None => return Err(KvError::JsonPathNotFound(path.to_string())),
+                Some(None) => return Err(KvError::JsonNotNumber),
+                Some(Some(_)) => {}
+            }
+        }
+        if results.iter().any(Option::is_some) {
+            self.store_json(key, &doc, &["JSON.NUMINCRBY", key, path, delta])?;
+        }
+        let reply = if parsed.is_legacy() {
+            results
+                .into_iter()
+                .flatten()
+                .next()
+                .unwrap_or(JsonValue::Null)
+        } else {
+            JsonValue::Array(
+                results
+                    .into_iter()
+                    .map(|r| r.unwrap_or(JsonValue::Null))
+                    .collect(),
+            )
+        };
+        Ok(reply.to_string())
+    }
+}
+
+/// Re-apply a logged JSON patch during WAL replay.
+pub(crate) fn replay(
+    store: &mut HashMap<String, Value>,
+    op: &str,
+    key: &str,
+    args: &[String],
+) -> bool {
+    let Some((path, rest)) = args.split_first() else {
+        return false;
+    };
+    let Ok(path) = JsonPath::parse(path) else {
+        return false;
+    };
+    let mut doc = match store.get(key) {
+        Some(Value::Json(doc)) => Some(doc.decode()),
+        _ => None,
+    };
+    let applied = match (op, rest) {
+        ("JSON.SET", [value]) => match parse_json(value) {
+            Ok(value) => set(&mut doc, &path, value, SetCondition::Always).is_ok(),
+            Err(_) => false,
+        },
+        ("JSON.DEL", []) => {
+            if let Some(doc) = &mut doc {
+                del(doc, &path);
+            }
+            true
+        }
+        ("JSON.ARRAPPEND", values) => {
+            let values: Result<Vec<_>, _> = values.iter().map(|v| parse_json(v)).collect();
+            match (&mut doc, values) {
+                (Some(doc), Ok(values)) => {
+                    arrappend(doc, &path, &values);
+                    true
+                }
+                _ => false,
+            }
+        }
+        ("JSON.NUMINCRBY", [delta]) => match (&mut doc, parse_delta(delta)) {
+            (Some(doc), Ok(delta)) => numincrby(doc, &path, &delta).is_ok(),
+            _ => false,
+        },
+        _ => return false,
+    };
+    if applied && let Some(doc) = doc {
+        store.insert(key.to_string(), Value::Json(JsonDoc::encode(&doc)));
+    }
+    true
+}
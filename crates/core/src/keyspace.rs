@@ -0,0 +1,142 @@
+//! The store's storage backend, with counts kept alongside it.
+//!
+//! `Keyspace` wraps a `Storage` and counts the keys under each `namespace:`
+//! prefix as keys come and go, so the `max_namespace_keys` limit is checked
+//! without scanning the data. It also keeps the size of the data for
+//! `maxmemory`: values stored or removed are measured as they go, and values
+//! edited in place through `get_mut` are measured again when the size is next
+//! asked for.
+
+use std::collections::{HashMap, HashSet};
+
+use storage::codec::encode_entry;
+use storage::{Storage, Value};
+
+pub(crate) struct Keyspace {
+    inner: Box<dyn Storage>,
+    /// Keys stored under each namespace; namespaces without keys are absent.
+    namespaces: HashMap<String, usize>,
+    /// Size of the values not in `dirty`, as `entry_size` measures them.
+    size: usize,
+    /// Keys handed out by `get_mut` since the size was last asked for.
+    dirty: HashSet<String>,
+}
+
+impl Keyspace {
+    pub(crate) fn new(inner: Box<dyn Storage>) -> Self {
+        let mut namespaces = HashMap::new();
+        let mut size = 0;
+        for (key, value) in inner.iter() {
+            if let Some(name) = namespace(key) {
+                *namespaces.entry(name.to_string()).or_default() += 1;
+            }
+            size += entry_size(key, value);
+        }
+        Keyspace {
+            inner,
+            namespaces,
+            size,
+            dirty: HashSet::new(),
+        }
+    }
+
+    /// Size of the data in bytes: what a snapshot of it would take. Only the
+    /// values edited in place since the last call are measured.
+    pub(crate) fn size(&mut self) -> usize {
+        for key in std::mem::take(&mut self.dirty) {
+            if let Some(value) = self.inner.get(&key) {
+                self.size += entry_size(&key, value);
+            }
+        }
+        self.size
+    }
+
+    /// Keys stored under `namespace`, expired ones not yet removed included.
+    pub(crate) fn namespace_len(&self, namespace: &str) -> usize {
+        self.namespaces.get(namespace).copied().unwrap_or(0)
+    }
+}
+
+/// The bytes `key` and `value` take in a snapshot, newline included.
+fn entry_size(key: &str, value: &Value) -> usize {
+    encode_entry(key, value).len() + 1
+}
+
+/// The namespace of `key`: what comes before its first `:`, if any.
+pub(crate) fn namespace(key: &str) -> Option<&str> {
+    key.split_once(':').map(|(namespace, _)| namespace)
+}
+
+impl Storage for Keyspace {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.inner.get(key)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        // The value may change size: leave it out until `size` measures it.
+        if !self.dirty.contains(key)
+            && let Some(value) = self.inner.get(key)
+        {
+            self.size -= entry_size(key, value);
+            self.dirty.insert(key.to_string());
+        }
+        self.inner.get_mut(key)
+    }
+
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        let name = namespace(&key).map(str::to_string);
+        let dirty = self.dirty.contains(&key);
+        if !dirty {
+            self.size += entry_size(&key, &value);
+        }
+        let old = self.inner.insert(key.clone(), value);
+        match &old {
+            Some(old) if !dirty => self.size -= entry_size(&key, old),
+            Some(_) => {}
+            None => {
+                if let Some(name) = name {
+                    *self.namespaces.entry(name).or_default() += 1;
+                }
+            }
+        }
+        old
+    }
+
+    fn delete(&mut self, key: &str) -> bool {
+        if !self.dirty.remove(key)
+            && let Some(value) = self.inner.get(key)
+        {
+            self.size -= entry_size(key, value);
+        }
+        let deleted = self.inner.delete(key);
+        if deleted
+            && let Some(name) = namespace(key)
+            && let Some(count) = self.namespaces.get_mut(name)
+        {
+            *count -= 1;
+            if *count == 0 {
+                self.namespaces.remove(name);
+            }
+        }
+        deleted
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        self.inner.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.namespaces.clear();
+        self.size = 0;
+        self.dirty.clear();
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+    }
+}
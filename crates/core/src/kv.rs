@@ -1,18 +1,41 @@
-use crate::wal::Wal;
+use crate::catalog;
+use crate::error::KvError;
+use crate::index::Indexes;
+use crate::keyspace::{self, Keyspace};
+use crate::limits::{LimitError, Limits};
+use crate::list::normalize_range;
+use crate::schema::Schemas;
+use crate::search::TextIndexes;
+use crate::wal::{FsyncPolicy, Wal};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use storage::{FileStorage, MemStorage, Storage};
+use storage::codec::{decode_entry, encode_entry};
+use storage::{FileStorage, MemStorage, Storage, Value};
+
+/// First line of a typed snapshot. Older snapshots are plain `key|value` lines.
+const SNAPSHOT_HEADER: &str = "#zyncdb-snapshot v2";
+
+/// Largest string `SETRANGE` and `APPEND` will produce (512 MiB, as in Redis).
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
 
 pub struct KvStore {
-    storage: Box<dyn Storage>,
+    pub(crate) storage: Keyspace,
     wal: Option<Arc<Mutex<Wal>>>,
-    expirations: HashMap<String, Instant>,
+    pub(crate) expirations: HashMap<String, Instant>,
     tx_buffer: Option<HashMap<String, String>>,
+    /// WAL records held back while an atomic batch runs; see `batch.rs`.
+    pub(crate) wal_buffer: Option<Vec<Vec<String>>>,
+    pub(crate) indexes: Indexes,
+    pub(crate) text_indexes: TextIndexes,
+    pub(crate) schemas: Schemas,
+    pub(crate) limits: Limits,
+    /// Where index and schema definitions are kept; see `catalog.rs`.
+    pub(crate) catalog: Option<PathBuf>,
 }
 
 pub enum Backend {
@@ -22,7 +45,12 @@ pub enum Backend {
 
 impl KvStore {
     pub fn open(path: &Path) -> std::io::Result<Self> {
-        let mut wal = Wal::open(path)?;
+        KvStore::open_with_limits(path, Limits::default())
+    }
+
+    /// Open with `limits` in force from the start, including for WAL replay.
+    pub fn open_with_limits(path: &Path, limits: Limits) -> std::io::Result<Self> {
+        let mut wal = Wal::open(path)?.with_limits(limits.clone());
         let map = wal.load_into()?;
 
         // Initialize MemStorage with data from WAL
@@ -31,16 +59,24 @@ impl KvStore {
             mem.insert(k, v);
         }
 
-        Ok(KvStore {
-            storage: Box::new(mem),
+        KvStore {
+            storage: Keyspace::new(Box::new(mem)),
             wal: Some(Arc::new(Mutex::new(wal))),
             expirations: HashMap::new(),
             tx_buffer: None,
-        })
+            wal_buffer: None,
+            indexes: Indexes::default(),
+            text_indexes: TextIndexes::default(),
+            schemas: Schemas::default(),
+            limits,
+            catalog: None,
+        }
+        .with_catalog(path)
     }
 
-    pub fn open_with_backend(path: &Path, backend: Backend) -> std::io::Result<Self> {
-        let mut wal = Wal::open(path)?;
+    /// Open with `limits` in force, keeping the data in `backend`.
+    pub fn open_with_backend(path: &Path, backend: Backend, limits: Limits) -> std::io::Result<Self> {
+        let mut wal = Wal::open(path)?.with_limits(limits.clone());
         let map = wal.load_into()?;
 
         let storage: Box<dyn Storage> = match backend {
@@ -60,12 +96,19 @@ impl KvStore {
             }
         };
 
-        Ok(KvStore {
-            storage,
+        KvStore {
+            storage: Keyspace::new(storage),
             wal: Some(Arc::new(Mutex::new(wal))),
             expirations: HashMap::new(),
             tx_buffer: None,
-        })
+            wal_buffer: None,
+            indexes: Indexes::default(),
+            text_indexes: TextIndexes::default(),
+            schemas: Schemas::default(),
+            limits,
+            catalog: None,
+        }
+        .with_catalog(path)
     }
 
     /// Create a snapshot of the current state and truncate the WAL.
@@ -75,13 +118,21 @@ impl KvStore {
         snapshot_path: &Path,
         wal_path: &Path,
     ) -> io::Result<()> {
-        // 1. Write snapshot
-        let file = File::create(snapshot_path)?;
+        // 1. Write snapshot, and have it on disk before the WAL goes: a crash
+        // leaves either the old snapshot and full WAL or the new snapshot.
+        let tmp_path = snapshot_path.with_extension("tmp");
+        let file = File::create(&tmp_path)?;
         let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", SNAPSHOT_HEADER)?;
         for (k, v) in self.storage.iter() {
-            writeln!(writer, "{}|{}", k, v)?;
+            writeln!(writer, "{}", encode_entry(k, v))?;
         }
         writer.flush()?;
+        writer.get_ref().sync_all()?;
+        std::fs::rename(&tmp_path, snapshot_path)?;
+        if let Some(dir) = snapshot_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
 
         // 2. Truncate WAL
         if let Some(wal) = &self.wal {
@@ -93,34 +144,103 @@ impl KvStore {
 
     /// Load from snapshot, then replay WAL.
     pub fn open_with_snapshot(snapshot_path: &Path, wal_path: &Path) -> io::Result<Self> {
-        let mut mem = MemStorage::new();
+        KvStore::open_with_snapshot_and_backend(snapshot_path, wal_path, Backend::Memory, Limits::default())
+    }
+
+    /// Load from snapshot, then replay WAL with `limits` in force, keeping
+    /// the data in `backend`.
+    pub fn open_with_snapshot_and_backend(
+        snapshot_path: &Path,
+        wal_path: &Path,
+        backend: Backend,
+        limits: Limits,
+    ) -> io::Result<Self> {
+        let mut map = HashMap::new();
 
         // 1. Load snapshot if exists
         if snapshot_path.exists() {
             let file = File::open(snapshot_path)?;
             let reader = BufReader::new(file);
-            for line in reader.lines() {
+            let mut typed = false;
+            for (i, line) in reader.lines().enumerate() {
                 let line = line?;
+                if i == 0 && line == SNAPSHOT_HEADER {
+                    typed = true;
+                    continue;
+                }
+                if typed {
+                    if let Some((k, v)) = decode_entry(&line) {
+                        map.insert(k, v);
+                    }
+                    continue;
+                }
                 let parts: Vec<&str> = line.trim().split('|').collect();
                 if parts.len() == 2 {
-                    mem.insert(parts[0].to_string(), parts[1].to_string());
+                    map.insert(parts[0].to_string(), Value::from(parts[1]));
                 }
             }
         }
 
         // 2. Replay WAL
-        let mut wal = Wal::open(wal_path)?;
-        let wal_map = wal.load_into()?;
-        for (k, v) in wal_map {
-            mem.insert(k, v);
-        }
+        let mut wal = Wal::open(wal_path)?.with_limits(limits.clone());
+        wal.replay_into(&mut map)?;
 
-        Ok(KvStore {
-            storage: Box::new(mem),
+        let storage: Box<dyn Storage> = match backend {
+            Backend::Memory => {
+                let mut mem = MemStorage::new();
+                for (k, v) in map {
+                    mem.insert(k, v);
+                }
+                Box::new(mem)
+            }
+            Backend::File(file_path) => {
+                let mut file_storage = FileStorage::new(file_path)?;
+                for (k, v) in map {
+                    file_storage.insert(k, v);
+                }
+                Box::new(file_storage)
+            }
+        };
+
+        KvStore {
+            storage: Keyspace::new(storage),
             wal: Some(Arc::new(Mutex::new(wal))),
             expirations: HashMap::new(),
             tx_buffer: None,
-        })
+            wal_buffer: None,
+            indexes: Indexes::default(),
+            text_indexes: TextIndexes::default(),
+            schemas: Schemas::default(),
+            limits,
+            catalog: None,
+        }
+        .with_catalog(wal_path)
+    }
+
+    /// Attach the catalog next to `wal_path`, load its schemas and rebuild the
+    /// indexes it defines.
+    fn with_catalog(mut self, wal_path: &Path) -> io::Result<Self> {
+        let path = catalog::path_for(wal_path);
+        let records = catalog::load(&path)?;
+        self.catalog = Some(path);
+        self.load_schemas(&records);
+        self.load_indexes(&records);
+        self.load_text_indexes(&records);
+        Ok(self)
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Apply `limits` to later writes. Keys already stored are kept.
+    pub fn set_limits(&mut self, limits: Limits) {
+        if let Some(wal) = &self.wal
+            && let Ok(mut wal) = wal.lock()
+        {
+            wal.set_limits(limits.clone());
+        }
+        self.limits = limits;
     }
 
     pub fn set_ttl(&mut self, key: &str, ttl_secs: u64) {
@@ -128,39 +248,191 @@ impl KvStore {
             .insert(key.to_string(), Instant::now() + Duration::from_secs(ttl_secs));
     }
 
-    pub fn get(&mut self, key: &str) -> Option<String> {
-        // Check expiration
-        if let Some(expiry) = self.expirations.get(key) {
-            if Instant::now() > *expiry {
-                self.storage.delete(key);
-                self.expirations.remove(key);
-                return None;
-            }
+    /// Drop `key` if its TTL has passed.
+    fn expire_if_needed(&mut self, key: &str) {
+        if let Some(expiry) = self.expirations.get(key)
+            && Instant::now() > *expiry
+        {
+            self.remove_value(key);
+            self.expirations.remove(key);
         }
+    }
+
+    /// Look up a live (non-expired) value of any type.
+    pub(crate) fn lookup(&mut self, key: &str) -> Option<&Value> {
+        self.expire_if_needed(key);
         self.storage.get(key)
     }
 
+    /// Mutable variant of `lookup`. Call `self.storage.flush()` after editing.
+    pub(crate) fn lookup_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.expire_if_needed(key);
+        self.storage.get_mut(key)
+    }
+
+    /// Fail if `key` may not hold `value`, e.g. because the namespace's schema
+    /// forbids it or a unique index already has the value under another key.
+    /// Call before logging the write.
+    pub(crate) fn check_write(&self, key: &str, value: &Value) -> Result<(), KvError> {
+        self.limits.check_key(key)?;
+        match value {
+            Value::String(s) => self.limits.check_value(s.len())?,
+            Value::Json(doc) => self.limits.check_value(doc.encoded_len())?,
+            _ => {}
+        }
+        if self.storage.get(key).is_none() {
+            self.check_namespace_room(key)?;
+        }
+        self.schemas.check(key, value)?;
+        self.indexes.check(key, value)
+    }
+
+    /// Fail if `key` would be one key too many for its namespace. Expired keys
+    /// not yet removed still count.
+    fn check_namespace_room(&self, key: &str) -> Result<(), LimitError> {
+        let (Some(max), Some(namespace)) = (self.limits.max_namespace_keys, keyspace::namespace(key)) else {
+            return Ok(());
+        };
+        if self.storage.namespace_len(namespace) >= max {
+            return Err(LimitError::NamespaceFull(namespace.to_string(), max));
+        }
+        Ok(())
+    }
+
+    /// Check the elements a collection command is about to store.
+    pub(crate) fn check_elements<'a>(
+        &self,
+        elements: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), KvError> {
+        for element in elements {
+            self.limits.check_element(element.len())?;
+        }
+        Ok(())
+    }
+
+    /// `check_write` for a command that creates `key` holding the empty
+    /// collection `empty` if it is missing, then edits it in place.
+    pub(crate) fn check_create(&mut self, key: &str, empty: Value) -> Result<(), KvError> {
+        if self.lookup(key).is_some() {
+            return Ok(());
+        }
+        self.check_write(key, &empty)
+    }
+
+    /// Store `value` at `key` and update the indexes.
+    pub(crate) fn write_value(&mut self, key: String, value: Value) -> Option<Value> {
+        self.indexes.update(&key, Some(&value));
+        self.text_indexes.update(&key, Some(&value));
+        self.storage.insert(key, value)
+    }
+
+    /// Remove `key` from storage and the indexes.
+    pub(crate) fn remove_value(&mut self, key: &str) -> bool {
+        self.indexes.update(key, None);
+        self.text_indexes.update(key, None);
+        self.storage.delete(key)
+    }
+
+    /// Append a record to the WAL, if this store has one.
+    pub(crate) fn log(&mut self, record: &[&str]) {
+        if let Some(buffer) = &mut self.wal_buffer {
+            buffer.push(record.iter().map(|s| s.to_string()).collect());
+            return;
+        }
+        if let Some(wal) = &self.wal
+            && let Ok(mut wal) = wal.lock()
+            && let Err(e) = wal.append(record)
+        {
+            eprintln!("WAL append error: {}", e);
+        }
+    }
+
+    /// Set when WAL writes are forced to disk.
+    pub fn set_fsync(&mut self, policy: FsyncPolicy) {
+        if let Some(wal) = &self.wal {
+            wal.lock().unwrap().set_fsync(policy);
+        }
+    }
+
+    /// Force every logged write to disk.
+    pub fn sync_wal(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes logged since the store was opened or last snapshotted.
+    pub fn changes(&self) -> u64 {
+        self.wal.as_ref().map_or(0, |wal| wal.lock().unwrap().changes())
+    }
+
+    /// Rough size of the data, in bytes: what a snapshot of it would take.
+    /// Kept as keys are written, so this does not walk the store.
+    pub fn used_memory(&mut self) -> usize {
+        self.storage.size()
+    }
+
+    /// Append `records` as one all-or-nothing group.
+    pub(crate) fn log_group(&self, records: &[Vec<String>]) {
+        if let Some(wal) = &self.wal
+            && let Ok(mut wal) = wal.lock()
+            && let Err(e) = wal.append_group(records)
+        {
+            eprintln!("WAL append error: {}", e);
+        }
+    }
+
+    /// Get the string stored at `key`. Values of other types are skipped; use
+    /// `get_string` when the caller needs to report WRONGTYPE.
+    pub fn get(&mut self, key: &str) -> Option<String> {
+        self.lookup(key).and_then(|v| v.as_string().cloned())
+    }
+
+    /// Get the string stored at `key`, failing if the key holds another type.
+    pub fn get_string(&mut self, key: &str) -> Result<Option<String>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Name of the type stored at `key`, as reported by `TYPE`.
+    pub fn key_type(&mut self, key: &str) -> Option<&'static str> {
+        self.lookup(key).map(|v| v.type_name())
+    }
+
     pub fn len(&self) -> usize {
         self.storage.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn clear(&mut self) {
         self.storage.clear();
+        self.indexes.clear_entries();
+        self.text_indexes.clear_entries();
     }
 
-    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
-        if let Some(buf) = &mut self.tx_buffer {
-            buf.insert(key, value)
-        } else {
-            if let Some(wal) = &self.wal {
-                if let Ok(mut wal) = wal.lock() {
-                    if let Err(e) = wal.append_put(&key, &value) {
-                        eprintln!("WAL append_put error: {}", e);
-                    }
-                }
-            }
-            self.storage.insert(key, value)
+    /// Set `key` to a string value, replacing whatever it held. Returns the
+    /// previous value if it was a string.
+    pub fn insert(&mut self, key: String, value: String) -> Result<Option<String>, KvError> {
+        let value = Value::String(value);
+        self.check_write(&key, &value)?;
+        if let Some(buf) = &mut self.tx_buffer
+            && let Value::String(value) = value
+        {
+            return Ok(buf.insert(key, value));
         }
+        if let Value::String(s) = &value {
+            self.log(&["PUT", &key, s]);
+        }
+        Ok(self
+            .write_value(key, value)
+            .and_then(|v| v.as_string().cloned()))
     }
 
     pub fn delete(&mut self, key: &str) -> bool {
@@ -169,37 +441,198 @@ impl KvStore {
                 buf.remove(key);
                 true
             } else {
-                if let Some(wal) = &self.wal {
-                    if let Ok(mut wal) = wal.lock() {
-                        if let Err(e) = wal.append_delete(key) {
-                            eprintln!("WAL append_delete error: {}", e);
-                        }
-                    }
-                }
-                self.storage.delete(key)
+                self.log(&["DELETE", key]);
+                self.remove_value(key)
             }
         } else {
             false
         }
     }
 
+    /// Increment the integer stored at `key` by one. Missing keys count as `0`.
+    pub fn incr(&mut self, key: &str) -> Result<i64, KvError> {
+        self.incr_by(key, 1)
+    }
+
+    /// Decrement the integer stored at `key` by one. Missing keys count as `0`.
+    pub fn decr(&mut self, key: &str) -> Result<i64, KvError> {
+        self.incr_by(key, -1)
+    }
+
+    /// Decrement the integer stored at `key` by `delta`.
+    pub fn decr_by(&mut self, key: &str, delta: i64) -> Result<i64, KvError> {
+        let delta = delta.checked_neg().ok_or(KvError::Overflow)?;
+        self.incr_by(key, delta)
+    }
+
+    /// Add `delta` to the integer stored at `key` and return the new value.
+    /// The result is written back as a regular PUT, so it is logged to the WAL.
+    pub fn incr_by(&mut self, key: &str, delta: i64) -> Result<i64, KvError> {
+        let current = match self.get_string(key)? {
+            Some(v) => v.parse::<i64>().map_err(|_| KvError::NotInteger)?,
+            None => 0,
+        };
+        let next = current.checked_add(delta).ok_or(KvError::Overflow)?;
+        self.insert(key.to_string(), next.to_string())?;
+        Ok(next)
+    }
+
+    /// Add `delta` to the float stored at `key` and return the new value.
+    pub fn incr_by_float(&mut self, key: &str, delta: f64) -> Result<f64, KvError> {
+        let current = match self.get_string(key)? {
+            Some(v) => v.parse::<f64>().map_err(|_| KvError::NotFloat)?,
+            None => 0.0,
+        };
+        if !current.is_finite() || !delta.is_finite() {
+            return Err(KvError::NotFinite);
+        }
+        let next = current + delta;
+        if !next.is_finite() {
+            return Err(KvError::NotFinite);
+        }
+        self.insert(key.to_string(), next.to_string())?;
+        Ok(next)
+    }
+
+    /// Append `value` to the string at `key`, creating it if missing.
+    /// Returns the new length in bytes.
+    pub fn append(&mut self, key: &str, value: &str) -> Result<usize, KvError> {
+        let mut current = self.get_string(key)?.unwrap_or_default();
+        if current.len() + value.len() > MAX_STRING_LEN {
+            return Err(KvError::StringTooLong);
+        }
+        current.push_str(value);
+        let len = current.len();
+        self.insert(key.to_string(), current)?;
+        Ok(len)
+    }
+
+    /// Return the byte range `start..=end` of the string at `key`.
+    /// Negative offsets count from the end, as in Redis `GETRANGE`.
+    pub fn get_range(&mut self, key: &str, start: i64, end: i64) -> Result<String, KvError> {
+        let value = self.get_string(key)?.unwrap_or_default();
+        Ok(match normalize_range(start, end, value.len()) {
+            Some((s, e)) => String::from_utf8_lossy(&value.as_bytes()[s..=e]).into_owned(),
+            None => String::new(),
+        })
+    }
+
+    /// Overwrite the string at `key` starting at byte `offset`, padding with
+    /// zero bytes if the string is shorter. Returns the new length.
+    pub fn set_range(&mut self, key: &str, offset: usize, value: &str) -> Result<usize, KvError> {
+        let current = self.get_string(key)?.unwrap_or_default();
+        if value.is_empty() {
+            return Ok(current.len());
+        }
+        let end = offset.checked_add(value.len()).ok_or(KvError::StringTooLong)?;
+        if end > MAX_STRING_LEN {
+            return Err(KvError::StringTooLong);
+        }
+        let mut bytes = current.into_bytes();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset..end].copy_from_slice(value.as_bytes());
+        let updated = String::from_utf8(bytes).map_err(|_| KvError::NotUtf8)?;
+        let len = updated.len();
+        self.insert(key.to_string(), updated)?;
+        Ok(len)
+    }
+
+    /// Length in bytes of the string at `key`, or `0` if missing.
+    pub fn strlen(&mut self, key: &str) -> Result<usize, KvError> {
+        Ok(self.get_string(key)?.map(|v| v.len()).unwrap_or(0))
+    }
+
+    /// Set `key` to `value` and return the previous value.
+    pub fn get_set(&mut self, key: &str, value: String) -> Result<Option<String>, KvError> {
+        let previous = self.get_string(key)?;
+        self.insert(key.to_string(), value)?;
+        Ok(previous)
+    }
+
+    /// Remove `key` and return the value it held.
+    pub fn get_del(&mut self, key: &str) -> Result<Option<String>, KvError> {
+        let previous = self.get_string(key)?;
+        if previous.is_some() {
+            self.delete(key);
+        }
+        Ok(previous)
+    }
+
+    /// Set `key` only if it does not exist. Returns whether the value was written.
+    pub fn set_nx(&mut self, key: &str, value: String) -> Result<bool, KvError> {
+        if self.lookup(key).is_some() {
+            return Ok(false);
+        }
+        self.insert(key.to_string(), value)?;
+        Ok(true)
+    }
+
+    /// Set `key` only if it already exists. Returns whether the value was written.
+    pub fn set_xx(&mut self, key: &str, value: String) -> Result<bool, KvError> {
+        if self.lookup(key).is_none() {
+            return Ok(false);
+        }
+        self.insert(key.to_string(), value)?;
+        Ok(true)
+    }
+
+    /// Set every pair in `pairs`. If one write fails, none of them happen.
+    pub fn mset(&mut self, pairs: Vec<(String, String)>) -> Result<(), KvError> {
+        self.limits.check_batch(pairs.len())?;
+        let keys: Vec<String> = pairs.iter().map(|(k, _)| k.clone()).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.all_or_nothing(&keys, |store| {
+            for (k, v) in pairs {
+                store.insert(k, v)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Set every pair in `pairs`, but only if none of the keys exist.
+    pub fn mset_nx(&mut self, pairs: Vec<(String, String)>) -> Result<bool, KvError> {
+        self.limits.check_batch(pairs.len())?;
+        if pairs.iter().any(|(k, _)| self.lookup(k).is_some()) {
+            return Ok(false);
+        }
+        self.mset(pairs)?;
+        Ok(true)
+    }
+
+    /// Fetch several keys at once; missing keys yield `None`.
+    pub fn mget(&mut self, keys: &[String]) -> Vec<Option<String>> {
+        keys.iter().map(|k| self.get(k)).collect()
+    }
+
     pub fn contains_key(&self, key: &str) -> bool {
         self.storage.get(key).is_some()
     }
 
-    pub fn iter(&self) -> Box<dyn Iterator<Item = (String, String)> + '_> {
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
         self.storage.iter()
     }
 
     pub fn begin_tx(&mut self) {
         self.tx_buffer = Some(HashMap::new());
     }
-    pub fn commit_tx(&mut self) {
-        if let Some(buf) = self.tx_buffer.take() {
-            for (k, v) in buf {
-                self.storage.insert(k, v);
+    /// Write the values buffered since `begin_tx`, all of them or, if one
+    /// breaks the limits, a schema or an index, none.
+    pub fn commit_tx(&mut self) -> Result<(), KvError> {
+        let Some(buf) = self.tx_buffer.take() else {
+            return Ok(());
+        };
+        let keys: Vec<&str> = buf.keys().map(String::as_str).collect();
+        let writes = buf.clone();
+        self.all_or_nothing(&keys, |store| {
+            for (key, value) in writes {
+                let value = Value::String(value);
+                store.check_write(&key, &value)?;
+                store.write_value(key, value);
             }
-        }
+            Ok(())
+        })
     }
     pub fn rollback_tx(&mut self) {
         self.tx_buffer = None;
@@ -1,7 +1,33 @@
+pub mod acl;
+pub mod auth;
+mod batch;
+mod catalog;
+pub mod config;
+pub mod error;
+pub mod exec;
+pub mod hash;
+pub mod index;
+pub mod json;
+mod keyspace;
 pub mod kv;
+pub mod limits;
+pub mod list;
+pub mod lock;
+pub mod planner;
+pub mod query;
+pub mod reply;
+pub mod resp;
+pub mod schema;
+pub mod search;
+pub mod set;
+pub mod stream;
 pub mod wal;
+pub mod zset;
 
 
+pub use error::KvError;
 pub use kv::KvStore;
+pub use limits::{Charset, LimitError, Limits};
+pub use storage::Value;
+pub use reply::Reply;
 pub use wal::Wal;
-
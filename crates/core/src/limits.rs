@@ -0,0 +1,125 @@
+//! Size and shape limits for keys, values and batches.
+//!
+//! A `KvStore` checks every write against its `Limits`, so the CLI, the
+//! server, batches and the Rust API all refuse the same inputs. WAL replay
+//! skips records whose key the limits forbid.
+
+use std::fmt;
+
+use crate::kv::MAX_STRING_LEN;
+
+/// Which characters a key may contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Anything but control characters and `|`.
+    Printable,
+    /// ASCII letters, digits and `: _ - . / @`.
+    Identifier,
+    /// Any character.
+    Any,
+}
+
+impl Charset {
+    pub fn allows(self, c: char) -> bool {
+        match self {
+            Charset::Printable => !c.is_control() && c != '|',
+            Charset::Identifier => c.is_ascii_alphanumeric() || ":_-./@".contains(c),
+            Charset::Any => true,
+        }
+    }
+}
+
+/// Which writes a store accepts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Longest key, in bytes.
+    pub max_key_len: usize,
+    /// Largest string value, document or collection element, in bytes.
+    pub max_value_len: usize,
+    pub allow_empty_values: bool,
+    pub charset: Charset,
+    /// Most commands in a `BATCH`, or pairs in an `MSET`.
+    pub max_batch: usize,
+    /// Most keys under one `namespace:` prefix; `None` for no limit.
+    pub max_namespace_keys: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_key_len: 255,
+            max_value_len: MAX_STRING_LEN,
+            allow_empty_values: false,
+            charset: Charset::Printable,
+            max_batch: 10_000,
+            max_namespace_keys: None,
+        }
+    }
+}
+
+/// Why a write was refused by the limits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitError {
+    EmptyKey,
+    KeyTooLong(usize),
+    KeyCharacter(char),
+    EmptyValue,
+    ValueTooLarge(usize),
+    BatchTooLarge(usize),
+    /// The namespace already holds the maximum number of keys.
+    NamespaceFull(String, usize),
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::EmptyKey => write!(f, "key must not be empty"),
+            LimitError::KeyTooLong(max) => write!(f, "key is longer than {} bytes", max),
+            LimitError::KeyCharacter(c) => write!(f, "key contains disallowed character {:?}", c),
+            LimitError::EmptyValue => write!(f, "value must not be empty"),
+            LimitError::ValueTooLarge(max) => write!(f, "value is larger than {} bytes", max),
+            LimitError::BatchTooLarge(max) => write!(f, "batch has more than {} entries", max),
+            LimitError::NamespaceFull(ns, max) => {
+                write!(f, "namespace '{}' already holds {} keys", ns, max)
+            }
+        }
+    }
+}
+
+impl Limits {
+    pub fn check_key(&self, key: &str) -> Result<(), LimitError> {
+        if key.is_empty() {
+            return Err(LimitError::EmptyKey);
+        }
+        if key.len() > self.max_key_len {
+            return Err(LimitError::KeyTooLong(self.max_key_len));
+        }
+        match key.chars().find(|&c| !self.charset.allows(c)) {
+            Some(c) => Err(LimitError::KeyCharacter(c)),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a string value, or a document `len` bytes long.
+    pub fn check_value(&self, len: usize) -> Result<(), LimitError> {
+        if len == 0 && !self.allow_empty_values {
+            return Err(LimitError::EmptyValue);
+        }
+        self.check_element(len)
+    }
+
+    /// Check one list element, hash field or value, set member or stream field.
+    pub fn check_element(&self, len: usize) -> Result<(), LimitError> {
+        if len > self.max_value_len {
+            return Err(LimitError::ValueTooLarge(self.max_value_len));
+        }
+        Ok(())
+    }
+
+    pub fn check_batch(&self, len: usize) -> Result<(), LimitError> {
+        if len > self.max_batch {
+            return Err(LimitError::BatchTooLarge(self.max_batch));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::{HashMap, VecDeque};
+
+use storage::{Storage, Value};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+
+pub use parser::ListEnd;
+
+impl KvStore {
+    /// Borrow the list at `key`, creating an empty one first if `create` is set.
+    fn list_mut(&mut self, key: &str, create: bool) -> Result<Option<&mut VecDeque<String>>, KvError> {
+        if create && self.lookup(key).is_none() {
+            self.write_value(key.to_string(), Value::List(VecDeque::new()));
+        }
+        match self.lookup_mut(key) {
+            None => Ok(None),
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    fn list(&mut self, key: &str) -> Result<Option<&VecDeque<String>>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Push `values` onto the head of the list, one after another. Returns the new length.
+    pub fn lpush(&mut self, key: &str, values: Vec<String>) -> Result<usize, KvError> {
+        self.push(key, values, ListEnd::Left)
+    }
+
+    /// Push `values` onto the tail of the list. Returns the new length.
+    pub fn rpush(&mut self, key: &str, values: Vec<String>) -> Result<usize, KvError> {
+        self.push(key, values, ListEnd::Right)
+    }
+
+    fn push(&mut self, key: &str, values: Vec<String>, end: ListEnd) -> Result<usize, KvError> {
+        if let Some(v) = self.lookup(key)
+            && !matches!(v, Value::List(_))
+        {
+            return Err(KvError::WrongType);
+        }
+        self.check_create(key, Value::List(VecDeque::new()))?;
+        self.check_elements(&values)?;
+        let mut record = vec![push_op(end), key];
+        record.extend(values.iter().map(String::as_str));
+        self.log(&record);
+
+        let list = self.list_mut(key, true)?.expect("list was just created");
+        push_all(list, values, end);
+        let len = list.len();
+        self.storage.flush();
+        Ok(len)
+    }
+
+    /// Pop up to `count` elements from the head of the list.
+    pub fn lpop(&mut self, key: &str, count: usize) -> Result<Vec<String>, KvError> {
+        self.pop(key, count, ListEnd::Left)
+    }
+
+    /// Pop up to `count` elements from the tail of the list.
+    pub fn rpop(&mut self, key: &str, count: usize) -> Result<Vec<String>, KvError> {
+        self.pop(key, count, ListEnd::Right)
+    }
+
+    fn pop(&mut self, key: &str, count: usize, end: ListEnd) -> Result<Vec<String>, KvError> {
+        let Some(list) = self.list_mut(key, false)? else {
+            return Ok(Vec::new());
+        };
+        let popped = pop_n(list, count, end);
+        let now_empty = list.is_empty();
+        if !popped.is_empty() {
+            self.log(&[pop_op(end), key, &popped.len().to_string()]);
+        }
+        if now_empty {
+            self.remove_value(key);
+        } else {
+            self.storage.flush();
+        }
+        Ok(popped)
+    }
+
+    /// Pop one element from the first non-empty list among `keys`.
+    /// This is the non-blocking half of `BLPOP`/`BRPOP`.
+    pub fn pop_first(&mut self, keys: &[String], end: ListEnd) -> Result<Option<(String, String)>, KvError> {
+        for key in keys {
+            if let Some(value) = self.pop(key, 1, end)?.pop() {
+                return Ok(Some((key.clone(), value)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Elements `start..=stop` of the list; negative indices count from the tail.
+    pub fn lrange(&mut self, key: &str, start: i64, stop: i64) -> Result<Vec<String>, KvError> {
+        let Some(list) = self.list(key)? else {
+            return Ok(Vec::new());
+        };
+        Ok(match normalize_range(start, stop, list.len()) {
+            Some((s, e)) => list.range(s..=e).cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
+    pub fn llen(&mut self, key: &str) -> Result<usize, KvError> {
+        Ok(self.list(key)?.map(|l| l.len()).unwrap_or(0))
+    }
+
+    pub fn lindex(&mut self, key: &str, index: i64) -> Result<Option<String>, KvError> {
+        let Some(list) = self.list(key)? else {
+            return Ok(None);
+        };
+        let index = if index < 0 { list.len() as i64 + index } else { index };
+        if index < 0 {
+            return Ok(None);
+        }
+        Ok(list.get(index as usize).cloned())
+    }
+
+    /// Keep only elements `start..=stop`; the key is removed if nothing remains.
+    pub fn ltrim(&mut self, key: &str, start: i64, stop: i64) -> Result<(), KvError> {
+        let Some(list) = self.list_mut(key, false)? else {
+            return Ok(());
+        };
+        trim(list, start, stop);
+        let now_empty = list.is_empty();
+        self.log(&["LTRIM", key, &start.to_string(), &stop.to_string()]);
+        if now_empty {
+            self.remove_value(key);
+        } else {
+            self.storage.flush();
+        }
+        Ok(())
+    }
+
+    /// Atomically pop from one end of `source` and push onto one end of `destination`.
+    pub fn lmove(
+        &mut self,
+        source: &str,
+        destination: &str,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<String>, KvError> {
+        self.list(source)?;
+        self.list(destination)?;
+        self.check_create(destination, Value::List(VecDeque::new()))?;
+        let Some(value) = self.pop(source, 1, from)?.pop() else {
+            return Ok(None);
+        };
+        self.push(destination, vec![value.clone()], to)?;
+        Ok(Some(value))
+    }
+}
+
+fn push_op(end: ListEnd) -> &'static str {
+    match end {
+        ListEnd::Left => "LPUSH",
+        ListEnd::Right => "RPUSH",
+    }
+}
+
+fn pop_op(end: ListEnd) -> &'static str {
+    match end {
+        ListEnd::Left => "LPOP",
+        ListEnd::Right => "RPOP",
+    }
+}
+
+fn push_all(list: &mut VecDeque<String>, values: Vec<String>, end: ListEnd) {
+    for value in values {
+        match end {
+            ListEnd::Left => list.push_front(value),
+            ListEnd::Right => list.push_back(value),
+        }
+    }
+}
+
+fn pop_n(list: &mut VecDeque<String>, count: usize, end: ListEnd) -> Vec<String> {
+    let mut popped = Vec::new();
+    while popped.len() < count {
+        let next = match end {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        };
+        match next {
+            Some(v) => popped.push(v),
+            None => break,
+        }
+    }
+    popped
+}
+
+fn trim(list: &mut VecDeque<String>, start: i64, stop: i64) {
+    match normalize_range(start, stop, list.len()) {
+        Some((s, e)) => {
+            list.truncate(e + 1);
+            list.drain(..s);
+        }
+        None => list.clear(),
+    }
+}
+
+/// Resolve Redis-style inclusive `start..=stop` indices against a length.
+/// Returns `None` when the range is empty.
+pub(crate) fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
+
+/// Re-apply a logged list operation during WAL replay.
+pub(crate) fn replay(store: &mut HashMap<String, Value>, op: &str, key: &str, args: &[String]) -> bool {
+    match op {
+        "LPUSH" | "RPUSH" => {
+            let end = if op == "LPUSH" { ListEnd::Left } else { ListEnd::Right };
+            let entry = store
+                .entry(key.to_string())
+                .or_insert_with(|| Value::List(VecDeque::new()));
+            if !matches!(entry, Value::List(_)) {
+                *entry = Value::List(VecDeque::new());
+            }
+            if let Value::List(list) = entry {
+                push_all(list, args.to_vec(), end);
+            }
+            true
+        }
+        "LPOP" | "RPOP" => {
+            let end = if op == "LPOP" { ListEnd::Left } else { ListEnd::Right };
+            let Some(count) = args.first().and_then(|n| n.parse().ok()) else {
+                return false;
+            };
+            edit_list(store, key, |list| {
+                pop_n(list, count, end);
+            });
+            true
+        }
+        "LTRIM" => {
+            let [start, stop] = args else {
+                return false;
+            };
+            let (Ok(start), Ok(stop)) = (start.parse(), stop.parse()) else {
+                return false;
+            };
+            edit_list(store, key, |list| trim(list, start, stop));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Apply `f` to the list at `key` and drop the key if the list ends up empty.
+fn edit_list(store: &mut HashMap<String, Value>, key: &str, f: impl FnOnce(&mut VecDeque<String>)) {
+    if let Some(Value::List(list)) = store.get_mut(key) {
+        f(list);
+        if list.is_empty() {
+            store.remove(key);
+        }
+    }
+}
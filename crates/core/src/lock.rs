@@ -0,0 +1,56 @@
+//! One process per data directory.
+//!
+//! Two processes appending to the same WAL would interleave their records, so
+//! the server and the CLI lock `.zyncdb.lock` in the data directory before
+//! opening the store. The lock is an OS file lock: a process that dies holding
+//! it releases it, and a file left behind by a crash does not block restarts.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+pub const LOCK_PATH: &str = ".zyncdb.lock";
+
+/// The lock on a data directory, holding the owner's process id.
+#[derive(Debug)]
+pub struct DataLock {
+    file: File,
+}
+
+impl DataLock {
+    /// Lock `dir`, creating it if needed. Fails if another process holds it.
+    pub fn acquire(dir: &Path) -> io::Result<DataLock> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_PATH);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if file.try_lock().is_err() {
+            let mut owner = String::new();
+            let _ = file.read_to_string(&mut owner);
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!(
+                    "{} is in use by another process (pid {})",
+                    dir.display(),
+                    owner.trim()
+                ),
+            ));
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(DataLock { file })
+    }
+
+    /// Unlock, once everything is on disk. The file is emptied but kept:
+    /// removing it would let a process that opened it before the removal
+    /// lock the orphaned file while another locks a new one in its place.
+    pub fn release(self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.unlock()
+    }
+}
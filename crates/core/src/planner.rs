@@ -0,0 +1,204 @@
+//! Choose how a query finds its candidate keys.
+//!
+//! The planner only narrows the set of keys to visit; the executor still
+//! evaluates the full `WHERE` clause on every candidate. In order of
+//! preference it uses an exact key, an index equality, an index range, a key
+//! prefix, and finally a scan of the namespace.
+
+use std::fmt;
+use std::ops::Bound;
+
+use parser::sql::{Column, CompareOp, Expr, Literal, Statement};
+
+use crate::index::{Index, IndexKey, Indexes};
+use crate::query::Datum;
+
+/// How candidate keys are found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// Visit every key in the namespace.
+    Scan,
+    /// Look up one key directly.
+    Key(String),
+    /// Visit keys starting with a prefix longer than the namespace's.
+    KeyPrefix(String),
+    /// Visit the keys an index holds between two bounds.
+    Index {
+        name: String,
+        lower: Bound<IndexKey>,
+        upper: Bound<IndexKey>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// Key prefix of the namespace, including the trailing `:`.
+    pub prefix: String,
+    pub access: Access,
+}
+
+/// Key prefix for the keys of namespace `ns`.
+pub fn namespace_prefix(ns: &str) -> String {
+    format!("{}:", ns)
+}
+
+pub(crate) fn plan(statement: &Statement, indexes: &Indexes) -> Plan {
+    let prefix = namespace_prefix(statement.namespace());
+    let conjuncts = statement.filter().map(conjuncts).unwrap_or_default();
+    let mut key_prefix = None;
+    for conjunct in &conjuncts {
+        match key_access(conjunct, &prefix) {
+            Some(found @ Access::Key(_)) => {
+                return Plan {
+                    prefix,
+                    access: found,
+                };
+            }
+            Some(found) if key_prefix.is_none() => key_prefix = Some(found),
+            _ => {}
+        }
+    }
+
+    let candidates = indexes
+        .iter()
+        .filter(|index| index.def.namespace == statement.namespace());
+    let mut range = None;
+    for index in candidates {
+        let (lower, upper) = index_bounds(index, &conjuncts);
+        let access = Access::Index {
+            name: index.def.name.clone(),
+            lower: lower.clone(),
+            upper: upper.clone(),
+        };
+        match (lower, upper) {
+            (Bound::Included(l), Bound::Included(u)) if l == u => return Plan { prefix, access },
+            (Bound::Unbounded, Bound::Unbounded) => {}
+            _ if range.is_none() => range = Some(access),
+            _ => {}
+        }
+    }
+
+    let access = range.or(key_prefix).unwrap_or(Access::Scan);
+    Plan { prefix, access }
+}
+
+/// Bounds on `index` implied by comparisons of its expression with literals.
+/// The first lower and the first upper bound found are used.
+fn index_bounds(index: &Index, conjuncts: &[&Expr]) -> (Bound<IndexKey>, Bound<IndexKey>) {
+    let mut lower = Bound::Unbounded;
+    let mut upper = Bound::Unbounded;
+    for conjunct in conjuncts {
+        let Expr::Compare(left, op, right) = conjunct else {
+            continue;
+        };
+        let (op, literal) = match (left.as_ref(), right.as_ref()) {
+            (target, Expr::Literal(literal)) if *target == index.def.target => (*op, literal),
+            (Expr::Literal(literal), target) if *target == index.def.target => (flip(*op), literal),
+            _ => continue,
+        };
+        let Some(key) = index.key_of(&Datum::from(literal)) else {
+            continue;
+        };
+        // A prefix only pins down equality; ordering of prefixes is not the
+        // ordering of the values.
+        if index.def.prefix.is_some() && op != CompareOp::Eq {
+            continue;
+        }
+        match op {
+            CompareOp::Eq => return (Bound::Included(key.clone()), Bound::Included(key)),
+            CompareOp::Gt if lower == Bound::Unbounded => lower = Bound::Excluded(key),
+            CompareOp::Ge if lower == Bound::Unbounded => lower = Bound::Included(key),
+            CompareOp::Lt if upper == Bound::Unbounded => upper = Bound::Excluded(key),
+            CompareOp::Le if upper == Bound::Unbounded => upper = Bound::Included(key),
+            _ => {}
+        }
+    }
+    (lower, upper)
+}
+
+/// The operator that keeps `a op b` true when its sides are swapped.
+fn flip(op: CompareOp) -> CompareOp {
+    match op {
+        CompareOp::Lt => CompareOp::Gt,
+        CompareOp::Le => CompareOp::Ge,
+        CompareOp::Gt => CompareOp::Lt,
+        CompareOp::Ge => CompareOp::Le,
+        other => other,
+    }
+}
+
+/// The terms of a chain of `AND`s.
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::And(left, right) => {
+            let mut out = conjuncts(left);
+            out.extend(conjuncts(right));
+            out
+        }
+        other => vec![other],
+    }
+}
+
+/// `key = 'literal'` or `key LIKE 'prefix%'` within the namespace.
+fn key_access(expr: &Expr, prefix: &str) -> Option<Access> {
+    let literal = |e: &Expr| match e {
+        Expr::Literal(Literal::String(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let is_key = |e: &Expr| *e == Expr::Column(Column::Key);
+    match expr {
+        Expr::Compare(left, CompareOp::Eq, right) => {
+            let key = if is_key(left) {
+                literal(right)
+            } else if is_key(right) {
+                literal(left)
+            } else {
+                None
+            }?;
+            Some(Access::Key(key))
+        }
+        Expr::Like {
+            expr,
+            pattern,
+            negated: false,
+        } if is_key(expr) => {
+            let pattern = literal(pattern)?;
+            let fixed = pattern.strip_suffix('%')?;
+            if fixed.contains(['%', '_']) || !fixed.starts_with(prefix) || fixed == prefix {
+                return None;
+            }
+            Some(Access::KeyPrefix(fixed.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// The access step, as the first line of `EXPLAIN`.
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.access {
+            Access::Scan => write!(f, "SCAN {}*", self.prefix),
+            Access::Key(key) => write!(f, "KEY {}", key),
+            Access::KeyPrefix(prefix) => write!(f, "PREFIX {}*", prefix),
+            Access::Index { name, lower, upper } => {
+                write!(f, "INDEX {}", name)?;
+                match (lower, upper) {
+                    (Bound::Included(l), Bound::Included(u)) if l == u => {
+                        return write!(f, " = {}", l);
+                    }
+                    _ => {}
+                }
+                match lower {
+                    Bound::Included(l) => write!(f, " >= {}", l)?,
+                    Bound::Excluded(l) => write!(f, " > {}", l)?,
+                    Bound::Unbounded => {}
+                }
+                match upper {
+                    Bound::Included(u) => write!(f, " <= {}", u),
+                    Bound::Excluded(u) => write!(f, " < {}", u),
+                    Bound::Unbounded => Ok(()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,443 @@
+//! Execute `SELECT`, `UPDATE` and `DELETE` statements over a namespace.
+//!
+//! Each key in the namespace is a row with a `key` and a `value` column.
+//! Expressions use SQL's three-valued logic: comparing with `NULL` yields
+//! `NULL`, and only rows whose filter is true are kept. Rows come out in key
+//! order unless `ORDER BY` says otherwise.
+
+use std::cmp::Ordering;
+
+use parser::SetCondition;
+use parser::sql::{Assignment, Column, CompareOp, Expr, Literal, Projection, Select, Statement};
+use serde_json::Value as JsonValue;
+use storage::Value;
+
+use crate::error::KvError;
+use crate::index::IndexKey;
+use crate::json::JsonPath;
+use crate::kv::KvStore;
+use crate::planner::{self, Access, Plan};
+use crate::reply::Reply;
+
+/// A value computed while evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Datum {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Datum {
+    fn from_json(value: &JsonValue) -> Datum {
+        match value {
+            JsonValue::Null => Datum::Null,
+            JsonValue::Bool(b) => Datum::Bool(*b),
+            JsonValue::Number(n) => match n.as_i64() {
+                Some(i) => Datum::Integer(i),
+                None => n.as_f64().map_or(Datum::Null, Datum::Float),
+            },
+            JsonValue::String(s) => Datum::Text(s.clone()),
+            other => Datum::Text(other.to_string()),
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Datum::Null => JsonValue::Null,
+            Datum::Bool(b) => JsonValue::Bool(*b),
+            Datum::Integer(n) => JsonValue::from(*n),
+            Datum::Float(f) => JsonValue::from(*f),
+            Datum::Text(s) => JsonValue::String(s.clone()),
+        }
+    }
+
+    /// The value's place in the order shared by comparisons, `ORDER BY` and
+    /// indexes. `NULL` has none.
+    pub(crate) fn index_key(&self) -> Option<IndexKey> {
+        let number = match self {
+            Datum::Null => return None,
+            Datum::Bool(b) => Some(*b as i64 as f64),
+            Datum::Integer(n) => Some(*n as f64),
+            Datum::Float(f) => Some(*f),
+            Datum::Text(s) => s.trim().parse::<f64>().ok(),
+        };
+        Some(match number.filter(|f| f.is_finite()) {
+            Some(f) => IndexKey::number(f),
+            None => IndexKey::Text(display(self)),
+        })
+    }
+
+    fn is_true(&self) -> bool {
+        match self {
+            Datum::Bool(b) => *b,
+            Datum::Integer(n) => *n != 0,
+            _ => false,
+        }
+    }
+
+    fn into_reply(self) -> Reply {
+        match self {
+            Datum::Null => Reply::Nil,
+            Datum::Bool(b) => Reply::Integer(b as i64),
+            Datum::Integer(n) => Reply::Integer(n),
+            Datum::Float(f) => Reply::Bulk(f.to_string()),
+            Datum::Text(s) => Reply::Bulk(s),
+        }
+    }
+
+    /// The string stored by `UPDATE ... SET value = ...`.
+    fn into_stored(self) -> Result<String, KvError> {
+        match self {
+            Datum::Null => Err(KvError::InvalidQueryValue("cannot store NULL".to_string())),
+            Datum::Bool(b) => Ok(b.to_string()),
+            Datum::Integer(n) => Ok(n.to_string()),
+            Datum::Float(f) => Ok(f.to_string()),
+            Datum::Text(s) => Ok(s),
+        }
+    }
+}
+
+/// One key of the namespace and its value.
+pub(crate) struct Row {
+    pub(crate) key: String,
+    pub(crate) value: Value,
+}
+
+impl Row {
+    fn column(&self, column: Column) -> Datum {
+        match (column, &self.value) {
+            (Column::Key, _) => Datum::Text(self.key.clone()),
+            (Column::Value, Value::String(s)) => Datum::Text(s.clone()),
+            (Column::Value, Value::Json(doc)) => Datum::Text(doc.to_text()),
+            (Column::Value, other) => Datum::Text(other.to_string()),
+        }
+    }
+
+    /// The JSON document behind the `value` column, if it holds one.
+    pub(crate) fn document(&self) -> Option<JsonValue> {
+        match &self.value {
+            Value::Json(doc) => Some(doc.decode()),
+            Value::String(s) => serde_json::from_str(s).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl From<&Literal> for Datum {
+    fn from(literal: &Literal) -> Datum {
+        match literal {
+            Literal::Null => Datum::Null,
+            Literal::Bool(b) => Datum::Bool(*b),
+            Literal::Integer(n) => Datum::Integer(*n),
+            Literal::Float(f) => Datum::Float(*f),
+            Literal::String(s) => Datum::Text(s.clone()),
+        }
+    }
+}
+
+pub(crate) fn eval(expr: &Expr, row: &Row) -> Result<Datum, KvError> {
+    Ok(match expr {
+        Expr::Column(column) => row.column(*column),
+        Expr::Literal(literal) => literal.into(),
+        Expr::JsonExtract(target, path) => {
+            let path = JsonPath::parse(path)?;
+            let doc = if **target == Expr::Column(Column::Value) {
+                row.document()
+            } else {
+                match eval(target, row)? {
+                    Datum::Text(s) => serde_json::from_str(&s).ok(),
+                    _ => None,
+                }
+            };
+            doc.as_ref()
+                .and_then(|doc| path.first(doc))
+                .map_or(Datum::Null, Datum::from_json)
+        }
+        Expr::Compare(left, op, right) => {
+            let ordering = compare(&eval(left, row)?, &eval(right, row)?);
+            match ordering {
+                None => Datum::Null,
+                Some(ordering) => Datum::Bool(match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::Ne => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::Ge => ordering != Ordering::Less,
+                }),
+            }
+        }
+        Expr::Like {
+            expr,
+            pattern,
+            negated,
+        } => match (eval(expr, row)?, eval(pattern, row)?) {
+            (Datum::Null, _) | (_, Datum::Null) => Datum::Null,
+            (value, pattern) => {
+                let text = display(&value);
+                let pattern = display(&pattern);
+                Datum::Bool(like(&text, &pattern) != *negated)
+            }
+        },
+        Expr::IsNull { expr, negated } => {
+            Datum::Bool((eval(expr, row)? == Datum::Null) != *negated)
+        }
+        Expr::And(left, right) => match (truth(&eval(left, row)?), truth(&eval(right, row)?)) {
+            (Some(false), _) | (_, Some(false)) => Datum::Bool(false),
+            (Some(true), Some(true)) => Datum::Bool(true),
+            _ => Datum::Null,
+        },
+        Expr::Or(left, right) => match (truth(&eval(left, row)?), truth(&eval(right, row)?)) {
+            (Some(true), _) | (_, Some(true)) => Datum::Bool(true),
+            (Some(false), Some(false)) => Datum::Bool(false),
+            _ => Datum::Null,
+        },
+        Expr::Not(inner) => match truth(&eval(inner, row)?) {
+            Some(b) => Datum::Bool(!b),
+            None => Datum::Null,
+        },
+    })
+}
+
+fn truth(datum: &Datum) -> Option<bool> {
+    match datum {
+        Datum::Null => None,
+        other => Some(other.is_true()),
+    }
+}
+
+pub(crate) fn display(datum: &Datum) -> String {
+    match datum {
+        Datum::Null => String::new(),
+        Datum::Bool(b) => b.to_string(),
+        Datum::Integer(n) => n.to_string(),
+        Datum::Float(f) => f.to_string(),
+        Datum::Text(s) => s.clone(),
+    }
+}
+
+/// Compare two values; `None` when either is `NULL`. Numbers compare
+/// numerically, including text that reads as a number, and sort before other
+/// text.
+fn compare(left: &Datum, right: &Datum) -> Option<Ordering> {
+    Some(left.index_key()?.cmp(&right.index_key()?))
+}
+
+/// Total order for `ORDER BY`: `NULL` first, then as `compare`.
+fn sort_order(left: &Datum, right: &Datum) -> Ordering {
+    left.index_key().cmp(&right.index_key())
+}
+
+/// SQL `LIKE`: `%` matches any run of characters and `_` exactly one.
+pub(crate) fn like(text: &str, pattern: &str) -> bool {
+    wildcard(text, pattern, '%', '_')
+}
+
+/// Key glob: `*` matches any run of characters and `?` exactly one.
+pub(crate) fn glob(text: &str, pattern: &str) -> bool {
+    wildcard(text, pattern, '*', '?')
+}
+
+fn wildcard(text: &str, pattern: &str, any: char, one: char) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // matches[j]: whether the text consumed so far matches pattern[..j].
+    let mut matches = vec![false; pattern.len() + 1];
+    matches[0] = true;
+    for j in 1..=pattern.len() {
+        matches[j] = matches[j - 1] && pattern[j - 1] == any;
+    }
+    for c in text {
+        let mut next = vec![false; pattern.len() + 1];
+        for j in 1..=pattern.len() {
+            let p = pattern[j - 1];
+            next[j] = if p == any {
+                next[j - 1] || matches[j]
+            } else if p == one {
+                matches[j - 1]
+            } else {
+                matches[j - 1] && p == c
+            };
+        }
+        matches = next;
+    }
+    matches[pattern.len()]
+}
+
+impl KvStore {
+    /// Live keys starting with `prefix`, in key order.
+    pub(crate) fn keys_with_prefix(&mut self, prefix: &str) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.retain(|key| self.lookup(key).is_some());
+        keys.sort();
+        keys
+    }
+
+    /// Rows in the namespace that `plan` selects and `filter` keeps.
+    fn matching_rows(&mut self, plan: &Plan, filter: Option<&Expr>) -> Result<Vec<Row>, KvError> {
+        let keys = match &plan.access {
+            Access::Scan => self.keys_with_prefix(&plan.prefix),
+            Access::KeyPrefix(prefix) => self.keys_with_prefix(prefix),
+            Access::Key(key) if key.starts_with(&plan.prefix) => vec![key.clone()],
+            Access::Key(_) => Vec::new(),
+            Access::Index { name, lower, upper } => match self.indexes.get(name) {
+                Some(index) => index.range(lower.as_ref(), upper.as_ref()),
+                None => self.keys_with_prefix(&plan.prefix),
+            },
+        };
+        let mut rows = Vec::new();
+        for key in keys {
+            let Some(value) = self.lookup(&key).cloned() else {
+                continue;
+            };
+            let row = Row { key, value };
+            if let Some(filter) = filter
+                && !eval(filter, &row)?.is_true()
+            {
+                continue;
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    /// Run a query statement.
+    pub fn query(&mut self, statement: &Statement) -> Result<Reply, KvError> {
+        let plan = planner::plan(statement, &self.indexes);
+        let rows = self.matching_rows(&plan, statement.filter())?;
+        match statement {
+            Statement::Select(select) => select_rows(select, rows),
+            Statement::Update(update) => {
+                let keys: Vec<&str> = rows.iter().map(|row| row.key.as_str()).collect();
+                self.all_or_nothing(&keys, |store| {
+                    for row in &rows {
+                        store.update_row(row, &update.assignments)?;
+                    }
+                    Ok(())
+                })?;
+                Ok(Reply::Integer(rows.len() as i64))
+            }
+            Statement::Delete(_) => {
+                for row in &rows {
+                    self.delete(&row.key);
+                }
+                Ok(Reply::Integer(rows.len() as i64))
+            }
+        }
+    }
+
+    fn update_row(&mut self, row: &Row, assignments: &[Assignment]) -> Result<(), KvError> {
+        if !matches!(row.value, Value::String(_) | Value::Json(_)) {
+            return Err(KvError::WrongType);
+        }
+        for assignment in assignments {
+            match assignment {
+                Assignment::Value(expr) => {
+                    let value = eval(expr, row)?.into_stored()?;
+                    self.insert(row.key.clone(), value)?;
+                }
+                Assignment::JsonPath(path, expr) => {
+                    let value = eval(expr, row)?.to_json().to_string();
+                    self.json_set(&row.key, path, &value, SetCondition::Always)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The steps `query` would take for `statement`, one per line.
+    pub fn explain(&self, statement: &Statement) -> Vec<String> {
+        let plan = planner::plan(statement, &self.indexes);
+        let mut steps = vec![plan.to_string()];
+        if statement.filter().is_some() {
+            steps.push("FILTER".to_string());
+        }
+        match statement {
+            Statement::Select(select) => {
+                if !select.order_by.is_empty() {
+                    steps.push("SORT".to_string());
+                }
+                if let Some(limit) = select.limit {
+                    steps.push(format!("LIMIT {} OFFSET {}", limit, select.offset));
+                }
+                if select.projection == Projection::Count {
+                    steps.push("COUNT".to_string());
+                }
+            }
+            Statement::Update(_) => steps.push("UPDATE".to_string()),
+            Statement::Delete(_) => steps.push("DELETE".to_string()),
+        }
+        steps
+    }
+}
+
+fn select_rows(select: &Select, mut rows: Vec<Row>) -> Result<Reply, KvError> {
+    let columns = match &select.projection {
+        // The count covers every matching row, as in SQL; LIMIT applies to
+        // the single result row.
+        Projection::Count => return Ok(Reply::Integer(rows.len() as i64)),
+        Projection::All => None,
+        Projection::Columns(columns) => Some(columns),
+    };
+    if !select.order_by.is_empty() {
+        let mut keyed = Vec::with_capacity(rows.len());
+        for row in rows {
+            let sort_key = select
+                .order_by
+                .iter()
+                .map(|order| eval(&order.expr, &row))
+                .collect::<Result<Vec<_>, _>>()?;
+            keyed.push((sort_key, row));
+        }
+        keyed.sort_by(|(a, _), (b, _)| {
+            a.iter()
+                .zip(b)
+                .zip(&select.order_by)
+                .map(|((x, y), order)| {
+                    let ordering = sort_order(x, y);
+                    if order.descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|ordering| *ordering != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+        rows = keyed.into_iter().map(|(_, row)| row).collect();
+    }
+    let rows = rows
+        .into_iter()
+        .skip(select.offset)
+        .take(select.limit.unwrap_or(usize::MAX));
+
+    match columns {
+        None => Ok(Reply::Array(
+            rows.map(|row| {
+                Reply::Array(vec![
+                    Reply::Bulk(row.key.clone()),
+                    row.column(Column::Value).into_reply(),
+                ])
+            })
+            .collect(),
+        )),
+        Some(columns) => {
+            let mut out = Vec::new();
+            for row in rows {
+                let values = columns
+                    .iter()
+                    .map(|expr| eval(expr, &row).map(Datum::into_reply))
+                    .collect::<Result<Vec<_>, _>>()?;
+                out.push(Reply::Array(values));
+            }
+            Ok(Reply::Array(out))
+        }
+    }
+}
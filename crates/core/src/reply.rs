@@ -0,0 +1,67 @@
+use std::fmt;
+
+/// The result of executing a command, independent of how a front end prints it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Ok,
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Nil,
+    Array(Vec<Reply>),
+    /// Field/value pairs; RESP3 sends a map, RESP2 and text a flat array.
+    Map(Vec<(Reply, Reply)>),
+}
+
+impl Reply {
+    pub fn error(e: impl fmt::Display) -> Reply {
+        Reply::Error(e.to_string())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Reply::Error(_))
+    }
+}
+
+impl From<Option<String>> for Reply {
+    fn from(value: Option<String>) -> Self {
+        match value {
+            Some(v) => Reply::Bulk(v),
+            None => Reply::Nil,
+        }
+    }
+}
+
+/// Renders the reply in the plain-text format used by the CLI and the line protocol.
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Ok => write!(f, "ok"),
+            Reply::Status(s) | Reply::Bulk(s) => write!(f, "{}", s),
+            Reply::Error(e) => write!(f, "Error: {}", e),
+            Reply::Integer(n) => write!(f, "{}", n),
+            Reply::Nil => write!(f, "(key not found)"),
+            Reply::Array(items) if items.is_empty() => write!(f, "(empty)"),
+            Reply::Map(pairs) if pairs.is_empty() => write!(f, "(empty)"),
+            Reply::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Reply::Map(pairs) => {
+                for (i, (field, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}\n{}", field, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
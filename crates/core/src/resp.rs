@@ -0,0 +1,288 @@
+//! RESP2/RESP3 framing, for Redis clients.
+//!
+//! A request is either a RESP array of bulk strings, as client libraries send
+//! it, or an inline line of the command language, as typed at a terminal; its
+//! first byte tells them apart. Replies go out in the protocol the connection
+//! has chosen: the plain-text lines of `Reply`'s `Display` until the client
+//! sends a RESP array, RESP2 from then on, and RESP3 after `HELLO 3`.
+
+use std::fmt;
+
+use crate::reply::Reply;
+
+/// Longest inline request or RESP header line, in bytes.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+/// Most arguments in one request.
+pub const MAX_ARGS: usize = 1024 * 1024;
+/// Longest bulk string, in bytes.
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// How replies are framed on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// One line per reply line, as the CLI prints them.
+    Text,
+    Resp2,
+    Resp3,
+}
+
+/// One request read from a client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// A line of the command language, without its line ending.
+    Inline(String),
+    /// The arguments of a RESP array.
+    Args(Vec<String>),
+}
+
+/// Input that cannot be framed. The connection cannot recover from it, so
+/// the server replies once and closes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    LineTooLong,
+    /// A header had a missing, malformed or out-of-range length.
+    InvalidLength(&'static str),
+    /// Expected the given byte or `\r\n` where something else was.
+    Expected(&'static str),
+    InvalidInteger,
+    InvalidUtf8,
+    UnknownType(u8),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Protocol error: ")?;
+        match self {
+            ProtocolError::LineTooLong => write!(f, "line longer than {} bytes", MAX_LINE_LEN),
+            ProtocolError::InvalidLength(what) => write!(f, "invalid {} length", what),
+            ProtocolError::Expected(what) => write!(f, "expected {}", what),
+            ProtocolError::InvalidInteger => write!(f, "invalid integer"),
+            ProtocolError::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            ProtocolError::UnknownType(b) => write!(f, "unknown type byte {:?}", char::from(*b)),
+        }
+    }
+}
+
+/// The first complete request in `buf` and how many bytes it took, or `None`
+/// if more input is needed.
+pub fn decode_request(buf: &[u8]) -> Result<Option<(Request, usize)>, ProtocolError> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'*') => decode_args(buf),
+        Some(_) => {
+            let Some((content, next)) = line(buf, 0)? else {
+                return Ok(None);
+            };
+            let text = std::str::from_utf8(content).map_err(|_| ProtocolError::InvalidUtf8)?;
+            Ok(Some((Request::Inline(text.to_string()), next)))
+        }
+    }
+}
+
+fn decode_args(buf: &[u8]) -> Result<Option<(Request, usize)>, ProtocolError> {
+    let Some((header, mut pos)) = line(buf, 0)? else {
+        return Ok(None);
+    };
+    let count = length(&header[1..], "multibulk")?;
+    if count > MAX_ARGS as i64 {
+        return Err(ProtocolError::InvalidLength("multibulk"));
+    }
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let Some((header, next)) = line(buf, pos)? else {
+            return Ok(None);
+        };
+        if header.first() != Some(&b'$') {
+            return Err(ProtocolError::Expected("'$'"));
+        }
+        let Some((arg, next)) = bulk(buf, next, length(&header[1..], "bulk")?)? else {
+            return Ok(None);
+        };
+        args.push(arg);
+        pos = next;
+    }
+    Ok(Some((Request::Args(args), pos)))
+}
+
+/// The line starting at `start`, without its line ending, and the offset
+/// after it. A bare `\n` ends a line too, for hand-typed input.
+fn line(buf: &[u8], start: usize) -> Result<Option<(&[u8], usize)>, ProtocolError> {
+    match buf[start..].iter().position(|&b| b == b'\n') {
+        Some(len) if len > MAX_LINE_LEN => Err(ProtocolError::LineTooLong),
+        Some(len) => {
+            let content = &buf[start..start + len];
+            Ok(Some((
+                content.strip_suffix(b"\r").unwrap_or(content),
+                start + len + 1,
+            )))
+        }
+        None if buf.len() - start > MAX_LINE_LEN => Err(ProtocolError::LineTooLong),
+        None => Ok(None),
+    }
+}
+
+/// The length in a header; `-1` marks a null bulk string or array.
+fn length(digits: &[u8], what: &'static str) -> Result<i64, ProtocolError> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|&n| n >= -1)
+        .ok_or(ProtocolError::InvalidLength(what))
+}
+
+/// The `len` bytes of a bulk string starting at `start` and the offset after
+/// its closing `\r\n`.
+fn bulk(buf: &[u8], start: usize, len: i64) -> Result<Option<(String, usize)>, ProtocolError> {
+    if !(0..=MAX_BULK_LEN as i64).contains(&len) {
+        return Err(ProtocolError::InvalidLength("bulk"));
+    }
+    let end = start + len as usize;
+    if buf.len() < end + 2 {
+        return Ok(None);
+    }
+    if &buf[end..end + 2] != b"\r\n" {
+        return Err(ProtocolError::Expected("'\\r\\n'"));
+    }
+    let text =
+        String::from_utf8(buf[start..end].to_vec()).map_err(|_| ProtocolError::InvalidUtf8)?;
+    Ok(Some((text, end + 2)))
+}
+
+/// Append `reply` to `out`, framed for `protocol`.
+pub fn encode(reply: &Reply, protocol: Protocol, out: &mut Vec<u8>) {
+    match protocol {
+        Protocol::Text => out.extend_from_slice(format!("{}\n", reply).as_bytes()),
+        Protocol::Resp2 => encode_resp(reply, false, out),
+        Protocol::Resp3 => encode_resp(reply, true, out),
+    }
+}
+
+fn encode_resp(reply: &Reply, resp3: bool, out: &mut Vec<u8>) {
+    match reply {
+        Reply::Ok => out.extend_from_slice(b"+OK\r\n"),
+        Reply::Status(s) => header(out, '+', one_line(s)),
+        Reply::Error(e) => header(out, '-', one_line(&error_code(e))),
+        Reply::Integer(n) => header(out, ':', n),
+        Reply::Bulk(s) => {
+            header(out, '$', s.len());
+            out.extend_from_slice(s.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+        Reply::Nil if resp3 => out.extend_from_slice(b"_\r\n"),
+        Reply::Nil => out.extend_from_slice(b"$-1\r\n"),
+        Reply::Array(items) => {
+            header(out, '*', items.len());
+            for item in items {
+                encode_resp(item, resp3, out);
+            }
+        }
+        Reply::Map(pairs) => {
+            if resp3 {
+                header(out, '%', pairs.len());
+            } else {
+                header(out, '*', pairs.len() * 2);
+            }
+            for (field, value) in pairs {
+                encode_resp(field, resp3, out);
+                encode_resp(value, resp3, out);
+            }
+        }
+    }
+}
+
+fn header(out: &mut Vec<u8>, kind: char, content: impl fmt::Display) {
+    out.extend_from_slice(format!("{}{}\r\n", kind, content).as_bytes());
+}
+
+/// Simple strings and errors cannot hold line breaks.
+fn one_line(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+/// Clients read the first word of an error as its code, so messages that do
+/// not start with one (`WRONGTYPE`, `EXECABORT`) get the generic `ERR`.
+fn error_code(message: &str) -> String {
+    let word = message.split(' ').next().unwrap_or_default();
+    if word.len() > 1 && word.bytes().all(|b| b.is_ascii_uppercase()) {
+        message.to_string()
+    } else {
+        format!("ERR {}", message)
+    }
+}
+
+/// The first complete RESP2 or RESP3 reply in `buf` and how many bytes it
+/// took, or `None` if more input is needed. The inverse of `encode`, for
+/// clients; `ERR ` is taken off errors again.
+pub fn decode_reply(buf: &[u8]) -> Result<Option<(Reply, usize)>, ProtocolError> {
+    reply_at(buf, 0)
+}
+
+fn reply_at(buf: &[u8], start: usize) -> Result<Option<(Reply, usize)>, ProtocolError> {
+    if start >= buf.len() {
+        return Ok(None);
+    }
+    let Some((line, next)) = line(buf, start)? else {
+        return Ok(None);
+    };
+    let Some((&kind, rest)) = line.split_first() else {
+        return Err(ProtocolError::Expected("a type byte"));
+    };
+    let text = || {
+        std::str::from_utf8(rest)
+            .map(str::to_string)
+            .map_err(|_| ProtocolError::InvalidUtf8)
+    };
+    let reply = match kind {
+        b'+' if rest == b"OK" => Reply::Ok,
+        b'+' => Reply::Status(text()?),
+        b'-' => {
+            let message = text()?;
+            Reply::Error(message.strip_prefix("ERR ").unwrap_or(&message).to_string())
+        }
+        b':' => Reply::Integer(text()?.parse().map_err(|_| ProtocolError::InvalidInteger)?),
+        b'_' => Reply::Nil,
+        b'#' => Reply::Integer(i64::from(rest == b"t")),
+        b',' => Reply::Bulk(text()?),
+        b'$' | b'=' => {
+            let len = length(rest, "bulk")?;
+            if len < 0 {
+                return Ok(Some((Reply::Nil, next)));
+            }
+            let Some((s, next)) = bulk(buf, next, len)? else {
+                return Ok(None);
+            };
+            // Verbatim strings start with a three-letter format and `:`.
+            let s = if kind == b'=' {
+                s.get(4..).unwrap_or_default().to_string()
+            } else {
+                s
+            };
+            return Ok(Some((Reply::Bulk(s), next)));
+        }
+        b'*' | b'~' | b'>' | b'%' => {
+            let len = length(rest, "multibulk")?;
+            if len < 0 {
+                return Ok(Some((Reply::Nil, next)));
+            }
+            let mut items = Vec::new();
+            let mut pos = next;
+            let count = if kind == b'%' { len * 2 } else { len };
+            for _ in 0..count {
+                let Some((item, next)) = reply_at(buf, pos)? else {
+                    return Ok(None);
+                };
+                items.push(item);
+                pos = next;
+            }
+            let reply = if kind == b'%' {
+                let mut items = items.into_iter();
+                Reply::Map(std::iter::from_fn(|| Some((items.next()?, items.next()?))).collect())
+            } else {
+                Reply::Array(items)
+            };
+            return Ok(Some((reply, pos)));
+        }
+        other => return Err(ProtocolError::UnknownType(other)),
+    };
+    Ok(Some((reply, next)))
+}
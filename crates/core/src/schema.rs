@@ -0,0 +1,485 @@
+//! Namespace schemas.
+//!
+//! A schema constrains the keys under `namespace:`: a glob for the rest of the
+//! key, the value type, key and value sizes, members every JSON document must
+//! have, and a JSON Schema for documents. `check_write` applies it to every
+//! write, so all front ends get the same answer. Lists, hashes, sets, sorted
+//! sets and streams are edited in place, so they are checked when a command
+//! would create them; value sizes and document rules apply to strings and JSON.
+//!
+//! Every `SCHEMA SET` makes a new version. A migration (`UPDATE` or `DELETE`)
+//! may run first, then every key in the namespace is checked against the new
+//! version; if one fails, the migration is undone and the old version stays.
+//! Definitions live in the catalog next to the index definitions.
+
+use std::collections::BTreeMap;
+
+use parser::SchemaDef;
+use serde_json::Value as JsonValue;
+use storage::Value;
+
+use crate::catalog::catalog_error;
+use crate::error::KvError;
+use crate::kv::KvStore;
+use crate::planner::namespace_prefix;
+use crate::query::glob;
+
+/// Keywords that describe a schema without constraining documents.
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+const JSON_TYPES: &[&str] = &[
+    "object", "array", "string", "number", "integer", "boolean", "null",
+];
+
+pub(crate) struct Schema {
+    def: SchemaDef,
+    version: u32,
+    prefix: String,
+    json_schema: Option<JsonValue>,
+}
+
+impl Schema {
+    fn new(mut def: SchemaDef, version: u32) -> Result<Schema, KvError> {
+        def.migration = None;
+        def.version = Some(version);
+        let json_schema = match &def.json_schema {
+            Some(text) => {
+                let schema: JsonValue = serde_json::from_str(text)
+                    .map_err(|e| KvError::InvalidSchema(e.to_string()))?;
+                check_json_schema(&schema).map_err(KvError::InvalidSchema)?;
+                Some(schema)
+            }
+            None => None,
+        };
+        Ok(Schema {
+            prefix: namespace_prefix(&def.namespace),
+            def,
+            version,
+            json_schema,
+        })
+    }
+
+    /// Why `value` may not be stored at `key`, if it may not.
+    fn violation(&self, key: &str, value: &Value) -> Option<String> {
+        let def = &self.def;
+        let name = &key[self.prefix.len()..];
+        if let Some(pattern) = &def.key_pattern
+            && !glob(name, pattern)
+        {
+            return Some(format!(
+                "key '{}' does not match '{}{}'",
+                key, self.prefix, pattern
+            ));
+        }
+        if let Some(max) = def.max_key_len
+            && key.len() > max
+        {
+            return Some(format!("key '{}' is longer than {} bytes", key, max));
+        }
+        if let Some(value_type) = &def.value_type
+            && value.type_name() != value_type
+        {
+            return Some(format!(
+                "'{}' must hold a {} value, not {}",
+                key,
+                value_type,
+                value.type_name()
+            ));
+        }
+        let (len, doc) = match value {
+            Value::String(s) => (s.len(), None),
+            Value::Json(doc) => (doc.to_text().len(), Some(doc.decode())),
+            _ => return None,
+        };
+        if let Some(max) = def.max_value_len
+            && len > max
+        {
+            return Some(format!("value of '{}' is longer than {} bytes", key, max));
+        }
+        let doc = doc?;
+        if let Some(field) = def.required.iter().find(|f| doc.get(f.as_str()).is_none()) {
+            return Some(format!("'{}' is missing required member '{}'", key, field));
+        }
+        let reason = validate(self.json_schema.as_ref()?, &doc, "$").err()?;
+        Some(format!("'{}' {}", key, reason))
+    }
+
+    fn check(&self, key: &str, value: &Value) -> Result<(), KvError> {
+        match self.violation(key, value) {
+            Some(reason) => Err(KvError::SchemaViolation(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// `SCHEMA GET` fields, in a fixed order.
+    fn describe(&self) -> Vec<(String, String)> {
+        let def = &self.def;
+        let mut fields = vec![("version".to_string(), self.version.to_string())];
+        let mut add = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                fields.push((name.to_string(), value));
+            }
+        };
+        add("keys", def.key_pattern.clone());
+        add("type", def.value_type.clone());
+        add("maxkeylen", def.max_key_len.map(|n| n.to_string()));
+        add("maxsize", def.max_value_len.map(|n| n.to_string()));
+        add(
+            "required",
+            Some(def.required.join(",")).filter(|r| !r.is_empty()),
+        );
+        add("jsonschema", def.json_schema.clone());
+        fields
+    }
+
+    /// The catalog record: `SCHEMA|ns|version|keys|type|maxkeylen|maxsize|jsonschema|required...`,
+    /// with empty fields for unset options.
+    fn record(&self) -> Vec<String> {
+        let def = &self.def;
+        let number = |n: Option<usize>| n.map(|n| n.to_string()).unwrap_or_default();
+        let mut record = vec![
+            "SCHEMA".to_string(),
+            def.namespace.clone(),
+            self.version.to_string(),
+            def.key_pattern.clone().unwrap_or_default(),
+            def.value_type.clone().unwrap_or_default(),
+            number(def.max_key_len),
+            number(def.max_value_len),
+            def.json_schema.clone().unwrap_or_default(),
+        ];
+        record.extend(def.required.iter().cloned());
+        record
+    }
+
+    fn from_record(fields: &[String]) -> Option<Schema> {
+        let [
+            tag,
+            namespace,
+            version,
+            keys,
+            value_type,
+            max_key,
+            max_value,
+            json,
+            required @ ..,
+        ] = fields
+        else {
+            return None;
+        };
+        if tag != "SCHEMA" {
+            return None;
+        }
+        let text = |s: &String| Some(s.clone()).filter(|s| !s.is_empty());
+        let number = |s: &String| {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                s.parse().map(Some)
+            }
+        };
+        let def = SchemaDef {
+            namespace: namespace.clone(),
+            key_pattern: text(keys),
+            value_type: text(value_type),
+            max_key_len: number(max_key).ok()?,
+            max_value_len: number(max_value).ok()?,
+            required: required.to_vec(),
+            json_schema: text(json),
+            ..SchemaDef::default()
+        };
+        Schema::new(def, version.parse().ok()?).ok()
+    }
+}
+
+/// The schema of every namespace that has one.
+#[derive(Default)]
+pub(crate) struct Schemas {
+    by_namespace: BTreeMap<String, Schema>,
+}
+
+impl Schemas {
+    /// Fail if the schema of `key`'s namespace forbids storing `value` there.
+    pub(crate) fn check(&self, key: &str, value: &Value) -> Result<(), KvError> {
+        let Some((namespace, _)) = key.split_once(':') else {
+            return Ok(());
+        };
+        match self.by_namespace.get(namespace) {
+            Some(schema) => schema.check(key, value),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn records(&self) -> Vec<Vec<String>> {
+        self.by_namespace.values().map(Schema::record).collect()
+    }
+}
+
+impl KvStore {
+    /// Make `def` the next version of its namespace's schema, after running
+    /// its migration. Returns the new version. If any key in the namespace
+    /// breaks the new version, the migration is undone and the old version
+    /// stays in force.
+    pub fn set_schema(&mut self, mut def: SchemaDef) -> Result<u32, KvError> {
+        let migration = def.migration.take();
+        let namespace = def.namespace.clone();
+        let current = self
+            .schemas
+            .by_namespace
+            .get(&namespace)
+            .map_or(0, |s| s.version);
+        if def.version.is_some_and(|v| v != current + 1) {
+            return Err(KvError::SchemaVersion(current));
+        }
+        let schema = Schema::new(def, current + 1)?;
+
+        let keys = self.keys_with_prefix(&schema.prefix);
+        let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+        // The migration writes data shaped for the new version, so the old one
+        // must not reject it.
+        let old = self.schemas.by_namespace.remove(&namespace);
+        let result = self.all_or_nothing(&key_refs, |store| {
+            if let Some(statement) = &migration {
+                store.query(statement)?;
+            }
+            for key in &keys {
+                if let Some(value) = store.lookup(key) {
+                    schema.check(key, value)?;
+                }
+            }
+            // Saved inside so a failed write undoes the migration too.
+            let version = schema.version;
+            store.schemas.by_namespace.insert(namespace.clone(), schema);
+            store.save_catalog().map_err(catalog_error)?;
+            Ok(version)
+        });
+        if result.is_err() {
+            match old {
+                Some(old) => self.schemas.by_namespace.insert(namespace, old),
+                None => self.schemas.by_namespace.remove(&namespace),
+            };
+        }
+        result
+    }
+
+    /// The current schema of `namespace` as field/value pairs.
+    pub fn schema(&self, namespace: &str) -> Option<Vec<(String, String)>> {
+        self.schemas
+            .by_namespace
+            .get(namespace)
+            .map(Schema::describe)
+    }
+
+    pub fn drop_schema(&mut self, namespace: &str) -> Result<(), KvError> {
+        let Some(schema) = self.schemas.by_namespace.remove(namespace) else {
+            return Err(KvError::NoSuchSchema(namespace.to_string()));
+        };
+        self.save_catalog().map_err(|e| {
+            self.schemas
+                .by_namespace
+                .insert(namespace.to_string(), schema);
+            catalog_error(e)
+        })
+    }
+
+    /// Load schema definitions from the catalog.
+    pub(crate) fn load_schemas(&mut self, records: &[Vec<String>]) {
+        for schema in records.iter().filter_map(|r| Schema::from_record(r)) {
+            self.schemas
+                .by_namespace
+                .insert(schema.def.namespace.clone(), schema);
+        }
+    }
+}
+
+/// Reject keywords the validator does not implement, so a schema never
+/// checks less than it appears to.
+fn check_json_schema(schema: &JsonValue) -> Result<(), String> {
+    let map = match schema {
+        JsonValue::Bool(_) => return Ok(()),
+        JsonValue::Object(map) => map,
+        _ => return Err("a schema must be an object or a boolean".to_string()),
+    };
+    for (keyword, arg) in map {
+        let ok = match keyword.as_str() {
+            "type" => match arg {
+                JsonValue::String(t) => JSON_TYPES.contains(&t.as_str()),
+                JsonValue::Array(types) => types
+                    .iter()
+                    .all(|t| t.as_str().is_some_and(|t| JSON_TYPES.contains(&t))),
+                _ => false,
+            },
+            "enum" => arg.is_array(),
+            "const" => true,
+            "properties" => match arg.as_object() {
+                Some(properties) => {
+                    properties.values().try_for_each(check_json_schema)?;
+                    true
+                }
+                None => false,
+            },
+            "required" => arg
+                .as_array()
+                .is_some_and(|fields| fields.iter().all(JsonValue::is_string)),
+            "additionalProperties" | "items" | "not" => {
+                check_json_schema(arg)?;
+                true
+            }
+            "allOf" | "anyOf" | "oneOf" => match arg.as_array() {
+                Some(schemas) if !schemas.is_empty() => {
+                    schemas.iter().try_for_each(check_json_schema)?;
+                    true
+                }
+                _ => false,
+            },
+            "minItems" | "maxItems" | "minLength" | "maxLength" => arg.is_u64(),
+            "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum" => arg.is_number(),
+            k if ANNOTATIONS.contains(&k) => true,
+            _ => return Err(format!("unsupported keyword '{}'", keyword)),
+        };
+        if !ok {
+            return Err(format!("invalid value for '{}'", keyword));
+        }
+    }
+    Ok(())
+}
+
+fn has_type(doc: &JsonValue, json_type: &str) -> bool {
+    match json_type {
+        "object" => doc.is_object(),
+        "array" => doc.is_array(),
+        "string" => doc.is_string(),
+        "number" => doc.is_number(),
+        "integer" => doc.is_i64() || doc.is_u64() || doc.as_f64().is_some_and(|f| f.fract() == 0.0),
+        "boolean" => doc.is_boolean(),
+        _ => doc.is_null(),
+    }
+}
+
+/// Check `doc` against a schema that passed `check_json_schema`. `path` is
+/// where `doc` sits in the whole document, for the error message.
+fn validate(schema: &JsonValue, doc: &JsonValue, path: &str) -> Result<(), String> {
+    let fail = |reason: String| Err(format!("at {}: {}", path, reason));
+    let map = match schema {
+        JsonValue::Bool(true) => return Ok(()),
+        JsonValue::Bool(false) => return fail("no value is allowed".to_string()),
+        JsonValue::Object(map) => map,
+        _ => return Ok(()),
+    };
+    for (keyword, arg) in map {
+        match keyword.as_str() {
+            "type" => {
+                let types: Vec<&str> = match arg {
+                    JsonValue::Array(types) => types.iter().filter_map(JsonValue::as_str).collect(),
+                    _ => arg.as_str().into_iter().collect(),
+                };
+                if !types.iter().any(|t| has_type(doc, t)) {
+                    return fail(format!("expected {}", types.join(" or ")));
+                }
+            }
+            "enum" if !arg.as_array().is_some_and(|values| values.contains(doc)) => {
+                return fail(format!("must be one of {}", arg));
+            }
+            "const" if arg != doc => return fail(format!("must be {}", arg)),
+            "required" => {
+                if let (Some(fields), Some(object)) = (arg.as_array(), doc.as_object())
+                    && let Some(field) = fields
+                        .iter()
+                        .filter_map(JsonValue::as_str)
+                        .find(|f| !object.contains_key(*f))
+                {
+                    return fail(format!("missing required member '{}'", field));
+                }
+            }
+            "properties" => {
+                if let (Some(properties), Some(object)) = (arg.as_object(), doc.as_object()) {
+                    for (name, schema) in properties {
+                        if let Some(member) = object.get(name) {
+                            validate(schema, member, &format!("{}.{}", path, name))?;
+                        }
+                    }
+                }
+            }
+            "additionalProperties" => {
+                if let Some(object) = doc.as_object() {
+                    let declared = map.get("properties").and_then(JsonValue::as_object);
+                    for (name, member) in object {
+                        if !declared.is_some_and(|d| d.contains_key(name)) {
+                            validate(arg, member, &format!("{}.{}", path, name))?;
+                        }
+                    }
+                }
+            }
+            "items" => {
+                if let Some(items) = doc.as_array() {
+                    for (i, item) in items.iter().enumerate() {
+                        validate(arg, item, &format!("{}[{}]", path, i))?;
+                    }
+                }
+            }
+            "minItems" | "maxItems" | "minLength" | "maxLength" => {
+                let len = match doc {
+                    JsonValue::Array(items) if keyword.ends_with("Items") => items.len(),
+                    JsonValue::String(s) if keyword.ends_with("Length") => s.chars().count(),
+                    _ => continue,
+                };
+                let bound = arg.as_u64().unwrap_or_default() as usize;
+                if keyword.starts_with("min") && len < bound {
+                    return fail(format!("length must be at least {}", bound));
+                }
+                if keyword.starts_with("max") && len > bound {
+                    return fail(format!("length must be at most {}", bound));
+                }
+            }
+            "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum" => {
+                let (Some(n), Some(bound)) = (doc.as_f64(), arg.as_f64()) else {
+                    continue;
+                };
+                let ok = match keyword.as_str() {
+                    "minimum" => n >= bound,
+                    "maximum" => n <= bound,
+                    "exclusiveMinimum" => n > bound,
+                    _ => n < bound,
+                };
+                if !ok {
+                    return fail(format!("must satisfy {} {}", keyword, bound));
+                }
+            }
+            "allOf" => {
+                for schema in arg.as_array().into_iter().flatten() {
+                    validate(schema, doc, path)?;
+                }
+            }
+            "anyOf" | "oneOf" => {
+                let matched = arg
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|schema| validate(schema, doc, path).is_ok())
+                    .count();
+                if matched == 0 || (keyword == "oneOf" && matched > 1) {
+                    return fail(format!(
+                        "must match {} of the listed schemas",
+                        if keyword == "oneOf" {
+                            "exactly one"
+                        } else {
+                            "at least one"
+                        }
+                    ));
+                }
+            }
+            "not" if validate(arg, doc, path).is_ok() => {
+                return fail("must not match the 'not' schema".to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
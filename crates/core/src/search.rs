@@ -0,0 +1,383 @@
+//! Full-text indexes.
+//!
+//! An index covers the keys of one namespace and reads text from the whole
+//! value (`value`) or from JSONPath fields of documents; string values holding
+//! JSON count as documents, as in queries. Text is split on anything that is
+//! not a letter or digit, lowercased, stripped of stop words and stemmed.
+//! `FT.SEARCH` returns the keys containing every query term, ranked by BM25.
+//!
+//! Postings change in the same call that writes the key. Definitions live in
+//! the catalog; postings are rebuilt from the data when the store opens.
+
+use std::collections::{BTreeMap, HashMap};
+
+use parser::TextIndexDef;
+use serde_json::Value as JsonValue;
+use storage::Value;
+
+use crate::catalog::catalog_error;
+use crate::error::KvError;
+use crate::json::JsonPath;
+use crate::kv::KvStore;
+use crate::planner::namespace_prefix;
+use crate::query::Row;
+
+/// BM25 term-frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document-length normalisation.
+const B: f64 = 0.75;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// Terms of `text`, in order, as they are indexed and searched.
+pub(crate) fn analyze(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .map(|word| stem(&word))
+        .collect()
+}
+
+/// A light English suffix stripper: plurals, `-ing`, `-ed`, `-ly` and a few
+/// derivational endings. It only needs to map related words to the same term.
+fn stem(word: &str) -> String {
+    const RULES: &[(&str, &str)] = &[
+        ("ational", "ate"),
+        ("ization", "ize"),
+        ("fulness", "ful"),
+        ("iveness", "ive"),
+        ("ies", "y"),
+        ("sses", "ss"),
+        ("ing", ""),
+        ("edly", ""),
+        ("ed", ""),
+        ("ly", ""),
+        ("s", ""),
+    ];
+    if !word.is_ascii() || word.len() <= 3 {
+        return word.to_string();
+    }
+    for (suffix, replacement) in RULES {
+        let Some(base) = word.strip_suffix(suffix) else {
+            continue;
+        };
+        if base.len() < 3 || (*suffix == "s" && base.ends_with('s')) {
+            return word.to_string();
+        }
+        let mut stem = format!("{}{}", base, replacement);
+        // running -> run, stopped -> stop
+        let bytes = stem.as_bytes();
+        if replacement.is_empty()
+            && matches!(*suffix, "ing" | "ed")
+            && bytes.len() >= 2
+            && bytes[bytes.len() - 1] == bytes[bytes.len() - 2]
+            && !b"aeioulsz".contains(&bytes[bytes.len() - 1])
+        {
+            stem.pop();
+        }
+        return stem;
+    }
+    word.to_string()
+}
+
+/// What an index holds for one key.
+struct Doc {
+    /// Number of terms, for the length normalisation.
+    length: usize,
+    /// Distinct terms, to find the postings to remove.
+    terms: Vec<String>,
+}
+
+pub(crate) struct TextIndex {
+    def: TextIndexDef,
+    prefix: String,
+    /// `None` for the whole value.
+    paths: Vec<Option<JsonPath>>,
+    /// Term -> key -> occurrences.
+    postings: HashMap<String, BTreeMap<String, u32>>,
+    docs: HashMap<String, Doc>,
+    total_length: usize,
+}
+
+impl TextIndex {
+    fn new(def: TextIndexDef) -> Result<TextIndex, KvError> {
+        let paths = def
+            .fields
+            .iter()
+            .map(|field| match field.as_str() {
+                "value" => Ok(None),
+                path => JsonPath::parse(path).map(Some),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TextIndex {
+            prefix: namespace_prefix(&def.namespace),
+            def,
+            paths,
+            postings: HashMap::new(),
+            docs: HashMap::new(),
+            total_length: 0,
+        })
+    }
+
+    /// The text this index reads from `value`.
+    fn text(&self, value: &Value) -> String {
+        let row = Row {
+            key: String::new(),
+            value: value.clone(),
+        };
+        let doc = if self.paths.iter().any(Option::is_some) {
+            row.document()
+        } else {
+            None
+        };
+        let mut parts = Vec::new();
+        for path in &self.paths {
+            match (path, value) {
+                (None, Value::String(s)) => parts.push(s.clone()),
+                (None, Value::Json(doc)) => collect_text(&doc.decode(), &mut parts),
+                (Some(path), _) => {
+                    if let Some(doc) = &doc {
+                        for found in path.values(doc) {
+                            collect_text(found, &mut parts);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        parts.join(" ")
+    }
+
+    fn set(&mut self, key: &str, value: Option<&Value>) {
+        if let Some(doc) = self.docs.remove(key) {
+            self.total_length -= doc.length;
+            for term in doc.terms {
+                if let Some(keys) = self.postings.get_mut(&term) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+        }
+        let Some(value) = value.filter(|_| key.starts_with(&self.prefix)) else {
+            return;
+        };
+        if !matches!(value, Value::String(_) | Value::Json(_)) {
+            return;
+        }
+        let terms = analyze(&self.text(value));
+        if terms.is_empty() {
+            return;
+        }
+        let length = terms.len();
+        let mut distinct = Vec::new();
+        for term in terms {
+            let count = self
+                .postings
+                .entry(term.clone())
+                .or_default()
+                .entry(key.to_string())
+                .or_default();
+            if *count == 0 {
+                distinct.push(term);
+            }
+            *count += 1;
+        }
+        self.total_length += length;
+        self.docs.insert(
+            key.to_string(),
+            Doc {
+                length,
+                terms: distinct,
+            },
+        );
+    }
+
+    /// Keys holding every term of `terms`.
+    fn candidates(&self, terms: &[String]) -> Vec<String> {
+        let Some((first, rest)) = terms.split_first() else {
+            return Vec::new();
+        };
+        let Some(keys) = self.postings.get(first) else {
+            return Vec::new();
+        };
+        keys.keys()
+            .filter(|key| {
+                rest.iter().all(|term| {
+                    self.postings
+                        .get(term)
+                        .is_some_and(|keys| keys.contains_key(*key))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// BM25 score of `key` for `terms`.
+    fn score(&self, key: &str, terms: &[String]) -> f64 {
+        let docs = self.docs.len() as f64;
+        let average = self.total_length as f64 / docs;
+        let length = self.docs.get(key).map_or(0, |doc| doc.length) as f64;
+        terms
+            .iter()
+            .filter_map(|term| {
+                let keys = self.postings.get(term)?;
+                let tf = f64::from(*keys.get(key)?);
+                let n = keys.len() as f64;
+                let idf = (1.0 + (docs - n + 0.5) / (n + 0.5)).ln();
+                Some(idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average)))
+            })
+            .sum()
+    }
+
+    /// The catalog record: `FT|name|namespace|field...`.
+    fn record(&self) -> Vec<String> {
+        let mut record = vec![
+            "FT".to_string(),
+            self.def.name.clone(),
+            self.def.namespace.clone(),
+        ];
+        record.extend(self.def.fields.iter().cloned());
+        record
+    }
+
+    fn from_record(fields: &[String]) -> Option<TextIndex> {
+        let [tag, name, namespace, fields @ ..] = fields else {
+            return None;
+        };
+        if tag != "FT" || fields.is_empty() {
+            return None;
+        }
+        TextIndex::new(TextIndexDef {
+            name: name.clone(),
+            namespace: namespace.clone(),
+            fields: fields.to_vec(),
+        })
+        .ok()
+    }
+}
+
+/// String leaves of `node`, and numbers and booleans as text.
+fn collect_text(node: &JsonValue, out: &mut Vec<String>) {
+    match node {
+        JsonValue::String(s) => out.push(s.clone()),
+        JsonValue::Number(n) => out.push(n.to_string()),
+        JsonValue::Bool(b) => out.push(b.to_string()),
+        JsonValue::Array(items) => items.iter().for_each(|item| collect_text(item, out)),
+        JsonValue::Object(members) => members
+            .values()
+            .for_each(|member| collect_text(member, out)),
+        JsonValue::Null => {}
+    }
+}
+
+/// Every full-text index of a store, by name.
+#[derive(Default)]
+pub(crate) struct TextIndexes {
+    by_name: BTreeMap<String, TextIndex>,
+}
+
+impl TextIndexes {
+    /// Record that `key` now holds `value`, or nothing.
+    pub(crate) fn update(&mut self, key: &str, value: Option<&Value>) {
+        for index in self.by_name.values_mut() {
+            index.set(key, value);
+        }
+    }
+
+    pub(crate) fn clear_entries(&mut self) {
+        for index in self.by_name.values_mut() {
+            index.postings.clear();
+            index.docs.clear();
+            index.total_length = 0;
+        }
+    }
+
+    pub(crate) fn records(&self) -> Vec<Vec<String>> {
+        self.by_name.values().map(TextIndex::record).collect()
+    }
+}
+
+impl KvStore {
+    /// Create a full-text index and fill it from the keys already in its namespace.
+    pub fn ft_create(&mut self, def: TextIndexDef) -> Result<(), KvError> {
+        if self.text_indexes.by_name.contains_key(&def.name) {
+            return Err(KvError::IndexExists(def.name));
+        }
+        let index = self.build_text_index(TextIndex::new(def)?);
+        let name = index.def.name.clone();
+        self.text_indexes.by_name.insert(name.clone(), index);
+        self.save_catalog().map_err(|e| {
+            self.text_indexes.by_name.remove(&name);
+            catalog_error(e)
+        })
+    }
+
+    pub fn ft_drop(&mut self, name: &str) -> Result<(), KvError> {
+        let Some(index) = self.text_indexes.by_name.remove(name) else {
+            return Err(KvError::NoSuchIndex(name.to_string()));
+        };
+        self.save_catalog().map_err(|e| {
+            self.text_indexes.by_name.insert(name.to_string(), index);
+            catalog_error(e)
+        })
+    }
+
+    /// Keys matching every term of `query`, best first, with their scores,
+    /// skipping `offset` and returning at most `count`. Also returns how many
+    /// keys matched in all.
+    pub fn ft_search(
+        &mut self,
+        name: &str,
+        query: &str,
+        offset: usize,
+        count: usize,
+    ) -> Result<(usize, Vec<(String, f64)>), KvError> {
+        let index = self
+            .text_indexes
+            .by_name
+            .get(name)
+            .ok_or_else(|| KvError::NoSuchIndex(name.to_string()))?;
+        let terms = analyze(query);
+        let candidates = index.candidates(&terms);
+        // Looking a key up removes it if it has expired, postings included.
+        let live: Vec<String> = candidates
+            .into_iter()
+            .filter(|key| self.lookup(key).is_some())
+            .collect();
+        let index = &self.text_indexes.by_name[name];
+        let mut hits: Vec<(String, f64)> = live
+            .into_iter()
+            .map(|key| {
+                let score = index.score(&key, &terms);
+                (key, score)
+            })
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let total = hits.len();
+        Ok((total, hits.into_iter().skip(offset).take(count).collect()))
+    }
+
+    fn build_text_index(&mut self, mut index: TextIndex) -> TextIndex {
+        for key in self.keys_with_prefix(&index.prefix) {
+            index.set(&key, self.lookup(&key));
+        }
+        index
+    }
+
+    /// Load full-text index definitions from the catalog and rebuild their postings.
+    pub(crate) fn load_text_indexes(&mut self, records: &[Vec<String>]) {
+        for index in records.iter().filter_map(|r| TextIndex::from_record(r)) {
+            let index = self.build_text_index(index);
+            self.text_indexes
+                .by_name
+                .insert(index.def.name.clone(), index);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet};
+
+use storage::{Storage, Value};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+
+impl KvStore {
+    fn set(&mut self, key: &str) -> Result<Option<&HashSet<String>>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::Set(members)) => Ok(Some(members)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Load every set in `keys`; missing keys count as empty sets.
+    fn sets(&mut self, keys: &[String]) -> Result<Vec<HashSet<String>>, KvError> {
+        keys.iter()
+            .map(|k| Ok(self.set(k)?.cloned().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Add `members` to the set at `key`. Returns how many were not already present.
+    pub fn sadd(&mut self, key: &str, members: Vec<String>) -> Result<usize, KvError> {
+        self.set(key)?;
+        self.check_create(key, Value::Set(HashSet::new()))?;
+        self.check_elements(&members)?;
+        let mut record = vec!["SADD", key];
+        record.extend(members.iter().map(String::as_str));
+        self.log(&record);
+
+        if self.lookup(key).is_none() {
+            self.write_value(key.to_string(), Value::Set(HashSet::new()));
+        }
+        let Some(Value::Set(set)) = self.lookup_mut(key) else {
+            unreachable!("set was just created");
+        };
+        let added = members.into_iter().filter(|m| set.insert(m.clone())).count();
+        self.storage.flush();
+        Ok(added)
+    }
+
+    /// Remove `members`; the key is removed once the set is empty.
+    pub fn srem(&mut self, key: &str, members: &[String]) -> Result<usize, KvError> {
+        if self.set(key)?.is_none() {
+            return Ok(0);
+        }
+        let Some(Value::Set(set)) = self.lookup_mut(key) else {
+            unreachable!("set checked above");
+        };
+        let removed = members.iter().filter(|m| set.remove(*m)).count();
+        let now_empty = set.is_empty();
+        if removed > 0 {
+            let mut record = vec!["SREM", key];
+            record.extend(members.iter().map(String::as_str));
+            self.log(&record);
+        }
+        if now_empty {
+            self.remove_value(key);
+        } else {
+            self.storage.flush();
+        }
+        Ok(removed)
+    }
+
+    /// Members of the set, sorted for stable output.
+    pub fn smembers(&mut self, key: &str) -> Result<Vec<String>, KvError> {
+        Ok(sorted(self.set(key)?.cloned().unwrap_or_default()))
+    }
+
+    pub fn sismember(&mut self, key: &str, member: &str) -> Result<bool, KvError> {
+        Ok(self.set(key)?.is_some_and(|s| s.contains(member)))
+    }
+
+    pub fn scard(&mut self, key: &str) -> Result<usize, KvError> {
+        Ok(self.set(key)?.map(|s| s.len()).unwrap_or(0))
+    }
+
+    pub fn sinter(&mut self, keys: &[String]) -> Result<Vec<String>, KvError> {
+        let mut sets = self.sets(keys)?.into_iter();
+        let first = sets.next().unwrap_or_default();
+        let rest: Vec<_> = sets.collect();
+        Ok(sorted(
+            first
+                .into_iter()
+                .filter(|m| rest.iter().all(|s| s.contains(m)))
+                .collect(),
+        ))
+    }
+
+    pub fn sunion(&mut self, keys: &[String]) -> Result<Vec<String>, KvError> {
+        Ok(sorted(self.sets(keys)?.into_iter().flatten().collect()))
+    }
+
+    /// Members of the first set that are in none of the others.
+    pub fn sdiff(&mut self, keys: &[String]) -> Result<Vec<String>, KvError> {
+        let mut sets = self.sets(keys)?.into_iter();
+        let first = sets.next().unwrap_or_default();
+        let rest: Vec<_> = sets.collect();
+        Ok(sorted(
+            first
+                .into_iter()
+                .filter(|m| !rest.iter().any(|s| s.contains(m)))
+                .collect(),
+        ))
+    }
+}
+
+fn sorted(members: HashSet<String>) -> Vec<String> {
+    let mut members: Vec<_> = members.into_iter().collect();
+    members.sort();
+    members
+}
+
+/// Re-apply a logged set operation during WAL replay.
+pub(crate) fn replay(store: &mut HashMap<String, Value>, op: &str, key: &str, args: &[String]) -> bool {
+    match op {
+        "SADD" => {
+            let entry = store
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Set(HashSet::new()));
+            if !matches!(entry, Value::Set(_)) {
+                *entry = Value::Set(HashSet::new());
+            }
+            if let Value::Set(set) = entry {
+                set.extend(args.iter().cloned());
+            }
+            true
+        }
+        "SREM" => {
+            if let Some(Value::Set(set)) = store.get_mut(key) {
+                for member in args {
+                    set.remove(member);
+                }
+                if set.is_empty() {
+                    store.remove(key);
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
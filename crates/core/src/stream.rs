@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use storage::stream::{ConsumerGroup, PendingEntry, Stream, StreamId};
+use storage::{Storage, Value};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+
+pub use parser::StreamTrim;
+
+/// A stream entry: its ID and field/value pairs.
+pub type StreamEntry = (StreamId, Vec<(String, String)>);
+
+/// One row of `XPENDING`: entry ID, owning consumer, idle milliseconds, delivery count.
+pub type PendingInfo = (StreamId, String, u64, u64);
+
+impl KvStore {
+    fn stream(&mut self, key: &str) -> Result<Option<&Stream>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    fn stream_mut(&mut self, key: &str) -> Result<Option<&mut Stream>, KvError> {
+        match self.lookup_mut(key) {
+            None => Ok(None),
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Append an entry. `id` is `*` for an auto-generated time-sequence ID,
+    /// otherwise an explicit `ms-seq` larger than the stream's last ID.
+    pub fn xadd(
+        &mut self,
+        key: &str,
+        id: &str,
+        fields: Vec<(String, String)>,
+        trim: Option<&StreamTrim>,
+    ) -> Result<StreamId, KvError> {
+        let trim = trim.map(parse_trim).transpose()?;
+        self.check_create(key, Value::Stream(Stream::new()))?;
+        self.check_elements(fields.iter().flat_map(|(f, v)| [f, v]))?;
+        let last_id = self.stream(key)?.map(|s| s.last_id).unwrap_or_default();
+        let id = if id == "*" {
+            let now = now_ms();
+            if now > last_id.ms {
+                StreamId::new(now, 0)
+            } else {
+                StreamId::new(last_id.ms, last_id.seq.checked_add(1).ok_or(KvError::StreamIdTooSmall)?)
+            }
+        } else {
+            let id: StreamId = id.parse().map_err(|_| KvError::InvalidStreamId)?;
+            if id == StreamId::MIN || id <= last_id {
+                return Err(KvError::StreamIdTooSmall);
+            }
+            id
+        };
+
+        let id_str = id.to_string();
+        let mut record = vec!["XADD", key, &id_str];
+        for (f, v) in &fields {
+            record.push(f);
+            record.push(v);
+        }
+        self.log(&record);
+
+        if self.lookup(key).is_none() {
+            self.write_value(key.to_string(), Value::Stream(Stream::new()));
+        }
+        let stream = self.stream_mut(key)?.expect("stream was just created");
+        stream.entries.insert(id, fields);
+        stream.last_id = id;
+        self.storage.flush();
+
+        if let Some(trim) = trim {
+            self.apply_trim(key, trim)?;
+        }
+        Ok(id)
+    }
+
+    pub fn xlen(&mut self, key: &str) -> Result<usize, KvError> {
+        Ok(self.stream(key)?.map(|s| s.len()).unwrap_or(0))
+    }
+
+    /// Entries with IDs in `start..=end`; `-` and `+` stand for the smallest and largest IDs.
+    pub fn xrange(
+        &mut self,
+        key: &str,
+        start: &str,
+        end: &str,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>, KvError> {
+        let start = parse_range_id(start, false)?;
+        let end = parse_range_id(end, true)?;
+        let Some(stream) = self.stream(key)? else {
+            return Ok(Vec::new());
+        };
+        if start > end {
+            return Ok(Vec::new());
+        }
+        Ok(stream
+            .entries
+            .range(start..=end)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect())
+    }
+
+    /// Entries after each given ID, per stream. Streams with nothing new are omitted.
+    pub fn xread(
+        &mut self,
+        streams: &[(String, String)],
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, KvError> {
+        let mut result = Vec::new();
+        for (key, id) in streams {
+            let Some(stream) = self.stream(key)? else {
+                continue;
+            };
+            let after = if id == "$" {
+                stream.last_id
+            } else {
+                id.parse().map_err(|_| KvError::InvalidStreamId)?
+            };
+            let entries = entries_after(stream, after, count);
+            if !entries.is_empty() {
+                result.push((key.clone(), entries));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Replace `$` IDs with each stream's current last ID, so that a blocking
+    /// `XREAD` only wakes up for entries added after it started waiting.
+    pub fn resolve_last_ids(&mut self, streams: &mut [(String, String)]) -> Result<(), KvError> {
+        for (key, id) in streams.iter_mut() {
+            if id == "$" {
+                *id = self.stream(key)?.map(|s| s.last_id).unwrap_or_default().to_string();
+            }
+        }
+        Ok(())
+    }
+
+    /// Evict old entries. Returns how many were removed.
+    pub fn xtrim(&mut self, key: &str, trim: &StreamTrim) -> Result<usize, KvError> {
+        let trim = parse_trim(trim)?;
+        self.apply_trim(key, trim)
+    }
+
+    fn apply_trim(&mut self, key: &str, trim: Trim) -> Result<usize, KvError> {
+        let Some(stream) = self.stream_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = trim_stream(stream, trim);
+        if removed > 0 {
+            let (strategy, arg) = match trim {
+                Trim::MaxLen(n) => ("MAXLEN", n.to_string()),
+                Trim::MinId(id) => ("MINID", id.to_string()),
+            };
+            self.log(&["XTRIM", key, strategy, &arg]);
+            self.storage.flush();
+        }
+        Ok(removed)
+    }
+
+    /// Create a consumer group that will deliver entries after `id` (`$` for new entries only).
+    pub fn xgroup_create(&mut self, key: &str, group: &str, id: &str, mkstream: bool) -> Result<(), KvError> {
+        if self.stream(key)?.is_none() {
+            if !mkstream {
+                return Err(KvError::NoSuchKey);
+            }
+            self.check_create(key, Value::Stream(Stream::new()))?;
+            self.write_value(key.to_string(), Value::Stream(Stream::new()));
+        }
+        let stream = self.stream_mut(key)?.expect("stream exists");
+        if stream.groups.contains_key(group) {
+            return Err(KvError::GroupExists);
+        }
+        let start = if id == "$" {
+            stream.last_id
+        } else {
+            id.parse().map_err(|_| KvError::InvalidStreamId)?
+        };
+        stream.groups.insert(
+            group.to_string(),
+            ConsumerGroup {
+                last_delivered: start,
+                ..ConsumerGroup::default()
+            },
+        );
+        self.storage.flush();
+        self.log(&["XGROUP", key, group, &start.to_string()]);
+        Ok(())
+    }
+
+    /// Read as `consumer` within `group`. The ID `>` delivers entries never
+    /// delivered to the group and records them as pending (unless `no_ack`);
+    /// any other ID re-reads the consumer's own pending entries after it.
+    pub fn xreadgroup(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(String, String)],
+        count: Option<usize>,
+        no_ack: bool,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, KvError> {
+        let mut result = Vec::new();
+        for (key, id) in streams {
+            let Some(stream) = self.stream(key)? else {
+                return Err(KvError::NoSuchGroup);
+            };
+            let Some(cg) = stream.groups.get(group) else {
+                return Err(KvError::NoSuchGroup);
+            };
+            let entries = if id == ">" {
+                entries_after(stream, cg.last_delivered, count)
+            } else {
+                let after: StreamId = id.parse().map_err(|_| KvError::InvalidStreamId)?;
+                cg.pending
+                    .iter()
+                    .filter(|(pid, p)| **pid > after && p.consumer == consumer)
+                    .filter_map(|(pid, _)| stream.entries.get(pid).map(|f| (*pid, f.clone())))
+                    .take(count.unwrap_or(usize::MAX))
+                    .collect()
+            };
+            if id == ">" && !entries.is_empty() {
+                let ids: Vec<StreamId> = entries.iter().map(|(id, _)| *id).collect();
+                self.deliver(key, group, consumer, &ids, no_ack);
+            }
+            if !entries.is_empty() {
+                result.push((key.clone(), entries));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Record a delivery (or claim) of `ids` to `consumer` and log it.
+    fn deliver(&mut self, key: &str, group: &str, consumer: &str, ids: &[StreamId], no_ack: bool) {
+        let now = now_ms();
+        if let Ok(Some(stream)) = self.stream_mut(key)
+            && let Some(cg) = stream.groups.get_mut(group)
+        {
+            record_delivery(cg, consumer, now, ids, no_ack);
+        }
+        let now = now.to_string();
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let no_ack = if no_ack { "1" } else { "0" };
+        let mut record = vec!["XDELIVER", key, group, consumer, &now, no_ack];
+        record.extend(ids.iter().map(String::as_str));
+        self.log(&record);
+        self.storage.flush();
+    }
+
+    /// Acknowledge entries, removing them from the group's pending list.
+    pub fn xack(&mut self, key: &str, group: &str, ids: &[String]) -> Result<usize, KvError> {
+        let ids = parse_ids(ids)?;
+        let Some(stream) = self.stream_mut(key)? else {
+            return Ok(0);
+        };
+        let Some(cg) = stream.groups.get_mut(group) else {
+            return Ok(0);
+        };
+        let acked = ack(cg, &ids);
+        if acked > 0 {
+            let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            let mut record = vec!["XACK", key, group];
+            record.extend(ids.iter().map(String::as_str));
+            self.log(&record);
+            self.storage.flush();
+        }
+        Ok(acked)
+    }
+
+    /// Transfer pending entries idle for at least `min_idle` ms to `consumer`.
+    pub fn xclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[String],
+    ) -> Result<Vec<StreamEntry>, KvError> {
+        let ids = parse_ids(ids)?;
+        let now = now_ms();
+        let Some(stream) = self.stream(key)? else {
+            return Err(KvError::NoSuchGroup);
+        };
+        let Some(cg) = stream.groups.get(group) else {
+            return Err(KvError::NoSuchGroup);
+        };
+        let claimed: Vec<StreamEntry> = ids
+            .iter()
+            .filter(|id| {
+                cg.pending
+                    .get(id)
+                    .is_some_and(|p| now.saturating_sub(p.delivered_at) >= min_idle)
+            })
+            .filter_map(|id| stream.entries.get(id).map(|f| (*id, f.clone())))
+            .collect();
+        if !claimed.is_empty() {
+            let ids: Vec<StreamId> = claimed.iter().map(|(id, _)| *id).collect();
+            self.deliver(key, group, consumer, &ids, false);
+        }
+        Ok(claimed)
+    }
+
+    /// The group's pending entries in ID order.
+    pub fn xpending(&mut self, key: &str, group: &str) -> Result<Vec<PendingInfo>, KvError> {
+        let now = now_ms();
+        let Some(stream) = self.stream(key)? else {
+            return Err(KvError::NoSuchGroup);
+        };
+        let Some(cg) = stream.groups.get(group) else {
+            return Err(KvError::NoSuchGroup);
+        };
+        Ok(cg
+            .pending
+            .iter()
+            .map(|(id, p)| {
+                let idle = now.saturating_sub(p.delivered_at);
+                (*id, p.consumer.clone(), idle, p.delivery_count)
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Trim {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+fn parse_trim(trim: &StreamTrim) -> Result<Trim, KvError> {
+    Ok(match trim {
+        StreamTrim::MaxLen(n) => Trim::MaxLen(*n),
+        StreamTrim::MinId(id) => Trim::MinId(id.parse().map_err(|_| KvError::InvalidStreamId)?),
+    })
+}
+
+fn parse_ids(ids: &[String]) -> Result<Vec<StreamId>, KvError> {
+    ids.iter()
+        .map(|id| id.parse().map_err(|_| KvError::InvalidStreamId))
+        .collect()
+}
+
+/// Parse an `XRANGE` bound. A bare millisecond value covers every sequence
+/// number at that time, so as an end bound it means `ms-<max>`.
+fn parse_range_id(id: &str, is_end: bool) -> Result<StreamId, KvError> {
+    match id {
+        "-" => Ok(StreamId::MIN),
+        "+" => Ok(StreamId::MAX),
+        _ if is_end && !id.contains('-') => {
+            let ms = id.parse().map_err(|_| KvError::InvalidStreamId)?;
+            Ok(StreamId::new(ms, u64::MAX))
+        }
+        _ => id.parse().map_err(|_| KvError::InvalidStreamId),
+    }
+}
+
+fn entries_after(stream: &Stream, after: StreamId, count: Option<usize>) -> Vec<StreamEntry> {
+    use std::ops::Bound::{Excluded, Unbounded};
+    stream
+        .entries
+        .range((Excluded(after), Unbounded))
+        .take(count.unwrap_or(usize::MAX))
+        .map(|(id, fields)| (*id, fields.clone()))
+        .collect()
+}
+
+fn trim_stream(stream: &mut Stream, trim: Trim) -> usize {
+    let before = stream.entries.len();
+    match trim {
+        Trim::MaxLen(max) => {
+            while stream.entries.len() > max {
+                stream.entries.pop_first();
+            }
+        }
+        Trim::MinId(min) => stream.entries = stream.entries.split_off(&min),
+    }
+    before - stream.entries.len()
+}
+
+fn record_delivery(cg: &mut ConsumerGroup, consumer: &str, now: u64, ids: &[StreamId], no_ack: bool) {
+    for id in ids {
+        cg.last_delivered = cg.last_delivered.max(*id);
+        if no_ack {
+            continue;
+        }
+        let entry = cg.pending.entry(*id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_string(),
+            delivered_at: now,
+            delivery_count: 0,
+        });
+        entry.consumer = consumer.to_string();
+        entry.delivered_at = now;
+        entry.delivery_count += 1;
+    }
+}
+
+fn ack(cg: &mut ConsumerGroup, ids: &[StreamId]) -> usize {
+    ids.iter().filter(|id| cg.pending.remove(id).is_some()).count()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Re-apply a logged stream operation during WAL replay.
+pub(crate) fn replay(store: &mut HashMap<String, Value>, op: &str, key: &str, args: &[String]) -> bool {
+    if !matches!(op, "XADD" | "XTRIM" | "XGROUP" | "XDELIVER" | "XACK") {
+        return false;
+    }
+    if !matches!(store.get(key), Some(Value::Stream(_))) {
+        if !matches!(op, "XADD" | "XGROUP") {
+            return true;
+        }
+        store.insert(key.to_string(), Value::Stream(Stream::new()));
+    }
+    let Some(Value::Stream(stream)) = store.get_mut(key) else {
+        return false;
+    };
+    replay_stream(stream, op, args).is_some()
+}
+
+fn replay_stream(stream: &mut Stream, op: &str, args: &[String]) -> Option<()> {
+    match (op, args) {
+        ("XADD", [id, fields @ ..]) if fields.len().is_multiple_of(2) => {
+            let id: StreamId = id.parse().ok()?;
+            let pairs = fields
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            stream.entries.insert(id, pairs);
+            stream.last_id = stream.last_id.max(id);
+        }
+        ("XTRIM", [strategy, arg]) => {
+            let trim = match strategy.as_str() {
+                "MAXLEN" => Trim::MaxLen(arg.parse().ok()?),
+                "MINID" => Trim::MinId(arg.parse().ok()?),
+                _ => return None,
+            };
+            trim_stream(stream, trim);
+        }
+        ("XGROUP", [group, id]) => {
+            let group_state = ConsumerGroup {
+                last_delivered: id.parse().ok()?,
+                ..ConsumerGroup::default()
+            };
+            stream.groups.insert(group.clone(), group_state);
+        }
+        ("XDELIVER", [group, consumer, now, no_ack, ids @ ..]) => {
+            let now = now.parse().ok()?;
+            let ids = ids.iter().map(|id| id.parse().ok()).collect::<Option<Vec<StreamId>>>()?;
+            if let Some(cg) = stream.groups.get_mut(group) {
+                record_delivery(cg, consumer, now, &ids, no_ack == "1");
+            }
+        }
+        ("XACK", [group, ids @ ..]) => {
+            let ids = ids.iter().map(|id| id.parse().ok()).collect::<Option<Vec<StreamId>>>()?;
+            if let Some(cg) = stream.groups.get_mut(group) {
+                ack(cg, &ids);
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
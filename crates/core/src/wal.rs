@@ -3,12 +3,37 @@ use std::io::{BufRead, BufReader, Write, Seek};
 use std::path::Path;
 use std::collections::HashMap;
 use std::io;
+use std::time::{Duration, Instant};
+
+use storage::Value;
+use storage::codec::{decode_record, encode_record};
+
+use crate::limits::Limits;
 
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
 
+/// When appended records are forced to disk, as Redis' `appendfsync`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every write, so nothing acknowledged is lost.
+    Always,
+    /// At most a second after a write; the owner calls `sync` on a timer.
+    EverySec,
+    /// Whenever the operating system flushes its cache.
+    No,
+}
+
 pub struct Wal {
     log_file: File,
+    /// Records whose key these limits forbid are skipped on replay.
+    limits: Limits,
+    fsync: FsyncPolicy,
+    /// Whether records were written since the last fsync.
+    unsynced: bool,
+    last_sync: Instant,
+    /// Records appended since the WAL was opened or last truncated.
+    changes: u64,
 }
 
 impl Wal {
@@ -18,45 +43,152 @@ impl Wal {
         #[cfg(unix)]
         opts.mode(0o600);
         let log_file = opts.open(path)?;
-        Ok(Wal { log_file })
+        Ok(Wal {
+            log_file,
+            limits: Limits::default(),
+            fsync: FsyncPolicy::No,
+            unsynced: false,
+            last_sync: Instant::now(),
+            changes: 0,
+        })
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Wal {
+        self.limits = limits;
+        self
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_fsync(&mut self, fsync: FsyncPolicy) {
+        self.fsync = fsync;
+    }
+
+    /// Records appended since the WAL was opened or last truncated.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// Force everything appended so far to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced {
+            self.log_file.sync_data()?;
+            self.unsynced = false;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Count `records` just written and sync as the policy asks.
+    fn written(&mut self, records: usize) -> io::Result<()> {
+        self.changes += records as u64;
+        self.unsynced = true;
+        match self.fsync {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::EverySec if self.last_sync.elapsed() >= Duration::from_secs(1) => {
+                self.sync()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether replay may apply `parts`. Keys written under looser limits
+    /// are reported and skipped.
+    fn admits(&self, parts: &[String]) -> bool {
+        let Some(key) = parts.get(1) else {
+            return true;
+        };
+        match self.limits.check_key(key) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("WAL: Skipping record for key {:?}: {}", key, e);
+                false
+            }
+        }
+    }
+
+    /// Appends an arbitrary record (operation name followed by its fields).
+    pub fn append(&mut self, record: &[&str]) -> io::Result<()> {
+        writeln!(self.log_file, "{}", encode_record(record))?;
+        self.written(1)
+    }
+
+    /// Appends `records` between `BEGIN` and `COMMIT` markers in a single
+    /// write. Replay applies a group only once its `COMMIT` has been read.
+    pub fn append_group(&mut self, records: &[Vec<String>]) -> io::Result<()> {
+        let mut buf = String::from("BEGIN\n");
+        for record in records {
+            let fields: Vec<&str> = record.iter().map(String::as_str).collect();
+            buf.push_str(&encode_record(&fields));
+            buf.push('\n');
+        }
+        buf.push_str("COMMIT\n");
+        self.log_file.write_all(buf.as_bytes())?;
+        self.written(records.len())
     }
 
     /// Appends a PUT command to the WAL.
     pub fn append_put(&mut self, key: &str, value: &str) -> io::Result<()> {
-        writeln!(self.log_file, "PUT|{}|{}", key, value)
+        self.append(&["PUT", key, value])
     }
 
     /// Appends a DELETE command to the WAL.
     pub fn append_delete(&mut self, key: &str) -> io::Result<()> {
-        writeln!(self.log_file, "DELETE|{}", key)
+        self.append(&["DELETE", key])
     }
 
     /// Loads all log entries into a HashMap as the current store state.
-    pub fn load_into(&mut self) -> io::Result<HashMap<String, String>> {
+    pub fn load_into(&mut self) -> io::Result<HashMap<String, Value>> {
+        let mut store = HashMap::new();
+        self.replay_into(&mut store)?;
+        Ok(store)
+    }
+
+    /// Replays all log entries on top of `store`, e.g. state loaded from a snapshot.
+    pub fn replay_into(&mut self, store: &mut HashMap<String, Value>) -> io::Result<()> {
         self.log_file.rewind()?;
         let reader = BufReader::new(&self.log_file);
-
-        let mut store = HashMap::new();
+        // Records of a batch whose COMMIT has not been read yet. A group cut
+        // short by a crash is dropped.
+        let mut group: Option<Vec<Vec<String>>> = None;
 
         for line in reader.lines() {
+            // `lines` has already dropped the terminator; anything else,
+            // such as whitespace at the end of a value, is data.
             let line = line?;
-            let line = line.trim();
             if line.is_empty() {
                 continue;
             }
-            let parts: Vec<&str> = line.split('|').collect();
+            let parts = decode_record(&line);
+            match parts.as_slice() {
+                [op] if op == "BEGIN" => {
+                    group = Some(Vec::new());
+                    continue;
+                }
+                [op] if op == "COMMIT" => {
+                    for parts in group.take().unwrap_or_default() {
+                        if self.admits(&parts) && !apply_record(store, &parts) {
+                            eprintln!("WAL: Unrecognized record in batch: {:?}", parts);
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(group) = &mut group {
+                group.push(parts);
+                continue;
+            }
 
-            if parts.len() == 3 && parts[0] == "PUT" {
-                store.insert(parts[1].to_string(), parts[2].to_string());
-            } else if parts.len() == 2 && parts[0] == "DELETE" {
-                store.remove(parts[1]);
-            } else {
+            if self.admits(&parts) && !apply_record(store, &parts) {
                 eprintln!("WAL: Unrecognized line format: {}", line);
                 // Do not touch the map
             }
         }
 
-        Ok(store)
+        Ok(())
     }
 
     /// Truncate the WAL file (clear all contents).
@@ -66,10 +198,35 @@ impl Wal {
             .write(true)
             .truncate(true)
             .open(path)?;
+        self.unsynced = false;
+        self.changes = 0;
         Ok(())
     }
 }
 
+/// Apply one decoded record to `store`. Returns `false` if the record is not understood.
+pub(crate) fn apply_record(store: &mut HashMap<String, Value>, parts: &[String]) -> bool {
+    match parts {
+        [op, key, value] if op == "PUT" => {
+            store.insert(key.clone(), Value::String(value.clone()));
+            true
+        }
+        [op, key] if op == "DELETE" => {
+            store.remove(key);
+            true
+        }
+        [op, key, args @ ..] => {
+            crate::list::replay(store, op, key, args)
+                || crate::hash::replay(store, op, key, args)
+                || crate::set::replay(store, op, key, args)
+                || crate::zset::replay(store, op, key, args)
+                || crate::stream::replay(store, op, key, args)
+                || crate::json::replay(store, op, key, args)
+        }
+        _ => false,
+    }
+}
+
 // Example usage in your codebase
 pub const WAL_PATH: &str = ".zyncdb.wal";
 pub const SNAPSHOT_PATH: &str = ".zyncdb.snapshot";
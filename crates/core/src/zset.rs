@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use storage::{Storage, Value, ZSet};
+
+use crate::error::KvError;
+use crate::kv::KvStore;
+use crate::list::normalize_range;
+
+pub use parser::{LexBound, ScoreBound, ZRange};
+
+impl KvStore {
+    fn zset(&mut self, key: &str) -> Result<Option<&ZSet>, KvError> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(Value::ZSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(KvError::WrongType),
+        }
+    }
+
+    /// Add or update members with their scores. Returns how many members are new.
+    pub fn zadd(&mut self, key: &str, members: Vec<(f64, String)>) -> Result<usize, KvError> {
+        self.zset(key)?;
+        self.check_create(key, Value::ZSet(ZSet::new()))?;
+        self.check_elements(members.iter().map(|(_, m)| m))?;
+        let scores: Vec<String> = members.iter().map(|(s, _)| s.to_string()).collect();
+        let mut record = vec!["ZADD", key];
+        for ((_, member), score) in members.iter().zip(&scores) {
+            record.push(score);
+            record.push(member);
+        }
+        self.log(&record);
+
+        if self.lookup(key).is_none() {
+            self.write_value(key.to_string(), Value::ZSet(ZSet::new()));
+        }
+        let Some(Value::ZSet(zset)) = self.lookup_mut(key) else {
+            unreachable!("sorted set was just created");
+        };
+        let added = members
+            .into_iter()
+            .filter(|(score, member)| zset.insert(member.clone(), *score))
+            .count();
+        self.storage.flush();
+        Ok(added)
+    }
+
+    /// Remove `members`; the key is removed once the sorted set is empty.
+    pub fn zrem(&mut self, key: &str, members: &[String]) -> Result<usize, KvError> {
+        if self.zset(key)?.is_none() {
+            return Ok(0);
+        }
+        let Some(Value::ZSet(zset)) = self.lookup_mut(key) else {
+            unreachable!("sorted set checked above");
+        };
+        let removed = members.iter().filter(|m| zset.remove(m)).count();
+        let now_empty = zset.is_empty();
+        if removed > 0 {
+            let mut record = vec!["ZREM", key];
+            record.extend(members.iter().map(String::as_str));
+            self.log(&record);
+        }
+        if now_empty {
+            self.remove_value(key);
+        } else {
+            self.storage.flush();
+        }
+        Ok(removed)
+    }
+
+    pub fn zscore(&mut self, key: &str, member: &str) -> Result<Option<f64>, KvError> {
+        Ok(self.zset(key)?.and_then(|z| z.score(member)))
+    }
+
+    pub fn zrank(&mut self, key: &str, member: &str) -> Result<Option<usize>, KvError> {
+        Ok(self.zset(key)?.and_then(|z| z.rank(member)))
+    }
+
+    /// Members selected by rank, score or lex range, in score order.
+    pub fn zrange(&mut self, key: &str, range: &ZRange) -> Result<Vec<(String, f64)>, KvError> {
+        let Some(zset) = self.zset(key)? else {
+            return Ok(Vec::new());
+        };
+        let entries = zset.iter().map(|(m, s)| (m.clone(), s));
+        Ok(match range {
+            ZRange::Rank { start, stop } => match normalize_range(*start, *stop, zset.len()) {
+                Some((s, e)) => entries.skip(s).take(e - s + 1).collect(),
+                None => Vec::new(),
+            },
+            ZRange::Score { min, max } => entries
+                .skip_while(|(_, score)| !above(min, *score))
+                .take_while(|(_, score)| below(max, *score))
+                .collect(),
+            ZRange::Lex { min, max } => entries
+                .filter(|(member, _)| lex_above(min, member) && lex_below(max, member))
+                .collect(),
+        })
+    }
+
+    /// Add `delta` to the score of `member`. Logged as a ZADD of the new score.
+    pub fn zincr_by(&mut self, key: &str, delta: f64, member: &str) -> Result<f64, KvError> {
+        let next = self.zscore(key, member)?.unwrap_or(0.0) + delta;
+        if next.is_nan() {
+            return Err(KvError::NotFinite);
+        }
+        self.zadd(key, vec![(next, member.to_string())])?;
+        Ok(next)
+    }
+
+    /// Remove and return up to `count` members with the lowest scores.
+    pub fn zpop_min(&mut self, key: &str, count: usize) -> Result<Vec<(String, f64)>, KvError> {
+        if self.zset(key)?.is_none() {
+            return Ok(Vec::new());
+        }
+        let Some(Value::ZSet(zset)) = self.lookup_mut(key) else {
+            unreachable!("sorted set checked above");
+        };
+        let popped: Vec<_> = std::iter::from_fn(|| zset.pop_min()).take(count).collect();
+        let now_empty = zset.is_empty();
+        if !popped.is_empty() {
+            let mut record = vec!["ZREM", key];
+            record.extend(popped.iter().map(|(m, _)| m.as_str()));
+            self.log(&record);
+        }
+        if now_empty {
+            self.remove_value(key);
+        } else {
+            self.storage.flush();
+        }
+        Ok(popped)
+    }
+}
+
+fn above(min: &ScoreBound, score: f64) -> bool {
+    if min.exclusive { score > min.value } else { score >= min.value }
+}
+
+fn below(max: &ScoreBound, score: f64) -> bool {
+    if max.exclusive { score < max.value } else { score <= max.value }
+}
+
+fn lex_above(min: &LexBound, member: &str) -> bool {
+    match min {
+        LexBound::Min => true,
+        LexBound::Max => false,
+        LexBound::Inclusive(m) => member >= m.as_str(),
+        LexBound::Exclusive(m) => member > m.as_str(),
+    }
+}
+
+fn lex_below(max: &LexBound, member: &str) -> bool {
+    match max {
+        LexBound::Min => false,
+        LexBound::Max => true,
+        LexBound::Inclusive(m) => member <= m.as_str(),
+        LexBound::Exclusive(m) => member < m.as_str(),
+    }
+}
+
+/// Re-apply a logged sorted-set operation during WAL replay.
+pub(crate) fn replay(store: &mut HashMap<String, Value>, op: &str, key: &str, args: &[String]) -> bool {
+    match op {
+        "ZADD" if args.len().is_multiple_of(2) => {
+            let mut members = Vec::new();
+            for pair in args.chunks(2) {
+                let Ok(score) = pair[0].parse::<f64>() else {
+                    return false;
+                };
+                members.push((score, pair[1].clone()));
+            }
+            let entry = store
+                .entry(key.to_string())
+                .or_insert_with(|| Value::ZSet(ZSet::new()));
+            if !matches!(entry, Value::ZSet(_)) {
+                *entry = Value::ZSet(ZSet::new());
+            }
+            if let Value::ZSet(zset) = entry {
+                for (score, member) in members {
+                    zset.insert(member, score);
+                }
+            }
+            true
+        }
+        "ZREM" => {
+            if let Some(Value::ZSet(zset)) = store.get_mut(key) {
+                for member in args {
+                    zset.remove(member);
+                }
+                if zset.is_empty() {
+                    store.remove(key);
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::PathBuf;
+
+use parser::{Parser, SimpleParser};
+use zyncdb_core::acl::{Acl, Category, DEFAULT_USER, User};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zyncdb_acl_{}_{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn user(rules: &[&str]) -> User {
+    let mut user = User::new("alice");
+    for rule in rules {
+        user.apply(rule).unwrap();
+    }
+    user
+}
+
+fn check(user: &User, line: &str) -> Result<(), String> {
+    user.check(&SimpleParser.parse(line).unwrap())
+}
+
+#[test]
+fn test_commands_are_checked_against_categories_keys_and_namespaces() {
+    assert_eq!(Category::of("get"), [Category::Read]);
+    assert_eq!(Category::of("snapshot"), [Category::Admin, Category::Dangerous]);
+    assert!(Category::of("ping").is_empty());
+
+    let reader = user(&["on", ">pw", "+@read", "~cache:*", "~orders:*", "ns:orders"]);
+    assert_eq!(check(&reader, "get orders:1"), Ok(()));
+    assert_eq!(check(&reader, "ping"), Ok(()));
+    assert_eq!(check(&reader, "select * from orders where value = 'x'"), Ok(()));
+    assert_eq!(
+        check(&reader, "put orders:1 x"),
+        Err("NOPERM User alice has no permissions to run the 'put' command".to_string())
+    );
+    // The glob allows cache:*, but the user is confined to the orders namespace.
+    assert_eq!(check(&reader, "get cache:1"), Err("NOPERM No permissions to access a key".to_string()));
+    assert_eq!(check(&reader, "mget orders:1 secret"), Err("NOPERM No permissions to access a key".to_string()));
+    assert_eq!(
+        check(&reader, "select * from users"),
+        Err("NOPERM No permissions to access the 'users' namespace".to_string())
+    );
+    assert!(check(&reader, "keys").is_err());
+    // A batch needs only what its commands need.
+    assert_eq!(check(&reader, "batch get orders:1; get orders:2"), Ok(()));
+    assert!(check(&reader, "batch get orders:1; get users:1").is_err());
+    assert!(check(&reader, "batch get orders:1; put orders:2 x").is_err());
+    assert_eq!(check(&reader, "acl whoami"), Ok(()));
+
+    // Admin does not grant the dangerous commands.
+    let admin = user(&["on", "nopass", "+@all", "-@dangerous", "allkeys"]);
+    assert_eq!(check(&admin, "config get *"), Ok(()));
+    assert_eq!(check(&admin, "keys"), Ok(()));
+    assert!(check(&admin, "snapshot").is_err());
+    assert!(check(&admin, "shutdown nosave").is_err());
+    assert_eq!(check(&admin, "blpop jobs 0"), Ok(()));
+}
+
+#[test]
+fn test_reading_commands_need_only_read_and_catalog_changes_need_admin() {
+    let reader = user(&["on", "nopass", "+@read", "allkeys"]);
+    assert_eq!(check(&reader, "xread count 1 block 0 streams events 0"), Ok(()));
+    assert_eq!(check(&reader, "schema get orders"), Ok(()));
+    assert_eq!(
+        check(&reader, "xreadgroup group g c streams events >"),
+        Err("NOPERM User alice has no permissions to run the 'xreadgroup' command".to_string())
+    );
+
+    // Indexes and schemas change what every user of a namespace sees, so
+    // writing data is not enough to change them.
+    let writer = user(&["on", "nopass", "+@read", "+@write", "allkeys"]);
+    let catalog = [
+        "create index by_value on orders (value)",
+        "drop index by_value",
+        "schema set orders type string",
+        "schema drop orders",
+        "ft.create idx on orders fields value",
+        "ft.dropindex idx",
+    ];
+    for line in catalog {
+        assert!(check(&reader, line).is_err(), "{}", line);
+        assert!(check(&writer, line).is_err(), "{}", line);
+    }
+    assert_eq!(check(&writer, "xreadgroup group g c streams events >"), Ok(()));
+    let owner = user(&["on", "nopass", "+@read", "+@write", "+@admin", "allkeys"]);
+    for line in catalog {
+        assert_eq!(check(&owner, line), Ok(()), "{}", line);
+    }
+}
+
+#[test]
+fn test_rules_change_users_and_describe_them() {
+    let mut alice = user(&["on", ">one", ">two", "+@all", "-@admin", "~a:*", "ns:a"]);
+    assert!(alice.accepts("one", "") && alice.accepts("two", ""));
+    assert!(!alice.accepts("three", ""));
+    alice.apply("<one").unwrap();
+    assert!(!alice.accepts("one", ""));
+    assert_eq!(alice.apply("<one"), Err("no such password".to_string()));
+    assert_eq!(alice.apply("+@nothing"), Err("unknown command category".to_string()));
+    assert_eq!(alice.apply("#plain"), Err("not an argon2 hash".to_string()));
+    assert_eq!(alice.apply("whatever"), Err("syntax error".to_string()));
+
+    let rules = alice.rules();
+    assert_eq!(rules[0], "on");
+    assert!(rules[1].starts_with("#$argon2"));
+    assert_eq!(rules[2..], ["+@read", "+@write", "+@dangerous", "~a:*", "ns:a"]);
+    // The rules rebuild the same user, password hashes included.
+    let mut rebuilt = User::new("alice");
+    for rule in &rules {
+        rebuilt.apply(rule).unwrap();
+    }
+    assert_eq!(rebuilt, alice);
+
+    alice.apply("off").unwrap();
+    assert!(!alice.accepts("two", ""));
+    alice.apply("reset").unwrap();
+    assert_eq!(alice, User::new("alice"));
+    assert_eq!(alice.rules(), ["off", "-@all", "resetkeys"]);
+
+    // requirepass logs in the default user, in place of nopass.
+    let default = Acl::default().user(DEFAULT_USER).cloned().unwrap();
+    assert!(default.accepts("anything", ""));
+    assert!(default.accepts("s3cret", "s3cret"));
+    assert!(!default.accepts("anything", "s3cret"));
+}
+
+#[test]
+fn test_users_are_saved_to_the_acl_file() {
+    let dir = temp_dir("file");
+    let path = dir.join(".zyncdb.acl");
+    let mut acl = Acl::load(&path).unwrap();
+    assert_eq!(acl.list(), ["user default on nopass +@all ~*"]);
+
+    let rules = |rules: &[&str]| rules.iter().map(|rule| rule.to_string()).collect::<Vec<_>>();
+    acl.set_user("bob", &rules(&["on", ">pw", "+@read", "~*"])).unwrap();
+    // A bad rule changes nothing.
+    assert_eq!(
+        acl.set_user("bob", &rules(&["off", "+@bogus"])).unwrap_err().to_string(),
+        "Error in ACL SETUSER modifier '+@bogus': unknown command category"
+    );
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.starts_with("user bob on #$argon2"), "{}", text);
+    assert!(!text.contains(">pw"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    let loaded = Acl::load(&path).unwrap();
+    assert_eq!(loaded.list(), acl.list());
+    assert!(loaded.user("bob").unwrap().accepts("pw", ""));
+
+    assert_eq!(
+        acl.del_users(&rules(&["default"])).unwrap_err().to_string(),
+        "The 'default' user cannot be removed"
+    );
+    assert_eq!(acl.del_users(&rules(&["bob", "carol"])).unwrap(), 1);
+    assert!(Acl::load(&path).unwrap().user("bob").is_none());
+
+    std::fs::write(&path, "user eve on +@everything\n").unwrap();
+    assert_eq!(
+        Acl::load(&path).unwrap_err().to_string(),
+        "ACL file line 1: rule '+@everything': unknown command category"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
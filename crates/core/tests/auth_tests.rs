@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use zyncdb_core::auth::{Throttle, Tokens, check_password, hash_secret, verify_secret};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zyncdb_auth_{}_{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_passwords_match_in_plain_text_or_as_hashes() {
+    let hash = hash_secret("s3cret");
+    assert!(hash.starts_with("$argon2"));
+    assert!(verify_secret("s3cret", &hash));
+    assert!(!verify_secret("s3cre", &hash));
+    assert!(!verify_secret("s3cret", "not a hash"));
+
+    assert!(check_password("s3cret", "s3cret"));
+    assert!(!check_password("s3cret", "s3cret "));
+    assert!(!check_password("s3cret", ""));
+    assert!(check_password(&hash, "s3cret"));
+    assert!(!check_password(&hash, &hash));
+}
+
+#[test]
+fn test_tokens_are_hashed_expire_and_persist() {
+    let dir = temp_dir("tokens");
+    let path = dir.join(".zyncdb.tokens");
+    let mut tokens = Tokens::load(&path).unwrap();
+
+    let ci = tokens.create("ci", "default", None).unwrap();
+    // Expiry counts whole seconds, so give hashing time to finish first.
+    let brief = tokens.create("brief", "alice", Some(2)).unwrap();
+    assert!(ci.starts_with("zdb_"));
+    assert_eq!(tokens.create("ci", "default", None).unwrap_err().to_string(), "token 'ci' already exists");
+    assert!(tokens.find(&ci).is_some_and(|token| token.verify(&ci)));
+    assert_eq!(tokens.find(&brief).unwrap().user, "alice");
+    assert!(!tokens.find(&ci).unwrap().verify(&brief));
+    // A guess with a real id still needs the secret.
+    let (id, _) = ci.rsplit_once('_').unwrap();
+    assert!(!tokens.find(&format!("{}_guess", id)).unwrap().verify(&format!("{}_guess", id)));
+    assert!(tokens.find("zdb_nope_nope").is_none());
+    assert!(!std::fs::read_to_string(&path).unwrap().contains(ci.rsplit('_').next().unwrap()));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    let mut reloaded = Tokens::load(&path).unwrap();
+    assert_eq!(reloaded.list().map(|token| token.name.as_str()).collect::<Vec<_>>(), ["ci", "brief"]);
+    std::thread::sleep(Duration::from_millis(2100));
+    assert!(reloaded.find(&brief).is_none());
+    assert!(reloaded.revoke("ci").unwrap());
+    assert!(!reloaded.revoke("ci").unwrap());
+    assert_eq!(Tokens::load(&path).unwrap().list().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_throttle_backs_off_after_repeated_failures() {
+    let mut throttle = Throttle::default();
+    for _ in 0..3 {
+        assert_eq!(throttle.wait("10.0.0.1"), None);
+        throttle.failed("10.0.0.1");
+    }
+    let wait = throttle.wait("10.0.0.1").unwrap();
+    assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    assert_eq!(throttle.wait("10.0.0.2"), None);
+
+    throttle.failed("10.0.0.1");
+    assert!(throttle.wait("10.0.0.1").unwrap() > Duration::from_millis(900));
+    throttle.succeeded("10.0.0.1");
+    assert_eq!(throttle.wait("10.0.0.1"), None);
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use parser::Command;
+use zyncdb_core::{Charset, KvError, LimitError, Reply};
+use zyncdb_core::config::{BackendKind, Config, SaveRule};
+use zyncdb_core::wal::FsyncPolicy;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zyncdb_config_{}_{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn args(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_config_files_take_redis_and_toml_lines() {
+    let config = Config::parse(
+        "# redis.conf style\n\
+         port 7000\n\
+         save 900 1\n\
+         save 300 10\n\
+         maxmemory 2mb\n\
+         \n\
+         [server]\n\
+         bind = \"0.0.0.0\"  # everywhere\n\
+         data-dir = '/var/lib/zyncdb'\n\
+         Append_Fsync = always\n\
+         max_clients = 5\n\
+         log_level = \"warning\"\n",
+    )
+    .unwrap();
+
+    assert_eq!(config.port, 7000);
+    assert_eq!(config.addr(), "0.0.0.0:7000");
+    assert_eq!(config.dir, PathBuf::from("/var/lib/zyncdb"));
+    assert_eq!(config.wal_path(), PathBuf::from("/var/lib/zyncdb/.zyncdb.wal"));
+    assert_eq!(config.appendfsync, FsyncPolicy::Always);
+    assert_eq!(
+        config.save,
+        vec![SaveRule { seconds: 900, changes: 1 }, SaveRule { seconds: 300, changes: 10 }]
+    );
+    assert_eq!(config.maxclients, 5);
+    assert_eq!(config.maxmemory, 2 * 1024 * 1024);
+    assert_eq!(config.loglevel, "warn");
+    assert_eq!(config.backend, BackendKind::Memory);
+    assert!(Config::parse("save \"\"\n").unwrap().save.is_empty());
+    let unix = Config::parse("port 0\nunixsocket /run/zyncdb.sock\nunixsocketperm 770\n").unwrap();
+    assert_eq!((unix.port, unix.unixsocketperm), (0, 0o770));
+    assert_eq!(unix.get("unixsocketperm").unwrap(), "770");
+
+    let error = |text: &str| Config::parse(text).unwrap_err().to_string();
+    assert_eq!(error("\nport 70000\n"), "line 2: invalid value '70000' for 'port': expected a port number");
+    assert_eq!(
+        error("unixsocketperm 778\n"),
+        "line 1: invalid value '778' for 'unixsocketperm': expected octal permission bits"
+    );
+    assert_eq!(error("colour blue\n"), "line 1: unknown setting 'colour'");
+    assert_eq!(error("maxmemory 1tb\n"), "line 1: invalid value '1tb' for 'maxmemory': expected a size");
+    assert_eq!(error("save 60\n"), "invalid value '60' for 'save': expected '<seconds> <changes>' pairs");
+    assert!(error("dir \"open\n").starts_with("line 1: invalid value"));
+}
+
+#[test]
+fn test_flags_override_environment_override_file() {
+    let dir = temp_dir("precedence");
+    let file = dir.join("zyncdb.conf");
+    std::fs::write(&file, "port 7000\nmaxclients 5\nbackend memory\n").unwrap();
+    let env: HashMap<&str, &str> = HashMap::from([
+        ("ZYNCDB_CONFIG", file.to_str().unwrap()),
+        ("ZYNCDB_PORT", "7001"),
+        ("ZYNCDB_BACKEND", "file"),
+    ]);
+    let env = |name: &str| env.get(name).map(|v| v.to_string());
+
+    let config = Config::load_from(args(&["--port", "7002", "--maxmemory=10k"]), env).unwrap();
+    assert_eq!(config.port, 7002);
+    assert_eq!(config.maxclients, 5);
+    assert_eq!(config.backend, BackendKind::File);
+    assert_eq!(config.maxmemory, 10_000);
+    assert_eq!(config.file, Some(file.clone()));
+
+    // A leading path names the file, as for redis-server.
+    let config = Config::load_from(args(&[file.to_str().unwrap()]), |_| None).unwrap();
+    assert_eq!((config.port, config.backend), (7000, BackendKind::Memory));
+    assert_eq!(Config::load_from(args(&[]), |_| None).unwrap(), Config::default());
+
+    let error = |items: &[&str]| Config::load_from(args(items), |_| None).unwrap_err().to_string();
+    assert_eq!(error(&["--port"]), "missing value for '--port'");
+    assert_eq!(error(&["--colour", "blue"]), "unknown setting 'colour'");
+    assert!(error(&["--config", "/nonexistent/zyncdb.conf"]).starts_with("/nonexistent/zyncdb.conf: "));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_config_commands_get_set_and_rewrite() {
+    let dir = temp_dir("rewrite");
+    let file = dir.join("zyncdb.conf");
+    std::fs::write(
+        &file,
+        "# Connections\nmaxclients 5\n\n[storage]\nappendfsync = \"always\" # safest\nsave 900 1\nsave 300 10\n",
+    )
+    .unwrap();
+    let mut config = Config::load_from(args(&[file.to_str().unwrap(), "--port", "7000"]), |_| None).unwrap();
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+
+    assert_eq!(
+        config.execute(Command::ConfigGet { pattern: "MAX*".to_string() }),
+        Reply::Map(vec![
+            (bulk("maxclients"), bulk("5")),
+            (bulk("maxmemory"), bulk("0")),
+            (bulk("maxkeylen"), bulk("255")),
+            (bulk("maxvaluelen"), bulk("536870912")),
+            (bulk("maxbatch"), bulk("10000")),
+            (bulk("maxnamespacekeys"), bulk("0")),
+        ])
+    );
+    let set = |pairs: &[(&str, &str)]| Command::ConfigSet {
+        pairs: pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+    };
+    assert_eq!(config.execute(set(&[("maxclients", "50"), ("appendfsync", "everysec")])), Reply::Ok);
+    assert_eq!((config.maxclients, config.appendfsync), (50, FsyncPolicy::EverySec));
+
+    // Startup-only settings and bad values leave every setting as it was.
+    assert_eq!(
+        config.execute(set(&[("maxclients", "60"), ("port", "7001")])),
+        Reply::Error("'port' can only be set at startup".to_string())
+    );
+    assert!(config.execute(set(&[("maxmemory", "lots")])).is_error());
+    assert_eq!((config.maxclients, config.port), (50, 7000));
+
+    assert_eq!(config.execute(set(&[("save", "60 1000")])), Reply::Ok);
+    assert_eq!(config.execute(Command::ConfigRewrite), Reply::Ok);
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "# Connections\nmaxclients 50\n\n[storage]\nappendfsync = \"everysec\"\nsave 60 1000\nport 7000\n"
+    );
+    assert_eq!(Config::load_from(args(&[file.to_str().unwrap()]), |_| None).unwrap(), config);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    let mut unsaved = Config::default();
+    assert_eq!(
+        unsaved.execute(Command::ConfigRewrite),
+        Reply::Error("the server is running without a config file".to_string())
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_open_store_reads_the_snapshot_and_the_wal() {
+    let dir = temp_dir("store");
+    let config = Config {
+        dir: dir.join("data"),
+        ..Config::default()
+    };
+
+    let mut store = config.open_store().unwrap();
+    store.insert("a".to_string(), "1".to_string()).unwrap();
+    assert_eq!(store.changes(), 1);
+    store.snapshot_and_compact(&config.snapshot_path(), &config.wal_path()).unwrap();
+    assert_eq!(store.changes(), 0);
+    store.insert("b".to_string(), "2".to_string()).unwrap();
+    store.sync_wal().unwrap();
+    drop(store);
+
+    let mut store = config.open_store().unwrap();
+    assert_eq!(store.get("a"), Some("1".to_string()));
+    assert_eq!(store.get("b"), Some("2".to_string()));
+    assert!(store.used_memory() > 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_limits_are_settings_the_store_opens_with() {
+    let dir = temp_dir("limits");
+    let mut config = Config::parse(
+        "max-key-len 8\n\
+         key-charset identifier\n\
+         max-value-len 1k\n\
+         allow-empty-values yes\n\
+         max-namespace-keys 2\n",
+    )
+    .unwrap();
+    config.dir = dir.join("data");
+    assert_eq!(config.limits.max_key_len, 8);
+    assert_eq!(config.limits.charset, Charset::Identifier);
+    assert_eq!(config.limits.max_value_len, 1000);
+    assert!(config.limits.allow_empty_values);
+    assert_eq!(config.limits.max_namespace_keys, Some(2));
+    assert_eq!(config.get("maxbatch").unwrap(), "10000");
+    assert_eq!(config.get("keycharset").unwrap(), "identifier");
+
+    let mut store = config.open_store().unwrap();
+    assert_eq!(
+        store.insert("k".repeat(9), "v".to_string()).unwrap_err(),
+        KvError::Limit(LimitError::KeyTooLong(8))
+    );
+    assert_eq!(
+        store.insert("a b".to_string(), "v".to_string()).unwrap_err(),
+        KvError::Limit(LimitError::KeyCharacter(' '))
+    );
+    store.insert("empty".to_string(), String::new()).unwrap();
+
+    assert_eq!(
+        config.execute(Command::ConfigSet { pairs: vec![("max-namespace-keys".to_string(), "0".to_string())] }),
+        Reply::Ok
+    );
+    assert_eq!(config.limits.max_namespace_keys, None);
+    assert_eq!(
+        config.set("keycharset", "ascii").unwrap_err().to_string(),
+        "invalid value 'ascii' for 'keycharset': expected 'printable', 'identifier' or 'any'"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
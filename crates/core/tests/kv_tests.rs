@@ -0,0 +1,898 @@
+use std::fs::remove_file;
+use std::path::PathBuf;
+
+use parser::{Parser, SetCondition, SimpleParser};
+use zyncdb_core::list::ListEnd;
+use zyncdb_core::exec::execute;
+use zyncdb_core::wal::Wal;
+use zyncdb_core::{Charset, KvError, KvStore, LimitError, Limits, Reply};
+
+fn temp_path() -> PathBuf {
+    let unique = format!("zyncdb_kv_test_{}.wal", uuid::Uuid::new_v4());
+    std::env::temp_dir().join(unique)
+}
+
+#[test]
+fn test_incr_and_decr() {
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    assert_eq!(store.incr("hits"), Ok(1));
+    assert_eq!(store.incr_by("hits", 41), Ok(42));
+    assert_eq!(store.decr("hits"), Ok(41));
+    assert_eq!(store.decr_by("hits", 50), Ok(-9));
+    assert_eq!(store.get("hits"), Some("-9".to_string()));
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_incr_errors() {
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    store.insert("name".to_string(), "alice".to_string()).unwrap();
+    assert_eq!(store.incr("name"), Err(KvError::NotInteger));
+    assert_eq!(store.incr_by_float("name", 1.0), Err(KvError::NotFloat));
+
+    store.insert("padded".to_string(), " 5 ".to_string()).unwrap();
+    assert_eq!(store.incr("padded"), Err(KvError::NotInteger));
+    assert_eq!(store.incr_by_float("padded", 1.0), Err(KvError::NotFloat));
+    assert_eq!(store.get("padded"), Some(" 5 ".to_string()));
+
+    store.insert("big".to_string(), i64::MAX.to_string()).unwrap();
+    assert_eq!(store.incr("big"), Err(KvError::Overflow));
+    assert_eq!(store.decr_by("big", i64::MIN), Err(KvError::Overflow));
+    assert_eq!(store.get("big"), Some(i64::MAX.to_string()));
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_incr_by_float_is_logged() {
+    let path = temp_path();
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        assert_eq!(store.incr_by_float("price", 10.5), Ok(10.5));
+        assert_eq!(store.incr_by_float("price", 0.25), Ok(10.75));
+    }
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("price"), Some("10.75".to_string()));
+    }
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_string_commands() {
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    assert_eq!(store.append("greeting", "Hello"), Ok(5));
+    assert_eq!(store.append("greeting", " World"), Ok(11));
+    assert_eq!(store.strlen("greeting"), Ok(11));
+    assert_eq!(store.get_range("greeting", 0, 4), Ok("Hello".to_string()));
+    assert_eq!(store.get_range("greeting", -5, -1), Ok("World".to_string()));
+    assert_eq!(store.get_range("greeting", 20, 30), Ok(String::new()));
+
+    assert_eq!(store.set_range("greeting", 6, "Redis"), Ok(11));
+    assert_eq!(store.get("greeting"), Some("Hello Redis".to_string()));
+    assert_eq!(store.set_range("pad", 3, "x"), Ok(4));
+    assert_eq!(store.get("pad"), Some("\0\0\0x".to_string()));
+
+    assert_eq!(
+        store.get_set("greeting", "bye".to_string()),
+        Ok(Some("Hello Redis".to_string()))
+    );
+    assert_eq!(store.get_del("greeting"), Ok(Some("bye".to_string())));
+    assert_eq!(store.get("greeting"), None);
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_conditional_and_multi_key_sets() {
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    assert_eq!(store.set_nx("a", "1".to_string()), Ok(true));
+    assert_eq!(store.set_nx("a", "2".to_string()), Ok(false));
+    assert_eq!(store.set_xx("a", "3".to_string()), Ok(true));
+    assert_eq!(store.set_xx("missing", "x".to_string()), Ok(false));
+
+    store.mset(vec![("b".to_string(), "2".to_string()), ("c".to_string(), "3".to_string())]).unwrap();
+    assert_eq!(store.mset_nx(vec![("c".to_string(), "x".to_string()), ("d".to_string(), "4".to_string())]), Ok(false));
+    assert_eq!(store.get("d"), None);
+
+    let keys = ["a", "b", "d"].map(String::from);
+    assert_eq!(
+        store.mget(&keys),
+        vec![Some("3".to_string()), Some("2".to_string()), None]
+    );
+
+    let _ = remove_file(&path);
+}
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_list_commands() {
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    assert_eq!(store.rpush("jobs", strings(&["b", "c"])), Ok(2));
+    assert_eq!(store.lpush("jobs", strings(&["a"])), Ok(3));
+    assert_eq!(store.lrange("jobs", 0, -1), Ok(strings(&["a", "b", "c"])));
+    assert_eq!(store.lindex("jobs", -1), Ok(Some("c".to_string())));
+    assert_eq!(store.llen("jobs"), Ok(3));
+
+    assert_eq!(store.lmove("jobs", "done", ListEnd::Left, ListEnd::Right), Ok(Some("a".to_string())));
+    assert_eq!(store.rpop("jobs", 5), Ok(strings(&["c", "b"])));
+    assert_eq!(store.key_type("jobs"), None);
+    assert_eq!(store.key_type("done"), Some("list"));
+
+    store.rpush("nums", strings(&["1", "2", "3", "4"])).unwrap();
+    store.ltrim("nums", 1, -2).unwrap();
+    assert_eq!(store.lrange("nums", 0, -1), Ok(strings(&["2", "3"])));
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_wrong_type_errors() {
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    store.insert("name".to_string(), "alice".to_string()).unwrap();
+    store.rpush("jobs", strings(&["a"])).unwrap();
+    assert_eq!(store.lpush("name", strings(&["x"])), Err(KvError::WrongType));
+    assert_eq!(store.get_string("jobs"), Err(KvError::WrongType));
+    assert_eq!(store.incr("jobs"), Err(KvError::WrongType));
+    assert_eq!(
+        execute(&mut store, SimpleParser.parse("get jobs").unwrap()),
+        Reply::Error(KvError::WrongType.to_string())
+    );
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_lists_survive_restart_and_snapshot() {
+    let wal_path = temp_path();
+    let snapshot_path = temp_path();
+    {
+        let mut store = KvStore::open(&wal_path).unwrap();
+        store.rpush("jobs", strings(&["a", "b|c", "d"])).unwrap();
+        store.lpop("jobs", 1).unwrap();
+    }
+    {
+        let mut store = KvStore::open_with_snapshot(&snapshot_path, &wal_path).unwrap();
+        assert_eq!(store.lrange("jobs", 0, -1), Ok(strings(&["b|c", "d"])));
+        store.snapshot_and_compact(&snapshot_path, &wal_path).unwrap();
+        store.rpush("jobs", strings(&["e"])).unwrap();
+    }
+    {
+        let mut store = KvStore::open_with_snapshot(&snapshot_path, &wal_path).unwrap();
+        assert_eq!(store.lrange("jobs", 0, -1), Ok(strings(&["b|c", "d", "e"])));
+    }
+
+    let _ = remove_file(&wal_path);
+    let _ = remove_file(&snapshot_path);
+}
+
+#[test]
+fn test_hash_commands_and_field_level_wal() {
+    let path = temp_path();
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        let pairs = vec![
+            ("name".to_string(), "alice".to_string()),
+            ("email".to_string(), "a@example.com".to_string()),
+        ];
+        assert_eq!(store.hset("user:1", pairs), Ok(2));
+        assert_eq!(store.hset("user:1", vec![("name".to_string(), "Alice".to_string())]), Ok(0));
+        assert_eq!(store.hincr_by("user:1", "visits", 3), Ok(3));
+        assert_eq!(store.hdel("user:1", &strings(&["email", "missing"])), Ok(1));
+        assert_eq!(store.hexists("user:1", "email"), Ok(false));
+        assert_eq!(store.hlen("user:1"), Ok(2));
+        assert_eq!(store.incr("user:1"), Err(KvError::WrongType));
+    }
+
+    // Each change is its own record instead of a rewrite of the whole hash.
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(log.lines().all(|l| l.starts_with("HSET|") || l.starts_with("HDEL|")));
+    assert!(log.contains("HSET|user:1|visits|3"));
+
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        let mut all = store.hgetall("user:1").unwrap();
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                ("name".to_string(), "Alice".to_string()),
+                ("visits".to_string(), "3".to_string()),
+            ]
+        );
+        assert_eq!(
+            store.hmget("user:1", &strings(&["name", "email"])),
+            Ok(vec![Some("Alice".to_string()), None])
+        );
+    }
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_set_commands() {
+    let path = temp_path();
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        assert_eq!(store.sadd("a", strings(&["x", "y", "z", "x"])), Ok(3));
+        assert_eq!(store.sadd("b", strings(&["y", "z", "w"])), Ok(3));
+        assert_eq!(store.srem("a", &strings(&["z"])), Ok(1));
+        assert_eq!(store.sismember("a", "x"), Ok(true));
+        assert_eq!(store.scard("a"), Ok(2));
+        assert_eq!(store.sinter(&strings(&["a", "b"])), Ok(strings(&["y"])));
+        assert_eq!(store.sunion(&strings(&["a", "b"])), Ok(strings(&["w", "x", "y", "z"])));
+        assert_eq!(store.sdiff(&strings(&["a", "b", "missing"])), Ok(strings(&["x"])));
+    }
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        assert_eq!(store.smembers("a"), Ok(strings(&["x", "y"])));
+    }
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_sorted_set_commands() {
+    use zyncdb_core::zset::{LexBound, ScoreBound, ZRange};
+
+    let path = temp_path();
+    let snapshot = temp_path();
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        let members = vec![
+            (100.0, "alice".to_string()),
+            (250.5, "bob".to_string()),
+            (50.0, "carol".to_string()),
+        ];
+        assert_eq!(store.zadd("board", members), Ok(3));
+        assert_eq!(store.zincr_by("board", 200.0, "alice"), Ok(300.0));
+        assert_eq!(store.zrank("board", "alice"), Ok(Some(2)));
+        assert_eq!(store.zscore("board", "bob"), Ok(Some(250.5)));
+
+        let by_rank = store.zrange("board", &ZRange::Rank { start: 0, stop: -1 }).unwrap();
+        let names: Vec<_> = by_rank.iter().map(|(m, _)| m.as_str()).collect();
+        assert_eq!(names, ["carol", "bob", "alice"]);
+
+        let by_score = ZRange::Score {
+            min: ScoreBound { value: 50.0, exclusive: true },
+            max: ScoreBound { value: f64::INFINITY, exclusive: false },
+        };
+        assert_eq!(
+            store.zrange("board", &by_score),
+            Ok(vec![("bob".to_string(), 250.5), ("alice".to_string(), 300.0)])
+        );
+
+        let by_lex = ZRange::Lex {
+            min: LexBound::Inclusive("b".to_string()),
+            max: LexBound::Max,
+        };
+        assert_eq!(store.zrange("board", &by_lex).unwrap().len(), 2);
+        assert_eq!(store.zpop_min("board", 1), Ok(vec![("carol".to_string(), 50.0)]));
+        store.snapshot_and_compact(&snapshot, &path).unwrap();
+        store.zrem("board", &strings(&["bob"])).unwrap();
+    }
+    {
+        let mut store = KvStore::open_with_snapshot(&snapshot, &path).unwrap();
+        let all = store.zrange("board", &ZRange::Rank { start: 0, stop: -1 }).unwrap();
+        assert_eq!(all, vec![("alice".to_string(), 300.0)]);
+    }
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&snapshot);
+}
+
+fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect()
+}
+
+#[test]
+fn test_stream_add_range_and_trim() {
+    use zyncdb_core::stream::StreamTrim;
+
+    let path = temp_path();
+    let mut store = KvStore::open(&path).unwrap();
+
+    let first = store.xadd("events", "*", fields(&[("type", "login")]), None).unwrap();
+    let second = store.xadd("events", "*", fields(&[("type", "logout")]), None).unwrap();
+    assert!(second > first);
+    assert_eq!(
+        store.xadd("events", "1-0", fields(&[("a", "b")]), None),
+        Err(KvError::StreamIdTooSmall)
+    );
+
+    let all = store.xrange("events", "-", "+", None).unwrap();
+    assert_eq!(all.len(), 2);
+    assert_eq!(all[1].1, fields(&[("type", "logout")]));
+    assert_eq!(store.xrange("events", "-", "+", Some(1)).unwrap()[0].0, first);
+
+    let after_first = store.xread(&[("events".to_string(), first.to_string())], None).unwrap();
+    assert_eq!(after_first[0].1.len(), 1);
+
+    store.xadd("events", "*", fields(&[("type", "login")]), Some(&StreamTrim::MaxLen(2))).unwrap();
+    assert_eq!(store.xlen("events"), Ok(2));
+    assert_eq!(store.xtrim("events", &StreamTrim::MinId("+".to_string())), Err(KvError::InvalidStreamId));
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_stream_consumer_groups_survive_restart() {
+    let path = temp_path();
+    let snapshot = temp_path();
+    let streams = vec![("jobs".to_string(), ">".to_string())];
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        assert_eq!(store.xgroup_create("jobs", "workers", "$", false), Err(KvError::NoSuchKey));
+        store.xgroup_create("jobs", "workers", "$", true).unwrap();
+        assert_eq!(store.xgroup_create("jobs", "workers", "$", false), Err(KvError::GroupExists));
+
+        store.xadd("jobs", "1-1", fields(&[("task", "a")]), None).unwrap();
+        store.xadd("jobs", "1-2", fields(&[("task", "b")]), None).unwrap();
+        let read = store.xreadgroup("workers", "alice", &streams, Some(1), false).unwrap();
+        assert_eq!(read[0].1[0].0.to_string(), "1-1");
+        store.snapshot_and_compact(&snapshot, &path).unwrap();
+        let read = store.xreadgroup("workers", "bob", &streams, None, false).unwrap();
+        assert_eq!(read[0].1[0].0.to_string(), "1-2");
+        assert_eq!(store.xack("jobs", "workers", &strings(&["1-2"])), Ok(1));
+    }
+    {
+        let mut store = KvStore::open_with_snapshot(&snapshot, &path).unwrap();
+        assert!(store.xreadgroup("workers", "carol", &streams, None, false).unwrap().is_empty());
+
+        let pending = store.xpending("jobs", "workers").unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].1, "alice");
+
+        let claimed = store.xclaim("jobs", "workers", "carol", 0, &strings(&["1-1"])).unwrap();
+        assert_eq!(claimed.len(), 1);
+        let pending = store.xpending("jobs", "workers").unwrap();
+        assert_eq!((pending[0].1.as_str(), pending[0].3), ("carol", 2));
+
+        let history = vec![("jobs".to_string(), "0".to_string())];
+        assert_eq!(store.xreadgroup("workers", "carol", &history, None, false).unwrap()[0].1.len(), 1);
+    }
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&snapshot);
+}
+
+#[test]
+fn test_json_paths_and_patch_level_wal() {
+    let path = temp_path();
+    let snapshot = temp_path();
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        let doc = r#"{"name":"ada","tags":["a"],"stats":{"visits":1,"score":1.5},"items":[{"n":1},{"n":2}]}"#;
+        assert_eq!(store.json_set("user", "$.name", "\"x\"", SetCondition::Always), Err(KvError::JsonRootRequired));
+        assert!(matches!(store.json_set("user", "$", "{bad", SetCondition::Always), Err(KvError::InvalidJson(_))));
+        assert_eq!(store.json_set("user", "$", doc, SetCondition::Always), Ok(true));
+        assert_eq!(store.json_set("user", "$.name", "\"bob\"", SetCondition::IfNotExists), Ok(false));
+        assert_eq!(store.json_set("user", "$.age", "36", SetCondition::IfNotExists), Ok(true));
+        store.snapshot_and_compact(&snapshot, &path).unwrap();
+
+        assert_eq!(store.json_get("user", Some(".name")), Ok(Some("\"ada\"".to_string())));
+        assert_eq!(store.json_get("user", Some("$..n")), Ok(Some("[1,2]".to_string())));
+        assert_eq!(store.json_get("user", Some("$.items[-1].n")), Ok(Some("[2]".to_string())));
+        assert_eq!(store.json_arrappend("user", "$.tags", &strings(&["\"b\"", "3"])), Ok(vec![Some(3)]));
+        assert_eq!(store.json_num_incr_by("user", ".stats.visits", "2"), Ok("3".to_string()));
+        assert_eq!(store.json_num_incr_by("user", "$.stats.*", "1"), Ok("[2.5,4]".to_string()));
+        assert_eq!(store.json_num_incr_by("user", ".name", "1"), Err(KvError::JsonNotNumber));
+        assert_eq!(store.json_del("user", Some("$.items[*].n")), Ok(2));
+        assert_eq!(store.key_type("user"), Some("json"));
+        assert_eq!(store.incr("user"), Err(KvError::WrongType));
+    }
+
+    // Edits are logged as path-level patches, not whole-document rewrites.
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(log.lines().all(|l| l.starts_with("JSON.")));
+    assert!(log.contains("JSON.NUMINCRBY|user|.stats.visits|2"));
+
+    {
+        let mut store = KvStore::open_with_snapshot(&snapshot, &path).unwrap();
+        assert_eq!(
+            store.json_get("user", None),
+            Ok(Some(
+                r#"{"age":36,"items":[{},{}],"name":"ada","stats":{"score":2.5,"visits":4},"tags":["a","b",3]}"#
+                    .to_string()
+            ))
+        );
+        assert_eq!(store.json_del("user", Some("$")), Ok(1));
+        assert_eq!(store.json_get("user", None), Ok(None));
+    }
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&snapshot);
+}
+
+#[test]
+fn test_batches_report_each_result_and_roll_back_atomically() {
+    let path = temp_path();
+    let run = |store: &mut KvStore, line: &str| execute(store, SimpleParser.parse(line).unwrap());
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        let reply = run(&mut store, "batch put a 1; incr a; incr missing; get a");
+        assert_eq!(
+            reply,
+            Reply::Array(vec![Reply::Ok, Reply::Integer(2), Reply::Integer(1), Reply::Bulk("2".to_string())])
+        );
+
+        // The failing INCR undoes the earlier PUT and LPUSH, and nothing reaches the WAL.
+        let reply = run(&mut store, "batch atomic put a 10; lpush l x; incr l; put b 1");
+        let Reply::Array(items) = reply else { panic!("expected an array") };
+        assert_eq!(items[2], Reply::Error(KvError::WrongType.to_string()));
+        assert!(items.iter().all(Reply::is_error));
+        assert_eq!(store.get("a"), Some("2".to_string()));
+        assert_eq!(store.key_type("l"), None);
+        assert_eq!(store.get("b"), None);
+
+        assert!(!run(&mut store, "batch atomic put a 3; rpush l y; ttl a 100").is_error());
+    }
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(!log.contains("|10"));
+    assert!(log.contains("BEGIN\nPUT|a|3\nRPUSH|l|y\nCOMMIT\n"));
+
+    // A group cut short by a crash is not applied.
+    std::fs::write(&path, format!("{}BEGIN\nPUT|a|4\n", log)).unwrap();
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("a"), Some("3".to_string()));
+        assert_eq!(store.lrange("l", 0, -1), Ok(strings(&["y"])));
+    }
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_sql_queries_over_a_namespace() {
+    let path = temp_path();
+    let run = |store: &mut KvStore, line: &str| execute(store, SimpleParser.parse(line).unwrap());
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        store.json_set("user:1", "$", r#"{"name":"ann","age":31}"#, SetCondition::Always).unwrap();
+        store.json_set("user:2", "$", r#"{"name":"bob","age":25}"#, SetCondition::Always).unwrap();
+        store.insert("user:3".to_string(), r#"{"name":"cy","age":40}"#.to_string()).unwrap();
+        store.insert("user:4".to_string(), "plain".to_string()).unwrap();
+        store.insert("order:1".to_string(), "ignored".to_string()).unwrap();
+
+        assert_eq!(run(&mut store, "select count(*) from user"), Reply::Integer(4));
+        assert_eq!(
+            run(&mut store, "select key, json_extract(value, '$.name') from user where json_extract(value, '$.age') > 30 order by json_extract(value, '$.age') desc"),
+            Reply::Array(vec![
+                Reply::Array(vec![bulk("user:3"), bulk("cy")]),
+                Reply::Array(vec![bulk("user:1"), bulk("ann")]),
+            ])
+        );
+        // Comparisons with a missing field are NULL, so the row is dropped either way.
+        assert_eq!(run(&mut store, "select count(*) from user where not json_extract(value, '$.age') > 30"), Reply::Integer(1));
+        assert_eq!(
+            run(&mut store, "select key from user where value like '%\"b_b\"%' or key = 'user:4' limit 5 offset 1"),
+            Reply::Array(vec![Reply::Array(vec![bulk("user:4")])])
+        );
+
+        assert_eq!(
+            run(&mut store, "explain select * from user where key = 'user:2' limit 1"),
+            Reply::Array(vec![
+                Reply::Status("KEY user:2".to_string()),
+                Reply::Status("FILTER".to_string()),
+                Reply::Status("LIMIT 1 OFFSET 0".to_string()),
+            ])
+        );
+        assert_eq!(
+            run(&mut store, "explain delete from user where key like 'user:1%'"),
+            Reply::Array(vec![
+                Reply::Status("PREFIX user:1*".to_string()),
+                Reply::Status("FILTER".to_string()),
+                Reply::Status("DELETE".to_string()),
+            ])
+        );
+
+        assert_eq!(
+            run(&mut store, "update user set json_extract(value, '$.age') = 32 where key = 'user:1'"),
+            Reply::Integer(1)
+        );
+        assert_eq!(store.json_get("user:1", Some("$.age")), Ok(Some("[32]".to_string())));
+
+        // A failing row undoes the rows updated before it.
+        store.lpush("user:5", strings(&["x"])).unwrap();
+        let reply = run(&mut store, "update user set value = 'reset'");
+        assert_eq!(reply, Reply::Error(KvError::WrongType.to_string()));
+        assert_eq!(store.get("user:4"), Some("plain".to_string()));
+
+        assert_eq!(run(&mut store, "delete from user where key >= 'user:4'"), Reply::Integer(2));
+        assert_eq!(run(&mut store, "update user set value = key where key = 'user:3'"), Reply::Integer(1));
+    }
+
+    let mut store = KvStore::open(&path).unwrap();
+    assert_eq!(store.get("user:3"), Some("user:3".to_string()));
+    assert_eq!(store.json_get("user:1", Some("$.age")), Ok(Some("[32]".to_string())));
+    assert_eq!(store.key_type("user:4"), None);
+    assert_eq!(store.get("order:1"), Some("ignored".to_string()));
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_secondary_indexes_are_maintained_and_rebuilt() {
+    let path = temp_path();
+    let catalog = path.with_extension("catalog");
+    let run = |store: &mut KvStore, line: &str| execute(store, SimpleParser.parse(line).unwrap());
+    let explain = |store: &mut KvStore, line: &str| match run(store, line) {
+        Reply::Array(steps) => steps[0].clone(),
+        other => panic!("unexpected {:?}", other),
+    };
+    let status = |s: &str| Reply::Status(s.to_string());
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        store.json_set("user:1", "$", r#"{"email":"a@x","age":31}"#, SetCondition::Always).unwrap();
+        store.json_set("user:2", "$", r#"{"email":"b@x","age":25}"#, SetCondition::Always).unwrap();
+        store.insert("user:3".to_string(), r#"{"age":40}"#.to_string()).unwrap();
+
+        assert_eq!(run(&mut store, "create unique index by_email on user (json_extract(value, '$.email'))"), Reply::Ok);
+        assert_eq!(run(&mut store, "create index by_age on user (json_extract(value, '$.age'))"), Reply::Ok);
+        assert_eq!(run(&mut store, "create index code on user (value(3))"), Reply::Ok);
+        assert_eq!(
+            run(&mut store, "create index by_age on user (value)"),
+            Reply::Error(KvError::IndexExists("by_age".to_string()).to_string())
+        );
+
+        assert_eq!(
+            explain(&mut store, "explain select key from user where json_extract(value, '$.email') = 'b@x'"),
+            status("INDEX by_email = 'b@x'")
+        );
+        assert_eq!(
+            explain(&mut store, "explain select key from user where 30 < json_extract(value, '$.age') and json_extract(value, '$.age') <= 40"),
+            status("INDEX by_age > 30 <= 40")
+        );
+        assert_eq!(
+            run(&mut store, "select key from user where 30 < json_extract(value, '$.age') and json_extract(value, '$.age') <= 40"),
+            Reply::Array(vec![
+                Reply::Array(vec![Reply::Bulk("user:1".to_string())]),
+                Reply::Array(vec![Reply::Bulk("user:3".to_string())]),
+            ])
+        );
+
+        // A duplicate is refused before anything changes or reaches the WAL.
+        let duplicate = store.json_set("user:9", "$", r#"{"email":"a@x"}"#, SetCondition::Always);
+        assert_eq!(duplicate, Err(KvError::UniqueViolation("by_email".to_string())));
+        assert_eq!(store.key_type("user:9"), None);
+        assert_eq!(
+            run(&mut store, "batch atomic put user:8 {\"email\":\"c@x\"}; update user set json_extract(value, '$.email') = 'a@x' where key = 'user:2'"),
+            Reply::Array(vec![
+                Reply::Error("EXECABORT batch rolled back".to_string()),
+                Reply::Error(KvError::UniqueViolation("by_email".to_string()).to_string()),
+            ])
+        );
+        assert_eq!(store.key_type("user:8"), None);
+
+        // Entries follow updates and deletes; the old value no longer matches.
+        store.json_set("user:1", "$.email", r#""z@x""#, SetCondition::Always).unwrap();
+        store.json_set("user:2", "$.email", r#""a@x""#, SetCondition::Always).unwrap();
+        store.delete("user:3");
+        assert_eq!(run(&mut store, "select count(*) from user where json_extract(value, '$.age') >= 25"), Reply::Integer(2));
+        assert_eq!(run(&mut store, "drop index code"), Reply::Ok);
+    }
+    assert!(std::fs::read_to_string(&catalog).unwrap().contains("INDEX|by_email|user|json|$.email|1"));
+
+    // Definitions come back from the catalog and entries from the data.
+    let mut store = KvStore::open(&path).unwrap();
+    assert_eq!(store.index_names("user"), strings(&["by_age", "by_email"]));
+    assert_eq!(
+        run(&mut store, "select key from user where json_extract(value, '$.email') = 'a@x'"),
+        Reply::Array(vec![Reply::Array(vec![Reply::Bulk("user:2".to_string())])])
+    );
+    assert_eq!(
+        store.insert("user:4".to_string(), r#"{"email":"z@x"}"#.to_string()),
+        Err(KvError::UniqueViolation("by_email".to_string()))
+    );
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&catalog);
+}
+
+#[test]
+fn test_failed_catalog_writes_are_reported_and_undone() {
+    let path = temp_path();
+    let catalog = path.with_extension("catalog");
+    let tmp = path.with_extension("catalog.tmp");
+    let run = |store: &mut KvStore, line: &str| execute(store, SimpleParser.parse(line).unwrap());
+    let mut store = KvStore::open(&path).unwrap();
+    store.json_set("user:1", "$", r#"{"email":"a@x"}"#, SetCondition::Always).unwrap();
+    assert_eq!(run(&mut store, "create index by_email on user (json_extract(value, '$.email'))"), Reply::Ok);
+    let saved = std::fs::read_to_string(&catalog).unwrap();
+
+    // A directory in the temporary file's place makes every write fail.
+    std::fs::create_dir(&tmp).unwrap();
+    let failed = |reply: Reply| matches!(reply, Reply::Error(e) if e.starts_with("catalog write failed"));
+    assert!(failed(run(&mut store, "create index by_age on user (json_extract(value, '$.age'))")));
+    assert!(failed(run(&mut store, "drop index by_email")));
+    assert!(failed(run(&mut store, "schema set user type json")));
+    assert_eq!(store.index_names("user"), vec!["by_email".to_string()]);
+    assert_eq!(run(&mut store, "schema get user"), Reply::Nil);
+    assert_eq!(std::fs::read_to_string(&catalog).unwrap(), saved);
+
+    std::fs::remove_dir(&tmp).unwrap();
+    assert_eq!(run(&mut store, "drop index by_email"), Reply::Ok);
+    assert!(store.index_names("user").is_empty());
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&catalog);
+}
+
+#[test]
+fn test_namespace_schemas_validate_writes_and_migrate() {
+    let path = temp_path();
+    let catalog = path.with_extension("catalog");
+    let run = |store: &mut KvStore, line: &str| execute(store, SimpleParser.parse(line).unwrap());
+    let violation = |reason: &str| KvError::SchemaViolation(reason.to_string());
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        store.json_set("user:1", "$", r#"{"name":"ann","age":31}"#, SetCondition::Always).unwrap();
+        assert_eq!(
+            run(&mut store, r#"schema set user keys u? type json maxkeylen 8 required name jsonschema '{"properties":{"age":{"type":"integer","minimum":0}}}'"#),
+            Reply::Error(KvError::SchemaViolation("key 'user:1' does not match 'user:u?'".to_string()).to_string())
+        );
+        assert_eq!(run(&mut store, "schema get user"), Reply::Nil);
+        assert_eq!(
+            run(&mut store, r#"schema set user version 1 keys ? type json maxkeylen 8 required name jsonschema '{"properties":{"age":{"type":"integer","minimum":0}}}'"#),
+            Reply::Integer(1)
+        );
+
+        assert_eq!(store.insert("user:2".to_string(), "ann".to_string()).unwrap_err(), violation("'user:2' must hold a json value, not string"));
+        assert_eq!(
+            store.json_set("user:2", "$", r#"{"age":3}"#, SetCondition::Always).unwrap_err(),
+            violation("'user:2' is missing required member 'name'")
+        );
+        assert_eq!(
+            store.json_set("user:1", "$.age", "-1", SetCondition::Always).unwrap_err(),
+            violation("'user:1' at $.age: must satisfy minimum 0")
+        );
+        assert_eq!(store.rpush("user:3", vec!["a".to_string()]).unwrap_err(), violation("'user:3' must hold a json value, not list"));
+        assert_eq!(store.key_type("user:3"), None);
+        assert_eq!(
+            store.json_set("user:22", "$", r#"{"name":"bo"}"#, SetCondition::Always).unwrap_err(),
+            violation("key 'user:22' does not match 'user:?'")
+        );
+        store.json_set("user:2", "$", r#"{"name":"bo","age":7}"#, SetCondition::Always).unwrap();
+        // Other namespaces are unaffected.
+        store.insert("users".to_string(), "x".to_string()).unwrap();
+
+        // A migration runs before the new version is checked, or not at all.
+        assert_eq!(
+            run(&mut store, "schema set user version 3 type json"),
+            Reply::Error(KvError::SchemaVersion(1).to_string())
+        );
+        assert_eq!(
+            run(&mut store, r#"schema set user version 2 type json required name,tier migrate "update user set json_extract(value, '$.tier') = 'free' where key = 'user:1'""#),
+            Reply::Error(KvError::SchemaViolation("'user:2' is missing required member 'tier'".to_string()).to_string())
+        );
+        assert_eq!(store.json_get("user:1", Some("$.tier")).unwrap(), Some("[]".to_string()));
+        assert_eq!(
+            run(&mut store, r#"schema set user version 2 type json required name,tier migrate "update user set json_extract(value, '$.tier') = 'free'""#),
+            Reply::Integer(2)
+        );
+        assert_eq!(
+            store.json_set("user:2", "$", r#"{"name":"bo"}"#, SetCondition::Always).unwrap_err(),
+            violation("'user:2' is missing required member 'tier'")
+        );
+    }
+    assert!(std::fs::read_to_string(&catalog).unwrap().contains("SCHEMA|user|2||json||||name|tier"));
+
+    // The schema comes back from the catalog, and the migration from the WAL.
+    let mut store = KvStore::open(&path).unwrap();
+    assert_eq!(store.json_get("user:2", Some("$.tier")).unwrap(), Some(r#"["free"]"#.to_string()));
+    assert_eq!(
+        run(&mut store, "schema get user"),
+        Reply::Map(
+            [("version", "2"), ("type", "json"), ("required", "name,tier")]
+                .iter()
+                .map(|(f, v)| (Reply::Bulk(f.to_string()), Reply::Bulk(v.to_string())))
+                .collect()
+        )
+    );
+    assert_eq!(store.insert("user:4".to_string(), "x".to_string()).unwrap_err(), violation("'user:4' must hold a json value, not string"));
+    assert_eq!(run(&mut store, "schema drop user"), Reply::Ok);
+    assert_eq!(store.insert("user:4".to_string(), "x".to_string()), Ok(None));
+    assert_eq!(
+        run(&mut store, "schema drop user"),
+        Reply::Error(KvError::NoSuchSchema("user".to_string()).to_string())
+    );
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&catalog);
+}
+
+#[test]
+fn test_limits_apply_to_every_write_and_replay() {
+    let path = temp_path();
+    let limit = |e: LimitError| KvError::Limit(e);
+    {
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append_put("ok:1", "v").unwrap();
+        wal.append(&["RPUSH", "bad key", "x"]).unwrap();
+    }
+    let limits = Limits {
+        max_key_len: 8,
+        max_value_len: 4,
+        charset: Charset::Identifier,
+        max_batch: 2,
+        max_namespace_keys: Some(2),
+        ..Limits::default()
+    };
+    let mut store = KvStore::open_with_limits(&path, limits).unwrap();
+    // Replay skips the record the limits forbid.
+    assert_eq!(store.get("ok:1"), Some("v".to_string()));
+    assert_eq!(store.key_type("bad key"), None);
+
+    assert_eq!(store.insert("".to_string(), "v".to_string()).unwrap_err(), limit(LimitError::EmptyKey));
+    assert_eq!(store.insert("a b".to_string(), "v".to_string()).unwrap_err(), limit(LimitError::KeyCharacter(' ')));
+    assert_eq!(store.insert("k".repeat(9), "v".to_string()).unwrap_err(), limit(LimitError::KeyTooLong(8)));
+    assert_eq!(store.insert("k".to_string(), String::new()).unwrap_err(), limit(LimitError::EmptyValue));
+    assert_eq!(store.append("ok:1", "long").unwrap_err(), limit(LimitError::ValueTooLarge(4)));
+    assert_eq!(store.rpush("l", strings(&["a", "large"])).unwrap_err(), limit(LimitError::ValueTooLarge(4)));
+    assert_eq!(store.key_type("l"), None);
+    assert_eq!(
+        store.mset(vec![("a".into(), "1".into()), ("b".into(), "2".into()), ("c".into(), "3".into())]).unwrap_err(),
+        limit(LimitError::BatchTooLarge(2))
+    );
+    assert_eq!(
+        execute(&mut store, SimpleParser.parse("batch put a 1; put b 2; put c 3").unwrap()),
+        Reply::Error("batch has more than 2 entries".to_string())
+    );
+
+    // Overwriting an existing key never counts against the namespace.
+    store.insert("ok:2".to_string(), "v".to_string()).unwrap();
+    store.insert("ok:2".to_string(), "w".to_string()).unwrap();
+    assert_eq!(
+        store.sadd("ok:3", strings(&["m"])).unwrap_err(),
+        limit(LimitError::NamespaceFull("ok".to_string(), 2))
+    );
+    store.delete("ok:1");
+    assert_eq!(store.sadd("ok:3", strings(&["m"])), Ok(1));
+    // Emptying a collection frees its key's place as well.
+    assert_eq!(store.srem("ok:3", &strings(&["m"])), Ok(1));
+    store.insert("ok:4".to_string(), "v".to_string()).unwrap();
+    assert_eq!(
+        store.insert("ok:5".to_string(), "v".to_string()).unwrap_err(),
+        limit(LimitError::NamespaceFull("ok".to_string(), 2))
+    );
+
+    // A transaction is checked as each value is buffered, then as a whole.
+    store.begin_tx();
+    assert_eq!(store.insert("a b".to_string(), "v".to_string()).unwrap_err(), limit(LimitError::KeyCharacter(' ')));
+    for key in ["new:1", "new:2", "new:3"] {
+        store.insert(key.to_string(), "v".to_string()).unwrap();
+    }
+    assert_eq!(store.commit_tx().unwrap_err(), limit(LimitError::NamespaceFull("new".to_string(), 2)));
+    assert_eq!((store.get("new:1"), store.get("new:2"), store.get("new:3")), (None, None, None));
+
+    store.set_limits(Limits::default());
+    assert_eq!(store.insert("a b".to_string(), "v".to_string()), Ok(None));
+
+    let _ = remove_file(&path);
+    let _ = remove_file(path.with_extension("catalog"));
+}
+
+#[test]
+fn test_full_text_indexes_rank_and_follow_writes() {
+    let path = temp_path();
+    let catalog = path.with_extension("catalog");
+    let run = |store: &mut KvStore, line: &str| execute(store, SimpleParser.parse(line).unwrap());
+    let keys = |reply: Reply| match reply {
+        Reply::Array(items) => items
+            .into_iter()
+            .filter_map(|item| match item {
+                Reply::Bulk(key) => Some(key),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        other => panic!("unexpected {:?}", other),
+    };
+    {
+        let mut store = KvStore::open(&path).unwrap();
+        store.insert("doc:1".to_string(), "The quick brown fox jumps over the lazy dog".to_string()).unwrap();
+        store.insert("doc:2".to_string(), "Foxes running; a fox ran. Fox!".to_string()).unwrap();
+        store.json_set("doc:3", "$", r#"{"title":"Running shoes","tags":["sport","fox"]}"#, SetCondition::Always).unwrap();
+        store.insert("other:1".to_string(), "fox".to_string()).unwrap();
+
+        assert_eq!(run(&mut store, "ft.create text on doc fields value"), Reply::Ok);
+        assert_eq!(run(&mut store, "ft.create titles on doc fields $.title"), Reply::Ok);
+        assert_eq!(
+            run(&mut store, "ft.create text on doc fields value"),
+            Reply::Error(KvError::IndexExists("text".to_string()).to_string())
+        );
+
+        // Lowercased and stemmed: "Foxes" and "fox" are the same term, and
+        // shorter keys that say it more often rank first.
+        assert_eq!(keys(run(&mut store, "ft.search text FOX")), strings(&["doc:2", "doc:3", "doc:1"]));
+        assert_eq!(run(&mut store, "ft.search text fox limit 1 1"), Reply::Array(vec![Reply::Integer(3), Reply::Bulk("doc:3".to_string())]));
+        assert_eq!(keys(run(&mut store, "ft.search text \"the running fox\"")), strings(&["doc:3", "doc:2"]));
+        assert_eq!(keys(run(&mut store, "ft.search titles run")), strings(&["doc:3"]));
+        assert_eq!(keys(run(&mut store, "ft.search titles fox")), Vec::<String>::new());
+        match run(&mut store, "ft.search text dog withscores") {
+            Reply::Array(items) => {
+                assert_eq!(items[..2], [Reply::Integer(1), Reply::Bulk("doc:1".to_string())]);
+                assert!(matches!(&items[2], Reply::Bulk(score) if score.parse::<f64>().unwrap() > 0.0));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Postings follow overwrites and deletes.
+        store.insert("doc:1".to_string(), "a lazy cat".to_string()).unwrap();
+        store.delete("doc:2");
+        assert_eq!(keys(run(&mut store, "ft.search text fox")), strings(&["doc:3"]));
+        assert_eq!(keys(run(&mut store, "ft.search text cats")), strings(&["doc:1"]));
+        assert_eq!(run(&mut store, "ft.dropindex titles"), Reply::Ok);
+        assert_eq!(
+            run(&mut store, "ft.search titles run"),
+            Reply::Error(KvError::NoSuchIndex("titles".to_string()).to_string())
+        );
+    }
+    assert!(std::fs::read_to_string(&catalog).unwrap().contains("FT|text|doc|value"));
+
+    // Definitions come back from the catalog and postings from the data.
+    let mut store = KvStore::open(&path).unwrap();
+    assert_eq!(keys(run(&mut store, "ft.search text lazy")), strings(&["doc:1"]));
+    assert_eq!(keys(run(&mut store, "ft.search text sport")), strings(&["doc:3"]));
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&catalog);
+}
+
+#[test]
+fn test_used_memory_follows_writes() {
+    let path = temp_path();
+    let snapshot = path.with_extension("snapshot");
+    let mut store = KvStore::open(&path).unwrap();
+    // What a snapshot of the data takes, less its header line.
+    let snapshot_size = |store: &mut KvStore| {
+        store.snapshot_and_compact(&snapshot, &path).unwrap();
+        std::fs::metadata(&snapshot).unwrap().len() as usize - "#zyncdb-snapshot v2\n".len()
+    };
+
+    assert_eq!(store.used_memory(), 0);
+    store.insert("a".to_string(), "1".to_string()).unwrap();
+    store.insert("a".to_string(), "longer".to_string()).unwrap();
+    store.rpush("l", strings(&["x", "y"])).unwrap();
+    store.hset("h", fields(&[("f", "v")])).unwrap();
+    assert_eq!(store.used_memory(), snapshot_size(&mut store));
+
+    // Edited in place, more than once between measurements.
+    store.rpush("l", strings(&["z"])).unwrap();
+    store.lpop("l", 1).unwrap();
+    store.rpush("l", strings(&["zz"])).unwrap();
+    store.hset("h", fields(&[("g", "w")])).unwrap();
+    store.delete("a");
+    assert_eq!(store.used_memory(), snapshot_size(&mut store));
+
+    store.rpush("l", strings(&["more"])).unwrap();
+    store.delete("l");
+    store.hset("h", fields(&[("h", "x")])).unwrap();
+    store.insert("h2".to_string(), "v".to_string()).unwrap();
+    assert_eq!(store.used_memory(), snapshot_size(&mut store));
+
+    store.clear();
+    assert_eq!(store.used_memory(), 0);
+
+    let _ = remove_file(&path);
+    let _ = remove_file(&snapshot);
+    let _ = remove_file(path.with_extension("catalog"));
+}
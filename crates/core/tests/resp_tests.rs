@@ -0,0 +1,68 @@
+use zyncdb_core::Reply;
+use zyncdb_core::resp::{Protocol, ProtocolError, Request, decode_reply, decode_request, encode};
+
+fn args(items: &[&str]) -> Request {
+    Request::Args(items.iter().map(|s| s.to_string()).collect())
+}
+
+#[test]
+fn test_requests_are_framed_inline_or_as_resp_arrays() {
+    let buf = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$6\r\na\r\nb c\r\nget k\nping\r\n";
+    let (first, used) = decode_request(buf).unwrap().unwrap();
+    assert_eq!(first, args(&["SET", "k", "a\r\nb c"]));
+    let (second, more) = decode_request(&buf[used..]).unwrap().unwrap();
+    assert_eq!(second, Request::Inline("get k".to_string()));
+    let (third, rest) = decode_request(&buf[used + more..]).unwrap().unwrap();
+    assert_eq!(third, Request::Inline("ping".to_string()));
+    assert_eq!(used + more + rest, buf.len());
+
+    // A request split across reads waits for the rest.
+    for end in 0..used {
+        assert_eq!(decode_request(&buf[..end]), Ok(None), "prefix of {} bytes", end);
+    }
+    assert_eq!(decode_request(b"*0\r\n"), Ok(Some((args(&[]), 4))));
+
+    assert_eq!(decode_request(b"*1\r\n+PING\r\n"), Err(ProtocolError::Expected("'$'")));
+    assert_eq!(decode_request(b"*x\r\n"), Err(ProtocolError::InvalidLength("multibulk")));
+    assert_eq!(decode_request(b"*1\r\n$-1\r\n"), Err(ProtocolError::InvalidLength("bulk")));
+    assert_eq!(decode_request(b"*1\r\n$1\r\nab\r\n"), Err(ProtocolError::Expected("'\\r\\n'")));
+    assert_eq!(decode_request(b"*1\r\n$1\r\n\xff\r\n"), Err(ProtocolError::InvalidUtf8));
+    assert_eq!(decode_request(&[b'x'; 70_000]), Err(ProtocolError::LineTooLong));
+}
+
+#[test]
+fn test_replies_round_trip_in_each_protocol() {
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+    let reply = Reply::Array(vec![
+        Reply::Ok,
+        Reply::Status("PONG".to_string()),
+        Reply::Integer(-7),
+        bulk("two\nlines"),
+        Reply::Nil,
+        Reply::Map(vec![(bulk("f"), bulk("v"))]),
+        Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+        Reply::Error("key must not be empty".to_string()),
+    ]);
+    let encoded = |protocol| {
+        let mut out = Vec::new();
+        encode(&reply, protocol, &mut out);
+        String::from_utf8(out).unwrap()
+    };
+
+    let resp2 = encoded(Protocol::Resp2);
+    assert_eq!(
+        resp2,
+        "*8\r\n+OK\r\n+PONG\r\n:-7\r\n$9\r\ntwo\nlines\r\n$-1\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n\
+         -WRONGTYPE Operation against a key holding the wrong kind of value\r\n-ERR key must not be empty\r\n"
+    );
+    let resp3 = encoded(Protocol::Resp3);
+    assert!(resp3.contains("\r\n_\r\n%1\r\n$1\r\nf\r\n"));
+    assert_eq!(encoded(Protocol::Text), format!("{}\n", reply));
+
+    assert_eq!(decode_reply(resp3.as_bytes()), Ok(Some((reply.clone(), resp3.len()))));
+    let Some((Reply::Array(items), _)) = decode_reply(resp2.as_bytes()).unwrap() else {
+        panic!("expected an array");
+    };
+    assert_eq!(items[5], Reply::Array(vec![bulk("f"), bulk("v")]));
+    assert_eq!(decode_reply(&resp2.as_bytes()[..resp2.len() - 1]), Ok(None));
+}
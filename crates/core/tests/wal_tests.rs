@@ -3,6 +3,7 @@ use std::fs::{File, remove_file};
 use std::io::Write;
 use std::path::PathBuf;
 
+use zyncdb_core::Value;
 use zyncdb_core::wal::Wal;
 
 fn temp_path() -> PathBuf {
@@ -32,7 +33,7 @@ fn test_append_and_load() {
         let map = wal.load_into().expect("Failed to load WAL");
 
         let mut expected = HashMap::new();
-        expected.insert("lang".to_string(), "rust".to_string());
+        expected.insert("lang".to_string(), Value::from("rust"));
 
         assert_eq!(map, expected);
     }
@@ -73,3 +74,50 @@ fn test_invalid_format_lines() {
 
     let _ = remove_file(&path);
 }
+
+#[test]
+fn test_fields_with_separators_round_trip() {
+    let path = temp_path();
+
+    {
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append_put("pipe", "a|b\\c\nd").unwrap();
+        wal.append(&["RPUSH", "jobs", "one", "two|three"]).unwrap();
+        wal.append(&["LPOP", "jobs", "1"]).unwrap();
+    }
+
+    {
+        let mut wal = Wal::open(&path).unwrap();
+        let map = wal.load_into().unwrap();
+        assert_eq!(map.get("pipe"), Some(&Value::from("a|b\\c\nd")));
+        assert_eq!(
+            map.get("jobs"),
+            Some(&Value::List(["two|three".to_string()].into_iter().collect()))
+        );
+    }
+
+    let _ = remove_file(&path);
+}
+
+#[test]
+fn test_edge_whitespace_round_trips() {
+    let path = temp_path();
+
+    {
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append_put("padded", "  v  ").unwrap();
+        wal.append(&["RPUSH", "jobs", "tab\t", " lead"]).unwrap();
+    }
+
+    {
+        let mut wal = Wal::open(&path).unwrap();
+        let map = wal.load_into().unwrap();
+        assert_eq!(map.get("padded"), Some(&Value::from("  v  ")));
+        assert_eq!(
+            map.get("jobs"),
+            Some(&Value::List(["tab\t".to_string(), " lead".to_string()].into_iter().collect()))
+        );
+    }
+
+    let _ = remove_file(&path);
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+
+/// Why a command line could not be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// The line holds no tokens (only whitespace or a comment).
+    Empty,
+    /// A quoted string or hex literal has no closing quote.
+    UnterminatedString,
+    /// A closing quote is directly followed by another character.
+    TrailingCharacters,
+    /// A backslash escape inside a double-quoted string is not recognised.
+    InvalidEscape(String),
+    /// A hex literal has an odd number of digits or a non-hex digit.
+    InvalidHex,
+    /// A hex literal or `\x` escape does not decode to valid UTF-8.
+    NotUtf8,
+    /// The first token does not name a command.
+    UnknownCommand(String),
+    /// The command was given too few or too many arguments for its arity.
+    WrongArity(String),
+    /// The command exists but its arguments do not fit its syntax.
+    InvalidArguments(String),
+    /// A `;` appeared outside a batch.
+    UnexpectedSeparator,
+    /// The command cannot run inside a batch.
+    NotInBatch(String),
+    /// The command changes definitions an `ATOMIC` batch cannot roll back.
+    NotInAtomicBatch(String),
+    /// A `SELECT`, `UPDATE` or `DELETE` statement is malformed.
+    Sql(String),
+    /// A `$n` placeholder is not numbered from 1.
+    InvalidPlaceholder(String),
+    /// A template uses both `?` and `$n` placeholders.
+    MixedPlaceholders,
+    /// `EXECUTE` passed the wrong number of parameters.
+    ParamCount { expected: usize, got: usize },
+    /// `EXECUTE` or `DEALLOCATE` named a statement that was never prepared.
+    UnknownStatement(String),
+    /// `PREPARE` past the connection's limit of statements.
+    TooManyStatements(usize),
+}
+
+/// A parse failure and the 1-based column (in characters) where it occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl ParseError {
+    pub fn new(column: usize, kind: ParseErrorKind) -> Self {
+        ParseError { column, kind }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Empty => write!(f, "empty command"),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ParseErrorKind::TrailingCharacters => {
+                write!(f, "closing quote must be followed by a space")
+            }
+            ParseErrorKind::InvalidEscape(escape) => write!(f, "invalid escape '{}'", escape),
+            ParseErrorKind::InvalidHex => write!(f, "invalid hex literal"),
+            ParseErrorKind::NotUtf8 => write!(f, "bytes are not valid UTF-8"),
+            ParseErrorKind::UnknownCommand(name) => write!(f, "unknown command '{}'", name),
+            ParseErrorKind::WrongArity(name) => {
+                write!(f, "wrong number of arguments for '{}'", name)
+            }
+            ParseErrorKind::InvalidArguments(name) => {
+                write!(f, "invalid arguments for '{}'", name)
+            }
+            ParseErrorKind::UnexpectedSeparator => write!(f, "';' is only allowed in a batch"),
+            ParseErrorKind::NotInBatch(name) => write!(f, "'{}' is not allowed in a batch", name),
+            ParseErrorKind::NotInAtomicBatch(name) => {
+                write!(f, "'{}' is not allowed in an atomic batch", name)
+            }
+            ParseErrorKind::Sql(reason) => write!(f, "invalid query: {}", reason),
+            ParseErrorKind::InvalidPlaceholder(p) => write!(f, "invalid placeholder '{}'", p),
+            ParseErrorKind::MixedPlaceholders => {
+                write!(f, "cannot mix '?' and '$n' placeholders")
+            }
+            ParseErrorKind::ParamCount { expected, got } => {
+                write!(f, "expected {} parameters, got {}", expected, got)
+            }
+            ParseErrorKind::UnknownStatement(name) => {
+                write!(f, "no prepared statement named '{}'", name)
+            }
+            ParseErrorKind::TooManyStatements(max) => {
+                write!(f, "too many prepared statements (at most {}); DEALLOCATE some first", max)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.kind, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
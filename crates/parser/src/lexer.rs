@@ -0,0 +1,246 @@
+//! Tokenizer for the command language.
+//!
+//! Tokens are separated by whitespace. A token is one of:
+//!
+//! - a bare word, where `\` escapes the next character (`hello\ world`, `a\;b`);
+//! - a double-quoted string with `\n \r \t \0 \\ \" \' \xHH \u{H...}` escapes;
+//! - a single-quoted string, where only `\'` and `\\` are escapes;
+//! - a hex byte literal, `x'68656c6c6f'`, which must decode to UTF-8.
+//!
+//! An unquoted `;` is a separator token, used to delimit commands in a batch.
+//! A closing quote must be followed by whitespace, `;`, or the `)` and `,` of
+//! a query expression.
+//! Bare words that read as numbers are typed as integers or floats. `#`, or
+//! `--` followed by whitespace, at the start of a token begins a comment that
+//! runs to the end of the line.
+
+use crate::error::{ParseError, ParseErrorKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Word,
+    Integer(i64),
+    Float(f64),
+    /// A single- or double-quoted string.
+    String,
+    /// A hex byte literal.
+    Bytes,
+    /// An unquoted `;`.
+    Separator,
+}
+
+/// One lexed token. `text` is the decoded value, so quotes and escapes are
+/// already resolved; `column` is the 1-based character column where it starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub column: usize,
+}
+
+impl Token {
+    /// Whether the token is an unquoted word equal to `keyword`, ignoring case.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == TokenKind::Word && self.text.eq_ignore_ascii_case(keyword)
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if starts_comment(&chars, i) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        let column = i + 1;
+        let (kind, text) = if c == ';' {
+            i += 1;
+            (TokenKind::Separator, ";".to_string())
+        } else if c == '"' || c == '\'' {
+            let (text, end) = quoted(&chars, i)?;
+            i = end;
+            (TokenKind::String, text)
+        } else if matches!(c, 'x' | 'X') && matches!(chars.get(i + 1), Some('\'' | '"')) {
+            let (text, end) = hex(&chars, i)?;
+            i = end;
+            (TokenKind::Bytes, text)
+        } else {
+            let (text, end) = word(&chars, i);
+            i = end;
+            (number(&text).unwrap_or(TokenKind::Word), text)
+        };
+        if matches!(kind, TokenKind::String | TokenKind::Bytes)
+            && chars.get(i).is_some_and(|&c| !c.is_whitespace() && !matches!(c, ';' | ')' | ','))
+        {
+            return Err(ParseError::new(i + 1, ParseErrorKind::TrailingCharacters));
+        }
+        tokens.push(Token { kind, text, column });
+    }
+    Ok(tokens)
+}
+
+/// Whether a comment starts at `i`, a token boundary: `#` opening a line or
+/// followed by whitespace, or `--` followed by whitespace. A `#` inside a
+/// value, as in `see #42`, is kept.
+fn starts_comment(chars: &[char], i: usize) -> bool {
+    let spaced = |next: usize| chars.get(next).is_none_or(|c| c.is_whitespace());
+    let opens_line = chars[..i]
+        .iter()
+        .rev()
+        .take_while(|&&c| c != '\n')
+        .all(|c| c.is_whitespace());
+    match chars[i] {
+        '#' => opens_line || spaced(i + 1),
+        '-' => chars.get(i + 1) == Some(&'-') && spaced(i + 2),
+        _ => false,
+    }
+}
+
+/// Read a bare word starting at `start`. Returns the text and the index after it.
+fn word(chars: &[char], start: usize) -> (String, usize) {
+    let mut text = String::new();
+    let mut i = start;
+    while i < chars.len() && !chars[i].is_whitespace() && chars[i] != ';' {
+        if chars[i] == '\\' && i + 1 < chars.len() {
+            i += 1;
+        }
+        text.push(chars[i]);
+        i += 1;
+    }
+    (text, i)
+}
+
+/// Read a quoted string whose opening quote is at `start`.
+fn quoted(chars: &[char], start: usize) -> Result<(String, usize), ParseError> {
+    let quote = chars[start];
+    let mut bytes = Vec::new();
+    let mut i = start + 1;
+    loop {
+        let Some(&c) = chars.get(i) else {
+            return Err(ParseError::new(start + 1, ParseErrorKind::UnterminatedString));
+        };
+        if c == quote {
+            break;
+        }
+        if c == '\\' && i + 1 < chars.len() {
+            let escaped = chars[i + 1];
+            if quote == '\'' {
+                if escaped == '\'' || escaped == '\\' {
+                    push_char(&mut bytes, escaped);
+                    i += 2;
+                } else {
+                    push_char(&mut bytes, c);
+                    i += 1;
+                }
+                continue;
+            }
+            i = escape(chars, i, &mut bytes)?;
+            continue;
+        }
+        push_char(&mut bytes, c);
+        i += 1;
+    }
+    let text = String::from_utf8(bytes)
+        .map_err(|_| ParseError::new(start + 1, ParseErrorKind::NotUtf8))?;
+    Ok((text, i + 1))
+}
+
+/// Decode the escape whose backslash is at `at`, returning the index after it.
+fn escape(chars: &[char], at: usize, bytes: &mut Vec<u8>) -> Result<usize, ParseError> {
+    let invalid = |len: usize| {
+        let end = (at + len).min(chars.len());
+        let escape = chars[at..end].iter().collect();
+        ParseError::new(at + 1, ParseErrorKind::InvalidEscape(escape))
+    };
+    let simple = match chars[at + 1] {
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        _ => None,
+    };
+    if let Some(c) = simple {
+        push_char(bytes, c);
+        return Ok(at + 2);
+    }
+    match chars[at + 1] {
+        'x' => {
+            let digits: String = chars.iter().skip(at + 2).take(2).collect();
+            if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid(4));
+            }
+            bytes.push(u8::from_str_radix(&digits, 16).map_err(|_| invalid(4))?);
+            Ok(at + 4)
+        }
+        'u' if chars.get(at + 2) == Some(&'{') => {
+            let close = chars[at..]
+                .iter()
+                .position(|&c| c == '}')
+                .map(|p| p + at)
+                .ok_or_else(|| invalid(3))?;
+            let digits: String = chars[at + 3..close].iter().collect();
+            let c = u32::from_str_radix(&digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| invalid(close + 1 - at))?;
+            push_char(bytes, c);
+            Ok(close + 1)
+        }
+        _ => Err(invalid(2)),
+    }
+}
+
+/// Read a hex literal whose `x` prefix is at `start`.
+fn hex(chars: &[char], start: usize) -> Result<(String, usize), ParseError> {
+    let quote = chars[start + 1];
+    let close = chars[start + 2..]
+        .iter()
+        .position(|&c| c == quote)
+        .map(|p| p + start + 2)
+        .ok_or_else(|| ParseError::new(start + 1, ParseErrorKind::UnterminatedString))?;
+    let digits: Vec<char> = chars[start + 2..close].to_vec();
+    if !digits.len().is_multiple_of(2) {
+        return Err(ParseError::new(start + 1, ParseErrorKind::InvalidHex));
+    }
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    if let Some(bad) = digits.iter().position(|c| !c.is_ascii_hexdigit()) {
+        return Err(ParseError::new(start + 3 + bad, ParseErrorKind::InvalidHex));
+    }
+    for pair in digits.chunks(2) {
+        let pair: String = pair.iter().collect();
+        bytes.push(u8::from_str_radix(&pair, 16).expect("checked hex digits"));
+    }
+    let text = String::from_utf8(bytes)
+        .map_err(|_| ParseError::new(start + 1, ParseErrorKind::NotUtf8))?;
+    Ok((text, close + 1))
+}
+
+fn push_char(bytes: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
+
+/// Type a bare word as a number if it reads as one. Words like `inf` or
+/// `nan` stay words.
+fn number(text: &str) -> Option<TokenKind> {
+    if let Ok(n) = text.parse::<i64>() {
+        return Some(TokenKind::Integer(n));
+    }
+    let digits = text.trim_start_matches(['+', '-']).trim_start_matches('.');
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    text.parse::<f64>().ok().map(TokenKind::Float)
+}
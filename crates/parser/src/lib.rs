@@ -1,2 +1,14 @@
+pub mod error;
+pub mod lexer;
 pub mod parser;
-pub use parser::{Parser, SimpleParser, Command};
+pub mod prepared;
+pub mod registry;
+pub mod sql;
+pub use error::{ParseError, ParseErrorKind};
+pub use lexer::{Token, TokenKind, tokenize};
+pub use registry::{COMMANDS, CommandSpec};
+pub use prepared::{MAX_STATEMENTS, Param, Prepared, Statements, prepare};
+pub use sql::{CreateIndex, Statement};
+pub use parser::{
+    Parser, SimpleParser, Command, parse_args, parse_tokens, CommandQuery, LexBound, ListEnd, SchemaDef, ScoreBound, SetCondition, StreamTrim, TextIndexDef, ZRange,
+};
@@ -1,4 +1,138 @@
-#[derive(Debug)]
+use crate::error::{ParseError, ParseErrorKind};
+use crate::lexer::{Token, TokenKind, tokenize};
+use crate::registry;
+use crate::prepared::{Param, Prepared};
+use crate::sql::{self, CreateIndex, Statement};
+
+/// Condition attached to `SET key value [NX|XX]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetCondition {
+    Always,
+    IfNotExists,
+    IfExists,
+}
+
+/// Which end of a list a push, pop or move works on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    fn parse(token: &str) -> Option<ListEnd> {
+        if token.eq_ignore_ascii_case("LEFT") {
+            Some(ListEnd::Left)
+        } else if token.eq_ignore_ascii_case("RIGHT") {
+            Some(ListEnd::Right)
+        } else {
+            None
+        }
+    }
+}
+
+/// One end of a `ZRANGE ... BYSCORE` interval; `(1.5` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(token: &str) -> Option<ScoreBound> {
+        let (exclusive, number) = match token.strip_prefix('(') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+        let value = number.parse::<f64>().ok().filter(|v| !v.is_nan())?;
+        Some(ScoreBound { value, exclusive })
+    }
+}
+
+/// One end of a `ZRANGE ... BYLEX` interval: `-`, `+`, `[member` or `(member`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(String),
+    Exclusive(String),
+}
+
+impl LexBound {
+    fn parse(token: &str) -> Option<LexBound> {
+        match token {
+            "-" => Some(LexBound::Min),
+            "+" => Some(LexBound::Max),
+            _ => {
+                if let Some(member) = token.strip_prefix('[') {
+                    Some(LexBound::Inclusive(member.to_string()))
+                } else {
+                    token
+                        .strip_prefix('(')
+                        .map(|member| LexBound::Exclusive(member.to_string()))
+                }
+            }
+        }
+    }
+}
+
+/// How `ZRANGE` selects members.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZRange {
+    Rank { start: i64, stop: i64 },
+    Score { min: ScoreBound, max: ScoreBound },
+    Lex { min: LexBound, max: LexBound },
+}
+
+/// What `COMMAND` reports: every command, the count, or details of some.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandQuery {
+    List,
+    Count,
+    Info(Vec<String>),
+    Docs(Vec<String>),
+}
+
+/// Trimming strategy for `XADD` and `XTRIM`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamTrim {
+    MaxLen(usize),
+    MinId(String),
+}
+
+/// Constraints declared with `SCHEMA SET` for the keys of one namespace.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SchemaDef {
+    pub namespace: String,
+    /// The version this definition must become; `None` takes the next one.
+    pub version: Option<u32>,
+    /// Glob (`*`, `?`) for the part of the key after `namespace:`.
+    pub key_pattern: Option<String>,
+    /// A `TYPE` name such as `string` or `json`.
+    pub value_type: Option<String>,
+    pub max_key_len: Option<usize>,
+    /// Maximum bytes of a string value or a document's JSON text.
+    pub max_value_len: Option<usize>,
+    /// Members every JSON document must have.
+    pub required: Vec<String>,
+    pub json_schema: Option<String>,
+    /// `UPDATE` or `DELETE` over the namespace, run before the new version
+    /// is checked against the existing keys.
+    pub migration: Option<Statement>,
+}
+
+/// `FT.CREATE name ON namespace FIELDS field ...`: a full-text index over
+/// the whole value (`value`) or JSONPath fields of its documents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextIndexDef {
+    pub name: String,
+    pub namespace: String,
+    pub fields: Vec<String>,
+}
+
+const VALUE_TYPES: &[&str] = &["string", "list", "hash", "set", "zset", "stream", "json"];
+
+#[derive(Debug, Clone)]
 pub enum Command {
     Put { key: String, value: String },
     Get { key: String },
@@ -9,73 +143,1427 @@ pub enum Command {
     Snapshot,
     List,
     Exit,
-    Unknown,
     Ttl { key: String, seconds: u64 },
-    Batch(Vec<Command>),
-    Help,
+    /// Sub-commands run in order; `atomic` batches apply all of them or none.
+    Batch { commands: Vec<Command>, atomic: bool },
+    Help { topic: Option<String> },
+    Introspect(CommandQuery),
+    /// `SELECT ... FROM`, `UPDATE` or `DELETE FROM` over a namespace.
+    Query(Statement),
+    Explain(Statement),
+    /// Connection-level prepared statements; see `Statements::resolve`.
+    Prepare { name: String, statement: Prepared },
+    Execute { name: String, params: Vec<Param> },
+    Deallocate { name: String },
+    /// Switch the connection to RESP2 or RESP3, and authenticate it with a
+    /// username and password if given; answered by the server.
+    Hello { protocol: Option<u32>, auth: Option<(String, String)> },
+    /// Authenticate the connection with a password or API token.
+    Auth { username: Option<String>, password: String },
+    /// API tokens, which `AUTH` takes in place of the password.
+    TokenCreate { name: String, ttl: Option<u64> },
+    TokenList,
+    TokenRevoke { name: String },
+    /// Users and their permissions; `rules` are applied in order, as by
+    /// Redis `ACL SETUSER`.
+    AclSetUser { name: String, rules: Vec<String> },
+    AclGetUser { name: String },
+    AclDelUser { names: Vec<String> },
+    AclList,
+    AclWhoami,
+    Ping { message: Option<String> },
+    /// Server settings; answered by the server or CLI that owns them.
+    ConfigGet { pattern: String },
+    ConfigSet { pairs: Vec<(String, String)> },
+    ConfigRewrite,
+    /// Stop the server; `save` is `SAVE` (`Some(true)`) or `NOSAVE`
+    /// (`Some(false)`), or `None` to snapshot only if snapshots are configured.
+    Shutdown { save: Option<bool> },
+    CreateIndex(CreateIndex),
+    DropIndex { name: String },
+    SchemaSet(SchemaDef),
+    SchemaGet { namespace: String },
+    SchemaDrop { namespace: String },
+    FtCreate(TextIndexDef),
+    /// `FT.SEARCH`: keys matching every query term, best first.
+    FtSearch { index: String, query: String, offset: usize, count: usize, with_scores: bool },
+    FtDropIndex { index: String },
+    Incr { key: String },
+    Decr { key: String },
+    IncrBy { key: String, delta: i64 },
+    DecrBy { key: String, delta: i64 },
+    IncrByFloat { key: String, delta: f64 },
+    Set { key: String, value: String, condition: SetCondition },
+    SetNx { key: String, value: String },
+    GetSet { key: String, value: String },
+    GetDel { key: String },
+    Append { key: String, value: String },
+    StrLen { key: String },
+    GetRange { key: String, start: i64, end: i64 },
+    SetRange { key: String, offset: usize, value: String },
+    MSet { pairs: Vec<(String, String)> },
+    MSetNx { pairs: Vec<(String, String)> },
+    MGet { keys: Vec<String> },
+    Type { key: String },
+    LPush { key: String, values: Vec<String> },
+    RPush { key: String, values: Vec<String> },
+    LPop { key: String, count: Option<usize> },
+    RPop { key: String, count: Option<usize> },
+    LRange { key: String, start: i64, stop: i64 },
+    LLen { key: String },
+    LIndex { key: String, index: i64 },
+    LTrim { key: String, start: i64, stop: i64 },
+    LMove { source: String, destination: String, from: ListEnd, to: ListEnd },
+    BLPop { keys: Vec<String>, timeout: f64 },
+    BRPop { keys: Vec<String>, timeout: f64 },
+    BLMove { source: String, destination: String, from: ListEnd, to: ListEnd, timeout: f64 },
+    HSet { key: String, pairs: Vec<(String, String)> },
+    HGet { key: String, field: String },
+    HMGet { key: String, fields: Vec<String> },
+    HDel { key: String, fields: Vec<String> },
+    HGetAll { key: String },
+    HKeys { key: String },
+    HLen { key: String },
+    HIncrBy { key: String, field: String, delta: i64 },
+    HExists { key: String, field: String },
+    SAdd { key: String, members: Vec<String> },
+    SRem { key: String, members: Vec<String> },
+    SMembers { key: String },
+    SIsMember { key: String, member: String },
+    SInter { keys: Vec<String> },
+    SUnion { keys: Vec<String> },
+    SDiff { keys: Vec<String> },
+    SCard { key: String },
+    ZAdd { key: String, members: Vec<(f64, String)> },
+    ZRem { key: String, members: Vec<String> },
+    ZScore { key: String, member: String },
+    ZRank { key: String, member: String },
+    ZRange { key: String, range: ZRange, with_scores: bool },
+    ZIncrBy { key: String, delta: f64, member: String },
+    ZPopMin { key: String, count: Option<usize> },
+    XAdd { key: String, trim: Option<StreamTrim>, id: String, fields: Vec<(String, String)> },
+    XLen { key: String },
+    XRange { key: String, start: String, end: String, count: Option<usize> },
+    XRead { count: Option<usize>, block: Option<u64>, streams: Vec<(String, String)> },
+    XTrim { key: String, trim: StreamTrim },
+    XGroupCreate { key: String, group: String, id: String, mkstream: bool },
+    XReadGroup {
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        block: Option<u64>,
+        no_ack: bool,
+        streams: Vec<(String, String)>,
+    },
+    XAck { key: String, group: String, ids: Vec<String> },
+    XClaim { key: String, group: String, consumer: String, min_idle: u64, ids: Vec<String> },
+    XPending { key: String, group: String },
+    JsonSet { key: String, path: String, value: String, condition: SetCondition },
+    JsonGet { key: String, path: Option<String> },
+    JsonDel { key: String, path: Option<String> },
+    JsonArrAppend { key: String, path: String, values: Vec<String> },
+    JsonNumIncrBy { key: String, path: String, delta: String },
+}
+
+impl Command {
+    /// Blocking commands wait for data in the server; other front ends run them once.
+    pub fn is_blocking(&self) -> bool {
+        self.timeout().is_some()
+    }
+
+    /// Keys the command reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Put { key, .. }
+            | Command::Get { key }
+            | Command::Delete { key }
+            | Command::Insert { key, .. }
+            | Command::Select { key }
+            | Command::Remove { key }
+            | Command::Ttl { key, .. }
+            | Command::Incr { key }
+            | Command::Decr { key }
+            | Command::IncrBy { key, .. }
+            | Command::DecrBy { key, .. }
+            | Command::IncrByFloat { key, .. }
+            | Command::Set { key, .. }
+            | Command::SetNx { key, .. }
+            | Command::GetSet { key, .. }
+            | Command::GetDel { key }
+            | Command::Append { key, .. }
+            | Command::StrLen { key }
+            | Command::GetRange { key, .. }
+            | Command::SetRange { key, .. }
+            | Command::Type { key }
+            | Command::LPush { key, .. }
+            | Command::RPush { key, .. }
+            | Command::LPop { key, .. }
+            | Command::RPop { key, .. }
+            | Command::LRange { key, .. }
+            | Command::LLen { key }
+            | Command::LIndex { key, .. }
+            | Command::LTrim { key, .. }
+            | Command::HSet { key, .. }
+            | Command::HGet { key, .. }
+            | Command::HMGet { key, .. }
+            | Command::HDel { key, .. }
+            | Command::HGetAll { key }
+            | Command::HKeys { key }
+            | Command::HLen { key }
+            | Command::HIncrBy { key, .. }
+            | Command::HExists { key, .. }
+            | Command::SAdd { key, .. }
+            | Command::SRem { key, .. }
+            | Command::SMembers { key }
+            | Command::SIsMember { key, .. }
+            | Command::SCard { key }
+            | Command::ZAdd { key, .. }
+            | Command::ZRem { key, .. }
+            | Command::ZScore { key, .. }
+            | Command::ZRank { key, .. }
+            | Command::ZRange { key, .. }
+            | Command::ZIncrBy { key, .. }
+            | Command::ZPopMin { key, .. }
+            | Command::XAdd { key, .. }
+            | Command::XLen { key }
+            | Command::XRange { key, .. }
+            | Command::XTrim { key, .. }
+            | Command::XGroupCreate { key, .. }
+            | Command::XAck { key, .. }
+            | Command::XClaim { key, .. }
+            | Command::XPending { key, .. }
+            | Command::JsonSet { key, .. }
+            | Command::JsonGet { key, .. }
+            | Command::JsonDel { key, .. }
+            | Command::JsonArrAppend { key, .. }
+            | Command::JsonNumIncrBy { key, .. } => vec![key],
+            Command::MSet { pairs } | Command::MSetNx { pairs } => {
+                pairs.iter().map(|(k, _)| k.as_str()).collect()
+            }
+            Command::MGet { keys }
+            | Command::BLPop { keys, .. }
+            | Command::BRPop { keys, .. }
+            | Command::SInter { keys }
+            | Command::SUnion { keys }
+            | Command::SDiff { keys } => keys.iter().map(String::as_str).collect(),
+            Command::LMove { source, destination, .. }
+            | Command::BLMove { source, destination, .. } => vec![source, destination],
+            Command::XRead { streams, .. } | Command::XReadGroup { streams, .. } => {
+                streams.iter().map(|(k, _)| k.as_str()).collect()
+            }
+            Command::Batch { commands, .. } => commands.iter().flat_map(Command::keys).collect(),
+            Command::Snapshot
+            | Command::List
+            | Command::Exit
+            | Command::Help { .. }
+            | Command::Introspect(_)
+            | Command::Query(_)
+            | Command::Explain(_)
+            | Command::Prepare { .. }
+            | Command::Execute { .. }
+            | Command::Deallocate { .. }
+            | Command::Hello { .. }
+            | Command::Auth { .. }
+            | Command::TokenCreate { .. }
+            | Command::TokenList
+            | Command::TokenRevoke { .. }
+            | Command::AclSetUser { .. }
+            | Command::AclGetUser { .. }
+            | Command::AclDelUser { .. }
+            | Command::AclList
+            | Command::AclWhoami
+            | Command::Ping { .. }
+            | Command::ConfigGet { .. }
+            | Command::ConfigSet { .. }
+            | Command::ConfigRewrite
+            | Command::Shutdown { .. }
+            | Command::CreateIndex(_)
+            | Command::DropIndex { .. }
+            | Command::SchemaSet(_)
+            | Command::SchemaGet { .. }
+            | Command::SchemaDrop { .. }
+            | Command::FtCreate(_)
+            | Command::FtSearch { .. }
+            | Command::FtDropIndex { .. } => Vec::new(),
+        }
+    }
+
+    /// Namespaces whose keys the command reads or writes as a whole, for
+    /// commands that do not name their keys up front. Atomic batches save
+    /// these keys before running; ACLs check them.
+    pub fn namespaces(&self) -> Vec<&str> {
+        match self {
+            Command::Query(statement) | Command::Explain(statement) => vec![statement.namespace()],
+            Command::CreateIndex(index) => vec![&index.namespace],
+            Command::SchemaSet(def) => vec![&def.namespace],
+            Command::SchemaGet { namespace } | Command::SchemaDrop { namespace } => vec![namespace],
+            Command::FtCreate(def) => vec![&def.namespace],
+            Command::Batch { commands, .. } => {
+                commands.iter().flat_map(Command::namespaces).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the command ranges over every key, or over an index whose
+    /// namespace only the store knows.
+    pub fn touches_every_key(&self) -> bool {
+        match self {
+            Command::List
+            | Command::DropIndex { .. }
+            | Command::FtSearch { .. }
+            | Command::FtDropIndex { .. } => true,
+            Command::Batch { commands, .. } => commands.iter().any(Command::touches_every_key),
+            _ => false,
+        }
+    }
+
+    /// Whether the command changes index or schema definitions, which an
+    /// atomic batch cannot roll back.
+    pub fn changes_catalog(&self) -> bool {
+        matches!(
+            self,
+            Command::CreateIndex(_)
+                | Command::DropIndex { .. }
+                | Command::SchemaSet(_)
+                | Command::SchemaDrop { .. }
+                | Command::FtCreate(_)
+                | Command::FtDropIndex { .. }
+        )
+    }
+
+    /// The registry entry whose flags govern the command: its subcommand's
+    /// where that has its own, as `SCHEMA GET` does, else its command's.
+    pub fn spec(&self) -> Option<&'static registry::CommandSpec> {
+        match self {
+            Command::SchemaGet { .. } => registry::subcommand("schema|get"),
+            Command::SchemaDrop { .. } => registry::subcommand("schema|drop"),
+            command => registry::lookup(command.name()),
+        }
+    }
+
+    /// The registry name of the command, for looking up its flags.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Put { .. } => "put",
+            Command::Get { .. } => "get",
+            Command::Delete { .. } => "delete",
+            Command::Insert { .. } => "insert",
+            Command::Select { .. } => "select",
+            Command::Remove { .. } => "remove",
+            Command::Snapshot => "snapshot",
+            Command::List => "list",
+            Command::Exit => "exit",
+            Command::Ttl { .. } => "ttl",
+            Command::Batch { .. } => "batch",
+            Command::Help { .. } => "help",
+            Command::Introspect(_) => "command",
+            Command::Query(Statement::Select(_)) => "select",
+            Command::Query(Statement::Update(_)) => "update",
+            Command::Query(Statement::Delete(_)) => "delete",
+            Command::Explain(_) => "explain",
+            Command::Prepare { .. } => "prepare",
+            Command::Execute { .. } => "execute",
+            Command::Deallocate { .. } => "deallocate",
+            Command::Hello { .. } => "hello",
+            Command::Auth { .. } => "auth",
+            Command::TokenCreate { .. } | Command::TokenList | Command::TokenRevoke { .. } => "token",
+            Command::AclSetUser { .. }
+            | Command::AclGetUser { .. }
+            | Command::AclDelUser { .. }
+            | Command::AclList
+            | Command::AclWhoami => "acl",
+            Command::Ping { .. } => "ping",
+            Command::ConfigGet { .. } | Command::ConfigSet { .. } | Command::ConfigRewrite => "config",
+            Command::Shutdown { .. } => "shutdown",
+            Command::CreateIndex(_) => "create",
+            Command::DropIndex { .. } => "drop",
+            Command::SchemaSet(_) | Command::SchemaGet { .. } | Command::SchemaDrop { .. } => "schema",
+            Command::FtCreate(_) => "ft.create",
+            Command::FtSearch { .. } => "ft.search",
+            Command::FtDropIndex { .. } => "ft.dropindex",
+            Command::Incr { .. } => "incr",
+            Command::Decr { .. } => "decr",
+            Command::IncrBy { .. } => "incrby",
+            Command::DecrBy { .. } => "decrby",
+            Command::IncrByFloat { .. } => "incrbyfloat",
+            Command::Set { .. } => "set",
+            Command::SetNx { .. } => "setnx",
+            Command::GetSet { .. } => "getset",
+            Command::GetDel { .. } => "getdel",
+            Command::Append { .. } => "append",
+            Command::StrLen { .. } => "strlen",
+            Command::GetRange { .. } => "getrange",
+            Command::SetRange { .. } => "setrange",
+            Command::MSet { .. } => "mset",
+            Command::MSetNx { .. } => "msetnx",
+            Command::MGet { .. } => "mget",
+            Command::Type { .. } => "type",
+            Command::LPush { .. } => "lpush",
+            Command::RPush { .. } => "rpush",
+            Command::LPop { .. } => "lpop",
+            Command::RPop { .. } => "rpop",
+            Command::LRange { .. } => "lrange",
+            Command::LLen { .. } => "llen",
+            Command::LIndex { .. } => "lindex",
+            Command::LTrim { .. } => "ltrim",
+            Command::LMove { .. } => "lmove",
+            Command::BLPop { .. } => "blpop",
+            Command::BRPop { .. } => "brpop",
+            Command::BLMove { .. } => "blmove",
+            Command::HSet { .. } => "hset",
+            Command::HGet { .. } => "hget",
+            Command::HMGet { .. } => "hmget",
+            Command::HDel { .. } => "hdel",
+            Command::HGetAll { .. } => "hgetall",
+            Command::HKeys { .. } => "hkeys",
+            Command::HLen { .. } => "hlen",
+            Command::HIncrBy { .. } => "hincrby",
+            Command::HExists { .. } => "hexists",
+            Command::SAdd { .. } => "sadd",
+            Command::SRem { .. } => "srem",
+            Command::SMembers { .. } => "smembers",
+            Command::SIsMember { .. } => "sismember",
+            Command::SInter { .. } => "sinter",
+            Command::SUnion { .. } => "sunion",
+            Command::SDiff { .. } => "sdiff",
+            Command::SCard { .. } => "scard",
+            Command::ZAdd { .. } => "zadd",
+            Command::ZRem { .. } => "zrem",
+            Command::ZScore { .. } => "zscore",
+            Command::ZRank { .. } => "zrank",
+            Command::ZRange { .. } => "zrange",
+            Command::ZIncrBy { .. } => "zincrby",
+            Command::ZPopMin { .. } => "zpopmin",
+            Command::XAdd { .. } => "xadd",
+            Command::XLen { .. } => "xlen",
+            Command::XRange { .. } => "xrange",
+            Command::XRead { .. } => "xread",
+            Command::XTrim { .. } => "xtrim",
+            Command::XGroupCreate { .. } => "xgroup",
+            Command::XReadGroup { .. } => "xreadgroup",
+            Command::XAck { .. } => "xack",
+            Command::XClaim { .. } => "xclaim",
+            Command::XPending { .. } => "xpending",
+            Command::JsonSet { .. } => "json.set",
+            Command::JsonGet { .. } => "json.get",
+            Command::JsonDel { .. } => "json.del",
+            Command::JsonArrAppend { .. } => "json.arrappend",
+            Command::JsonNumIncrBy { .. } => "json.numincrby",
+        }
+    }
+
+    /// Seconds a blocking command may wait; `0` means forever.
+    pub fn timeout(&self) -> Option<f64> {
+        match self {
+            Command::BLPop { timeout, .. }
+            | Command::BRPop { timeout, .. }
+            | Command::BLMove { timeout, .. } => Some(*timeout),
+            Command::XRead { block, .. } | Command::XReadGroup { block, .. } => {
+                block.map(|ms| ms as f64 / 1000.0)
+            }
+            _ => None,
+        }
+    }
 }
 
 
 pub trait Parser {
-    fn parse(&self, input: &str) -> Command;
+    fn parse(&self, input: &str) -> Result<Command, ParseError>;
 }
 
 pub struct SimpleParser;
 
 impl Parser for SimpleParser {
-    fn parse(&self, input: &str) -> Command {
-        let input = input.trim();
-        let tokens: Vec<&str> = input.split_whitespace().collect();
+    fn parse(&self, input: &str) -> Result<Command, ParseError> {
+        parse_tokens(&tokenize(input)?)
+    }
+}
+
+/// Parse one command sent as separate arguments, as RESP clients send them.
+/// An argument is never split or unescaped, but keeps the kind it would have
+/// as a bare word, so numbers and keywords read as typed. A lone `;` is a
+/// separator only inside `BATCH`.
+pub fn parse_args(args: &[String]) -> Result<Command, ParseError> {
+    let batch = args.first().is_some_and(|name| name.eq_ignore_ascii_case("batch"));
+    let tokens: Vec<Token> = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let kind = match tokenize(arg).as_deref() {
+                Ok([token])
+                    if token.text == *arg && (batch || token.kind != TokenKind::Separator) =>
+                {
+                    token.kind.clone()
+                }
+                _ => TokenKind::String,
+            };
+            Token {
+                kind,
+                text: arg.clone(),
+                column: i + 1,
+            }
+        })
+        .collect();
+    parse_tokens(&tokens)
+}
 
-        match tokens.as_slice() {
-            ["INSERT", key, rest @ ..] if !rest.is_empty() => Command::Insert {
+/// Parse one command from already-lexed tokens.
+pub fn parse_tokens(lexed: &[Token]) -> Result<Command, ParseError> {
+    let Some((name, args)) = lexed.split_first() else {
+        return Err(ParseError::new(1, ParseErrorKind::Empty));
+    };
+    let spec = registry::lookup(&name.text).ok_or_else(|| {
+        ParseError::new(name.column, ParseErrorKind::UnknownCommand(name.text.clone()))
+    })?;
+    if spec.name != "batch"
+        && let Some(separator) = args.iter().find(|t| t.kind == TokenKind::Separator)
+    {
+        return Err(ParseError::new(separator.column, ParseErrorKind::UnexpectedSeparator));
+    }
+    if !spec.accepts(lexed.len()) {
+        return Err(ParseError::new(
+            name.column,
+            ParseErrorKind::WrongArity(spec.name.to_string()),
+        ));
+    }
+    match spec.name {
+        "batch" => return batch(args),
+        "update" => return sql::parse_statement(lexed).map(Command::Query),
+        "select" | "delete" if args.len() > 1 => {
+            return sql::parse_statement(lexed).map(Command::Query);
+        }
+        "explain" => return sql::parse_statement(args).map(Command::Explain),
+        "prepare" => return prepare(args),
+        "create" => return sql::parse_create_index(lexed).map(Command::CreateIndex),
+        "schema" => return schema(name, args),
+        "execute" => {
+            return Ok(Command::Execute {
+                name: args[0].text.clone(),
+                params: args[1..].iter().map(Param::from_token).collect(),
+            });
+        }
+        _ => {}
+    }
+    let args: Vec<&str> = args.iter().map(|t| t.text.as_str()).collect();
+
+    let command = match (spec.name, args.as_slice()) {
+        ("insert", [key, rest @ ..]) if !rest.is_empty() => Command::Insert {
+            key: key.to_string(),
+            value: rest.join(" "),
+        },
+        ("select", [key]) => Command::Select {
+            key: key.to_string(),
+        },
+        ("remove", [key]) => Command::Remove {
+            key: key.to_string(),
+        },
+        ("put", [key, rest @ ..]) if !rest.is_empty() => Command::Put {
+            key: key.to_string(),
+            value: rest.join(" "),
+        },
+        ("get", [key]) => Command::Get {
+            key: key.to_string(),
+        },
+        ("delete", [key]) => Command::Delete {
+            key: key.to_string(),
+        },
+        ("drop", [index, name]) if index.eq_ignore_ascii_case("INDEX") => Command::DropIndex {
+            name: name.to_string(),
+        },
+        ("ft.create", [index, on, namespace, kw, fields @ ..])
+            if on.eq_ignore_ascii_case("ON") && kw.eq_ignore_ascii_case("FIELDS") && !fields.is_empty() =>
+        {
+            Command::FtCreate(TextIndexDef {
+                name: index.to_string(),
+                namespace: namespace.to_string(),
+                fields: strings(fields),
+            })
+        }
+        ("ft.search", [index, query, options @ ..]) => match ft_search_options(options) {
+            Some((offset, count, with_scores)) => Command::FtSearch {
+                index: index.to_string(),
+                query: query.to_string(),
+                offset,
+                count,
+                with_scores,
+            },
+            None => {
+                return Err(ParseError::new(
+                    name.column,
+                    ParseErrorKind::InvalidArguments(spec.name.to_string()),
+                ));
+            }
+        },
+        ("ft.dropindex", [index]) => Command::FtDropIndex {
+            index: index.to_string(),
+        },
+        ("deallocate", [name]) => Command::Deallocate {
+            name: name.to_string(),
+        },
+        ("snapshot", []) => Command::Snapshot,
+        ("list", []) => Command::List,
+        ("exit", []) => Command::Exit,
+        ("hello", []) => Command::Hello { protocol: None, auth: None },
+        ("hello", [version]) if version.parse::<u32>().is_ok() => Command::Hello {
+            protocol: version.parse().ok(),
+            auth: None,
+        },
+        ("hello", [version, auth, username, password])
+            if version.parse::<u32>().is_ok() && auth.eq_ignore_ascii_case("AUTH") =>
+        {
+            Command::Hello {
+                protocol: version.parse().ok(),
+                auth: Some((username.to_string(), password.to_string())),
+            }
+        }
+        ("auth", [password]) => Command::Auth {
+            username: None,
+            password: password.to_string(),
+        },
+        ("auth", [username, password]) => Command::Auth {
+            username: Some(username.to_string()),
+            password: password.to_string(),
+        },
+        ("token", [sub, name]) if sub.eq_ignore_ascii_case("CREATE") => Command::TokenCreate {
+            name: name.to_string(),
+            ttl: None,
+        },
+        ("token", [sub, name, ttl, secs])
+            if sub.eq_ignore_ascii_case("CREATE")
+                && ttl.eq_ignore_ascii_case("TTL")
+                && secs.parse::<u64>().is_ok_and(|secs| secs > 0) =>
+        {
+            Command::TokenCreate {
+                name: name.to_string(),
+                ttl: secs.parse().ok(),
+            }
+        }
+        ("token", [sub]) if sub.eq_ignore_ascii_case("LIST") => Command::TokenList,
+        ("token", [sub, name]) if sub.eq_ignore_ascii_case("REVOKE") => Command::TokenRevoke {
+            name: name.to_string(),
+        },
+        ("acl", [sub, name, rules @ ..]) if sub.eq_ignore_ascii_case("SETUSER") => {
+            Command::AclSetUser {
+                name: name.to_string(),
+                rules: strings(rules),
+            }
+        }
+        ("acl", [sub, name]) if sub.eq_ignore_ascii_case("GETUSER") => Command::AclGetUser {
+            name: name.to_string(),
+        },
+        ("acl", [sub, names @ ..]) if sub.eq_ignore_ascii_case("DELUSER") && !names.is_empty() => {
+            Command::AclDelUser { names: strings(names) }
+        }
+        ("acl", [sub]) if sub.eq_ignore_ascii_case("LIST") => Command::AclList,
+        ("acl", [sub]) if sub.eq_ignore_ascii_case("WHOAMI") => Command::AclWhoami,
+        ("ping", []) => Command::Ping { message: None },
+        ("ping", [message]) => Command::Ping {
+            message: Some(message.to_string()),
+        },
+        ("ttl", [key, secs]) if secs.parse::<u64>().is_ok() => Command::Ttl {
+            key: key.to_string(),
+            seconds: secs.parse().unwrap(),
+        },
+        ("incr", [key]) => Command::Incr {
+            key: key.to_string(),
+        },
+        ("decr", [key]) => Command::Decr {
+            key: key.to_string(),
+        },
+        ("incrby", [key, delta]) if delta.parse::<i64>().is_ok() => {
+            Command::IncrBy {
+                key: key.to_string(),
+                delta: delta.parse().unwrap(),
+            }
+        }
+        ("decrby", [key, delta]) if delta.parse::<i64>().is_ok() => {
+            Command::DecrBy {
+                key: key.to_string(),
+                delta: delta.parse().unwrap(),
+            }
+        }
+        ("incrbyfloat", [key, delta])
+            if delta.parse::<f64>().is_ok() =>
+        {
+            Command::IncrByFloat {
+                key: key.to_string(),
+                delta: delta.parse().unwrap(),
+            }
+        }
+        ("set", [key, rest @ ..]) if !rest.is_empty() => {
+            let (condition, value) = match rest.split_last() {
+                Some((flag, value)) if !value.is_empty() && flag.eq_ignore_ascii_case("NX") => {
+                    (SetCondition::IfNotExists, value)
+                }
+                Some((flag, value)) if !value.is_empty() && flag.eq_ignore_ascii_case("XX") => {
+                    (SetCondition::IfExists, value)
+                }
+                _ => (SetCondition::Always, rest),
+            };
+            Command::Set {
+                key: key.to_string(),
+                value: value.join(" "),
+                condition,
+            }
+        }
+        ("setnx", [key, rest @ ..]) if !rest.is_empty() => {
+            Command::SetNx {
                 key: key.to_string(),
                 value: rest.join(" "),
-            },
-            ["SELECT", key] => Command::Select {
+            }
+        }
+        ("getset", [key, rest @ ..]) if !rest.is_empty() => {
+            Command::GetSet {
                 key: key.to_string(),
-            },
-            ["REMOVE", key] => Command::Remove {
+                value: rest.join(" "),
+            }
+        }
+        ("getdel", [key]) => Command::GetDel {
+            key: key.to_string(),
+        },
+        ("append", [key, rest @ ..]) if !rest.is_empty() => {
+            Command::Append {
                 key: key.to_string(),
-            },
-            ["PUT", key, rest @ ..] | ["put", key, rest @ ..] if !rest.is_empty() => Command::Put {
+                value: rest.join(" "),
+            }
+        }
+        ("strlen", [key]) => Command::StrLen {
+            key: key.to_string(),
+        },
+        ("getrange", [key, start, end])
+            if start.parse::<i64>().is_ok() && end.parse::<i64>().is_ok() =>
+        {
+            Command::GetRange {
                 key: key.to_string(),
+                start: start.parse().unwrap(),
+                end: end.parse().unwrap(),
+            }
+        }
+        ("setrange", [key, offset, rest @ ..])
+            if !rest.is_empty() && offset.parse::<usize>().is_ok() =>
+        {
+            Command::SetRange {
+                key: key.to_string(),
+                offset: offset.parse().unwrap(),
                 value: rest.join(" "),
-            },
-            ["GET", key] | ["get", key] => Command::Get {
+            }
+        }
+        ("mset", rest) if !rest.is_empty() && rest.len().is_multiple_of(2) => {
+            Command::MSet { pairs: pairs(rest) }
+        }
+        ("msetnx", rest)
+            if !rest.is_empty() && rest.len().is_multiple_of(2) =>
+        {
+            Command::MSetNx { pairs: pairs(rest) }
+        }
+        ("mget", keys) if !keys.is_empty() => Command::MGet {
+            keys: strings(keys),
+        },
+        ("type", [key]) => Command::Type {
+            key: key.to_string(),
+        },
+        ("lpush", [key, values @ ..]) if !values.is_empty() => {
+            Command::LPush {
                 key: key.to_string(),
-            },
-            ["DELETE", key] | ["delete", key] => Command::Delete {
+                values: strings(values),
+            }
+        }
+        ("rpush", [key, values @ ..]) if !values.is_empty() => {
+            Command::RPush {
                 key: key.to_string(),
-            },
-            ["SNAPSHOT"] | ["snapshot"] => Command::Snapshot,
-            ["LIST"] | ["list"] | ["KEYS"] | ["keys"] => Command::List,
-            ["EXIT"] | ["exit"] | ["QUIT"] | ["quit"] => Command::Exit,
-            ["TTL", key, secs] if secs.parse::<u64>().is_ok() => Command::Ttl {
+                values: strings(values),
+            }
+        }
+        ("lpop", [key]) => Command::LPop {
+            key: key.to_string(),
+            count: None,
+        },
+        ("lpop", [key, count]) if count.parse::<usize>().is_ok() => {
+            Command::LPop {
                 key: key.to_string(),
-                seconds: secs.parse().unwrap(),
-            },
-            ["BATCH", rest @ ..] => {
-                // Parse a batch of commands
-                // Assuming the format is "BATCH put key1 value1 put key2 value2 ..."
-                let mut cmds = Vec::new();
-                let mut i = 0;
-                while i < rest.len() {
-                    match rest.get(i) {
-                        Some(&"put") if i + 2 < rest.len() => {
-                            cmds.push(Command::Put {
-                                key: rest[i + 1].to_string(),
-                                value: rest[i + 2].to_string(),
-                            });
-                            i += 3;
-                        }
-                        _ => break,
-                    }
+                count: count.parse().ok(),
+            }
+        }
+        ("rpop", [key]) => Command::RPop {
+            key: key.to_string(),
+            count: None,
+        },
+        ("rpop", [key, count]) if count.parse::<usize>().is_ok() => {
+            Command::RPop {
+                key: key.to_string(),
+                count: count.parse().ok(),
+            }
+        }
+        ("lrange", [key, start, stop])
+            if start.parse::<i64>().is_ok() && stop.parse::<i64>().is_ok() =>
+        {
+            Command::LRange {
+                key: key.to_string(),
+                start: start.parse().unwrap(),
+                stop: stop.parse().unwrap(),
+            }
+        }
+        ("llen", [key]) => Command::LLen {
+            key: key.to_string(),
+        },
+        ("lindex", [key, index]) if index.parse::<i64>().is_ok() => {
+            Command::LIndex {
+                key: key.to_string(),
+                index: index.parse().unwrap(),
+            }
+        }
+        ("ltrim", [key, start, stop])
+            if start.parse::<i64>().is_ok() && stop.parse::<i64>().is_ok() =>
+        {
+            Command::LTrim {
+                key: key.to_string(),
+                start: start.parse().unwrap(),
+                stop: stop.parse().unwrap(),
+            }
+        }
+        ("lmove", [source, destination, from, to])
+            if ListEnd::parse(from).is_some() && ListEnd::parse(to).is_some() =>
+        {
+            Command::LMove {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                from: ListEnd::parse(from).unwrap(),
+                to: ListEnd::parse(to).unwrap(),
+            }
+        }
+        ("blpop", [keys @ .., timeout])
+            if !keys.is_empty() && parse_timeout(timeout).is_some() =>
+        {
+            Command::BLPop {
+                keys: strings(keys),
+                timeout: parse_timeout(timeout).unwrap(),
+            }
+        }
+        ("brpop", [keys @ .., timeout])
+            if !keys.is_empty() && parse_timeout(timeout).is_some() =>
+        {
+            Command::BRPop {
+                keys: strings(keys),
+                timeout: parse_timeout(timeout).unwrap(),
+            }
+        }
+        ("blmove", [source, destination, from, to, timeout])
+            if ListEnd::parse(from).is_some()
+                && ListEnd::parse(to).is_some()
+                && parse_timeout(timeout).is_some() =>
+        {
+            Command::BLMove {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                from: ListEnd::parse(from).unwrap(),
+                to: ListEnd::parse(to).unwrap(),
+                timeout: parse_timeout(timeout).unwrap(),
+            }
+        }
+        ("hset", [key, rest @ ..])
+            if !rest.is_empty() && rest.len().is_multiple_of(2) =>
+        {
+            Command::HSet {
+                key: key.to_string(),
+                pairs: pairs(rest),
+            }
+        }
+        ("hget", [key, field]) => Command::HGet {
+            key: key.to_string(),
+            field: field.to_string(),
+        },
+        ("hmget", [key, fields @ ..]) if !fields.is_empty() => {
+            Command::HMGet {
+                key: key.to_string(),
+                fields: strings(fields),
+            }
+        }
+        ("hdel", [key, fields @ ..]) if !fields.is_empty() => {
+            Command::HDel {
+                key: key.to_string(),
+                fields: strings(fields),
+            }
+        }
+        ("hgetall", [key]) => Command::HGetAll {
+            key: key.to_string(),
+        },
+        ("hkeys", [key]) => Command::HKeys {
+            key: key.to_string(),
+        },
+        ("hlen", [key]) => Command::HLen {
+            key: key.to_string(),
+        },
+        ("hincrby", [key, field, delta])
+            if delta.parse::<i64>().is_ok() =>
+        {
+            Command::HIncrBy {
+                key: key.to_string(),
+                field: field.to_string(),
+                delta: delta.parse().unwrap(),
+            }
+        }
+        ("hexists", [key, field]) => Command::HExists {
+            key: key.to_string(),
+            field: field.to_string(),
+        },
+        ("sadd", [key, members @ ..]) if !members.is_empty() => {
+            Command::SAdd {
+                key: key.to_string(),
+                members: strings(members),
+            }
+        }
+        ("srem", [key, members @ ..]) if !members.is_empty() => {
+            Command::SRem {
+                key: key.to_string(),
+                members: strings(members),
+            }
+        }
+        ("smembers", [key]) => Command::SMembers {
+            key: key.to_string(),
+        },
+        ("sismember", [key, member]) => Command::SIsMember {
+            key: key.to_string(),
+            member: member.to_string(),
+        },
+        ("sinter", keys) if !keys.is_empty() => Command::SInter {
+            keys: strings(keys),
+        },
+        ("sunion", keys) if !keys.is_empty() => Command::SUnion {
+            keys: strings(keys),
+        },
+        ("sdiff", keys) if !keys.is_empty() => Command::SDiff {
+            keys: strings(keys),
+        },
+        ("scard", [key]) => Command::SCard {
+            key: key.to_string(),
+        },
+        ("zadd", [key, rest @ ..]) if scored_members(rest).is_some() => {
+            Command::ZAdd {
+                key: key.to_string(),
+                members: scored_members(rest).unwrap(),
+            }
+        }
+        ("zrem", [key, members @ ..]) if !members.is_empty() => {
+            Command::ZRem {
+                key: key.to_string(),
+                members: strings(members),
+            }
+        }
+        ("zscore", [key, member]) => Command::ZScore {
+            key: key.to_string(),
+            member: member.to_string(),
+        },
+        ("zrank", [key, member]) => Command::ZRank {
+            key: key.to_string(),
+            member: member.to_string(),
+        },
+        ("zrange", [key, start, stop, options @ ..])
+            if zrange(start, stop, options).is_some() =>
+        {
+            let (range, with_scores) = zrange(start, stop, options).unwrap();
+            Command::ZRange {
+                key: key.to_string(),
+                range,
+                with_scores,
+            }
+        }
+        ("zrangebyscore", [key, min, max, options @ ..])
+            if zrange(min, max, &[&["BYSCORE"], options].concat()).is_some() =>
+        {
+            let (range, with_scores) = zrange(min, max, &[&["BYSCORE"], options].concat()).unwrap();
+            Command::ZRange {
+                key: key.to_string(),
+                range,
+                with_scores,
+            }
+        }
+        ("zrangebylex", [key, min, max])
+            if zrange(min, max, &["BYLEX"]).is_some() =>
+        {
+            let (range, with_scores) = zrange(min, max, &["BYLEX"]).unwrap();
+            Command::ZRange {
+                key: key.to_string(),
+                range,
+                with_scores,
+            }
+        }
+        ("zincrby", [key, delta, member])
+            if delta.parse::<f64>().is_ok() =>
+        {
+            Command::ZIncrBy {
+                key: key.to_string(),
+                delta: delta.parse().unwrap(),
+                member: member.to_string(),
+            }
+        }
+        ("zpopmin", [key]) => Command::ZPopMin {
+            key: key.to_string(),
+            count: None,
+        },
+        ("zpopmin", [key, count]) if count.parse::<usize>().is_ok() => {
+            Command::ZPopMin {
+                key: key.to_string(),
+                count: count.parse().ok(),
+            }
+        }
+        ("xadd", [key, rest @ ..]) if xadd(key, rest).is_some() => {
+            xadd(key, rest).unwrap()
+        }
+        ("xlen", [key]) => Command::XLen {
+            key: key.to_string(),
+        },
+        ("xrange", [key, start, end]) => Command::XRange {
+            key: key.to_string(),
+            start: start.to_string(),
+            end: end.to_string(),
+            count: None,
+        },
+        ("xrange", [key, start, end, option, count])
+            if option.eq_ignore_ascii_case("COUNT") && count.parse::<usize>().is_ok() =>
+        {
+            Command::XRange {
+                key: key.to_string(),
+                start: start.to_string(),
+                end: end.to_string(),
+                count: count.parse().ok(),
+            }
+        }
+        // NOACK only means something to a consumer group.
+        ("xread", rest) if xread(rest).is_some_and(|(_, _, no_ack, _)| !no_ack) => {
+            let (count, block, _, streams) = xread(rest).unwrap();
+            Command::XRead {
+                count,
+                block,
+                streams,
+            }
+        }
+        ("xtrim", [key, strategy, arg])
+            if stream_trim(strategy, arg).is_some() =>
+        {
+            Command::XTrim {
+                key: key.to_string(),
+                trim: stream_trim(strategy, arg).unwrap(),
+            }
+        }
+        ("xgroup", [sub, key, group, id, rest @ ..])
+            if sub.eq_ignore_ascii_case("CREATE")
+                && (rest.is_empty() || matches!(rest, [flag] if flag.eq_ignore_ascii_case("MKSTREAM"))) =>
+        {
+            Command::XGroupCreate {
+                key: key.to_string(),
+                group: group.to_string(),
+                id: id.to_string(),
+                mkstream: !rest.is_empty(),
+            }
+        }
+        ("xreadgroup", [option, group, consumer, rest @ ..])
+            if option.eq_ignore_ascii_case("GROUP") && xread(rest).is_some() =>
+        {
+            let (count, block, no_ack, streams) = xread(rest).unwrap();
+            Command::XReadGroup {
+                group: group.to_string(),
+                consumer: consumer.to_string(),
+                count,
+                block,
+                no_ack,
+                streams,
+            }
+        }
+        ("xack", [key, group, ids @ ..]) if !ids.is_empty() => {
+            Command::XAck {
+                key: key.to_string(),
+                group: group.to_string(),
+                ids: strings(ids),
+            }
+        }
+        ("xclaim", [key, group, consumer, min_idle, ids @ ..])
+            if !ids.is_empty() && min_idle.parse::<u64>().is_ok() =>
+        {
+            Command::XClaim {
+                key: key.to_string(),
+                group: group.to_string(),
+                consumer: consumer.to_string(),
+                min_idle: min_idle.parse().unwrap(),
+                ids: strings(ids),
+            }
+        }
+        ("xpending", [key, group]) => Command::XPending {
+            key: key.to_string(),
+            group: group.to_string(),
+        },
+        ("json.set", [key, path, rest @ ..])
+            if !rest.is_empty() =>
+        {
+            let (condition, value) = match rest.split_last() {
+                Some((flag, value)) if !value.is_empty() && flag.eq_ignore_ascii_case("NX") => {
+                    (SetCondition::IfNotExists, value)
+                }
+                Some((flag, value)) if !value.is_empty() && flag.eq_ignore_ascii_case("XX") => {
+                    (SetCondition::IfExists, value)
+                }
+                _ => (SetCondition::Always, rest),
+            };
+            Command::JsonSet {
+                key: key.to_string(),
+                path: path.to_string(),
+                value: value.join(" "),
+                condition,
+            }
+        }
+        ("json.get", [key, path @ ..]) if path.len() <= 1 => {
+            Command::JsonGet {
+                key: key.to_string(),
+                path: path.first().map(|p| p.to_string()),
+            }
+        }
+        ("json.del", [key, path @ ..]) if path.len() <= 1 => {
+            Command::JsonDel {
+                key: key.to_string(),
+                path: path.first().map(|p| p.to_string()),
+            }
+        }
+        ("json.arrappend", [key, path, values @ ..])
+            if !values.is_empty() =>
+        {
+            Command::JsonArrAppend {
+                key: key.to_string(),
+                path: path.to_string(),
+                values: strings(values),
+            }
+        }
+        ("json.numincrby", [key, path, delta]) => {
+            Command::JsonNumIncrBy {
+                key: key.to_string(),
+                path: path.to_string(),
+                delta: delta.to_string(),
+            }
+        }
+        ("help", []) => Command::Help { topic: None },
+        ("help", [topic]) => Command::Help {
+            topic: Some(topic.to_string()),
+        },
+        ("command", []) => Command::Introspect(CommandQuery::List),
+        ("command", [sub]) if sub.eq_ignore_ascii_case("COUNT") => {
+            Command::Introspect(CommandQuery::Count)
+        }
+        ("command", [sub, names @ ..]) if sub.eq_ignore_ascii_case("INFO") => {
+            Command::Introspect(CommandQuery::Info(strings(names)))
+        }
+        ("command", [sub, names @ ..]) if sub.eq_ignore_ascii_case("DOCS") => {
+            Command::Introspect(CommandQuery::Docs(strings(names)))
+        }
+        ("config", [sub, pattern]) if sub.eq_ignore_ascii_case("GET") => Command::ConfigGet {
+            pattern: pattern.to_string(),
+        },
+        ("config", [sub, rest @ ..])
+            if sub.eq_ignore_ascii_case("SET") && !rest.is_empty() && rest.len().is_multiple_of(2) =>
+        {
+            Command::ConfigSet { pairs: pairs(rest) }
+        }
+        ("config", [sub]) if sub.eq_ignore_ascii_case("REWRITE") => Command::ConfigRewrite,
+        ("shutdown", []) => Command::Shutdown { save: None },
+        ("shutdown", [mode]) if mode.eq_ignore_ascii_case("SAVE") => {
+            Command::Shutdown { save: Some(true) }
+        }
+        ("shutdown", [mode]) if mode.eq_ignore_ascii_case("NOSAVE") => {
+            Command::Shutdown { save: Some(false) }
+        }
+        _ => {
+            return Err(ParseError::new(
+                name.column,
+                ParseErrorKind::InvalidArguments(spec.name.to_string()),
+            ));
+        }
+    };
+    Ok(command)
+}
+
+/// `PREPARE name command...`, or `PREPARE name "command"` with the template
+/// in one quoted string.
+fn prepare(args: &[Token]) -> Result<Command, ParseError> {
+    let (name, template) = args.split_first().expect("arity checked");
+    let statement = match template {
+        [quoted] if quoted.kind == TokenKind::String => {
+            // Columns inside the template count from the opening quote.
+            let tokens = tokenize(&quoted.text)?
+                .into_iter()
+                .map(|t| Token { column: t.column + quoted.column, ..t })
+                .collect();
+            Prepared::from_tokens(tokens)?
+        }
+        tokens => Prepared::from_tokens(tokens.to_vec())?,
+    };
+    Ok(Command::Prepare {
+        name: name.text.clone(),
+        statement,
+    })
+}
+
+/// `SCHEMA SET ns [option ...]`, `SCHEMA GET ns` or `SCHEMA DROP ns`.
+fn schema(name: &Token, args: &[Token]) -> Result<Command, ParseError> {
+    let invalid =
+        |column| ParseError::new(column, ParseErrorKind::InvalidArguments("schema".to_string()));
+    let [sub, namespace, options @ ..] = args else {
+        return Err(invalid(name.column));
+    };
+    let namespace = namespace.text.clone();
+    if sub.is_keyword("GET") && options.is_empty() {
+        return Ok(Command::SchemaGet { namespace });
+    }
+    if sub.is_keyword("DROP") && options.is_empty() {
+        return Ok(Command::SchemaDrop { namespace });
+    }
+    if !sub.is_keyword("SET") {
+        return Err(invalid(sub.column));
+    }
+    let mut def = SchemaDef {
+        namespace,
+        ..SchemaDef::default()
+    };
+    for pair in options.chunks(2) {
+        let [option, arg] = pair else {
+            return Err(invalid(pair[0].column));
+        };
+        let number = || arg.text.parse().map_err(|_| invalid(arg.column));
+        let text = Some(arg.text.clone());
+        match option.text.to_ascii_uppercase().as_str() {
+            "VERSION" => {
+                let version = arg.text.parse().ok().filter(|v| *v > 0);
+                def.version = Some(version.ok_or_else(|| invalid(arg.column))?);
+            }
+            "KEYS" => def.key_pattern = text,
+            "TYPE" => {
+                let value_type = arg.text.to_ascii_lowercase();
+                if !VALUE_TYPES.contains(&value_type.as_str()) {
+                    return Err(invalid(arg.column));
+                }
+                def.value_type = Some(value_type);
+            }
+            "MAXKEYLEN" => def.max_key_len = Some(number()?),
+            "MAXSIZE" => def.max_value_len = Some(number()?),
+            "REQUIRED" => {
+                def.required = arg.text.split(',').map(|f| f.trim().to_string()).collect();
+            }
+            "JSONSCHEMA" => def.json_schema = text,
+            "MIGRATE" => {
+                // Columns inside the statement count from the opening quote.
+                let tokens: Vec<Token> = tokenize(&arg.text)?
+                    .into_iter()
+                    .map(|t| Token { column: t.column + arg.column, ..t })
+                    .collect();
+                let statement = sql::parse_statement(&tokens)?;
+                if matches!(statement, Statement::Select(_))
+                    || statement.namespace() != def.namespace
+                {
+                    return Err(invalid(arg.column));
                 }
-                Command::Batch(cmds)
+                def.migration = Some(statement);
+            }
+            _ => return Err(invalid(option.column)),
+        }
+    }
+    Ok(Command::SchemaSet(def))
+}
+
+/// `BATCH [ATOMIC] command ; command ; ...`. Empty segments are skipped, so a
+/// trailing `;` is allowed. The older form without `;`, `BATCH put k1 v1 put
+/// k2 v2`, is still read as PUTs, rather than as one PUT of `v1 put k2 v2`.
+fn batch(tokens: &[Token]) -> Result<Command, ParseError> {
+    let (atomic, tokens) = match tokens.split_first() {
+        Some((first, rest)) if first.is_keyword("ATOMIC") => (true, rest),
+        _ => (false, tokens),
+    };
+    let legacy = tokens.len().is_multiple_of(3)
+        && tokens.iter().all(|t| t.kind != TokenKind::Separator)
+        && tokens
+            .chunks(3)
+            .all(|put| registry::lookup(&put[0].text).is_some_and(|spec| spec.name == "put"));
+    let segments: Vec<&[Token]> = if legacy {
+        tokens.chunks(3).collect()
+    } else {
+        tokens.split(|t| t.kind == TokenKind::Separator).collect()
+    };
+    let mut commands = Vec::new();
+    for segment in segments {
+        let Some(name) = segment.first() else {
+            continue;
+        };
+        if let Some(spec) = registry::lookup(&name.text)
+            && spec.has_flag("nobatch")
+        {
+            return Err(ParseError::new(
+                name.column,
+                ParseErrorKind::NotInBatch(spec.name.to_string()),
+            ));
+        }
+        let command = parse_tokens(segment)?;
+        if atomic && command.changes_catalog() {
+            return Err(ParseError::new(
+                name.column,
+                ParseErrorKind::NotInAtomicBatch(command.name().to_string()),
+            ));
+        }
+        commands.push(command);
+    }
+    if commands.is_empty() {
+        let column = tokens.first().map_or(1, |t| t.column);
+        return Err(ParseError::new(column, ParseErrorKind::InvalidArguments("batch".to_string())));
+    }
+    Ok(Command::Batch { commands, atomic })
+}
+
+
+fn pairs(tokens: &[&str]) -> Vec<(String, String)> {
+    tokens
+        .chunks(2)
+        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+        .collect()
+}
+
+fn strings(tokens: &[&str]) -> Vec<String> {
+    tokens.iter().map(|t| t.to_string()).collect()
+}
+
+/// Blocking timeouts are non-negative seconds, fractions allowed.
+fn parse_timeout(token: &str) -> Option<f64> {
+    token.parse::<f64>().ok().filter(|t| t.is_finite() && *t >= 0.0)
+}
+
+/// `score member [score member ...]` as used by `ZADD`.
+fn scored_members(tokens: &[&str]) -> Option<Vec<(f64, String)>> {
+    if tokens.is_empty() || !tokens.len().is_multiple_of(2) {
+        return None;
+    }
+    tokens
+        .chunks(2)
+        .map(|pair| {
+            let score = pair[0].parse::<f64>().ok().filter(|s| !s.is_nan())?;
+            Some((score, pair[1].to_string()))
+        })
+        .collect()
+}
+
+/// `ZRANGE key start stop [BYSCORE|BYLEX] [WITHSCORES]`.
+fn zrange(start: &str, stop: &str, options: &[&str]) -> Option<(ZRange, bool)> {
+    let mut by = None;
+    let mut with_scores = false;
+    for option in options {
+        if option.eq_ignore_ascii_case("BYSCORE") || option.eq_ignore_ascii_case("BYLEX") {
+            if by.is_some() {
+                return None;
+            }
+            by = Some(option.to_ascii_uppercase());
+        } else if option.eq_ignore_ascii_case("WITHSCORES") {
+            with_scores = true;
+        } else {
+            return None;
+        }
+    }
+    let range = match by.as_deref() {
+        None => ZRange::Rank {
+            start: start.parse().ok()?,
+            stop: stop.parse().ok()?,
+        },
+        Some("BYSCORE") => ZRange::Score {
+            min: ScoreBound::parse(start)?,
+            max: ScoreBound::parse(stop)?,
+        },
+        _ if with_scores => return None,
+        _ => ZRange::Lex {
+            min: LexBound::parse(start)?,
+            max: LexBound::parse(stop)?,
+        },
+    };
+    Some((range, with_scores))
+}
+
+fn stream_trim(strategy: &str, arg: &str) -> Option<StreamTrim> {
+    if strategy.eq_ignore_ascii_case("MAXLEN") {
+        arg.parse().ok().map(StreamTrim::MaxLen)
+    } else if strategy.eq_ignore_ascii_case("MINID") {
+        Some(StreamTrim::MinId(arg.to_string()))
+    } else {
+        None
+    }
+}
+
+/// `XADD key [MAXLEN n | MINID id] id field value [field value ...]`.
+fn xadd(key: &str, rest: &[&str]) -> Option<Command> {
+    let (trim, rest) = match rest {
+        [strategy, arg, rest @ ..] if stream_trim(strategy, arg).is_some() => {
+            (stream_trim(strategy, arg), rest)
+        }
+        _ => (None, rest),
+    };
+    let [id, fields @ ..] = rest else {
+        return None;
+    };
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(Command::XAdd {
+        key: key.to_string(),
+        trim,
+        id: id.to_string(),
+        fields: pairs(fields),
+    })
+}
+
+/// `FT.SEARCH` options `[LIMIT offset count] [WITHSCORES]`; ten results by default.
+fn ft_search_options(tokens: &[&str]) -> Option<(usize, usize, bool)> {
+    let (mut offset, mut count, mut with_scores) = (0, 10, false);
+    let mut i = 0;
+    while i < tokens.len() {
+        let option = tokens[i];
+        if option.eq_ignore_ascii_case("LIMIT") {
+            offset = tokens.get(i + 1)?.parse().ok()?;
+            count = tokens.get(i + 2)?.parse().ok()?;
+            i += 3;
+        } else if option.eq_ignore_ascii_case("WITHSCORES") {
+            with_scores = true;
+            i += 1;
+        } else {
+            return None;
+        }
+    }
+    Some((offset, count, with_scores))
+}
+
+/// Options shared by `XREAD` and `XREADGROUP`:
+/// `[COUNT n] [BLOCK ms] [NOACK] STREAMS key [key ...] id [id ...]`.
+#[allow(clippy::type_complexity)]
+fn xread(tokens: &[&str]) -> Option<(Option<usize>, Option<u64>, bool, Vec<(String, String)>)> {
+    let mut count = None;
+    let mut block = None;
+    let mut no_ack = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let option = tokens[i];
+        if option.eq_ignore_ascii_case("COUNT") {
+            count = Some(tokens.get(i + 1)?.parse().ok()?);
+            i += 2;
+        } else if option.eq_ignore_ascii_case("BLOCK") {
+            block = Some(tokens.get(i + 1)?.parse().ok()?);
+            i += 2;
+        } else if option.eq_ignore_ascii_case("NOACK") {
+            no_ack = true;
+            i += 1;
+        } else if option.eq_ignore_ascii_case("STREAMS") {
+            let rest = &tokens[i + 1..];
+            if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                return None;
             }
-            ["HELP"] | ["help"] => Command::Help,
-            _ => Command::Unknown,
+            let (keys, ids) = rest.split_at(rest.len() / 2);
+            let streams = keys
+                .iter()
+                .zip(ids)
+                .map(|(k, id)| (k.to_string(), id.to_string()))
+                .collect();
+            return Some((count, block, no_ack, streams));
+        } else {
+            return None;
         }
     }
+    None
 }
@@ -0,0 +1,543 @@
+//! Prepared statements.
+//!
+//! A template is lexed once, with `?` (numbered left to right) or `$1`, `$2`,
+//! ... placeholders standing for whole tokens, so a parameter is always
+//! exactly one argument: a value holding quotes, `;` or a newline can never
+//! become part of another command. Quote a `?` that is meant literally.
+//!
+//! `PREPARE` also parses the template once, with a marker string in each
+//! placeholder's place, and keeps the command as its plan; `EXECUTE` copies
+//! the plan and puts the parameters where the markers are. A template whose
+//! placeholders stand for numbers or keywords, such as `ttl ? ?`, cannot be
+//! parsed without its parameters and is parsed on every `EXECUTE` instead, as
+//! is a binding the plan cannot take exactly (a number for a key).
+
+use std::collections::HashMap;
+
+use crate::error::{ParseError, ParseErrorKind};
+use crate::lexer::{Token, TokenKind, tokenize};
+use crate::parser::{Command, parse_tokens};
+use crate::registry;
+use crate::sql::{Assignment, Expr, Literal, Projection, Statement};
+
+/// Prepared statements one connection may hold.
+pub const MAX_STATEMENTS: usize = 1000;
+
+/// A typed parameter value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Param {
+    /// Type an `EXECUTE` argument: quoted strings stay strings, numbers become
+    /// numbers, and `TRUE`, `FALSE` and `NULL` become booleans and null.
+    pub fn from_token(token: &Token) -> Param {
+        match &token.kind {
+            TokenKind::Integer(n) => Param::Int(*n),
+            TokenKind::Float(f) => Param::Float(*f),
+            TokenKind::Word if token.is_keyword("NULL") => Param::Null,
+            TokenKind::Word if token.is_keyword("TRUE") => Param::Bool(true),
+            TokenKind::Word if token.is_keyword("FALSE") => Param::Bool(false),
+            _ => Param::Str(token.text.clone()),
+        }
+    }
+
+    fn to_token(&self, column: usize) -> Token {
+        let (kind, text) = match self {
+            Param::Null => (TokenKind::Word, "NULL".to_string()),
+            Param::Bool(b) => (
+                TokenKind::Word,
+                if *b { "TRUE" } else { "FALSE" }.to_string(),
+            ),
+            Param::Int(n) => (TokenKind::Integer(*n), n.to_string()),
+            Param::Float(f) => (TokenKind::Float(*f), f.to_string()),
+            Param::Str(s) => (TokenKind::String, s.clone()),
+        };
+        Token { kind, text, column }
+    }
+
+    fn to_literal(&self) -> Literal {
+        match self {
+            Param::Null => Literal::Null,
+            Param::Bool(b) => Literal::Bool(*b),
+            Param::Int(n) => Literal::Integer(*n),
+            Param::Float(f) => Literal::Float(*f),
+            Param::Str(s) => Literal::String(s.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Token(Token),
+    /// Zero-based parameter index.
+    Param {
+        index: usize,
+        column: usize,
+    },
+}
+
+/// A lexed command template waiting for parameters.
+#[derive(Debug, Clone)]
+pub struct Prepared {
+    pieces: Vec<Piece>,
+    params: usize,
+    /// The template parsed with markers for its parameters, when it parses
+    /// that way; see the module comment.
+    plan: Option<Box<Command>>,
+}
+
+/// Lex `template` and check that it names a command with a fitting arity.
+pub fn prepare(template: &str) -> Result<Prepared, ParseError> {
+    Prepared::from_tokens(tokenize(template)?)
+}
+
+impl Prepared {
+    pub fn from_tokens(tokens: Vec<Token>) -> Result<Prepared, ParseError> {
+        let mut pieces = Vec::new();
+        let mut numbering = Numbering::default();
+        for token in tokens {
+            if token.kind == TokenKind::Word {
+                split_placeholders(token, &mut numbering, &mut pieces)?;
+            } else {
+                pieces.push(Piece::Token(token));
+            }
+        }
+        let Some(Piece::Token(name)) = pieces.first() else {
+            let column = pieces.first().map_or(1, Piece::column);
+            return Err(ParseError::new(column, ParseErrorKind::Empty));
+        };
+        let spec = registry::lookup(&name.text).ok_or_else(|| {
+            ParseError::new(
+                name.column,
+                ParseErrorKind::UnknownCommand(name.text.clone()),
+            )
+        })?;
+        if matches!(spec.name, "prepare" | "execute" | "deallocate") {
+            return Err(ParseError::new(
+                name.column,
+                ParseErrorKind::InvalidArguments("prepare".to_string()),
+            ));
+        }
+        if spec.name != "batch" && !spec.accepts(pieces.len()) {
+            return Err(ParseError::new(
+                name.column,
+                ParseErrorKind::WrongArity(spec.name.to_string()),
+            ));
+        }
+        let mut prepared = Prepared {
+            pieces,
+            params: numbering.count,
+            plan: None,
+        };
+        prepared.plan = prepared.plan().map(Box::new);
+        Ok(prepared)
+    }
+
+    /// Number of parameters `bind` expects.
+    pub fn params(&self) -> usize {
+        self.params
+    }
+
+    /// Whether `EXECUTE` reuses the parsed template rather than parsing again.
+    pub fn is_planned(&self) -> bool {
+        self.plan.is_some()
+    }
+
+    /// Parse the template with markers for its parameters, and keep the
+    /// result if every marker came through untouched where a parameter can
+    /// be put back.
+    fn plan(&self) -> Option<Command> {
+        let tokens: Vec<Token> = self
+            .pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Token(token) => token.clone(),
+                Piece::Param { index, column } => Token {
+                    kind: TokenKind::String,
+                    text: marker(*index),
+                    column: *column,
+                },
+            })
+            .collect();
+        let mut command = parse_tokens(&tokens).ok()?;
+        if self.params == 0 {
+            return Some(command);
+        }
+        let mut found = Vec::new();
+        if !slots(&mut command, &mut found) {
+            return None;
+        }
+        let placed: usize = found
+            .iter()
+            .map(|slot| (0..self.params).map(|index| slot.text().matches(&marker(index)).count()).sum::<usize>())
+            .sum();
+        let wanted = self.pieces.iter().filter(|piece| matches!(piece, Piece::Param { .. })).count();
+        (placed == wanted).then_some(command)
+    }
+
+    /// Substitute `params` for the placeholders and parse the command.
+    pub fn bind(&self, params: &[Param]) -> Result<Command, ParseError> {
+        if params.len() != self.params {
+            return Err(ParseError::new(
+                1,
+                ParseErrorKind::ParamCount {
+                    expected: self.params,
+                    got: params.len(),
+                },
+            ));
+        }
+        if let Some(plan) = &self.plan
+            && let Some(command) = fill(plan, params)
+        {
+            return Ok(command);
+        }
+        let tokens: Vec<Token> = self
+            .pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Token(token) => token.clone(),
+                Piece::Param { index, column } => params[*index].to_token(*column),
+            })
+            .collect();
+        parse_tokens(&tokens)
+    }
+}
+
+/// The stand-in for parameter `index` while the template is parsed. A
+/// template that spells one out with escapes gets no plan, as `plan` then
+/// finds more markers than placeholders.
+fn marker(index: usize) -> String {
+    format!("\u{1}{}\u{1}", index)
+}
+
+/// The plan with `params` in place, or `None` if a parameter is not a
+/// string but lands where the template parsed a string: the grammar might
+/// have read it otherwise, so the caller parses it.
+fn fill(plan: &Command, params: &[Param]) -> Option<Command> {
+    let mut command = plan.clone();
+    let mut found = Vec::new();
+    slots(&mut command, &mut found);
+    for slot in found {
+        match slot {
+            Slot::Literal(literal) => {
+                if let Literal::String(text) = literal
+                    && let Some(index) = (0..params.len()).find(|&index| *text == marker(index))
+                {
+                    *literal = params[index].to_literal();
+                } else if let Literal::String(text) = literal {
+                    fill_text(text, params)?;
+                }
+            }
+            Slot::Text(text) => fill_text(text, params)?,
+        }
+    }
+    Some(command)
+}
+
+fn fill_text(text: &mut String, params: &[Param]) -> Option<()> {
+    for (index, param) in params.iter().enumerate() {
+        let marker = marker(index);
+        if text.contains(&marker) {
+            let Param::Str(value) = param else {
+                return None;
+            };
+            *text = text.replace(&marker, value);
+        }
+    }
+    Some(())
+}
+
+/// Where a parameter can go in a parsed command.
+enum Slot<'a> {
+    Text(&'a mut String),
+    /// A SQL literal, which takes a parameter of any type.
+    Literal(&'a mut Literal),
+}
+
+impl Slot<'_> {
+    fn text(&self) -> &str {
+        match self {
+            Slot::Text(text) => text,
+            Slot::Literal(Literal::String(text)) => text,
+            Slot::Literal(_) => "",
+        }
+    }
+}
+
+/// Collect the places of `command` a parameter can be put back into.
+/// Returns false for commands whose strings are not listed here; such
+/// templates are parsed on every `EXECUTE`.
+fn slots<'a>(command: &'a mut Command, out: &mut Vec<Slot<'a>>) -> bool {
+    let mut text = |texts: Vec<&'a mut String>| out.extend(texts.into_iter().map(Slot::Text));
+    match command {
+        Command::Put { key, value }
+        | Command::Insert { key, value }
+        | Command::Set { key, value, .. }
+        | Command::SetNx { key, value }
+        | Command::GetSet { key, value }
+        | Command::Append { key, value }
+        | Command::SetRange { key, value, .. } => text(vec![key, value]),
+        Command::Get { key }
+        | Command::Delete { key }
+        | Command::Select { key }
+        | Command::Remove { key }
+        | Command::Ttl { key, .. }
+        | Command::Incr { key }
+        | Command::Decr { key }
+        | Command::IncrBy { key, .. }
+        | Command::DecrBy { key, .. }
+        | Command::IncrByFloat { key, .. }
+        | Command::GetDel { key }
+        | Command::StrLen { key }
+        | Command::GetRange { key, .. }
+        | Command::Type { key }
+        | Command::LPop { key, .. }
+        | Command::RPop { key, .. }
+        | Command::LRange { key, .. }
+        | Command::LLen { key }
+        | Command::LIndex { key, .. }
+        | Command::LTrim { key, .. }
+        | Command::HGetAll { key }
+        | Command::HKeys { key }
+        | Command::HLen { key }
+        | Command::SMembers { key }
+        | Command::SCard { key }
+        | Command::ZPopMin { key, .. }
+        | Command::XLen { key } => text(vec![key]),
+        Command::MGet { keys }
+        | Command::SInter { keys }
+        | Command::SUnion { keys }
+        | Command::SDiff { keys }
+        | Command::BLPop { keys, .. }
+        | Command::BRPop { keys, .. } => text(keys.iter_mut().collect()),
+        Command::MSet { pairs } | Command::MSetNx { pairs } => {
+            text(pairs.iter_mut().flat_map(|(key, value)| [key, value]).collect())
+        }
+        Command::LPush { key, values: list }
+        | Command::RPush { key, values: list }
+        | Command::HMGet { key, fields: list }
+        | Command::HDel { key, fields: list }
+        | Command::SAdd { key, members: list }
+        | Command::SRem { key, members: list }
+        | Command::ZRem { key, members: list } => text(std::iter::once(key).chain(list.iter_mut()).collect()),
+        Command::LMove { source, destination, .. } | Command::BLMove { source, destination, .. } => {
+            text(vec![source, destination])
+        }
+        Command::HSet { key, pairs } => {
+            text(std::iter::once(key).chain(pairs.iter_mut().flat_map(|(field, value)| [field, value])).collect())
+        }
+        Command::HGet { key, field: member }
+        | Command::HIncrBy { key, field: member, .. }
+        | Command::HExists { key, field: member }
+        | Command::SIsMember { key, member }
+        | Command::ZScore { key, member }
+        | Command::ZRank { key, member }
+        | Command::ZIncrBy { key, member, .. } => text(vec![key, member]),
+        Command::ZAdd { key, members } => {
+            text(std::iter::once(key).chain(members.iter_mut().map(|(_, member)| member)).collect())
+        }
+        Command::JsonSet { key, path, value, .. } | Command::JsonNumIncrBy { key, path, delta: value } => {
+            text(vec![key, path, value])
+        }
+        Command::JsonGet { key, path } | Command::JsonDel { key, path } => {
+            text(std::iter::once(key).chain(path.as_mut()).collect())
+        }
+        Command::JsonArrAppend { key, path, values } => {
+            text([key, path].into_iter().chain(values.iter_mut()).collect())
+        }
+        Command::Ping { message } => text(message.as_mut().into_iter().collect()),
+        Command::Query(statement) | Command::Explain(statement) => statement_slots(statement, out),
+        Command::Batch { commands, .. } => return commands.iter_mut().all(|command| slots(command, out)),
+        _ => return false,
+    }
+    true
+}
+
+fn statement_slots<'a>(statement: &'a mut Statement, out: &mut Vec<Slot<'a>>) {
+    match statement {
+        Statement::Select(select) => {
+            if let Projection::Columns(columns) = &mut select.projection {
+                columns.iter_mut().for_each(|expr| expr_slots(expr, out));
+            }
+            select.filter.iter_mut().for_each(|expr| expr_slots(expr, out));
+            select.order_by.iter_mut().for_each(|order| expr_slots(&mut order.expr, out));
+        }
+        Statement::Update(update) => {
+            for assignment in &mut update.assignments {
+                match assignment {
+                    Assignment::Value(expr) => expr_slots(expr, out),
+                    Assignment::JsonPath(path, expr) => {
+                        out.push(Slot::Text(path));
+                        expr_slots(expr, out);
+                    }
+                }
+            }
+            update.filter.iter_mut().for_each(|expr| expr_slots(expr, out));
+        }
+        Statement::Delete(delete) => delete.filter.iter_mut().for_each(|expr| expr_slots(expr, out)),
+    }
+}
+
+fn expr_slots<'a>(expr: &'a mut Expr, out: &mut Vec<Slot<'a>>) {
+    match expr {
+        Expr::Column(_) => {}
+        Expr::Literal(literal) => out.push(Slot::Literal(literal)),
+        Expr::JsonExtract(target, path) => {
+            expr_slots(target, out);
+            out.push(Slot::Text(path));
+        }
+        Expr::Compare(left, _, right) | Expr::And(left, right) | Expr::Or(left, right) => {
+            expr_slots(left, out);
+            expr_slots(right, out);
+        }
+        Expr::Like { expr, pattern, .. } => {
+            expr_slots(expr, out);
+            expr_slots(pattern, out);
+        }
+        Expr::IsNull { expr, .. } | Expr::Not(expr) => expr_slots(expr, out),
+    }
+}
+
+impl Piece {
+    fn column(&self) -> usize {
+        match self {
+            Piece::Token(token) => token.column,
+            Piece::Param { column, .. } => *column,
+        }
+    }
+}
+
+/// Placeholder numbering for one template. `?` and `$n` cannot be mixed.
+#[derive(Default)]
+struct Numbering {
+    positional: bool,
+    numbered: bool,
+    count: usize,
+}
+
+impl Numbering {
+    fn index(&mut self, placeholder: &str, column: usize) -> Result<usize, ParseError> {
+        let index = if placeholder == "?" {
+            self.positional = true;
+            self.count += 1;
+            self.count - 1
+        } else {
+            self.numbered = true;
+            let n: usize = placeholder[1..].parse().unwrap_or(0);
+            if n == 0 {
+                return Err(ParseError::new(
+                    column,
+                    ParseErrorKind::InvalidPlaceholder(placeholder.to_string()),
+                ));
+            }
+            self.count = self.count.max(n);
+            n - 1
+        };
+        if self.positional && self.numbered {
+            return Err(ParseError::new(column, ParseErrorKind::MixedPlaceholders));
+        }
+        Ok(index)
+    }
+}
+
+/// Split a bare word into word pieces and placeholders. A placeholder must
+/// stand alone or be set off by query punctuation, as in `key=?` or
+/// `json_extract(value,$2)`; a `?` inside a word such as `why?` is literal.
+fn split_placeholders(
+    token: Token,
+    numbering: &mut Numbering,
+    pieces: &mut Vec<Piece>,
+) -> Result<(), ParseError> {
+    let chars: Vec<char> = token.text.chars().collect();
+    let is_punct = |c: char| matches!(c, '(' | ')' | ',' | '=' | '<' | '>' | '!');
+    let mut start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let len = match chars[i] {
+            '?' => 1,
+            '$' => {
+                1 + chars[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count()
+            }
+            _ => 0,
+        };
+        let bounded =
+            (i == 0 || is_punct(chars[i - 1])) && chars.get(i + len).is_none_or(|&c| is_punct(c));
+        if len == 0 || (chars[i] == '$' && len == 1) || !bounded {
+            i += 1;
+            continue;
+        }
+        if start < i {
+            pieces.push(Piece::Token(Token {
+                kind: TokenKind::Word,
+                text: chars[start..i].iter().collect(),
+                column: token.column + start,
+            }));
+        }
+        let placeholder: String = chars[i..i + len].iter().collect();
+        let column = token.column + i;
+        pieces.push(Piece::Param {
+            index: numbering.index(&placeholder, column)?,
+            column,
+        });
+        i += len;
+        start = i;
+    }
+    if start == 0 {
+        pieces.push(Piece::Token(token));
+    } else if start < chars.len() {
+        pieces.push(Piece::Token(Token {
+            kind: TokenKind::Word,
+            text: chars[start..].iter().collect(),
+            column: token.column + start,
+        }));
+    }
+    Ok(())
+}
+
+/// The prepared statements of one connection.
+#[derive(Debug, Default)]
+pub struct Statements {
+    cache: HashMap<String, Prepared>,
+}
+
+impl Statements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle `PREPARE`, `EXECUTE` and `DEALLOCATE`. Returns the command to
+    /// run: the bound statement for `EXECUTE`, `None` when the command was
+    /// handled here, or any other command unchanged. A connection holds at
+    /// most `MAX_STATEMENTS`; preparing a name again replaces it.
+    pub fn resolve(&mut self, command: Command) -> Result<Option<Command>, ParseError> {
+        match command {
+            Command::Prepare { name, statement } => {
+                if self.cache.len() >= MAX_STATEMENTS && !self.cache.contains_key(&name) {
+                    return Err(ParseError::new(1, ParseErrorKind::TooManyStatements(MAX_STATEMENTS)));
+                }
+                self.cache.insert(name, statement);
+                Ok(None)
+            }
+            Command::Execute { name, params } => {
+                let statement = self.cache.get(&name).ok_or_else(|| {
+                    ParseError::new(1, ParseErrorKind::UnknownStatement(name.clone()))
+                })?;
+                statement.bind(&params).map(Some)
+            }
+            Command::Deallocate { name } => match self.cache.remove(&name) {
+                Some(_) => Ok(None),
+                None => Err(ParseError::new(1, ParseErrorKind::UnknownStatement(name))),
+            },
+            other => Ok(Some(other)),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! The command table: one entry per command with its arity, flags, help text
+//! and the version that introduced it. Parsing, `help` and `COMMAND` all read
+//! from here.
+
+/// Metadata for one command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandSpec {
+    /// Canonical lower-case name.
+    pub name: &'static str,
+    /// Other names accepted for the command.
+    pub aliases: &'static [&'static str],
+    /// Number of tokens including the name, as in Redis: a positive value is
+    /// an exact count, a negative value a minimum of `-arity`.
+    pub arity: i32,
+    pub flags: &'static [&'static str],
+    /// Data type or area the command belongs to, e.g. `string` or `server`.
+    pub group: &'static str,
+    /// Argument synopsis, without the command name.
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub since: &'static str,
+}
+
+impl CommandSpec {
+    /// Whether `name` is this command or one of its aliases, ignoring case.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether a command line of `tokens` tokens (including the name) fits the arity.
+    pub fn accepts(&self, tokens: usize) -> bool {
+        let arity = self.arity.unsigned_abs() as usize;
+        if self.arity < 0 { tokens >= arity } else { tokens == arity }
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// One `help` line: synopsis and summary.
+    pub fn help_line(&self) -> String {
+        let synopsis = format!("{} {}", self.name, self.usage);
+        format!("  {:<40} - {}", synopsis.trim_end(), self.summary)
+    }
+}
+
+const READ: &[&str] = &["readonly", "fast"];
+const READ_SLOW: &[&str] = &["readonly"];
+// `denyoom` writes may grow the data, so they are refused over `maxmemory`;
+// the `DELETE` ones only shrink it.
+const WRITE: &[&str] = &["write", "denyoom", "fast"];
+const WRITE_SLOW: &[&str] = &["write", "denyoom"];
+const DELETE: &[&str] = &["write", "fast"];
+const DELETE_SLOW: &[&str] = &["write"];
+const BLOCKING: &[&str] = &["write", "blocking"];
+const READ_BLOCKING: &[&str] = &["readonly", "blocking"];
+// Writes to the catalog of indexes and schemas, which shape what every user
+// of a namespace may store or query.
+const CATALOG: &[&str] = &["write", "denyoom", "admin"];
+const CATALOG_DROP: &[&str] = &["write", "admin"];
+// `nobatch` commands belong to the connection or the server as a whole, so
+// the front ends run them themselves and a `BATCH` may not hold them.
+const SESSION: &[&str] = &["nobatch"];
+const ADMIN: &[&str] = &["admin", "nobatch"];
+// Admin commands that stop the server or rewrite all of its data.
+const DANGEROUS: &[&str] = &["admin", "dangerous", "nobatch"];
+const NONE: &[&str] = &[];
+
+const V1: &str = "0.1.0";
+const V2: &str = "0.2.0";
+const V3: &str = "0.3.0";
+
+const fn cmd(
+    name: &'static str,
+    arity: i32,
+    flags: &'static [&'static str],
+    group: &'static str,
+    since: &'static str,
+    usage: &'static str,
+    summary: &'static str,
+) -> CommandSpec {
+    CommandSpec { name, aliases: &[], arity, flags, group, usage, summary, since }
+}
+
+const fn alias(spec: CommandSpec, aliases: &'static [&'static str]) -> CommandSpec {
+    CommandSpec { aliases, ..spec }
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    cmd("put", -3, WRITE, "string", V1, "<key> <value>", "Insert or update a key"),
+    cmd("get", 2, READ, "string", V1, "<key>", "Get value for a key"),
+    cmd("delete", -2, DELETE, "generic", V1, "<key> | FROM <ns> [WHERE <expr>]", "Delete a key, or the matching keys of a namespace"),
+    cmd("insert", -3, WRITE, "string", V1, "<key> <value>", "SQL-like insert"),
+    cmd("select", -2, READ, "string", V1, "<key> | <*|COUNT(*)|expr, ...> FROM <ns> [WHERE <expr>] [ORDER BY <expr> [DESC]] [LIMIT n [OFFSET m]]", "Get a key, or query a namespace"),
+    cmd("remove", 2, DELETE, "generic", V1, "<key>", "SQL-like remove"),
+    cmd("update", -4, WRITE_SLOW, "generic", V3, "<ns> SET value|json_extract(value, <path>) = <expr> [, ...] [WHERE <expr>]", "Update the matching keys of a namespace"),
+    cmd("explain", -3, READ_SLOW, "generic", V3, "SELECT|UPDATE|DELETE ...", "Show how a query would run"),
+    cmd("create", -6, CATALOG, "generic", V3, "[UNIQUE] INDEX <name> ON <ns> (json_extract(value, <path>) | value | value(n))", "Create a secondary index on a namespace"),
+    cmd("drop", 3, CATALOG_DROP, "generic", V3, "INDEX <name>", "Drop a secondary index"),
+    cmd("schema", -3, CATALOG, "generic", V3, "SET <ns> [VERSION n] [KEYS <glob>] [TYPE <type>] [MAXKEYLEN n] [MAXSIZE n] [REQUIRED <field,...>] [JSONSCHEMA <json>] [MIGRATE \"<update|delete>\"] | GET <ns> | DROP <ns>", "Declare, show or drop the schema of a namespace"),
+    cmd("ttl", 3, DELETE, "generic", V1, "<key> <seconds>", "Set time-to-live for a key"),
+    cmd("incr", 2, WRITE, "string", V2, "<key>", "Increment integer value by one"),
+    cmd("decr", 2, WRITE, "string", V2, "<key>", "Decrement integer value by one"),
+    cmd("incrby", 3, WRITE, "string", V2, "<key> <n>", "Increment integer value by n"),
+    cmd("decrby", 3, WRITE, "string", V2, "<key> <n>", "Decrement integer value by n"),
+    cmd("incrbyfloat", 3, WRITE, "string", V2, "<key> <f>", "Increment float value by f"),
+    cmd("set", -3, WRITE_SLOW, "string", V2, "<key> <value> [NX|XX]", "Set a key, optionally only if missing/present"),
+    cmd("setnx", -3, WRITE, "string", V2, "<key> <value>", "Set a key only if it does not exist"),
+    cmd("getset", -3, WRITE, "string", V2, "<key> <value>", "Set a key and return the old value"),
+    cmd("getdel", 2, DELETE, "string", V2, "<key>", "Get a key and delete it"),
+    cmd("append", -3, WRITE, "string", V2, "<key> <value>", "Append to a string value"),
+    cmd("strlen", 2, READ, "string", V2, "<key>", "Length of a string value"),
+    cmd("getrange", 4, READ_SLOW, "string", V2, "<key> <start> <end>", "Substring of a string value"),
+    cmd("setrange", -4, WRITE_SLOW, "string", V2, "<key> <offset> <value>", "Overwrite part of a string value"),
+    cmd("mset", -3, WRITE_SLOW, "string", V2, "<key> <value> [key value ...]", "Set several keys"),
+    cmd("msetnx", -3, WRITE_SLOW, "string", V2, "<key> <value> [key value ...]", "Set several keys if none exist"),
+    cmd("mget", -2, READ, "string", V2, "<key> [key ...]", "Get several keys"),
+    cmd("type", 2, READ, "generic", V2, "<key>", "Type of the value stored at a key"),
+    cmd("lpush", -3, WRITE, "list", V2, "<key> <value> [value ...]", "Push values onto the head of a list"),
+    cmd("rpush", -3, WRITE, "list", V2, "<key> <value> [value ...]", "Push values onto the tail of a list"),
+    cmd("lpop", -2, DELETE, "list", V2, "<key> [count]", "Pop values from the head of a list"),
+    cmd("rpop", -2, DELETE, "list", V2, "<key> [count]", "Pop values from the tail of a list"),
+    cmd("lrange", 4, READ_SLOW, "list", V2, "<key> <start> <stop>", "Range of list elements"),
+    cmd("llen", 2, READ, "list", V2, "<key>", "Length of a list"),
+    cmd("lindex", 3, READ_SLOW, "list", V2, "<key> <index>", "List element at an index"),
+    cmd("ltrim", 4, DELETE_SLOW, "list", V2, "<key> <start> <stop>", "Trim a list to a range"),
+    cmd("lmove", 5, WRITE_SLOW, "list", V2, "<source> <destination> LEFT|RIGHT LEFT|RIGHT", "Move an element between lists"),
+    cmd("blpop", -3, BLOCKING, "list", V2, "<key> [key ...] <timeout>", "Pop from the first non-empty list, waiting if none"),
+    cmd("brpop", -3, BLOCKING, "list", V2, "<key> [key ...] <timeout>", "Pop from the tail of the first non-empty list, waiting if none"),
+    cmd("blmove", 6, BLOCKING, "list", V2, "<source> <destination> LEFT|RIGHT LEFT|RIGHT <timeout>", "Move an element between lists, waiting if the source is empty"),
+    cmd("hset", -4, WRITE, "hash", V2, "<key> <field> <value> [field value ...]", "Set hash fields"),
+    cmd("hget", 3, READ, "hash", V2, "<key> <field>", "Get a hash field"),
+    cmd("hmget", -3, READ, "hash", V2, "<key> <field> [field ...]", "Get several hash fields"),
+    cmd("hdel", -3, DELETE, "hash", V2, "<key> <field> [field ...]", "Delete hash fields"),
+    cmd("hgetall", 2, READ_SLOW, "hash", V2, "<key>", "All fields and values of a hash"),
+    cmd("hkeys", 2, READ_SLOW, "hash", V2, "<key>", "All fields of a hash"),
+    cmd("hlen", 2, READ, "hash", V2, "<key>", "Number of fields in a hash"),
+    cmd("hincrby", 4, WRITE, "hash", V2, "<key> <field> <n>", "Increment a hash field by n"),
+    cmd("hexists", 3, READ, "hash", V2, "<key> <field>", "Whether a hash field exists"),
+    cmd("sadd", -3, WRITE, "set", V2, "<key> <member> [member ...]", "Add set members"),
+    cmd("srem", -3, DELETE, "set", V2, "<key> <member> [member ...]", "Remove set members"),
+    cmd("smembers", 2, READ_SLOW, "set", V2, "<key>", "All members of a set"),
+    cmd("sismember", 3, READ, "set", V2, "<key> <member>", "Whether a set contains a member"),
+    cmd("sinter", -2, READ_SLOW, "set", V2, "<key> [key ...]", "Intersection of sets"),
+    cmd("sunion", -2, READ_SLOW, "set", V2, "<key> [key ...]", "Union of sets"),
+    cmd("sdiff", -2, READ_SLOW, "set", V2, "<key> [key ...]", "Difference of sets"),
+    cmd("scard", 2, READ, "set", V2, "<key>", "Number of members in a set"),
+    cmd("zadd", -4, WRITE, "sorted-set", V2, "<key> <score> <member> [score member ...]", "Add sorted-set members"),
+    cmd("zrem", -3, DELETE, "sorted-set", V2, "<key> <member> [member ...]", "Remove sorted-set members"),
+    cmd("zscore", 3, READ, "sorted-set", V2, "<key> <member>", "Score of a member"),
+    cmd("zrank", 3, READ, "sorted-set", V2, "<key> <member>", "Rank of a member"),
+    cmd("zrange", -4, READ_SLOW, "sorted-set", V2, "<key> <start> <stop> [BYSCORE|BYLEX] [WITHSCORES]", "Range of a sorted set"),
+    cmd("zrangebyscore", -4, READ_SLOW, "sorted-set", V2, "<key> <min> <max> [WITHSCORES]", "Members within a score range"),
+    cmd("zrangebylex", 4, READ_SLOW, "sorted-set", V2, "<key> <min> <max>", "Members within a lexicographic range"),
+    cmd("zincrby", 4, WRITE, "sorted-set", V2, "<key> <delta> <member>", "Increment a member's score"),
+    cmd("zpopmin", -2, DELETE, "sorted-set", V2, "<key> [count]", "Pop lowest-scored members"),
+    cmd("xadd", -5, WRITE, "stream", V2, "<key> [MAXLEN n|MINID id] <id|*> <field> <value> [field value ...]", "Append a stream entry"),
+    cmd("xlen", 2, READ, "stream", V2, "<key>", "Number of stream entries"),
+    cmd("xrange", -4, READ_SLOW, "stream", V2, "<key> <start> <end> [COUNT n]", "Range of stream entries"),
+    cmd("xread", -4, READ_BLOCKING, "stream", V2, "[COUNT n] [BLOCK ms] STREAMS <key> [key ...] <id> [id ...]", "Read new stream entries"),
+    cmd("xtrim", 4, DELETE_SLOW, "stream", V2, "<key> MAXLEN|MINID <arg>", "Trim a stream"),
+    cmd("xgroup", -5, WRITE_SLOW, "stream", V2, "CREATE <key> <group> <id|$> [MKSTREAM]", "Create a consumer group"),
+    cmd("xreadgroup", -7, BLOCKING, "stream", V2, "GROUP <group> <consumer> [COUNT n] [BLOCK ms] [NOACK] STREAMS <key> ... <id> ...", "Read as a group consumer"),
+    cmd("xack", -4, DELETE, "stream", V2, "<key> <group> <id> [id ...]", "Acknowledge stream entries"),
+    cmd("xclaim", -6, WRITE, "stream", V2, "<key> <group> <consumer> <min-idle-ms> <id> [id ...]", "Claim pending entries"),
+    cmd("xpending", 3, READ_SLOW, "stream", V2, "<key> <group>", "Pending entries of a group"),
+    cmd("json.set", -4, WRITE_SLOW, "json", V2, "<key> <path> <json> [NX|XX]", "Set a JSON value at a path"),
+    cmd("json.get", -2, READ_SLOW, "json", V2, "<key> [path]", "Get JSON at a path"),
+    cmd("json.del", -2, DELETE_SLOW, "json", V2, "<key> [path]", "Delete JSON at a path"),
+    cmd("json.arrappend", -4, WRITE_SLOW, "json", V2, "<key> <path> <json> [json ...]", "Append to JSON arrays"),
+    cmd("json.numincrby", 4, WRITE_SLOW, "json", V2, "<key> <path> <n>", "Increment JSON numbers"),
+    cmd("ft.create", -6, CATALOG, "search", V3, "<index> ON <ns> FIELDS value|<path> [value|<path> ...]", "Create a full-text index over a namespace"),
+    cmd("ft.search", -3, READ_SLOW, "search", V3, "<index> <query> [LIMIT offset count] [WITHSCORES]", "Keys matching every query term, ranked by BM25"),
+    cmd("ft.dropindex", 2, CATALOG_DROP, "search", V3, "<index>", "Drop a full-text index"),
+    cmd("batch", -2, SESSION, "generic", V1, "[ATOMIC] <command> [; command ...]", "Run several commands, optionally all-or-nothing"),
+    cmd("snapshot", 1, DANGEROUS, "server", V1, "", "Create snapshot and compact WAL"),
+    cmd("shutdown", -1, DANGEROUS, "server", V3, "[NOSAVE | SAVE]", "Finish in-flight requests, sync the WAL and stop the server"),
+    cmd("config", -2, ADMIN, "server", V3, "GET <pattern> | SET <name> <value> [name value ...] | REWRITE", "Read, change or save server settings"),
+    alias(cmd("list", 1, READ_SLOW, "generic", V1, "", "List all keys/values"), &["keys"]),
+    cmd("command", -1, NONE, "server", V2, "[COUNT | INFO name ... | DOCS name ...]", "Describe available commands"),
+    cmd("prepare", -3, SESSION, "connection", V3, "<name> <command> | <name> \"<command>\"", "Prepare a command with ? or $n placeholders"),
+    cmd("execute", -2, SESSION, "connection", V3, "<name> [param ...]", "Run a prepared command with parameters"),
+    cmd("deallocate", 2, SESSION, "connection", V3, "<name>", "Forget a prepared command"),
+    cmd("hello", -1, SESSION, "connection", V3, "[protover [AUTH <username> <password>]]", "Switch to RESP2 or RESP3 and describe the server"),
+    cmd("auth", -2, SESSION, "connection", V3, "[username] <password | token>", "Authenticate the connection"),
+    cmd("token", -2, ADMIN, "server", V3, "CREATE <name> [TTL <seconds>] | LIST | REVOKE <name>", "Manage API tokens for AUTH"),
+    cmd("acl", -2, ADMIN, "server", V3, "SETUSER <user> [rule ...] | GETUSER <user> | DELUSER <user> [user ...] | LIST | WHOAMI", "Manage users and what they may run and touch"),
+    cmd("ping", -1, NONE, "connection", V3, "[message]", "Check that the server answers"),
+    cmd("help", -1, SESSION, "connection", V1, "[command]", "Show help for all commands or one command"),
+    alias(cmd("exit", 1, SESSION, "connection", V1, "", "Exit the session"), &["quit"]),
+];
+
+/// Subcommands whose flags differ from their command's, named
+/// `command|subcommand` as in Redis. They are not parsed under these names.
+pub const SUBCOMMANDS: &[CommandSpec] = &[
+    cmd("schema|get", 3, READ_SLOW, "generic", V3, "<ns>", "Show the schema of a namespace"),
+    cmd("schema|drop", 3, CATALOG_DROP, "generic", V3, "<ns>", "Drop the schema of a namespace"),
+];
+
+/// Find a command by name or alias, ignoring case.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.matches(name))
+}
+
+/// Find a subcommand by its `command|subcommand` name, ignoring case.
+pub fn subcommand(name: &str) -> Option<&'static CommandSpec> {
+    SUBCOMMANDS.iter().find(|spec| spec.matches(name))
+}
+
+/// Help text for every command, or for `topic` alone.
+pub fn help(topic: Option<&str>) -> String {
+    match topic {
+        None => {
+            let mut out = String::from("Available commands:\n");
+            for spec in COMMANDS {
+                out.push_str(&spec.help_line());
+                out.push('\n');
+            }
+            out
+        }
+        Some(name) => match lookup(name) {
+            Some(spec) => {
+                let mut out = format!("{}\n", spec.help_line());
+                if !spec.aliases.is_empty() {
+                    out.push_str(&format!("  aliases: {}\n", spec.aliases.join(", ")));
+                }
+                out.push_str(&format!("  group: {}, since: {}\n", spec.group, spec.since));
+                out
+            }
+            None => format!("No help for unknown command '{}'\n", name),
+        },
+    }
+}
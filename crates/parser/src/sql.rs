@@ -0,0 +1,640 @@
+//! A small SQL dialect over namespaces of keys.
+//!
+//! ```text
+//! SELECT * | COUNT(*) | expr [, expr ...] FROM ns [WHERE expr]
+//!        [ORDER BY expr [ASC|DESC] [, ...]] [LIMIT n [OFFSET m]]
+//! UPDATE ns SET value = expr | json_extract(value, 'path') = expr [, ...] [WHERE expr]
+//! DELETE FROM ns [WHERE expr]
+//! CREATE [UNIQUE] INDEX name ON ns (json_extract(value, 'path') | value | value(n))
+//! ```
+//!
+//! A namespace `ns` holds the keys that start with `ns:`. Rows have two
+//! columns, `key` and `value`. The command lexer has already split the line on
+//! whitespace; words are split further here on SQL punctuation.
+
+use crate::error::{ParseError, ParseErrorKind};
+use crate::lexer::{Token, TokenKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Select),
+    Update(Update),
+    Delete(Delete),
+}
+
+impl Statement {
+    pub fn namespace(&self) -> &str {
+        match self {
+            Statement::Select(s) => &s.namespace,
+            Statement::Update(u) => &u.namespace,
+            Statement::Delete(d) => &d.namespace,
+        }
+    }
+
+    pub fn filter(&self) -> Option<&Expr> {
+        match self {
+            Statement::Select(s) => s.filter.as_ref(),
+            Statement::Update(u) => u.filter.as_ref(),
+            Statement::Delete(d) => d.filter.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub projection: Projection,
+    pub namespace: String,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    /// `*`: the key and the value.
+    All,
+    /// `COUNT(*)`.
+    Count,
+    Columns(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub namespace: String,
+    pub assignments: Vec<Assignment>,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Assignment {
+    /// `value = expr` replaces the whole value.
+    Value(Expr),
+    /// `json_extract(value, path) = expr` sets one path of a JSON document.
+    JsonPath(String, Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub namespace: String,
+    pub filter: Option<Expr>,
+}
+
+/// `CREATE INDEX`. `target` is `value` or `json_extract(value, path)`;
+/// `prefix` limits a `value` index to its first `n` characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub namespace: String,
+    pub target: Expr,
+    pub prefix: Option<usize>,
+    pub unique: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    Key,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(Column),
+    Literal(Literal),
+    JsonExtract(Box<Expr>, String),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SqlToken {
+    Ident(String),
+    Str(String),
+    Integer(i64),
+    Float(f64),
+    Symbol(&'static str),
+}
+
+#[derive(Debug, Clone)]
+struct Lexeme {
+    token: SqlToken,
+    column: usize,
+}
+
+const SYMBOLS: &[&str] = &["!=", "<>", "<=", ">=", "(", ")", ",", "*", "=", "<", ">"];
+
+fn sql_error(column: usize, reason: impl Into<String>) -> ParseError {
+    ParseError::new(column, ParseErrorKind::Sql(reason.into()))
+}
+
+/// Split command tokens into SQL lexemes.
+fn lex(tokens: &[Token]) -> Result<Vec<Lexeme>, ParseError> {
+    let mut out = Vec::new();
+    for token in tokens {
+        let column = token.column;
+        match &token.kind {
+            TokenKind::String | TokenKind::Bytes => out.push(Lexeme {
+                token: SqlToken::Str(token.text.clone()),
+                column,
+            }),
+            TokenKind::Integer(n) => out.push(Lexeme {
+                token: SqlToken::Integer(*n),
+                column,
+            }),
+            TokenKind::Float(f) => out.push(Lexeme {
+                token: SqlToken::Float(*f),
+                column,
+            }),
+            TokenKind::Separator => {
+                return Err(ParseError::new(column, ParseErrorKind::UnexpectedSeparator));
+            }
+            TokenKind::Word => lex_word(&token.text, column, &mut out)?,
+        }
+    }
+    Ok(out)
+}
+
+fn lex_word(word: &str, column: usize, out: &mut Vec<Lexeme>) -> Result<(), ParseError> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let at = column + i;
+        let rest: String = chars[i..].iter().collect();
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            out.push(Lexeme {
+                token: SqlToken::Symbol(symbol),
+                column: at,
+            });
+            i += symbol.len();
+        } else if c == '\'' || c == '"' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(ParseError::new(at, ParseErrorKind::UnterminatedString)),
+                    Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                        text.push(c);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&other) => {
+                        text.push(other);
+                        i += 1;
+                    }
+                }
+            }
+            out.push(Lexeme {
+                token: SqlToken::Str(text),
+                column: at,
+            });
+        } else if c.is_ascii_digit()
+            || (matches!(c, '-' | '+' | '.')
+                && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '.'
+                    || (matches!(chars[i], '-' | '+') && matches!(chars[i - 1], 'e' | 'E')))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let token = match (text.parse::<i64>(), text.parse::<f64>()) {
+                (Ok(n), _) => SqlToken::Integer(n),
+                (_, Ok(f)) => SqlToken::Float(f),
+                _ => return Err(sql_error(at, format!("invalid number '{}'", text))),
+            };
+            out.push(Lexeme { token, column: at });
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | ':' | '-'))
+            {
+                i += 1;
+            }
+            out.push(Lexeme {
+                token: SqlToken::Ident(chars[start..i].iter().collect()),
+                column: at,
+            });
+        } else {
+            return Err(sql_error(at, format!("unexpected character '{}'", c)));
+        }
+    }
+    Ok(())
+}
+
+struct SqlParser {
+    lexemes: Vec<Lexeme>,
+    pos: usize,
+    /// Column just past the input, for errors at the end.
+    end: usize,
+}
+
+/// Parse a `SELECT`, `UPDATE` or `DELETE` statement. `tokens` includes the
+/// leading keyword.
+pub fn parse_statement(tokens: &[Token]) -> Result<Statement, ParseError> {
+    parse_all(tokens, SqlParser::statement)
+}
+
+/// Parse `CREATE [UNIQUE] INDEX ...`. `tokens` includes `CREATE`.
+pub fn parse_create_index(tokens: &[Token]) -> Result<CreateIndex, ParseError> {
+    parse_all(tokens, SqlParser::create_index)
+}
+
+fn parse_all<T>(
+    tokens: &[Token],
+    parse: impl FnOnce(&mut SqlParser) -> Result<T, ParseError>,
+) -> Result<T, ParseError> {
+    let end = tokens
+        .last()
+        .map_or(1, |t| t.column + t.text.chars().count());
+    let mut parser = SqlParser {
+        lexemes: lex(tokens)?,
+        pos: 0,
+        end,
+    };
+    let parsed = parse(&mut parser)?;
+    if let Some(extra) = parser.peek_lexeme() {
+        return Err(sql_error(extra.column, "unexpected input after statement"));
+    }
+    Ok(parsed)
+}
+
+impl SqlParser {
+    fn peek(&self) -> Option<&SqlToken> {
+        self.lexemes.get(self.pos).map(|l| &l.token)
+    }
+
+    fn peek_lexeme(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos)
+    }
+
+    fn column(&self) -> usize {
+        self.peek_lexeme().map_or(self.end, |l| l.column)
+    }
+
+    fn error<T>(&self, reason: impl Into<String>) -> Result<T, ParseError> {
+        Err(sql_error(self.column(), reason))
+    }
+
+    fn next(&mut self) -> Option<SqlToken> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(SqlToken::Ident(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            self.error(format!("expected {}", keyword))
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(SqlToken::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", symbol))
+        }
+    }
+
+    fn identifier(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(SqlToken::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => self.error(format!("expected {}", what)),
+        }
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(SqlToken::Str(s)) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => self.error(format!("expected {}", what)),
+        }
+    }
+
+    fn count(&mut self, what: &str) -> Result<usize, ParseError> {
+        match self.peek() {
+            Some(SqlToken::Integer(n)) if *n >= 0 => {
+                let n = *n as usize;
+                self.pos += 1;
+                Ok(n)
+            }
+            _ => self.error(format!("expected a non-negative integer for {}", what)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        if self.eat_keyword("SELECT") {
+            self.select().map(Statement::Select)
+        } else if self.eat_keyword("UPDATE") {
+            self.update().map(Statement::Update)
+        } else if self.eat_keyword("DELETE") {
+            self.expect_keyword("FROM")?;
+            let namespace = self.identifier("a namespace")?;
+            let filter = self.filter()?;
+            Ok(Statement::Delete(Delete { namespace, filter }))
+        } else {
+            self.error("expected SELECT, UPDATE or DELETE")
+        }
+    }
+
+    fn create_index(&mut self) -> Result<CreateIndex, ParseError> {
+        self.expect_keyword("CREATE")?;
+        let unique = self.eat_keyword("UNIQUE");
+        self.expect_keyword("INDEX")?;
+        let name = self.identifier("an index name")?;
+        self.expect_keyword("ON")?;
+        let namespace = self.identifier("a namespace")?;
+        self.expect_symbol("(")?;
+        let column = self.column();
+        let target = self.primary()?;
+        let mut prefix = None;
+        match &target {
+            Expr::Column(Column::Value) => {
+                if self.eat_symbol("(") {
+                    prefix = Some(self.count("the prefix length")?);
+                    self.expect_symbol(")")?;
+                }
+            }
+            Expr::JsonExtract(inner, _) if **inner == Expr::Column(Column::Value) => {}
+            _ => {
+                return Err(sql_error(
+                    column,
+                    "only value, value(n) or json_extract(value, path) can be indexed",
+                ));
+            }
+        }
+        self.expect_symbol(")")?;
+        Ok(CreateIndex {
+            name,
+            namespace,
+            target,
+            prefix,
+            unique,
+        })
+    }
+
+    fn select(&mut self) -> Result<Select, ParseError> {
+        let projection = if self.eat_symbol("*") {
+            Projection::All
+        } else if self.at_keyword("COUNT")
+            && self.lexemes.get(self.pos + 1).map(|l| &l.token) == Some(&SqlToken::Symbol("("))
+        {
+            self.pos += 2;
+            self.expect_symbol("*")?;
+            self.expect_symbol(")")?;
+            Projection::Count
+        } else {
+            let mut columns = vec![self.expr()?];
+            while self.eat_symbol(",") {
+                columns.push(self.expr()?);
+            }
+            Projection::Columns(columns)
+        };
+        self.expect_keyword("FROM")?;
+        let namespace = self.identifier("a namespace")?;
+        let filter = self.filter()?;
+
+        let mut order_by = Vec::new();
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let expr = self.expr()?;
+                let descending = if self.eat_keyword("DESC") {
+                    true
+                } else {
+                    self.eat_keyword("ASC");
+                    false
+                };
+                order_by.push(OrderBy { expr, descending });
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        let mut limit = None;
+        let mut offset = 0;
+        if self.eat_keyword("LIMIT") {
+            limit = Some(self.count("LIMIT")?);
+            if self.eat_keyword("OFFSET") {
+                offset = self.count("OFFSET")?;
+            }
+        }
+        Ok(Select {
+            projection,
+            namespace,
+            filter,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn update(&mut self) -> Result<Update, ParseError> {
+        let namespace = self.identifier("a namespace")?;
+        self.expect_keyword("SET")?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.column();
+            let target = self.primary()?;
+            self.expect_symbol("=")?;
+            let value = self.expr()?;
+            assignments.push(match target {
+                Expr::Column(Column::Value) => Assignment::Value(value),
+                Expr::JsonExtract(inner, path) if *inner == Expr::Column(Column::Value) => {
+                    Assignment::JsonPath(path, value)
+                }
+                _ => {
+                    return Err(sql_error(
+                        column,
+                        "only value or json_extract(value, path) can be assigned",
+                    ));
+                }
+            });
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        let filter = self.filter()?;
+        Ok(Update {
+            namespace,
+            assignments,
+            filter,
+        })
+    }
+
+    fn filter(&mut self) -> Result<Option<Expr>, ParseError> {
+        if self.eat_keyword("WHERE") {
+            self.expr().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.and()?;
+        while self.eat_keyword("OR") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.not()?;
+        while self.eat_keyword("AND") {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword("NOT") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.primary()?;
+        let op = match self.peek() {
+            Some(SqlToken::Symbol("=")) => Some(CompareOp::Eq),
+            Some(SqlToken::Symbol("!=" | "<>")) => Some(CompareOp::Ne),
+            Some(SqlToken::Symbol("<")) => Some(CompareOp::Lt),
+            Some(SqlToken::Symbol("<=")) => Some(CompareOp::Le),
+            Some(SqlToken::Symbol(">")) => Some(CompareOp::Gt),
+            Some(SqlToken::Symbol(">=")) => Some(CompareOp::Ge),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            let right = self.primary()?;
+            return Ok(Expr::Compare(Box::new(left), op, Box::new(right)));
+        }
+        if self.eat_keyword("IS") {
+            let negated = self.eat_keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(Expr::IsNull {
+                expr: Box::new(left),
+                negated,
+            });
+        }
+        let negated = self.at_keyword("NOT")
+            && matches!(self.lexemes.get(self.pos + 1).map(|l| &l.token),
+                Some(SqlToken::Ident(w)) if w.eq_ignore_ascii_case("LIKE"));
+        if negated {
+            self.pos += 1;
+        }
+        if self.eat_keyword("LIKE") {
+            let pattern = self.primary()?;
+            return Ok(Expr::Like {
+                expr: Box::new(left),
+                pattern: Box::new(pattern),
+                negated,
+            });
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let column = self.column();
+        match self.next() {
+            Some(SqlToken::Str(s)) => Ok(Expr::Literal(Literal::String(s))),
+            Some(SqlToken::Integer(n)) => Ok(Expr::Literal(Literal::Integer(n))),
+            Some(SqlToken::Float(f)) => Ok(Expr::Literal(Literal::Float(f))),
+            Some(SqlToken::Symbol("(")) => {
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Some(SqlToken::Ident(word)) => match word.to_ascii_lowercase().as_str() {
+                "key" => Ok(Expr::Column(Column::Key)),
+                "value" => Ok(Expr::Column(Column::Value)),
+                "null" => Ok(Expr::Literal(Literal::Null)),
+                "true" => Ok(Expr::Literal(Literal::Bool(true))),
+                "false" => Ok(Expr::Literal(Literal::Bool(false))),
+                "json_extract" => {
+                    self.expect_symbol("(")?;
+                    let target = self.expr()?;
+                    self.expect_symbol(",")?;
+                    let path = self.string("a JSON path string")?;
+                    self.expect_symbol(")")?;
+                    Ok(Expr::JsonExtract(Box::new(target), path))
+                }
+                _ => Err(sql_error(column, format!("unknown column '{}'", word))),
+            },
+            _ => Err(sql_error(column, "expected an expression")),
+        }
+    }
+}
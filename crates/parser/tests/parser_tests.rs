@@ -0,0 +1,397 @@
+use parser::sql::{Column, CompareOp, Expr, Literal, Projection, Statement};
+use parser::{COMMANDS, Command, MAX_STATEMENTS, SchemaDef, Param, Statements, TextIndexDef, ParseError, ParseErrorKind, Parser, SetCondition, SimpleParser, TokenKind, parse_args, prepare, registry, tokenize};
+
+fn parse(input: &str) -> Result<Command, ParseError> {
+    SimpleParser.parse(input)
+}
+
+#[test]
+fn test_quoted_values_keep_their_whitespace() {
+    match parse(r#"put greeting "  hello   world  ""#) {
+        Ok(Command::Put { key, value }) => {
+            assert_eq!(key, "greeting");
+            assert_eq!(value, "  hello   world  ");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse(r"set 'my key' '' NX") {
+        Ok(Command::Set { key, value, .. }) => {
+            assert_eq!(key, "my key");
+            assert_eq!(value, "");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse(r"get path\ with\ spaces # trailing comment") {
+        Ok(Command::Get { key }) => assert_eq!(key, "path with spaces"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_escapes_hex_literals_and_numbers() {
+    let tokens = tokenize(r#""a\tb\x41\u{e9}" 'it\'s' x'68690a' -12 2.5e3 1-1 -- comment"#).unwrap();
+    let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+    assert_eq!(texts, vec!["a\tbAé", "it's", "hi\n", "-12", "2.5e3", "1-1"]);
+    assert_eq!(tokens[2].kind, TokenKind::Bytes);
+    assert_eq!(tokens[3].kind, TokenKind::Integer(-12));
+    assert_eq!(tokens[4].kind, TokenKind::Float(2500.0));
+    assert_eq!(tokens[5].kind, TokenKind::Word);
+    assert_eq!(tokens[1].column, 18);
+}
+
+#[test]
+fn test_errors_report_column_and_reason() {
+    let err = parse(r#"put key "unterminated"#).unwrap_err();
+    assert_eq!(err, ParseError::new(9, ParseErrorKind::UnterminatedString));
+
+    let err = parse(r#"put key "bad \q escape""#).unwrap_err();
+    assert_eq!(err, ParseError::new(14, ParseErrorKind::InvalidEscape(r"\q".to_string())));
+
+    let err = parse("put key x'6g'").unwrap_err();
+    assert_eq!(err, ParseError::new(12, ParseErrorKind::InvalidHex));
+
+    let err = parse("put key x'ff'").unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::NotUtf8);
+
+    let err = parse(r#"put key "a"b"#).unwrap_err();
+    assert_eq!(err, ParseError::new(12, ParseErrorKind::TrailingCharacters));
+
+    let err = parse("  frobnicate key").unwrap_err();
+    assert_eq!(err, ParseError::new(3, ParseErrorKind::UnknownCommand("frobnicate".to_string())));
+    assert_eq!(err.to_string(), "unknown command 'frobnicate' at column 3");
+
+    let err = parse("incrby counter lots").unwrap_err();
+    assert_eq!(err, ParseError::new(1, ParseErrorKind::InvalidArguments("incrby".to_string())));
+
+    assert_eq!(parse("   # only a comment").unwrap_err().kind, ParseErrorKind::Empty);
+    // A `#` that does not stand alone is part of the value.
+    match parse("put note see #42") {
+        Ok(Command::Put { key, value }) => assert_eq!((key.as_str(), value.as_str()), ("note", "see #42")),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse("put tag #42 # the issue") {
+        Ok(Command::Put { value, .. }) => assert_eq!(value, "#42"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_keywords_are_case_insensitive_with_aliases() {
+    assert!(matches!(parse("Put k v"), Ok(Command::Put { .. })));
+    assert!(matches!(parse("insert k v"), Ok(Command::Insert { .. })));
+    assert!(matches!(parse("tTl k 10"), Ok(Command::Ttl { seconds: 10, .. })));
+    assert!(matches!(parse("Keys"), Ok(Command::List)));
+    assert!(matches!(parse("QUIT"), Ok(Command::Exit)));
+    assert!(matches!(parse("Json.Get doc"), Ok(Command::JsonGet { .. })));
+    match parse("help LPUSH") {
+        Ok(Command::Help { topic }) => assert_eq!(topic.as_deref(), Some("LPUSH")),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_registry_drives_arity_and_help() {
+    let err = parse("get a b").unwrap_err();
+    assert_eq!(err, ParseError::new(1, ParseErrorKind::WrongArity("get".to_string())));
+    assert_eq!(err.to_string(), "wrong number of arguments for 'get' at column 1");
+    assert_eq!(parse("hset h f").unwrap_err().kind, ParseErrorKind::WrongArity("hset".to_string()));
+
+    let spec = registry::lookup("KEYS").unwrap();
+    assert_eq!((spec.name, spec.arity), ("list", 1));
+    assert!(registry::lookup("blpop").unwrap().has_flag("blocking"));
+
+    // Every registered command appears in the help text, and nothing else does.
+    let help = registry::help(None);
+    assert_eq!(help.lines().count(), COMMANDS.len() + 1);
+    assert!(COMMANDS.iter().all(|spec| help.contains(&spec.help_line())));
+    assert!(registry::help(Some("quit")).contains("aliases: quit"));
+}
+
+#[test]
+fn test_batch_delimiters_and_atomic_option() {
+    match parse(r#"BATCH ATOMIC put a "x;y"; incr n ;get a;"#) {
+        Ok(Command::Batch { commands, atomic }) => {
+            assert!(atomic);
+            assert_eq!(commands.len(), 3);
+            assert!(matches!(&commands[0], Command::Put { value, .. } if value == "x;y"));
+            assert!(matches!(&commands[1], Command::Incr { .. }));
+            assert!(matches!(&commands[2], Command::Get { .. }));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(parse("batch ttl k 5"), Ok(Command::Batch { atomic: false, .. })));
+    // The form without `;` from before delimiters still means PUTs.
+    match parse("batch put k1 v1 put k2 v2") {
+        Ok(Command::Batch { commands, atomic: false }) => {
+            assert_eq!(commands.len(), 2);
+            assert!(matches!(&commands[0], Command::Put { key, value } if key == "k1" && value == "v1"));
+            assert!(matches!(&commands[1], Command::Put { key, value } if key == "k2" && value == "v2"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Errors inside a batch point at the offending sub-command.
+    let err = parse("batch get a; incrby n lots").unwrap_err();
+    assert_eq!(err, ParseError::new(14, ParseErrorKind::InvalidArguments("incrby".to_string())));
+    let err = parse("batch get a; batch get b").unwrap_err();
+    assert_eq!(err, ParseError::new(14, ParseErrorKind::NotInBatch("batch".to_string())));
+    // Connection and server commands are run by the front ends, never in a batch.
+    for (line, name) in [
+        ("batch get a; config set maxclients 5", "config"),
+        ("batch get a; acl whoami", "acl"),
+        ("batch get a; token list", "token"),
+        ("batch get a; auth secret", "auth"),
+        ("batch get a; hello 3", "hello"),
+        ("batch get a; shutdown nosave", "shutdown"),
+        ("batch get a; quit", "exit"),
+    ] {
+        let err = parse(line).unwrap_err();
+        assert_eq!(err, ParseError::new(14, ParseErrorKind::NotInBatch(name.to_string())), "{}", line);
+    }
+    // Index and schema changes would outlive a rollback.
+    let err = parse("batch atomic put a 1; drop index by_a").unwrap_err();
+    assert_eq!(err, ParseError::new(23, ParseErrorKind::NotInAtomicBatch("drop".to_string())));
+    assert!(parse("batch put a 1; drop index by_a").is_ok());
+    assert_eq!(parse("batch ;;").unwrap_err().kind, ParseErrorKind::InvalidArguments("batch".to_string()));
+    assert_eq!(parse("get a; get b").unwrap_err(), ParseError::new(6, ParseErrorKind::UnexpectedSeparator));
+}
+
+#[test]
+fn test_sql_statements_and_legacy_forms() {
+    match parse("SELECT key, json_extract(value, '$.age') FROM user WHERE json_extract(value,'$.age')>=30 AND key LIKE 'user:a%' ORDER BY key DESC LIMIT 2 OFFSET 1") {
+        Ok(Command::Query(Statement::Select(select))) => {
+            assert_eq!(select.namespace, "user");
+            assert!(matches!(&select.projection, Projection::Columns(columns) if columns.len() == 2));
+            assert!(matches!(&select.filter, Some(Expr::And(left, _))
+                if matches!(**left, Expr::Compare(_, CompareOp::Ge, ref right) if **right == Expr::Literal(Literal::Integer(30)))));
+            assert!(select.order_by[0].descending);
+            assert_eq!((select.limit, select.offset), (Some(2), 1));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(parse("select count(*) from user"),
+        Ok(Command::Query(Statement::Select(s))) if s.projection == Projection::Count));
+    assert!(matches!(parse("UPDATE user SET json_extract(value, '$.age') = 31, value = 'x' WHERE key = 'user:1'"),
+        Ok(Command::Query(Statement::Update(u))) if u.assignments.len() == 2));
+    assert!(matches!(parse("delete from user where not (value is null)"), Ok(Command::Query(Statement::Delete(_)))));
+    assert!(matches!(parse("explain select * from user"), Ok(Command::Explain(_))));
+    assert!(matches!(parse("CREATE UNIQUE INDEX code ON user (value(4))"),
+        Ok(Command::CreateIndex(def)) if def.unique && def.prefix == Some(4) && def.target == Expr::Column(Column::Value)));
+    assert!(matches!(parse("drop index code"), Ok(Command::DropIndex { name }) if name == "code"));
+    assert_eq!(
+        parse("create index k on user (key)").unwrap_err().kind,
+        ParseErrorKind::Sql("only value, value(n) or json_extract(value, path) can be indexed".to_string())
+    );
+
+    match parse(r#"schema set user version 2 keys u* TYPE JSON maxsize 512 required name,email jsonschema '{"type":"object"}' migrate "update user set json_extract(value, '$.v') = 2""#) {
+        Ok(Command::SchemaSet(def)) => {
+            assert!(matches!(def.migration, Some(Statement::Update(_))));
+            assert_eq!(
+                def,
+                SchemaDef {
+                    namespace: "user".to_string(),
+                    version: Some(2),
+                    key_pattern: Some("u*".to_string()),
+                    value_type: Some("json".to_string()),
+                    max_value_len: Some(512),
+                    required: vec!["name".to_string(), "email".to_string()],
+                    json_schema: Some(r#"{"type":"object"}"#.to_string()),
+                    migration: def.migration.clone(),
+                    ..SchemaDef::default()
+                }
+            );
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(parse("schema get user"), Ok(Command::SchemaGet { namespace }) if namespace == "user"));
+    assert_eq!(parse("schema set user type blob").unwrap_err(), ParseError::new(22, ParseErrorKind::InvalidArguments("schema".to_string())));
+    assert_eq!(parse("schema set user version 0").unwrap_err().column, 25);
+    // A migration must rewrite the namespace whose schema changes.
+    assert_eq!(parse(r#"schema set user migrate "delete from orders""#).unwrap_err().column, 25);
+
+    // One argument keeps the key-value meaning.
+    assert!(matches!(parse("select user:1"), Ok(Command::Select { .. })));
+    assert!(matches!(parse("delete user:1"), Ok(Command::Delete { .. })));
+
+    let err = parse("select * from user where value =").unwrap_err();
+    assert_eq!(err, ParseError::new(33, ParseErrorKind::Sql("expected an expression".to_string())));
+    let err = parse("select * from user limit -1").unwrap_err();
+    assert_eq!(err.column, 26);
+    let err = parse("update user set key = 'x'").unwrap_err();
+    assert_eq!(err.to_string(), "invalid query: only value or json_extract(value, path) can be assigned at column 17");
+}
+
+#[test]
+fn test_resp_arguments_are_never_split() {
+    let parse = |args: &[&str]| parse_args(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    assert!(matches!(
+        parse(&["SET", "k", "two words; \"quoted\"", "NX"]),
+        Ok(Command::Set { key, value, condition: SetCondition::IfNotExists }) if key == "k" && value == "two words; \"quoted\""
+    ));
+    assert!(matches!(parse(&["incrby", "n", "-3"]), Ok(Command::IncrBy { delta: -3, .. })));
+    assert!(matches!(parse(&["set", "k", ";"]), Ok(Command::Set { value, .. }) if value == ";"));
+    assert!(matches!(parse(&["batch", "put", "a", "1", ";", "incr", "a"]), Ok(Command::Batch { commands, .. }) if commands.len() == 2));
+    assert!(matches!(parse(&["hello", "3"]), Ok(Command::Hello { protocol: Some(3), auth: None })));
+    assert_eq!(parse(&["get"]).unwrap_err(), ParseError::new(1, ParseErrorKind::WrongArity("get".to_string())));
+    assert_eq!(parse(&[]).unwrap_err().kind, ParseErrorKind::Empty);
+}
+
+#[test]
+fn test_full_text_commands() {
+    let parse = |line: &str| SimpleParser.parse(line);
+    match parse("FT.CREATE posts ON post FIELDS $.title $.body") {
+        Ok(Command::FtCreate(def)) => assert_eq!(
+            def,
+            TextIndexDef {
+                name: "posts".to_string(),
+                namespace: "post".to_string(),
+                fields: vec!["$.title".to_string(), "$.body".to_string()],
+            }
+        ),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(
+        parse(r#"ft.search posts "quick fox" LIMIT 10 5 withscores"#),
+        Ok(Command::FtSearch { index, query, offset: 10, count: 5, with_scores: true })
+            if index == "posts" && query == "quick fox"
+    ));
+    assert!(matches!(parse("ft.search posts fox"), Ok(Command::FtSearch { offset: 0, count: 10, with_scores: false, .. })));
+    assert!(matches!(parse("ft.dropindex posts"), Ok(Command::FtDropIndex { index }) if index == "posts"));
+
+    assert_eq!(parse("ft.create posts on post fields").unwrap_err().kind, ParseErrorKind::WrongArity("ft.create".to_string()));
+    assert_eq!(parse("ft.search posts fox limit 1").unwrap_err(), ParseError::new(1, ParseErrorKind::InvalidArguments("ft.search".to_string())));
+}
+
+#[test]
+fn test_config_commands() {
+    let parse = |line: &str| SimpleParser.parse(line);
+    assert!(matches!(parse("config get max*"), Ok(Command::ConfigGet { pattern }) if pattern == "max*"));
+    assert!(matches!(
+        parse(r#"CONFIG SET save "60 1000" maxclients 5"#),
+        Ok(Command::ConfigSet { pairs }) if pairs == [("save".to_string(), "60 1000".to_string()), ("maxclients".to_string(), "5".to_string())]
+    ));
+    assert!(matches!(parse("config rewrite"), Ok(Command::ConfigRewrite)));
+    assert_eq!(parse("config set maxclients").unwrap_err(), ParseError::new(1, ParseErrorKind::InvalidArguments("config".to_string())));
+}
+
+#[test]
+fn test_acl_commands() {
+    let parse = |line: &str| SimpleParser.parse(line);
+    assert!(matches!(
+        parse("acl setuser alice on >pw +@read ~cache:* ns:orders"),
+        Ok(Command::AclSetUser { name, rules }) if name == "alice" && rules == ["on", ">pw", "+@read", "~cache:*", "ns:orders"]
+    ));
+    assert!(matches!(parse("ACL SETUSER bob"), Ok(Command::AclSetUser { rules, .. }) if rules.is_empty()));
+    assert!(matches!(parse("acl getuser alice"), Ok(Command::AclGetUser { name }) if name == "alice"));
+    assert!(matches!(parse("acl deluser a b"), Ok(Command::AclDelUser { names }) if names == ["a", "b"]));
+    assert!(matches!(parse("acl list"), Ok(Command::AclList)));
+    assert!(matches!(parse("acl whoami"), Ok(Command::AclWhoami)));
+    assert_eq!(parse("acl deluser").unwrap_err(), ParseError::new(1, ParseErrorKind::InvalidArguments("acl".to_string())));
+
+    // Permissions come from the registry entry a command was parsed from.
+    for line in ["keys", "lpop l 2", "select * from user", "delete from user", "delete k", "xgroup create s g $", "acl whoami"] {
+        let name = line.split_whitespace().next().unwrap();
+        assert_eq!(Some(parse(line).unwrap().name()), registry::lookup(name).map(|spec| spec.name), "{}", line);
+    }
+    assert_eq!(parse("update user set value = 1").unwrap().name(), "update");
+    assert_eq!(parse("zrangebyscore z 0 1").unwrap().name(), "zrange");
+}
+
+#[test]
+fn test_prepared_statements_bind_typed_parameters() {
+    let put = prepare("put ? ?").unwrap();
+    assert_eq!(put.params(), 2);
+    // A hostile value stays a single argument.
+    let hostile = Param::Str("x\nsnapshot; delete other".to_string());
+    match put.bind(&[Param::Str("k".to_string()), hostile]) {
+        Ok(Command::Put { key, value }) => {
+            assert_eq!(key, "k");
+            assert_eq!(value, "x\nsnapshot; delete other");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let query = prepare("select key from user where key=$2 or json_extract(value,'$.age') > $1").unwrap();
+    match query.bind(&[Param::Int(30), Param::Str("user:1' or '1'='1".to_string())]) {
+        Ok(Command::Query(Statement::Select(select))) => {
+            let Some(Expr::Or(left, right)) = select.filter else { panic!("expected OR") };
+            assert!(matches!(*left, Expr::Compare(_, CompareOp::Eq, ref value)
+                if **value == Expr::Literal(Literal::String("user:1' or '1'='1".to_string()))));
+            assert!(matches!(*right, Expr::Compare(_, CompareOp::Gt, ref value)
+                if **value == Expr::Literal(Literal::Integer(30))));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Typing happens at bind time: a string cannot stand in for a count.
+    let ttl = prepare("ttl ? ?").unwrap();
+    assert!(matches!(ttl.bind(&[Param::Str("k".into()), Param::Int(5)]), Ok(Command::Ttl { seconds: 5, .. })));
+    assert_eq!(
+        ttl.bind(&[Param::Str("k".into()), Param::Str("5s".into())]).unwrap_err().kind,
+        ParseErrorKind::InvalidArguments("ttl".to_string())
+    );
+    assert_eq!(
+        ttl.bind(&[Param::Null]).unwrap_err().kind,
+        ParseErrorKind::ParamCount { expected: 2, got: 1 }
+    );
+
+    // Templates are parsed once when their placeholders stand for strings
+    // or SQL literals; numbers for strings still go through the grammar.
+    assert!(put.is_planned() && query.is_planned());
+    assert!(!ttl.is_planned());
+    assert!(matches!(put.bind(&[Param::Int(7), Param::Null]), Ok(Command::Put { key, value }) if key == "7" && value == "NULL"));
+    let batch = prepare("batch hset h f $1 ; lpush l $1").unwrap();
+    assert!(batch.is_planned());
+    match batch.bind(&[Param::Str("v".into())]) {
+        Ok(Command::Batch { commands, .. }) => {
+            assert!(matches!(&commands[0], Command::HSet { pairs, .. } if pairs[0].1 == "v"));
+            assert!(matches!(&commands[1], Command::LPush { values, .. } if values[0] == "v"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    assert!(matches!(prepare("get why?").unwrap().params(), 0));
+    assert_eq!(prepare("mget ? $1").unwrap_err(), ParseError::new(8, ParseErrorKind::MixedPlaceholders));
+    assert_eq!(prepare("get $0").unwrap_err().kind, ParseErrorKind::InvalidPlaceholder("$0".to_string()));
+    assert_eq!(prepare("get ? ?").unwrap_err().kind, ParseErrorKind::WrongArity("get".to_string()));
+
+    match parse(r#"PREPARE q "get ?""#) {
+        Ok(Command::Prepare { name, statement }) => assert_eq!((name.as_str(), statement.params()), ("q", 1)),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse("execute q 'a b' 2 2.5 null TRUE") {
+        Ok(Command::Execute { params, .. }) => assert_eq!(
+            params,
+            vec![Param::Str("a b".into()), Param::Int(2), Param::Float(2.5), Param::Null, Param::Bool(true)]
+        ),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(parse("batch execute q 1").unwrap_err().kind, ParseErrorKind::NotInBatch("execute".to_string()));
+
+    // A connection holds a bounded number of statements.
+    let mut statements = Statements::new();
+    for i in 0..MAX_STATEMENTS {
+        statements.resolve(parse(&format!("prepare q{} get ?", i)).unwrap()).unwrap();
+    }
+    statements.resolve(parse("prepare q0 get ?").unwrap()).unwrap();
+    assert_eq!(
+        statements.resolve(parse("prepare extra get ?").unwrap()).unwrap_err().kind,
+        ParseErrorKind::TooManyStatements(MAX_STATEMENTS)
+    );
+    statements.resolve(parse("deallocate q1").unwrap()).unwrap();
+    statements.resolve(parse("prepare extra get ?").unwrap()).unwrap();
+}
+
+#[test]
+fn test_stream_reads_take_noack_only_in_a_group() {
+    match parse("xread count 2 streams events 0") {
+        Ok(Command::XRead { count, streams, .. }) => {
+            assert_eq!(count, Some(2));
+            assert_eq!(streams, [("events".to_string(), "0".to_string())]);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let err = parse("xread noack streams events 0").unwrap_err();
+    assert_eq!(err.kind, ParseErrorKind::InvalidArguments("xread".to_string()));
+    assert!(parse("xreadgroup group g c noack streams events >").is_ok());
+}
@@ -0,0 +1,446 @@
+//! One client connection: framing, pipelining and dispatch.
+//!
+//! A connection is a task that reads into its own input buffer, runs every
+//! complete request in it and writes the replies back in one go. While a
+//! write is pending nothing more is read, so a client that stops reading its
+//! replies stops being served instead of growing the server's buffers.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use parser::{Command, ParseErrorKind, Parser, SimpleParser, Statements, parse_args, registry};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+use zyncdb_core::Reply;
+use zyncdb_core::acl::{DEFAULT_USER, User};
+use zyncdb_core::exec::{execute, prepare_blocking};
+use zyncdb_core::resp::{self, Protocol, Request};
+
+use crate::{Shared, Shutdown};
+
+/// Replies wait in a connection's output buffer until its pipelined requests
+/// have all run, unless this many bytes pile up first.
+const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+/// Unparsed input a client may send ahead before it is disconnected.
+const MAX_QUERY_BUFFER: usize = 1024 * 1024 * 1024;
+/// Room made in the input buffer before each read.
+const READ_SIZE: usize = 4096;
+/// Idle connections give back buffers that grew past this size.
+const IDLE_BUFFER_CAPACITY: usize = 16 * 1024;
+
+/// Run a blocking list or stream command, waiting until it yields a value,
+/// times out or the server stops.
+async fn run_blocking(shared: &Shared, command: Command) -> Reply {
+    let deadline = match command.timeout() {
+        Some(secs) if secs > 0.0 => Some(Instant::now() + Duration::from_secs_f64(secs)),
+        _ => None,
+    };
+    let command = match prepare_blocking(&mut shared.store.lock().unwrap(), command) {
+        Ok(command) => command,
+        Err(reply) => return reply,
+    };
+    let mut stopping = shared.shutdown.subscribe();
+    loop {
+        // Listen before trying, so a write between the two is not missed.
+        let changed = shared.changed.notified();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+        let reply = execute(&mut shared.store.lock().unwrap(), command.clone());
+        if reply != Reply::Nil {
+            shared.changed.notify_waiters();
+            return reply;
+        }
+        let timeout = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = changed => {}
+            _ = timeout => return Reply::Nil,
+            _ = stopping.wait_for(Option::is_some) => return Reply::Nil,
+        }
+    }
+}
+
+/// Whether the command may grow the data, which is refused while over
+/// `maxmemory`. A batch does if any of its commands does.
+fn grows_data(command: &Command) -> bool {
+    match command {
+        Command::Batch { commands, .. } => commands.iter().any(grows_data),
+        command => command.spec().is_some_and(|spec| spec.has_flag("denyoom")),
+    }
+}
+
+/// Commands a client may send before it has authenticated.
+fn allowed_before_auth(command: &Command) -> bool {
+    matches!(command, Command::Auth { .. } | Command::Hello { .. } | Command::Exit)
+}
+
+fn noauth() -> Reply {
+    Reply::Error("NOAUTH Authentication required.".to_string())
+}
+
+/// `ACL GETUSER`: a user's flags and passwords, and its permissions as rules.
+fn describe_user(user: &User) -> Reply {
+    let field = |name: &str| Reply::Bulk(name.to_string());
+    let rules = user.rules();
+    let joined = |pick: fn(&str) -> bool| {
+        Reply::Bulk(rules.iter().filter(|rule| pick(rule)).cloned().collect::<Vec<_>>().join(" "))
+    };
+    let mut flags = vec![field(if user.enabled { "on" } else { "off" })];
+    if user.nopass {
+        flags.push(field("nopass"));
+    }
+    Reply::Map(vec![
+        (field("flags"), Reply::Array(flags)),
+        (
+            field("passwords"),
+            Reply::Array(
+                rules
+                    .iter()
+                    .filter_map(|rule| rule.strip_prefix('#'))
+                    .map(field)
+                    .collect(),
+            ),
+        ),
+        (field("commands"), joined(|rule| rule.starts_with("+@") || rule.starts_with("-@"))),
+        (field("keys"), joined(|rule| rule.starts_with('~') || rule == "resetkeys")),
+        (field("namespaces"), joined(|rule| rule.starts_with("ns:"))),
+    ])
+}
+
+/// Per-connection state.
+struct Session<S> {
+    id: u64,
+    /// The peer's address, under which failed logins are throttled.
+    client: String,
+    /// The user the client runs as, once it has run AUTH, or from the start
+    /// when the default user needs no password; `None` until then.
+    user: Option<String>,
+    stream: S,
+    protocol: Protocol,
+    statements: Statements,
+    /// Encoded replies not yet written.
+    output: Vec<u8>,
+    /// Set by `exit`/`quit`; the connection closes after the reply.
+    closing: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    /// Reply to one request, or `None` for a blank line.
+    async fn reply(&mut self, request: Request, shared: &Shared) -> Option<Reply> {
+        let parsed = match request {
+            Request::Inline(line) => SimpleParser.parse(&line),
+            Request::Args(args) => parse_args(&args),
+        };
+        if self.user.is_none() && !parsed.as_ref().is_ok_and(allowed_before_auth) {
+            if matches!(&parsed, Err(e) if e.kind == ParseErrorKind::Empty) {
+                return None;
+            }
+            return Some(noauth());
+        }
+        let command = match parsed.and_then(|c| self.statements.resolve(c)) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Some(Reply::Ok),
+            Err(e) if e.kind == ParseErrorKind::Empty => return None,
+            Err(e) => return Some(Reply::error(e)),
+        };
+        if grows_data(&command) && shared.over_maxmemory() {
+            return Some(Reply::Error(
+                "OOM command not allowed when used memory > 'maxmemory'".to_string(),
+            ));
+        }
+        if let Err(denied) = self.permit(shared, &command) {
+            return Some(denied);
+        }
+        if command.is_blocking() {
+            // Earlier replies must not wait for this one.
+            let _ = self.flush().await;
+            return Some(run_blocking(shared, command).await);
+        }
+        let reply = match command {
+            Command::Hello { protocol, auth } => match auth {
+                Some((username, password)) => match self.auth(shared, Some(&username), password).await {
+                    Reply::Ok => self.hello(protocol),
+                    error => error,
+                },
+                None => self.hello(protocol),
+            },
+            Command::Auth { username, password } => {
+                self.auth(shared, username.as_deref(), password).await
+            }
+            Command::TokenCreate { name, ttl } => {
+                let user = self.user.as_deref().unwrap_or(DEFAULT_USER);
+                match shared.tokens.lock().unwrap().create(&name, user, ttl) {
+                    Ok(token) => Reply::Bulk(token),
+                    Err(e) => Reply::error(e),
+                }
+            }
+            Command::TokenList => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                let tokens = shared.tokens.lock().unwrap();
+                // Seconds left, as TTL gives them: -1 for a token that never expires.
+                Reply::Map(
+                    tokens
+                        .list()
+                        .map(|token| {
+                            let left = token.expires.map_or(-1, |at| at.saturating_sub(now) as i64);
+                            (Reply::Bulk(token.name.clone()), Reply::Integer(left))
+                        })
+                        .collect(),
+                )
+            }
+            Command::TokenRevoke { name } => match shared.tokens.lock().unwrap().revoke(&name) {
+                Ok(revoked) => Reply::Integer(revoked as i64),
+                Err(e) => Reply::Error(format!("token file error: {}", e)),
+            },
+            Command::AclSetUser { name, rules } => {
+                match shared.acl.lock().unwrap().set_user(&name, &rules) {
+                    Ok(()) => Reply::Ok,
+                    Err(e) => Reply::error(e),
+                }
+            }
+            Command::AclGetUser { name } => match shared.acl.lock().unwrap().user(&name) {
+                Some(user) => describe_user(user),
+                None => Reply::Nil,
+            },
+            Command::AclDelUser { names } => match shared.acl.lock().unwrap().del_users(&names) {
+                Ok(removed) => Reply::Integer(removed as i64),
+                Err(e) => Reply::error(e),
+            },
+            Command::AclList => {
+                Reply::Array(shared.acl.lock().unwrap().list().into_iter().map(Reply::Bulk).collect())
+            }
+            Command::AclWhoami => Reply::Bulk(self.user.clone().unwrap_or_default()),
+            Command::Help { topic } => {
+                Reply::Bulk(registry::help(topic.as_deref()).trim_end().to_string())
+            }
+            Command::Exit => {
+                self.closing = true;
+                Reply::Ok
+            }
+            Command::Shutdown { save } => {
+                self.closing = true;
+                shared.stop(Shutdown::from(save));
+                Reply::Ok
+            }
+            command @ (Command::ConfigGet { .. }
+            | Command::ConfigSet { .. }
+            | Command::ConfigRewrite) => {
+                let mut config = shared.config.lock().unwrap();
+                let reply = config.execute(command);
+                shared.apply(&config);
+                reply
+            }
+            Command::Snapshot => {
+                let (snapshot_path, wal_path) = {
+                    let config = shared.config.lock().unwrap();
+                    (config.snapshot_path(), config.wal_path())
+                };
+                let mut store = shared.store.lock().unwrap();
+                match store.snapshot_and_compact(&snapshot_path, &wal_path) {
+                    Ok(_) => Reply::Status("Snapshot and compaction complete.".to_string()),
+                    Err(e) => Reply::Error(format!("Snapshot error: {}", e)),
+                }
+            }
+            command => {
+                let reply = execute(&mut shared.store.lock().unwrap(), command);
+                shared.changed.notify_waiters();
+                reply
+            }
+        };
+        Some(reply)
+    }
+
+    fn send(&mut self, reply: &Reply) {
+        resp::encode(reply, self.protocol, &mut self.output);
+    }
+
+    /// Write out every pending reply in one go.
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if !self.output.is_empty() {
+            self.stream.write_all(&self.output).await?;
+            self.output.clear();
+        }
+        Ok(())
+    }
+
+    /// Check the permissions of the session's user against its current
+    /// rules. A user removed or disabled since it logged in is logged out.
+    fn permit(&mut self, shared: &Shared, command: &Command) -> Result<(), Reply> {
+        let Some(name) = &self.user else {
+            return Ok(());
+        };
+        let acl = shared.acl.lock().unwrap();
+        match acl.user(name).filter(|user| user.enabled) {
+            Some(user) => user.check(command).map_err(Reply::Error),
+            None => {
+                drop(acl);
+                self.user = None;
+                Err(noauth())
+            }
+        }
+    }
+
+    /// Log in as `username`, or the default user, with a password or an API
+    /// token, unless this client has failed too often lately. The hash
+    /// checks run off the async threads, as they are slow on purpose.
+    async fn auth(&mut self, shared: &Shared, username: Option<&str>, password: String) -> Reply {
+        let name = username.unwrap_or(DEFAULT_USER).to_string();
+        let requirepass = shared.config.lock().unwrap().requirepass.clone();
+        let user = shared.acl.lock().unwrap().user(&name).cloned();
+        if username.is_none() && requirepass.is_empty() && user.as_ref().is_some_and(|user| user.nopass) {
+            return Reply::Error(
+                "AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?"
+                    .to_string(),
+            );
+        }
+        if let Some(wait) = shared.throttle.lock().unwrap().wait(&self.client) {
+            return Reply::Error(format!(
+                "too many failed authentication attempts, try again in {:.1}s",
+                wait.as_secs_f64()
+            ));
+        }
+        let token = shared.tokens.lock().unwrap().find(&password).filter(|token| token.user == name).cloned();
+        let valid = tokio::task::spawn_blocking(move || {
+            user.is_some_and(|user| {
+                user.accepts(&password, &requirepass)
+                    || (user.enabled && token.is_some_and(|token| token.verify(&password)))
+            })
+        })
+        .await
+        .unwrap_or(false);
+        let mut throttle = shared.throttle.lock().unwrap();
+        if valid {
+            throttle.succeeded(&self.client);
+            self.user = Some(name);
+            Reply::Ok
+        } else {
+            throttle.failed(&self.client);
+            log::warn!("Client {} from {} failed to authenticate as {}", self.id, self.client, name);
+            Reply::Error("WRONGPASS invalid username-password pair or user is disabled.".to_string())
+        }
+    }
+
+    /// Switch protocol if asked, and describe the server.
+    fn hello(&mut self, protocol: Option<u32>) -> Reply {
+        match protocol {
+            Some(2) => self.protocol = Protocol::Resp2,
+            Some(3) => self.protocol = Protocol::Resp3,
+            Some(_) => return Reply::Error("NOPROTO unsupported protocol version".to_string()),
+            None => {}
+        }
+        let proto = if self.protocol == Protocol::Resp3 { 3 } else { 2 };
+        let field = |name: &str| Reply::Bulk(name.to_string());
+        Reply::Map(vec![
+            (field("server"), field("zyncdb")),
+            (field("version"), field(env!("CARGO_PKG_VERSION"))),
+            (field("proto"), Reply::Integer(proto)),
+            (field("id"), Reply::Integer(self.id as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), Reply::Array(Vec::new())),
+        ])
+    }
+}
+
+/// Who is on the other end of a connection.
+pub struct Peer {
+    pub id: u64,
+    /// The client's address, under which failed logins are throttled.
+    pub addr: String,
+    /// The user named by the client's verified TLS certificate.
+    pub certified: Option<String>,
+}
+
+/// Serve one connection until the client leaves or breaks the protocol.
+/// Each read may carry many pipelined requests; they run in order and their
+/// replies go back in a single write.
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(stream: S, shared: Arc<Shared>, peer: Peer) {
+    // A client certificate logs in its user. Otherwise, without a password,
+    // connections start as the default user, as in Redis.
+    let passwordless = shared.config.lock().unwrap().requirepass.is_empty();
+    let user = {
+        let acl = shared.acl.lock().unwrap();
+        match &peer.certified {
+            Some(name) => acl.user(name).filter(|user| user.enabled),
+            None => acl.user(DEFAULT_USER).filter(|user| passwordless && user.enabled && user.nopass),
+        }
+        .map(|user| user.name.clone())
+    };
+    if let Some(name) = peer.certified.as_ref().filter(|_| user.is_none()) {
+        log::warn!("Client {} has a certificate for '{}', which is not an enabled user", peer.id, name);
+    }
+    let mut session = Session {
+        id: peer.id,
+        client: peer.addr,
+        user,
+        stream,
+        protocol: Protocol::Text,
+        statements: Statements::new(),
+        output: Vec::new(),
+        closing: false,
+    };
+    let mut input = Vec::new();
+    let mut stopping = shared.shutdown.subscribe();
+
+    loop {
+        let mut pos = 0;
+        let open = loop {
+            // Anything that sends RESP arrays expects RESP replies.
+            if input.get(pos) == Some(&b'*') && session.protocol == Protocol::Text {
+                session.protocol = Protocol::Resp2;
+            }
+            let request = match resp::decode_request(&input[pos..]) {
+                Ok(Some((request, used))) => {
+                    pos += used;
+                    request
+                }
+                Ok(None) => break true,
+                Err(e) => {
+                    session.send(&Reply::error(e));
+                    break false;
+                }
+            };
+            if let Some(reply) = session.reply(request, &shared).await {
+                session.send(&reply);
+            }
+            if session.closing {
+                break false;
+            }
+            if session.output.len() >= MAX_PENDING_OUTPUT && session.flush().await.is_err() {
+                return;
+            }
+        };
+        input.drain(..pos);
+        if session.flush().await.is_err() || !open {
+            return;
+        }
+        if input.len() > MAX_QUERY_BUFFER {
+            log::warn!("Client {} exceeded the query buffer limit", session.id);
+            return;
+        }
+        if input.is_empty() && input.capacity() > IDLE_BUFFER_CAPACITY {
+            input = Vec::new();
+        }
+        if session.output.capacity() > IDLE_BUFFER_CAPACITY {
+            session.output = Vec::new();
+        }
+        input.reserve(READ_SIZE);
+        // Every request read so far is answered; once the server is stopping
+        // nothing more is read.
+        tokio::select! {
+            biased;
+            _ = stopping.wait_for(Option::is_some) => return,
+            read = session.stream.read_buf(&mut input) => match read {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            },
+        }
+    }
+}
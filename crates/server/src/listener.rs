@@ -0,0 +1,138 @@
+//! The sockets the server listens on: TCP unless `port` is 0, and a Unix
+//! socket when `unixsocket` is set. Clients of either are served alike.
+
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use zyncdb_core::config::Config;
+
+/// An accepted client's byte stream, whichever socket it came in on.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+pub struct Listeners {
+    tcp: Option<TcpListener>,
+    #[cfg(unix)]
+    unix: Option<(UnixListener, PathBuf)>,
+}
+
+impl Listeners {
+    pub async fn bind(config: &Config) -> io::Result<Listeners> {
+        let tcp = match config.port {
+            0 => None,
+            _ => Some(TcpListener::bind(config.addr()).await?),
+        };
+        #[cfg(unix)]
+        let unix = match &config.unixsocket {
+            Some(path) => Some((bind_unix(path, config.unixsocketperm)?, path.clone())),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if config.unixsocket.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "unixsocket needs a Unix system"));
+        }
+        Ok(Listeners {
+            tcp,
+            #[cfg(unix)]
+            unix,
+        })
+    }
+
+    /// Where clients can connect, for the log.
+    pub fn describe(&self) -> String {
+        let mut places = Vec::new();
+        if let Some(tcp) = &self.tcp
+            && let Ok(addr) = tcp.local_addr()
+        {
+            places.push(addr.to_string());
+        }
+        #[cfg(unix)]
+        if let Some((_, path)) = &self.unix {
+            places.push(path.display().to_string());
+        }
+        places.join(" and ")
+    }
+
+    /// Wait for the next client on any socket. Returns its stream and the
+    /// address failed logins are counted under: the IP for TCP and, for the
+    /// Unix socket, the user the client process runs as, so one local user
+    /// guessing passwords does not hold back the others.
+    pub async fn accept(&self) -> io::Result<(Box<dyn Stream>, String)> {
+        let tcp = async {
+            let Some(listener) = &self.tcp else {
+                return std::future::pending().await;
+            };
+            let (stream, peer) = listener.accept().await?;
+            let _ = stream.set_nodelay(true);
+            Ok((Box::new(stream) as Box<dyn Stream>, peer.ip().to_string()))
+        };
+        #[cfg(unix)]
+        let unix = async {
+            let Some((listener, _)) = &self.unix else {
+                return std::future::pending().await;
+            };
+            let (stream, _) = listener.accept().await?;
+            let addr = match stream.peer_cred() {
+                Ok(cred) => format!("unix:uid={}", cred.uid()),
+                Err(_) => "unix".to_string(),
+            };
+            Ok((Box::new(stream) as Box<dyn Stream>, addr))
+        };
+        #[cfg(not(unix))]
+        let unix = std::future::pending();
+        tokio::select! {
+            accepted = tcp => accepted,
+            accepted = unix => accepted,
+        }
+    }
+
+    /// Stop listening, removing the Unix socket file.
+    pub fn close(self) {
+        #[cfg(unix)]
+        if let Some((listener, path)) = self.unix {
+            drop(listener);
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("Could not remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Listen on the Unix socket `path` with permission bits `mode`, replacing a
+/// socket left behind by an earlier run. Any other file there is kept.
+///
+/// The socket is bound inside a new directory only its owner can enter and
+/// renamed into place once `mode` is set, so no other user can connect while
+/// it still has the umask's permissions.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, mode: u32) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let staging = path.with_file_name(format!(".zyncdb-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let bound = staging.join("s");
+    let listener = UnixListener::bind(&bound)
+        .and_then(|listener| {
+            std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&bound, path)?;
+            Ok(listener)
+        })
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)));
+    let _ = std::fs::remove_dir_all(&staging);
+    listener
+}
@@ -1,86 +1,339 @@
-use std::net::{TcpListener, TcpStream};
-use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::LevelFilter;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Notify, watch};
 use zyncdb_core::KvStore;
-use parser::{SimpleParser, Parser, Command};
+use zyncdb_core::acl::Acl;
+use zyncdb_core::auth::{Throttle, Tokens};
+use zyncdb_core::config::Config;
+use zyncdb_core::lock::DataLock;
+use zyncdb_core::wal::FsyncPolicy;
 
-fn handle_client(stream: TcpStream, store: Arc<Mutex<KvStore>>) {
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
-    let mut writer = stream;
-    let parser = SimpleParser;
+mod connection;
+mod listener;
+mod tls;
 
-    let _ = writer.write_all("Welcome to zyncdb 🦀\nType 'help' for commands.\n".as_bytes());
-    let _ = writer.flush();
-    println!("Client connected success");
+use connection::Peer;
+use listener::{Listeners, Stream};
+use tokio_rustls::TlsAcceptor;
 
-    loop {
-        println!("Client connected");
-        let mut input = String::new();
-        if reader.read_line(&mut input).is_err() {
-            break;
+/// How long clients get to finish their requests once the server stops.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a TLS client may take to finish its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the server was asked to stop. SIGINT and SIGTERM stop it as a bare
+/// SHUTDOWN does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    /// Snapshot if `save` rules are configured.
+    Default,
+    Save,
+    NoSave,
+}
+
+impl From<Option<bool>> for Shutdown {
+    fn from(save: Option<bool>) -> Self {
+        match save {
+            None => Shutdown::Default,
+            Some(true) => Shutdown::Save,
+            Some(false) => Shutdown::NoSave,
         }
-        let command = parser.parse(&input);
-        let mut store = store.lock().unwrap();
-        let response = match command {
-            Command::Put { key, value } => {
-                store.insert(key, value);
-                "ok\n".to_string()
-            }
-            Command::Get { key } => match store.get(&key) {
-                Some(value) => format!("{}\n", value),
-                None => "(key not found)\n".to_string(),
-            },
-            Command::Delete { key } => {
-                if store.delete(&key) {
-                    "deleted\n".to_string()
-                } else {
-                    "(key not found)\n".to_string()
-                }
-            }
-            Command::Help => {
-                "Available commands:\n\
-                put <key> <value>\n\
-                get <key>\n\
-                delete <key>\n\
-                insert <key> <value>\n\
-                select <key>\n\
-                remove <key>\n\
-                ttl <key> <seconds>\n\
-                batch ...\n\
-                snapshot\n\
-                list\n\
-                help\n\
-                exit\n".to_string()
-            }
-            Command::Exit => break,
-            _ => "Unknown command\n".to_string(),
-        };
-        let _ = writer.write_all(response.as_bytes());
     }
 }
 
-fn main() -> std::io::Result<()> {
-    env_logger::init();
-    let wal_path = PathBuf::from(".zyncdb.wal");
-    let store = Arc::new(Mutex::new(KvStore::open(&wal_path)?));
-    let listener = TcpListener::bind("127.0.0.1:6379")?;
-    log::info!("Server listening on 127.0.0.1:6379");
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let store = Arc::clone(&store);
-                thread::spawn(move || {
-                    log::debug!("Client connected: {:?}", stream.peer_addr());
-                    handle_client(stream, store);
-                });
-            }
-            Err(e) => {
-                log::error!("Connection failed: {}", e);
+/// State shared by all connections. `changed` is signalled after every
+/// command so that clients blocked in BLPOP/BRPOP/BLMOVE/XREAD can retry.
+pub struct Shared {
+    store: Mutex<KvStore>,
+    changed: Notify,
+    config: Mutex<Config>,
+    /// Connections being served, checked against `maxclients`.
+    clients: AtomicUsize,
+    /// Signalled whenever a connection closes.
+    disconnected: Notify,
+    /// Set once the server is stopping; see `Shutdown`.
+    shutdown: watch::Sender<Option<Shutdown>>,
+    /// Data size last measured by `cron`, checked against `maxmemory`.
+    used_memory: AtomicUsize,
+    /// API tokens accepted by AUTH besides `requirepass`.
+    tokens: Mutex<Tokens>,
+    /// Users and their permissions, checked before every command.
+    acl: Mutex<Acl>,
+    /// Failed AUTH attempts by client address.
+    throttle: Mutex<Throttle>,
+}
+
+impl Shared {
+    /// Whether writes that grow the data must be refused.
+    fn over_maxmemory(&self) -> bool {
+        let maxmemory = self.config.lock().unwrap().maxmemory;
+        maxmemory > 0 && self.used_memory.load(Ordering::Relaxed) as u64 > maxmemory
+    }
+
+    /// Start stopping the server. Only the first request counts.
+    fn stop(&self, how: Shutdown) {
+        self.shutdown.send_if_modified(|current| {
+            let first = current.is_none();
+            if first {
+                *current = Some(how);
             }
+            first
+        });
+    }
+
+    /// Put changed settings into effect.
+    fn apply(&self, config: &Config) {
+        config.apply_to(&mut self.store.lock().unwrap());
+        log::set_max_level(log_level(config));
+    }
+}
+
+fn log_level(config: &Config) -> LevelFilter {
+    config.loglevel.parse().unwrap_or(LevelFilter::Info)
+}
+
+/// Once a second: sync the WAL under `appendfsync everysec`, take the
+/// snapshots `save` asks for and measure the data for `maxmemory`. The work
+/// waits on the disk, so it runs on a blocking thread rather than holding up
+/// the connections sharing this runtime thread.
+async fn cron(shared: Arc<Shared>) {
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    let mut last_save = Instant::now();
+    loop {
+        ticks.tick().await;
+        let shared = Arc::clone(&shared);
+        match tokio::task::spawn_blocking(move || tick(&shared, last_save)).await {
+            Ok(saved) => last_save = saved,
+            Err(e) => log::error!("Background task failed: {}", e),
+        }
+    }
+}
+
+/// One round of `cron`, given when the last snapshot was taken. Returns that
+/// time, or now if this round took a snapshot.
+fn tick(shared: &Shared, mut last_save: Instant) -> Instant {
+    let config = shared.config.lock().unwrap().clone();
+    let mut store = shared.store.lock().unwrap();
+    if config.appendfsync == FsyncPolicy::EverySec
+        && let Err(e) = store.sync_wal()
+    {
+        log::error!("WAL sync failed: {}", e);
+    }
+    let elapsed = last_save.elapsed().as_secs();
+    let changes = store.changes();
+    if changes > 0
+        && config
+            .save
+            .iter()
+            .any(|rule| elapsed >= rule.seconds && changes >= rule.changes)
+    {
+        // A failed snapshot is retried once a rule matches again.
+        last_save = Instant::now();
+        match store.snapshot_and_compact(&config.snapshot_path(), &config.wal_path()) {
+            Ok(()) => log::info!("Saved a snapshot after {} changes in {}s", changes, elapsed),
+            Err(e) => log::error!("Snapshot failed: {}", e),
+        }
+    }
+    let used = if config.maxmemory > 0 { store.used_memory() } else { 0 };
+    shared.used_memory.store(used, Ordering::Relaxed);
+    last_save
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM (`docker stop`, systemd).
+async fn signalled() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Wait for the open connections to finish, for at most `SHUTDOWN_TIMEOUT`.
+async fn drain(shared: &Shared) {
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+    loop {
+        let disconnected = shared.disconnected.notified();
+        tokio::pin!(disconnected);
+        disconnected.as_mut().enable();
+        let open = shared.clients.load(Ordering::Relaxed);
+        if open == 0 {
+            return;
+        }
+        if tokio::time::timeout_at(deadline, disconnected).await.is_err() {
+            log::warn!("Closing {} clients that did not finish in time", open);
+            return;
+        }
+    }
+}
+
+/// Put every acknowledged write on disk, snapshot if asked to, release the
+/// data directory and exit. The store stays locked to the end, so no
+/// connection still running can write after the final sync.
+fn finish(shared: &Shared, how: Shutdown, lock: DataLock) -> ! {
+    let config = shared.config.lock().unwrap().clone();
+    let mut store = shared.store.lock().unwrap();
+    let mut status = 0;
+    if let Err(e) = store.sync_wal() {
+        log::error!("WAL sync failed: {}", e);
+        status = 1;
+    }
+    let save = match how {
+        Shutdown::Default => !config.save.is_empty(),
+        Shutdown::Save => true,
+        Shutdown::NoSave => false,
+    };
+    if save {
+        match store.snapshot_and_compact(&config.snapshot_path(), &config.wal_path()) {
+            Ok(()) => log::info!("Saved the final snapshot"),
+            // The synced WAL still holds everything.
+            Err(e) => log::error!("Final snapshot failed: {}", e),
         }
     }
-    Ok(())
-}
\ No newline at end of file
+    if let Err(e) = lock.release() {
+        log::error!("Could not release the data directory lock: {}", e);
+    }
+    log::info!("Server stopped");
+    std::process::exit(status);
+}
+
+/// Tell a client the server is full. A TLS client can only read that once
+/// its handshake is done.
+async fn refuse(mut stream: Box<dyn Stream>, tls: Option<TlsAcceptor>) -> std::io::Result<()> {
+    const REFUSAL: &[u8] = b"-ERR max number of clients reached\r\n";
+    match tls {
+        None => stream.write_all(REFUSAL).await,
+        Some(tls) => {
+            let mut stream = tls.accept(stream).await?;
+            stream.write_all(REFUSAL).await?;
+            stream.shutdown().await
+        }
+    }
+}
+
+/// Serve a client on its own task, after the TLS handshake when TLS is on,
+/// unless `maxclients` are already connected.
+fn admit(shared: &Arc<Shared>, stream: Box<dyn Stream>, addr: String, tls: &Option<TlsAcceptor>, next_id: &mut u64) {
+    let maxclients = shared.config.lock().unwrap().maxclients;
+    if shared.clients.load(Ordering::Relaxed) >= maxclients {
+        log::warn!("Refused {}: max number of clients reached", addr);
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let _ = tokio::time::timeout(HANDSHAKE_TIMEOUT, refuse(stream, tls)).await;
+        });
+        return;
+    }
+    shared.clients.fetch_add(1, Ordering::Relaxed);
+    let shared = Arc::clone(shared);
+    let id = *next_id;
+    *next_id += 1;
+    let tls = tls.clone();
+    tokio::spawn(async move {
+        log::debug!("Client {} connected from {}", id, addr);
+        match tls {
+            None => connection::serve(stream, Arc::clone(&shared), Peer { id, addr, certified: None }).await,
+            Some(tls) => match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let certified = tls::client_name(stream.get_ref().1);
+                    connection::serve(stream, Arc::clone(&shared), Peer { id, addr, certified }).await
+                }
+                Ok(Err(e)) => log::warn!("Client {} failed the TLS handshake: {}", id, e),
+                Err(_) => log::warn!("Client {} did not finish the TLS handshake in time", id),
+            },
+        }
+        log::debug!("Client {} disconnected", id);
+        shared.clients.fetch_sub(1, Ordering::Relaxed);
+        shared.disconnected.notify_waiters();
+    });
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let config = match Config::load(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("server: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Filter by the global maximum only, so CONFIG SET loglevel can raise it.
+    env_logger::Builder::new().filter_level(LevelFilter::Trace).init();
+    log::set_max_level(log_level(&config));
+    if config.port == 0 && config.unixsocket.is_none() {
+        eprintln!("server: port 0 turns TCP off, so unixsocket must be set");
+        std::process::exit(1);
+    }
+
+    let lock = match DataLock::acquire(&config.dir) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("server: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let tokens = Tokens::load(&config.tokens_path())?;
+    let acl = match Acl::load(&config.acl_path()) {
+        Ok(acl) => acl,
+        Err(e) => {
+            eprintln!("server: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let tls = match tls::acceptor(&config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("server: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let listeners = Listeners::bind(&config).await?;
+    let shared = Arc::new(Shared {
+        store: Mutex::new(config.open_store()?),
+        changed: Notify::new(),
+        config: Mutex::new(config),
+        clients: AtomicUsize::new(0),
+        disconnected: Notify::new(),
+        shutdown: watch::Sender::new(None),
+        used_memory: AtomicUsize::new(0),
+        tokens: Mutex::new(tokens),
+        acl: Mutex::new(acl),
+        throttle: Mutex::new(Throttle::default()),
+    });
+    log::info!("Server listening on {}{}", listeners.describe(), if tls.is_some() { " (TLS)" } else { "" });
+    tokio::spawn(cron(Arc::clone(&shared)));
+    let signals = Arc::clone(&shared);
+    tokio::spawn(async move {
+        signalled().await;
+        log::info!("Received a shutdown signal");
+        signals.stop(Shutdown::Default);
+    });
+
+    let mut stopping = shared.shutdown.subscribe();
+    let mut next_id = 1;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listeners.accept() => accepted,
+            _ = stopping.wait_for(Option::is_some) => break,
+        };
+        match accepted {
+            Ok((stream, addr)) => admit(&shared, stream, addr, &tls, &mut next_id),
+            Err(e) => log::error!("Connection failed: {}", e),
+        }
+    }
+
+    // New connections are refused from here on.
+    listeners.close();
+    let how = shared.shutdown.borrow().unwrap_or(Shutdown::Default);
+    log::info!("Shutting down: waiting for {} clients", shared.clients.load(Ordering::Relaxed));
+    drain(&shared).await;
+    finish(&shared, how, lock)
+}
@@ -0,0 +1,75 @@
+//! TLS on the listener.
+//!
+//! With `tls-cert-file` and `tls-key-file` set, every connection starts with
+//! a TLS handshake and nothing is served in plain text. `tls-auth-clients`
+//! asks clients for a certificate signed by `tls-ca-cert-file`; a verified
+//! certificate logs the client in as the ACL user its common name names.
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use tokio_rustls::TlsAcceptor;
+use zyncdb_core::config::{Config, TlsAuthClients};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("{}: no certificates found", path.display())));
+    }
+    Ok(certs)
+}
+
+/// The handshake for the configured certificates, or `None` when TLS is off.
+pub fn acceptor(config: &Config) -> io::Result<Option<TlsAcceptor>> {
+    let (cert, key) = match (&config.tlscertfile, &config.tlskeyfile) {
+        (None, None) => return Ok(None),
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Err(invalid("tls-cert-file and tls-key-file must be set together".to_string())),
+    };
+    let certs = certificates(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(format!("{}: {}", key.display(), e)))?;
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid(e.to_string()))?;
+    let builder = match (config.tlsauthclients, &config.tlscacertfile) {
+        (TlsAuthClients::No, _) => builder.with_no_client_auth(),
+        (_, None) => return Err(invalid("tls-auth-clients needs tls-ca-cert-file".to_string())),
+        (auth, Some(ca)) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(ca)? {
+                roots.add(cert).map_err(|e| invalid(format!("{}: {}", ca.display(), e)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match auth {
+                TlsAuthClients::Optional => verifier.allow_unauthenticated(),
+                _ => verifier,
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| invalid(e.to_string()))?)
+        }
+    };
+    let server = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(format!("{}: {}", cert.display(), e)))?;
+    Ok(Some(TlsAcceptor::from(Arc::new(server))))
+}
+
+/// The common name of the client's certificate, which rustls has verified.
+pub fn client_name(connection: &ServerConnection) -> Option<String> {
+    let cert = connection.peer_certificates()?.first()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(name.to_string())
+}
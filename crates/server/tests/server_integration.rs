@@ -1,18 +1,747 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zyncdb_core::{Reply, resp};
+
+// Every server binds the same port, so tests take turns.
+static SERVER_LOCK: Mutex<()> = Mutex::new(());
+
+struct TestServer {
+    child: Child,
+    dir: PathBuf,
+    /// Whether the server reads `zyncdb.conf`.
+    configured: bool,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestServer {
+    fn start() -> TestServer {
+        TestServer::start_with_config(None)
+    }
+
+    /// Start with `config` written to `zyncdb.conf` in the server's directory.
+    fn start_with_config(config: Option<&str>) -> TestServer {
+        let guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("zyncdb_server_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        if let Some(config) = config {
+            std::fs::write(dir.join("zyncdb.conf"), config).unwrap();
+        }
+        let configured = config.is_some();
+        TestServer {
+            child: TestServer::spawn(&dir, configured),
+            dir,
+            configured,
+            _guard: guard,
+        }
+    }
+
+    fn spawn(dir: &PathBuf, configured: bool) -> Child {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_server"));
+        if configured {
+            command.arg("zyncdb.conf");
+        }
+        command
+            .current_dir(dir)
+            .env("ZYNCDB_LOGLEVEL", "warn")
+            .spawn()
+            .expect("Failed to start server")
+    }
+
+    /// Start again on the same directory after the server has exited.
+    fn restart(&mut self) {
+        self.child = TestServer::spawn(&self.dir, self.configured);
+    }
+
+    /// Send the server a signal and wait for it to exit.
+    fn signal(&mut self, signal: &str) -> ExitStatus {
+        let status = Command::new("kill")
+            .args(["-s", signal, &self.child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        self.child.wait().unwrap()
+    }
+
+    fn connect(&self) -> (TcpStream, BufReader<TcpStream>) {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect("127.0.0.1:6379") {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                return (stream, reader);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Connect failed");
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn send(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, cmd: &str) -> String {
+    writeln!(stream, "{}", cmd).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    line
+}
+
 #[test]
 fn test_server_put_and_get() {
-    use std::net::TcpStream;
-    use std::io::{Write, BufRead, BufReader};
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
 
-    let mut stream = TcpStream::connect("127.0.0.1:6379").expect("Connect failed");
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    assert!(send(&mut stream, &mut reader, "put foo bar").contains("ok"));
+    assert!(send(&mut stream, &mut reader, "get foo").contains("bar"));
+    // The server applies the same key and value limits as the CLI.
+    assert_eq!(send(&mut stream, &mut reader, "put a|b x"), "Error: key contains disallowed character '|'\n");
+    assert_eq!(send(&mut stream, &mut reader, "put k \"\""), "Error: value must not be empty\n");
+}
 
-    writeln!(stream, "put foo bar").unwrap();
+#[test]
+fn test_server_counters() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    assert_eq!(send(&mut stream, &mut reader, "incr hits"), "1\n");
+    assert_eq!(send(&mut stream, &mut reader, "incrby hits 10"), "11\n");
+    assert_eq!(send(&mut stream, &mut reader, "decr hits"), "10\n");
+    send(&mut stream, &mut reader, "put name alice");
+    assert!(send(&mut stream, &mut reader, "incr name").starts_with("Error:"));
+}
+
+#[test]
+fn test_server_string_commands() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    assert_eq!(send(&mut stream, &mut reader, "set greeting hello NX"), "ok\n");
+    assert_eq!(send(&mut stream, &mut reader, "set greeting other NX"), "(key not found)\n");
+    assert_eq!(send(&mut stream, &mut reader, "append greeting !"), "6\n");
+    assert_eq!(send(&mut stream, &mut reader, "strlen greeting"), "6\n");
+    assert_eq!(send(&mut stream, &mut reader, "getdel greeting"), "hello!\n");
+}
+
+#[test]
+fn test_server_command_introspection() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    let count = parser::COMMANDS.len();
+    assert_eq!(send(&mut stream, &mut reader, "COMMAND COUNT"), format!("{}\n", count));
+    assert!(send(&mut stream, &mut reader, "Get a b").starts_with("Error: wrong number of arguments"));
+    // Multi-line reply: the first line is the canonical name of the alias.
+    assert_eq!(send(&mut stream, &mut reader, "command info Keys"), "list\n");
+}
+
+#[test]
+fn test_server_batch() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    assert_eq!(send(&mut stream, &mut reader, "batch put a 1; incr a"), "ok\n");
     let mut line = String::new();
     reader.read_line(&mut line).unwrap();
-    assert!(line.contains("ok"));
+    assert_eq!(line, "2\n");
+    assert!(send(&mut stream, &mut reader, "batch atomic incr a; lpush a x").starts_with("Error: EXECABORT"));
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.starts_with("Error: WRONGTYPE"));
+    assert_eq!(send(&mut stream, &mut reader, "get a"), "2\n");
+}
+
+#[test]
+fn test_server_prepared_statements_are_per_connection() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
 
-    writeln!(stream, "get foo").unwrap();
-    line.clear();
+    assert_eq!(send(&mut stream, &mut reader, "prepare store put ? ?"), "ok\n");
+    // The parameter is one value, so the `;` and `del` are stored, not run.
+    assert_eq!(send(&mut stream, &mut reader, r#"execute store k "v; delete k""#), "ok\n");
+    assert_eq!(send(&mut stream, &mut reader, "get k"), "v; delete k\n");
+    assert_eq!(send(&mut stream, &mut reader, r#"prepare n "incrby $1 $2""#), "ok\n");
+    assert_eq!(send(&mut stream, &mut reader, "execute n c 5"), "5\n");
+    assert_eq!(
+        send(&mut stream, &mut reader, "execute n c"),
+        "Error: expected 2 parameters, got 1 at column 1\n"
+    );
+
+    let (mut other, mut other_reader) = server.connect();
+    assert_eq!(
+        send(&mut other, &mut other_reader, "execute n c 5"),
+        "Error: no prepared statement named 'n' at column 1\n"
+    );
+    assert_eq!(send(&mut stream, &mut reader, "deallocate n"), "ok\n");
+    assert!(send(&mut stream, &mut reader, "execute n c 5").starts_with("Error: no prepared"));
+}
+
+#[test]
+fn test_server_blocking_pop() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    assert_eq!(send(&mut stream, &mut reader, "blpop jobs 0.1"), "(key not found)\n");
+
+    let (mut waiter, mut waiter_reader) = server.connect();
+    writeln!(waiter, "blpop jobs 5").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(send(&mut stream, &mut reader, "rpush jobs task1"), "1\n");
+
+    let mut line = String::new();
+    waiter_reader.read_line(&mut line).unwrap();
+    waiter_reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "jobs\ntask1\n");
+    assert_eq!(send(&mut stream, &mut reader, "llen jobs"), "0\n");
+}
+
+#[test]
+fn test_server_blocking_xread() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+    let (mut waiter, mut waiter_reader) = server.connect();
+
+    assert_eq!(send(&mut stream, &mut reader, "xadd events 1-1 type old"), "1-1\n");
+    writeln!(waiter, "xread BLOCK 5000 STREAMS events $").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(send(&mut stream, &mut reader, "xadd events 2-1 type new"), "2-1\n");
+
+    let mut lines = String::new();
+    for _ in 0..4 {
+        waiter_reader.read_line(&mut lines).unwrap();
+    }
+    assert_eq!(lines, "events\n2-1\ntype\nnew\n");
+}
+
+/// Send one RESP request and read one RESP reply.
+fn send_resp(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, args: &[&str]) -> Reply {
+    stream.write_all(&request(args)).unwrap();
+    reply(reader)
+}
+
+fn request(args: &[&str]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len());
+    for arg in args {
+        request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    request.into_bytes()
+}
+
+/// Read one RESP reply, a byte at a time so nothing past it is consumed.
+fn reply(reader: &mut impl Read) -> Reply {
+    let mut input = Vec::new();
+    loop {
+        if let Some((reply, _)) = resp::decode_reply(&input).unwrap() {
+            return reply;
+        }
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        input.push(byte[0]);
+    }
+}
+
+#[test]
+fn test_server_speaks_resp2_and_resp3() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+
+    // Arguments arrive whole: no quoting, and `;` is only a value.
+    assert_eq!(send_resp(&mut stream, &mut reader, &["SET", "greeting", "hello world; del x"]), Reply::Ok);
+    assert_eq!(send_resp(&mut stream, &mut reader, &["GET", "greeting"]), bulk("hello world; del x"));
+    assert_eq!(send_resp(&mut stream, &mut reader, &["GET", "missing"]), Reply::Nil);
+    assert_eq!(send_resp(&mut stream, &mut reader, &["PING"]), Reply::Status("PONG".to_string()));
+    assert_eq!(send_resp(&mut stream, &mut reader, &["INCRBY", "n", "5"]), Reply::Integer(5));
+    assert_eq!(
+        send_resp(&mut stream, &mut reader, &["LPUSH", "n", "x"]),
+        Reply::Error(zyncdb_core::KvError::WrongType.to_string())
+    );
+    assert_eq!(send_resp(&mut stream, &mut reader, &["HSET", "h", "f", "v"]), Reply::Integer(1));
+    // RESP2 flattens maps; the multi-line help is one bulk string.
+    assert_eq!(send_resp(&mut stream, &mut reader, &["HGETALL", "h"]), Reply::Array(vec![bulk("f"), bulk("v")]));
+    assert!(matches!(send_resp(&mut stream, &mut reader, &["HELP"]), Reply::Bulk(help) if help.lines().count() == parser::COMMANDS.len() + 1));
+
+    assert!(send_resp(&mut stream, &mut reader, &["HELLO", "4"]).is_error());
+    match send_resp(&mut stream, &mut reader, &["HELLO", "3"]) {
+        Reply::Map(fields) => assert!(fields.contains(&(bulk("proto"), Reply::Integer(3)))),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(send_resp(&mut stream, &mut reader, &["HGETALL", "h"]), Reply::Map(vec![(bulk("f"), bulk("v"))]));
+    assert_eq!(send_resp(&mut stream, &mut reader, &["GET", "missing"]), Reply::Nil);
+
+    // Inline commands still work on the same connection, with RESP replies.
+    writeln!(stream, "get greeting").unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "$18\r\n");
+}
+
+#[test]
+fn test_server_closes_on_protocol_errors() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    stream.write_all(b"*1\r\n+PING\r\n").unwrap();
+    let mut line = String::new();
     reader.read_line(&mut line).unwrap();
-    assert!(line.contains("bar"));
-}
\ No newline at end of file
+    assert_eq!(line, "-ERR Protocol error: expected '$'\r\n");
+    assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+}
+
+/// Read one RESP reply from whatever the server has sent so far.
+fn read_resp(reader: &mut BufReader<TcpStream>, input: &mut Vec<u8>) -> Reply {
+    loop {
+        if let Some((reply, used)) = resp::decode_reply(input).unwrap() {
+            input.drain(..used);
+            return reply;
+        }
+        let buf = reader.fill_buf().unwrap();
+        assert!(!buf.is_empty(), "connection closed");
+        input.extend_from_slice(buf);
+        let n = buf.len();
+        reader.consume(n);
+    }
+}
+
+#[test]
+fn test_server_pipelines_requests_and_frames_every_reply() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+    let mut input = Vec::new();
+
+    // One write carries every request, the multi-line help included.
+    stream
+        .write_all(b"HELLO 3\r\nput a 1\r\nincr a\r\n*1\r\n$4\r\nhelp\r\n\r\n# comment\r\nget a\r\nrpush l x y\r\nlrange l 0 -1\r\n")
+        .unwrap();
+    assert!(matches!(read_resp(&mut reader, &mut input), Reply::Map(_)));
+    assert_eq!(read_resp(&mut reader, &mut input), Reply::Ok);
+    assert_eq!(read_resp(&mut reader, &mut input), Reply::Integer(2));
+    assert!(matches!(read_resp(&mut reader, &mut input), Reply::Bulk(help) if help.starts_with("Available commands:")));
+    // Blank lines and comments get no reply.
+    assert_eq!(read_resp(&mut reader, &mut input), Reply::Bulk("2".to_string()));
+    assert_eq!(read_resp(&mut reader, &mut input), Reply::Integer(2));
+    assert_eq!(
+        read_resp(&mut reader, &mut input),
+        Reply::Array(vec![Reply::Bulk("x".to_string()), Reply::Bulk("y".to_string())])
+    );
+
+    // Replies before a blocking command are sent before it waits.
+    stream.write_all(b"ping\r\nblpop jobs 5\r\n").unwrap();
+    assert_eq!(read_resp(&mut reader, &mut input), Reply::Status("PONG".to_string()));
+    let (mut other, mut other_reader) = server.connect();
+    assert_eq!(send(&mut other, &mut other_reader, "rpush jobs task"), "1\n");
+    assert_eq!(
+        read_resp(&mut reader, &mut input),
+        Reply::Array(vec![Reply::Bulk("jobs".to_string()), Reply::Bulk("task".to_string())])
+    );
+
+    // `quit` is answered, then the connection closes.
+    stream.write_all(b"quit\r\nget a\r\n").unwrap();
+    assert_eq!(read_resp(&mut reader, &mut input), Reply::Ok);
+    assert!(input.is_empty());
+    assert_eq!(reader.fill_buf().unwrap().len(), 0);
+}
+
+#[test]
+fn test_server_holds_many_idle_connections() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+
+    let mut idle: Vec<_> = (0..500).map(|_| server.connect()).collect();
+    assert_eq!(send(&mut stream, &mut reader, "put k v"), "ok\n");
+    for (idle_stream, idle_reader) in idle.iter_mut().step_by(50) {
+        assert_eq!(send(idle_stream, idle_reader, "get k"), "v\n");
+    }
+}
+
+#[test]
+fn test_server_keeps_serving_while_a_client_stops_reading() {
+    let server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+    assert_eq!(send(&mut stream, &mut reader, &format!("put big {}", "x".repeat(60_000))), "ok\n");
+
+    // Far more replies than the socket buffers hold, never read.
+    let (mut stalled, _stalled_reader) = server.connect();
+    stalled.write_all("get big\n".repeat(4_000).as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let (mut other, mut other_reader) = server.connect();
+    assert_eq!(send(&mut other, &mut other_reader, "incr n"), "1\n");
+    assert_eq!(send(&mut stream, &mut reader, "incr n"), "2\n");
+}
+
+#[test]
+fn test_server_reads_changes_and_rewrites_its_config() {
+    let server = TestServer::start_with_config(Some("# Limits\nmaxclients 2\ndir data\n"));
+    let (mut stream, mut reader) = server.connect();
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+    let config_get = |stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, name: &str| {
+        send_resp(stream, reader, &["CONFIG", "GET", name])
+    };
+
+    assert_eq!(config_get(&mut stream, &mut reader, "maxclients"), Reply::Array(vec![bulk("maxclients"), bulk("2")]));
+    assert_eq!(send_resp(&mut stream, &mut reader, &["SET", "k", "v"]), Reply::Ok);
+    assert!(server.dir.join("data/.zyncdb.wal").exists());
+
+    // The second client fills the server; a third is turned away until the limit is raised.
+    let (_second, _) = server.connect();
+    let (_third, mut third_reader) = server.connect();
+    let mut line = String::new();
+    third_reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "-ERR max number of clients reached\r\n");
+    assert_eq!(send_resp(&mut stream, &mut reader, &["CONFIG", "SET", "maxclients", "3"]), Reply::Ok);
+    let (mut fourth, mut fourth_reader) = server.connect();
+    assert_eq!(send(&mut fourth, &mut fourth_reader, "get k"), "v\n");
+
+    assert_eq!(
+        send_resp(&mut stream, &mut reader, &["CONFIG", "SET", "port", "7000"]),
+        Reply::Error("'port' can only be set at startup".to_string())
+    );
+
+    // Over maxmemory, writes that grow the data are refused but deletes are not.
+    assert_eq!(send_resp(&mut stream, &mut reader, &["CONFIG", "SET", "maxmemory", "1"]), Reply::Ok);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(
+        send_resp(&mut stream, &mut reader, &["SET", "k2", "v"]),
+        Reply::Error("OOM command not allowed when used memory > 'maxmemory'".to_string())
+    );
+    assert_eq!(send_resp(&mut stream, &mut reader, &["GET", "k"]), bulk("v"));
+    assert!(!send_resp(&mut stream, &mut reader, &["DELETE", "k"]).is_error());
+    assert_eq!(send_resp(&mut stream, &mut reader, &["CONFIG", "SET", "maxmemory", "0"]), Reply::Ok);
+    assert_eq!(send_resp(&mut stream, &mut reader, &["SET", "k2", "v"]), Reply::Ok);
+
+    // The running settings are saved, including the test's environment override.
+    assert_eq!(send_resp(&mut stream, &mut reader, &["CONFIG", "REWRITE"]), Reply::Ok);
+    assert_eq!(
+        std::fs::read_to_string(server.dir.join("zyncdb.conf")).unwrap(),
+        "# Limits\nmaxclients 3\ndir data\nloglevel warn\n"
+    );
+}
+
+#[test]
+fn test_server_stops_cleanly_on_sigterm() {
+    let mut server = TestServer::start_with_config(Some("appendfsync no\nsave \"\"\n"));
+    let (mut stream, mut reader) = server.connect();
+    assert_eq!(send(&mut stream, &mut reader, "put a 1"), "ok\n");
+    assert!(server.dir.join(".zyncdb.lock").exists());
+
+    // A client blocked without a timeout is let go rather than holding up the shutdown.
+    let (mut blocked, mut blocked_reader) = server.connect();
+    writeln!(blocked, "blpop queue 0").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let started = Instant::now();
+    assert!(server.signal("TERM").success());
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let mut line = String::new();
+    blocked_reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "(key not found)\n");
+    assert_eq!(blocked_reader.read_line(&mut line).unwrap(), 0);
+    // The lock is let go; the emptied file stays for the next run to lock.
+    assert_eq!(std::fs::read_to_string(server.dir.join(".zyncdb.lock")).unwrap(), "");
+
+    // The write was acknowledged, so it survives even without periodic fsyncs.
+    server.restart();
+    let (mut stream, mut reader) = server.connect();
+    assert_eq!(send(&mut stream, &mut reader, "get a"), "1\n");
+}
+
+#[test]
+fn test_server_shutdown_command_saves_and_stops() {
+    let mut server = TestServer::start();
+    let (mut stream, mut reader) = server.connect();
+    assert_eq!(send(&mut stream, &mut reader, "put k v"), "ok\n");
+
+    assert_eq!(send(&mut stream, &mut reader, "shutdown save"), "ok\n");
+    let mut line = String::new();
+    assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    assert!(server.child.wait().unwrap().success());
+    assert!(TcpStream::connect("127.0.0.1:6379").is_err());
+    assert!(server.dir.join(".zyncdb.snapshot").exists());
+    assert_eq!(std::fs::metadata(server.dir.join(".zyncdb.wal")).unwrap().len(), 0);
+
+    server.restart();
+    let (mut stream, mut reader) = server.connect();
+    assert_eq!(send(&mut stream, &mut reader, "get k"), "v\n");
+    assert!(send(&mut stream, &mut reader, "shutdown later").starts_with("Error:"));
+}
+
+#[test]
+fn test_server_refuses_a_data_directory_in_use() {
+    let server = TestServer::start();
+    server.connect();
+    let output = Command::new(env!("CARGO_BIN_EXE_server"))
+        .current_dir(&server.dir)
+        .args(["--port", "6380"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("is in use by another process (pid {})", server.child.id())), "{}", stderr);
+}
+
+#[test]
+fn test_server_requires_auth_and_throttles_failures() {
+    let server = TestServer::start_with_config(Some("requirepass s3cret\n"));
+    let (mut stream, mut reader) = server.connect();
+    let noauth = Reply::Error("NOAUTH Authentication required.".to_string());
+    let wrongpass = Reply::Error("WRONGPASS invalid username-password pair or user is disabled.".to_string());
+
+    // Nothing runs, and unknown commands are not told apart, before AUTH.
+    assert_eq!(send_resp(&mut stream, &mut reader, &["GET", "k"]), noauth);
+    assert_eq!(send_resp(&mut stream, &mut reader, &["NOSUCH"]), noauth);
+    for _ in 0..3 {
+        assert_eq!(send_resp(&mut stream, &mut reader, &["AUTH", "guess"]), wrongpass);
+    }
+    let throttled = send_resp(&mut stream, &mut reader, &["AUTH", "s3cret"]);
+    assert!(matches!(&throttled, Reply::Error(e) if e.contains("too many failed")), "{:?}", throttled);
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(send_resp(&mut stream, &mut reader, &["AUTH", "default", "s3cret"]), Reply::Ok);
+    assert_eq!(send_resp(&mut stream, &mut reader, &["SET", "k", "v"]), Reply::Ok);
+
+    let Reply::Bulk(token) = send_resp(&mut stream, &mut reader, &["TOKEN", "CREATE", "ci"]) else {
+        panic!("TOKEN CREATE gave no token");
+    };
+    let Reply::Bulk(brief) = send_resp(&mut stream, &mut reader, &["TOKEN", "CREATE", "brief", "TTL", "1"]) else {
+        panic!("TOKEN CREATE gave no token");
+    };
+    assert!(send_resp(&mut stream, &mut reader, &["TOKEN", "CREATE", "ci"]).is_error());
+    let Reply::Array(listed) = send_resp(&mut stream, &mut reader, &["TOKEN", "LIST"]) else {
+        panic!("TOKEN LIST gave no list");
+    };
+    assert_eq!(listed[..2], [Reply::Bulk("ci".to_string()), Reply::Integer(-1)]);
+    // Only a hash of the secret is kept.
+    let stored = std::fs::read_to_string(server.dir.join(".zyncdb.tokens")).unwrap();
+    assert!(stored.contains("ci") && !stored.contains(token.rsplit('_').next().unwrap()));
+
+    let (mut other, mut other_reader) = server.connect();
+    let hello = send_resp(&mut other, &mut other_reader, &["HELLO", "3", "AUTH", "default", &token]);
+    assert!(!hello.is_error(), "{:?}", hello);
+    assert_eq!(send_resp(&mut other, &mut other_reader, &["GET", "k"]), Reply::Bulk("v".to_string()));
+
+    assert_eq!(send_resp(&mut stream, &mut reader, &["TOKEN", "REVOKE", "ci"]), Reply::Integer(1));
+    thread::sleep(Duration::from_millis(1100));
+    let (mut late, mut late_reader) = server.connect();
+    assert_eq!(send_resp(&mut late, &mut late_reader, &["AUTH", &token]), wrongpass);
+    assert_eq!(send_resp(&mut late, &mut late_reader, &["AUTH", &brief]), wrongpass);
+    assert_eq!(send_resp(&mut late, &mut late_reader, &["GET", "k"]), noauth);
+}
+
+#[test]
+fn test_server_enforces_acl_users_and_keeps_them() {
+    let mut server = TestServer::start_with_config(Some("requirepass s3cret\n"));
+    let (mut admin, mut admin_reader) = server.connect();
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+    assert_eq!(send_resp(&mut admin, &mut admin_reader, &["AUTH", "s3cret"]), Reply::Ok);
+    assert_eq!(send_resp(&mut admin, &mut admin_reader, &["ACL", "WHOAMI"]), bulk("default"));
+    assert_eq!(
+        send_resp(&mut admin, &mut admin_reader, &["ACL", "SETUSER", "app", "on", ">apppw", "+@read", "+@write", "~orders:*", "ns:orders"]),
+        Reply::Ok
+    );
+    assert_eq!(send_resp(&mut admin, &mut admin_reader, &["SET", "secret", "x"]), Reply::Ok);
+    let Reply::Array(listed) = send_resp(&mut admin, &mut admin_reader, &["ACL", "LIST"]) else {
+        panic!("ACL LIST gave no list");
+    };
+    assert!(matches!(&listed[0], Reply::Bulk(line) if line.starts_with("user app on #$argon2") && line.ends_with("+@read +@write ~orders:* ns:orders")));
+
+    let (mut app, mut app_reader) = server.connect();
+    assert!(send_resp(&mut app, &mut app_reader, &["AUTH", "app", "wrong"]).is_error());
+    assert_eq!(send_resp(&mut app, &mut app_reader, &["AUTH", "app", "apppw"]), Reply::Ok);
+    assert_eq!(send_resp(&mut app, &mut app_reader, &["ACL", "WHOAMI"]), bulk("app"));
+    assert_eq!(send_resp(&mut app, &mut app_reader, &["SET", "orders:1", "{}"]), Reply::Ok);
+    assert_eq!(
+        send_resp(&mut app, &mut app_reader, &["GET", "secret"]),
+        Reply::Error("NOPERM No permissions to access a key".to_string())
+    );
+    // Refused before it can block.
+    assert_eq!(
+        send_resp(&mut app, &mut app_reader, &["BLPOP", "jobs", "0"]),
+        Reply::Error("NOPERM No permissions to access a key".to_string())
+    );
+    assert_eq!(
+        send_resp(&mut app, &mut app_reader, &["SNAPSHOT"]),
+        Reply::Error("NOPERM User app has no permissions to run the 'snapshot' command".to_string())
+    );
+    assert!(send_resp(&mut app, &mut app_reader, &["ACL", "LIST"]).is_error());
+    assert!(send_resp(&mut app, &mut app_reader, &["SELECT", "*", "FROM", "users"]).is_error());
+    assert!(!send_resp(&mut app, &mut app_reader, &["SELECT", "*", "FROM", "orders"]).is_error());
+
+    // Changes apply to logged-in clients at once; a disabled user is logged out.
+    assert_eq!(send_resp(&mut admin, &mut admin_reader, &["ACL", "SETUSER", "app", "-@write"]), Reply::Ok);
+    assert!(send_resp(&mut app, &mut app_reader, &["SET", "orders:2", "{}"]).is_error());
+    // RESP3 keeps the reply to GETUSER a map.
+    assert!(!send_resp(&mut admin, &mut admin_reader, &["HELLO", "3"]).is_error());
+    let Reply::Map(described) = send_resp(&mut admin, &mut admin_reader, &["ACL", "GETUSER", "app"]) else {
+        panic!("ACL GETUSER gave no map");
+    };
+    assert_eq!(described[0], (bulk("flags"), Reply::Array(vec![bulk("on")])));
+    assert_eq!(described[2], (bulk("commands"), bulk("+@read")));
+    assert_eq!(send_resp(&mut admin, &mut admin_reader, &["ACL", "SETUSER", "app", "off"]), Reply::Ok);
+    assert_eq!(
+        send_resp(&mut app, &mut app_reader, &["GET", "orders:1"]),
+        Reply::Error("NOAUTH Authentication required.".to_string())
+    );
+
+    // Users survive a restart.
+    assert_eq!(send_resp(&mut admin, &mut admin_reader, &["ACL", "SETUSER", "app", "on"]), Reply::Ok);
+    assert!(server.signal("TERM").success());
+    server.restart();
+    let (mut app, mut app_reader) = server.connect();
+    assert_eq!(send_resp(&mut app, &mut app_reader, &["AUTH", "app", "apppw"]), Reply::Ok);
+    assert_eq!(send_resp(&mut app, &mut app_reader, &["GET", "orders:1"]), bulk("{}"));
+    assert!(send_resp(&mut app, &mut app_reader, &["ACL", "DELUSER", "app"]).is_error());
+}
+
+/// Certificates for a TLS test, made fresh in `dir`: a CA, a server
+/// certificate for 127.0.0.1 and a client certificate for user `app`.
+fn make_certificates(dir: &std::path::Path) {
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair};
+    std::fs::create_dir_all(dir).unwrap();
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, "zyncdb test CA");
+    let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+    for (name, params) in [
+        ("server", CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap()),
+        ("app", {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.distinguished_name.push(DnType::CommonName, "app");
+            params
+        }),
+    ] {
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca).unwrap();
+        std::fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+        std::fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+    }
+}
+
+type TlsStream = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+/// Connect over TLS, trusting the test CA, with the client certificate
+/// `client` if given.
+fn connect_tls(server: &TestServer, certs: &std::path::Path, client: Option<&str>) -> TlsStream {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(certs.join("ca.pem")).unwrap()).unwrap();
+    let builder = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match client {
+        None => builder.with_no_client_auth(),
+        Some(name) => builder
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_file(certs.join(format!("{}.pem", name))).unwrap()],
+                PrivateKeyDer::from_pem_file(certs.join(format!("{}.key", name))).unwrap(),
+            )
+            .unwrap(),
+    };
+    let name = rustls::pki_types::ServerName::try_from("127.0.0.1").unwrap();
+    let connection = rustls::ClientConnection::new(std::sync::Arc::new(config), name).unwrap();
+    let (stream, _) = server.connect();
+    rustls::StreamOwned::new(connection, stream)
+}
+
+fn send_tls(stream: &mut TlsStream, args: &[&str]) -> Reply {
+    stream.write_all(&request(args)).unwrap();
+    reply(stream)
+}
+
+#[test]
+fn test_server_speaks_tls_and_logs_in_client_certificates() {
+    let certs = std::env::temp_dir().join(format!("zyncdb_certs_{}", std::process::id()));
+    make_certificates(&certs);
+    let config = format!(
+        "requirepass s3cret\ntls-cert-file {0}/server.pem\ntls-key-file {0}/server.key\n\
+         tls-ca-cert-file {0}/ca.pem\ntls-auth-clients optional\n",
+        certs.display()
+    );
+    let server = TestServer::start_with_config(Some(&config));
+    let bulk = |s: &str| Reply::Bulk(s.to_string());
+
+    // Nothing is served in plain text.
+    let (mut plain, mut plain_reader) = server.connect();
+    plain.write_all(&request(&["PING"])).unwrap();
+    let mut answer = Vec::new();
+    let _ = plain_reader.read_to_end(&mut answer);
+    assert!(!answer.windows(4).any(|w| w == b"PONG"));
+
+    // Without a certificate a client logs in as usual.
+    let mut admin = connect_tls(&server, &certs, None);
+    assert_eq!(
+        send_tls(&mut admin, &["GET", "k"]),
+        Reply::Error("NOAUTH Authentication required.".to_string())
+    );
+    assert_eq!(send_tls(&mut admin, &["AUTH", "s3cret"]), Reply::Ok);
+    assert_eq!(send_tls(&mut admin, &["SET", "k", "v"]), Reply::Ok);
+
+    // A certificate names an ACL user, who needs no password; until the user
+    // exists the client is not logged in.
+    let mut app = connect_tls(&server, &certs, Some("app"));
+    assert!(send_tls(&mut app, &["GET", "k"]).is_error());
+    assert_eq!(send_tls(&mut admin, &["ACL", "SETUSER", "app", "on", "+@read", "~*"]), Reply::Ok);
+    let mut app = connect_tls(&server, &certs, Some("app"));
+    assert_eq!(send_tls(&mut app, &["ACL", "WHOAMI"]), bulk("app"));
+    assert_eq!(send_tls(&mut app, &["GET", "k"]), bulk("v"));
+    assert!(send_tls(&mut app, &["SET", "k", "w"]).is_error());
+    drop(server);
+    let _ = std::fs::remove_dir_all(&certs);
+}
+
+#[test]
+fn test_tls_clients_are_told_when_the_server_is_full() {
+    let certs = std::env::temp_dir().join(format!("zyncdb_certs_full_{}", std::process::id()));
+    make_certificates(&certs);
+    let config = format!(
+        "maxclients 1\ntls-cert-file {0}/server.pem\ntls-key-file {0}/server.key\n",
+        certs.display()
+    );
+    let server = TestServer::start_with_config(Some(&config));
+
+    let mut first = connect_tls(&server, &certs, None);
+    assert_eq!(send_tls(&mut first, &["PING"]), Reply::Status("PONG".to_string()));
+    // The refusal comes after the handshake, where the client can read it.
+    let mut second = connect_tls(&server, &certs, None);
+    assert_eq!(reply(&mut second), Reply::Error("max number of clients reached".to_string()));
+    drop(server);
+    let _ = std::fs::remove_dir_all(&certs);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_server_listens_on_a_unix_socket_instead_of_tcp() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    let mut server = TestServer::start_with_config(Some("port 0\nunixsocket zyncdb.sock\nunixsocketperm 770\n"));
+    let path = server.dir.join("zyncdb.sock");
+    let mut stream = (0..50)
+        .find_map(|_| {
+            UnixStream::connect(&path)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(100)))
+                .ok()
+        })
+        .expect("Connect failed");
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o770);
+    // The socket was bound in a private directory, gone once it was moved out.
+    assert!(!server.dir.join(format!(".zyncdb-{}", server.child.id())).exists());
+    assert!(TcpStream::connect("127.0.0.1:6379").is_err());
+
+    stream.write_all(&request(&["SET", "k", "v"])).unwrap();
+    assert_eq!(reply(&mut stream), Reply::Ok);
+    stream.write_all(&request(&["GET", "k"])).unwrap();
+    assert_eq!(reply(&mut stream), Reply::Bulk("v".to_string()));
+
+    // The socket goes away with the server.
+    assert!(server.signal("TERM").success());
+    assert!(!path.exists());
+}
@@ -0,0 +1,68 @@
+//! Line-oriented record encoding shared by the WAL, snapshots and `FileStorage`.
+//!
+//! A record is a list of fields joined with `|`. Backslashes, pipes and line
+//! breaks inside a field are escaped, so arbitrary values round-trip safely.
+
+use crate::value::Value;
+
+pub const SEPARATOR: char = '|';
+
+pub fn escape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '|' => out.push_str("\\|"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn encode_record<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|f| escape(f.as_ref()))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+pub fn decode_record(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => current.push('\n'),
+                Some('r') => current.push('\r'),
+                Some(other) => current.push(other),
+                None => current.push('\\'),
+            },
+            SEPARATOR => fields.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    fields.push(current);
+    fields
+}
+
+/// Encode a whole key/value pair as `type|key|fields...`.
+pub fn encode_entry(key: &str, value: &Value) -> String {
+    let (tag, fields) = value.to_fields();
+    let mut record = vec![tag.to_string(), key.to_string()];
+    record.extend(fields);
+    encode_record(&record)
+}
+
+/// Decode a line produced by `encode_entry`.
+pub fn decode_entry(line: &str) -> Option<(String, Value)> {
+    let fields = decode_record(line);
+    if fields.len() < 2 {
+        return None;
+    }
+    let value = Value::from_fields(&fields[0], &fields[2..])?;
+    Some((fields[1].clone(), value))
+}
@@ -4,9 +4,14 @@ use std::io::{BufReader, BufWriter, Write, BufRead, Result};
 use std::path::Path;
 
 use super::Storage;
+use crate::codec::{decode_entry, encode_entry};
+use crate::value::Value;
+
+/// First line of a typed data file. Files without it use the legacy `key=value` format.
+const HEADER: &str = "#zyncdb-file v2";
 
 pub struct FileStorage {
-    map: HashMap<String, String>,
+    map: HashMap<String, Value>,
     file_path: String,
 }
 
@@ -18,11 +23,22 @@ impl FileStorage {
         // Load existing data if file exists
         if let Ok(file) = File::open(&file_path) {
             let reader = BufReader::new(file);
-            for line in reader.lines() {
+            let mut typed = false;
+            for (i, line) in reader.lines().enumerate() {
                 let line = line?;
-                let parts: Vec<&str> = line.trim().splitn(2, '=').collect();
-                if parts.len() == 2 {
-                    map.insert(parts[0].to_string(), parts[1].to_string());
+                if i == 0 && line == HEADER {
+                    typed = true;
+                    continue;
+                }
+                if typed {
+                    if let Some((key, value)) = decode_entry(&line) {
+                        map.insert(key, value);
+                    }
+                } else {
+                    let parts: Vec<&str> = line.trim().splitn(2, '=').collect();
+                    if parts.len() == 2 {
+                        map.insert(parts[0].to_string(), Value::from(parts[1]));
+                    }
                 }
             }
         }
@@ -33,18 +49,22 @@ impl FileStorage {
     fn persist(&self) -> Result<()> {
         let file = OpenOptions::new().write(true).truncate(true).create(true).open(&self.file_path)?;
         let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", HEADER)?;
         for (k, v) in &self.map {
-            writeln!(writer, "{}={}", k, v)?;
+            writeln!(writer, "{}", encode_entry(k, v))?;
         }
         writer.flush()
     }
 }
 
 impl Storage for FileStorage {
-    fn get(&self, key: &str) -> Option<String> {
-        self.map.get(key).cloned()
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.map.get(key)
+    }
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.map.get_mut(key)
     }
-    fn insert(&mut self, key: String, value: String) -> Option<String> {
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
         let prev = self.map.insert(key, value);
         let _ = self.persist();
         prev
@@ -54,8 +74,8 @@ impl Storage for FileStorage {
         let _ = self.persist();
         existed
     }
-    fn iter(&self) -> Box<dyn Iterator<Item = (String, String)> + '_> {
-        Box::new(self.map.clone().into_iter())
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(self.map.iter())
     }
     fn len(&self) -> usize {
         self.map.len()
@@ -64,4 +84,7 @@ impl Storage for FileStorage {
         self.map.clear();
         let _ = self.persist();
     }
-}
\ No newline at end of file
+    fn flush(&mut self) {
+        let _ = self.persist();
+    }
+}
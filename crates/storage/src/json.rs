@@ -0,0 +1,168 @@
+//! Compact binary encoding for JSON documents.
+//!
+//! Each value starts with a one-byte tag. Lengths and integers are LEB128
+//! varints (signed integers zig-zag encoded), floats are 8 little-endian
+//! bytes, and object members are stored as key/value pairs.
+
+use serde_json::{Map, Number, Value as JsonValue};
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INT: u8 = 3;
+const UINT: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const ARRAY: u8 = 7;
+const OBJECT: u8 = 8;
+
+/// A JSON document held in its binary encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonDoc(Vec<u8>);
+
+impl JsonDoc {
+    pub fn encode(value: &JsonValue) -> JsonDoc {
+        let mut buf = Vec::new();
+        write_value(&mut buf, value);
+        JsonDoc(buf)
+    }
+
+    pub fn decode(&self) -> JsonValue {
+        let mut pos = 0;
+        read_value(&self.0, &mut pos).expect("JsonDoc holds a valid encoding")
+    }
+
+    /// Parse and validate JSON text.
+    pub fn parse(text: &str) -> Result<JsonDoc, serde_json::Error> {
+        let value: JsonValue = serde_json::from_str(text)?;
+        Ok(JsonDoc::encode(&value))
+    }
+
+    /// Compact JSON text, used for snapshots and replies.
+    pub fn to_text(&self) -> String {
+        self.decode().to_string()
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut n = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn read_str(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    let len = read_varint(bytes, pos)? as usize;
+    let s = bytes.get(*pos..*pos + len)?;
+    *pos += len;
+    String::from_utf8(s.to_vec()).ok()
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &JsonValue) {
+    match value {
+        JsonValue::Null => buf.push(NULL),
+        JsonValue::Bool(false) => buf.push(FALSE),
+        JsonValue::Bool(true) => buf.push(TRUE),
+        JsonValue::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                buf.push(INT);
+                write_varint(buf, ((i << 1) ^ (i >> 63)) as u64);
+            } else if let Some(u) = n.as_u64() {
+                buf.push(UINT);
+                write_varint(buf, u);
+            } else {
+                buf.push(FLOAT);
+                buf.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_le_bytes());
+            }
+        }
+        JsonValue::String(s) => {
+            buf.push(STRING);
+            write_str(buf, s);
+        }
+        JsonValue::Array(items) => {
+            buf.push(ARRAY);
+            write_varint(buf, items.len() as u64);
+            for item in items {
+                write_value(buf, item);
+            }
+        }
+        JsonValue::Object(members) => {
+            buf.push(OBJECT);
+            write_varint(buf, members.len() as u64);
+            for (k, v) in members {
+                write_str(buf, k);
+                write_value(buf, v);
+            }
+        }
+    }
+}
+
+fn read_value(bytes: &[u8], pos: &mut usize) -> Option<JsonValue> {
+    let tag = *bytes.get(*pos)?;
+    *pos += 1;
+    Some(match tag {
+        NULL => JsonValue::Null,
+        FALSE => JsonValue::Bool(false),
+        TRUE => JsonValue::Bool(true),
+        INT => {
+            let z = read_varint(bytes, pos)?;
+            JsonValue::from(((z >> 1) as i64) ^ -((z & 1) as i64))
+        }
+        UINT => JsonValue::from(read_varint(bytes, pos)?),
+        FLOAT => {
+            let raw: [u8; 8] = bytes.get(*pos..*pos + 8)?.try_into().ok()?;
+            *pos += 8;
+            JsonValue::Number(Number::from_f64(f64::from_le_bytes(raw))?)
+        }
+        STRING => JsonValue::String(read_str(bytes, pos)?),
+        ARRAY => {
+            let len = read_varint(bytes, pos)? as usize;
+            let mut items = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                items.push(read_value(bytes, pos)?);
+            }
+            JsonValue::Array(items)
+        }
+        OBJECT => {
+            let len = read_varint(bytes, pos)? as usize;
+            let mut members = Map::new();
+            for _ in 0..len {
+                let key = read_str(bytes, pos)?;
+                members.insert(key, read_value(bytes, pos)?);
+            }
+            JsonValue::Object(members)
+        }
+        _ => return None,
+    })
+}
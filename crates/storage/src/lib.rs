@@ -1,4 +1,13 @@
+pub mod codec;
 pub mod storage;
+pub mod stream;
 pub mod file_storage;
+pub mod json;
+pub mod value;
+pub mod zset;
 pub use storage::{Storage, MemStorage};
-pub use file_storage::FileStorage;
\ No newline at end of file
+pub use stream::{Stream, StreamId};
+pub use file_storage::FileStorage;
+pub use json::JsonDoc;
+pub use value::Value;
+pub use zset::ZSet;
@@ -1,16 +1,26 @@
 use std::collections::HashMap;
 
+use crate::value::Value;
+
 pub trait Storage: Send + Sync {
-    fn get(&self, key: &str) -> Option<String>;
-    fn insert(&mut self, key: String, value: String) -> Option<String>;
+    fn get(&self, key: &str) -> Option<&Value>;
+    /// Mutable access for in-place updates. Callers must call `flush` afterwards
+    /// so that persistent backends can write the change out.
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value>;
+    fn insert(&mut self, key: String, value: Value) -> Option<Value>;
     fn delete(&mut self, key: &str) -> bool;
-    fn iter(&self) -> Box<dyn Iterator<Item = (String, String)> + '_>;
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_>;
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     fn clear(&mut self);
+    fn flush(&mut self) {}
 }
 
+#[derive(Default)]
 pub struct MemStorage {
-    map: HashMap<String, String>,
+    map: HashMap<String, Value>,
 }
 
 impl MemStorage {
@@ -20,17 +30,20 @@ impl MemStorage {
 }
 
 impl Storage for MemStorage {
-    fn get(&self, key: &str) -> Option<String> {
-        self.map.get(key).cloned()
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.map.get(key)
     }
-    fn insert(&mut self, key: String, value: String) -> Option<String> {
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.map.get_mut(key)
+    }
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
         self.map.insert(key, value)
     }
     fn delete(&mut self, key: &str) -> bool {
         self.map.remove(key).is_some()
     }
-    fn iter(&self) -> Box<dyn Iterator<Item = (String, String)> + '_> {
-        Box::new(self.map.clone().into_iter())
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(self.map.iter())
     }
     fn len(&self) -> usize {
         self.map.len()
@@ -38,4 +51,4 @@ impl Storage for MemStorage {
     fn clear(&mut self) {
         self.map.clear();
     }
-}
\ No newline at end of file
+}
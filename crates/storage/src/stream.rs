@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// A stream entry ID: milliseconds since the epoch plus a sequence number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Parses `ms-seq`, or a bare `ms` meaning `ms-0`.
+impl FromStr for StreamId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((ms, seq)) => Ok(StreamId {
+                ms: ms.parse().map_err(|_| ())?,
+                seq: seq.parse().map_err(|_| ())?,
+            }),
+            None => Ok(StreamId {
+                ms: s.parse().map_err(|_| ())?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+/// An entry delivered to a consumer but not yet acknowledged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivered_at: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+}
+
+/// An append-only log of field/value entries ordered by ID.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    pub last_id: StreamId,
+    pub groups: HashMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Flatten into persistence fields:
+    /// `last_id n (id k field value...)* g (name last n (id consumer at count)*)*`.
+    pub fn to_fields(&self) -> Vec<String> {
+        let mut out = vec![self.last_id.to_string(), self.entries.len().to_string()];
+        for (id, fields) in &self.entries {
+            out.push(id.to_string());
+            out.push(fields.len().to_string());
+            for (f, v) in fields {
+                out.push(f.clone());
+                out.push(v.clone());
+            }
+        }
+        out.push(self.groups.len().to_string());
+        for (name, group) in &self.groups {
+            out.push(name.clone());
+            out.push(group.last_delivered.to_string());
+            out.push(group.pending.len().to_string());
+            for (id, p) in &group.pending {
+                out.push(id.to_string());
+                out.push(p.consumer.clone());
+                out.push(p.delivered_at.to_string());
+                out.push(p.delivery_count.to_string());
+            }
+        }
+        out
+    }
+
+    /// Inverse of `to_fields`.
+    pub fn from_fields(fields: &[String]) -> Option<Stream> {
+        let mut it = fields.iter();
+        let mut next = || it.next();
+        let mut stream = Stream {
+            last_id: next()?.parse().ok()?,
+            ..Stream::default()
+        };
+        let entries: usize = next()?.parse().ok()?;
+        for _ in 0..entries {
+            let id: StreamId = next()?.parse().ok()?;
+            let count: usize = next()?.parse().ok()?;
+            let mut pairs = Vec::with_capacity(count);
+            for _ in 0..count {
+                pairs.push((next()?.clone(), next()?.clone()));
+            }
+            stream.entries.insert(id, pairs);
+        }
+        let groups: usize = next()?.parse().ok()?;
+        for _ in 0..groups {
+            let name = next()?.clone();
+            let mut group = ConsumerGroup {
+                last_delivered: next()?.parse().ok()?,
+                ..ConsumerGroup::default()
+            };
+            let pending: usize = next()?.parse().ok()?;
+            for _ in 0..pending {
+                let id: StreamId = next()?.parse().ok()?;
+                let entry = PendingEntry {
+                    consumer: next()?.clone(),
+                    delivered_at: next()?.parse().ok()?,
+                    delivery_count: next()?.parse().ok()?,
+                };
+                group.pending.insert(id, entry);
+            }
+            stream.groups.insert(name, group);
+        }
+        if next().is_some() {
+            return None;
+        }
+        Some(stream)
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::json::JsonDoc;
+use crate::stream::Stream;
+use crate::zset::ZSet;
+
+/// A typed value held by a `Storage` backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    List(VecDeque<String>),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+    ZSet(ZSet),
+    Stream(Stream),
+    Json(JsonDoc),
+}
+
+impl Value {
+    /// The name reported by `TYPE` and used as the tag in persisted records.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+            Value::Json(_) => "json",
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&String> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Flatten the value into a type tag plus fields, for persistence.
+    pub fn to_fields(&self) -> (&'static str, Vec<String>) {
+        let fields = match self {
+            Value::String(s) => vec![s.clone()],
+            Value::List(items) => items.iter().cloned().collect(),
+            Value::Hash(fields) => fields
+                .iter()
+                .flat_map(|(f, v)| [f.clone(), v.clone()])
+                .collect(),
+            Value::Set(members) => members.iter().cloned().collect(),
+            Value::ZSet(zset) => zset
+                .iter()
+                .flat_map(|(member, score)| [member.clone(), score.to_string()])
+                .collect(),
+            Value::Stream(stream) => stream.to_fields(),
+            Value::Json(doc) => vec![doc.to_text()],
+        };
+        (self.type_name(), fields)
+    }
+
+    /// Inverse of `to_fields`. Returns `None` for an unknown tag or malformed fields.
+    pub fn from_fields(tag: &str, fields: &[String]) -> Option<Value> {
+        match tag {
+            "string" if fields.len() == 1 => Some(Value::String(fields[0].clone())),
+            "list" => Some(Value::List(fields.iter().cloned().collect())),
+            "hash" if fields.len().is_multiple_of(2) => Some(Value::Hash(
+                fields
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+            )),
+            "set" => Some(Value::Set(fields.iter().cloned().collect())),
+            "zset" if fields.len().is_multiple_of(2) => {
+                let mut zset = ZSet::new();
+                for pair in fields.chunks(2) {
+                    zset.insert(pair[0].clone(), pair[1].parse().ok()?);
+                }
+                Some(Value::ZSet(zset))
+            }
+            "stream" => Stream::from_fields(fields).map(Value::Stream),
+            "json" if fields.len() == 1 => JsonDoc::parse(&fields[0]).ok().map(Value::Json),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Hash(fields) => {
+                write!(f, "{{")?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Set(members) => {
+                write!(f, "{{")?;
+                for (i, member) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", member)?;
+                }
+                write!(f, "}}")
+            }
+            Value::ZSet(zset) => {
+                write!(f, "[")?;
+                for (i, (member, score)) in zset.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", member, score)?;
+                }
+                write!(f, "]")
+            }
+            Value::Stream(stream) => write!(f, "(stream of {} entries)", stream.len()),
+            Value::Json(doc) => write!(f, "{}", doc.to_text()),
+        }
+    }
+}